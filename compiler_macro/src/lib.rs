@@ -1,8 +1,9 @@
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{Attribute, Data, DeriveInput, Expr, Fields, Lit, Meta};
+use syn::punctuated::Punctuated;
+use syn::{Attribute, Data, DeriveInput, Expr, Fields, Lit, Meta, Path, Token};
 
-#[proc_macro_derive(Inst, attributes(asm_name, is_branch))]
+#[proc_macro_derive(Inst, attributes(asm_name, is_branch, implicit_defs, implicit_uses))]
 pub fn inst_macro(input: TokenStream) -> TokenStream {
     let ast = syn::parse(input).unwrap();
     inst_macro_impl(ast)
@@ -17,6 +18,20 @@ fn is_branch(attrs: &[Attribute]) -> bool {
     false
 }
 
+/// Get the registers listed in an attribute like `#[implicit_defs(RA, A0)]`.
+fn get_implicit_regs(attrs: &[Attribute], name: &str) -> Vec<Path> {
+    for attr in attrs {
+        if attr.path().is_ident(name) {
+            return attr
+                .parse_args_with(Punctuated::<Path, Token![,]>::parse_terminated)
+                .unwrap()
+                .into_iter()
+                .collect();
+        }
+    }
+    vec![]
+}
+
 fn get_asm_name(attrs: &[Attribute]) -> Option<String> {
     for attr in attrs {
         if attr.path().is_ident("asm_name") {
@@ -68,6 +83,28 @@ fn inst_macro_impl(ast: DeriveInput) -> TokenStream {
         format_str.pop();
     }
 
+    // Registers are named `rd` when they are written and `rs`, `rs1` or `rs2` when they are read,
+    // with an `f` instead of the `r` for floating-point registers.
+    let defs = fields
+        .iter()
+        .filter(|field| ["rd", "fd"].iter().any(|name| *field == name))
+        .collect::<Vec<_>>();
+    let uses = fields
+        .iter()
+        .filter(|field| {
+            ["rs", "rs1", "rs2", "fs", "fs1", "fs2"]
+                .iter()
+                .any(|name| *field == name)
+        })
+        .collect::<Vec<_>>();
+    let implicit_defs = get_implicit_regs(&ast.attrs, "implicit_defs");
+    let implicit_uses = get_implicit_regs(&ast.attrs, "implicit_uses");
+    let label = if fields.iter().any(|field| *field == "label") {
+        quote! { Some(&self.label) }
+    } else {
+        quote! { None }
+    };
+
     let is_branch = is_branch(&ast.attrs);
     let gen = quote! {
         impl Inst for #name {
@@ -78,6 +115,18 @@ fn inst_macro_impl(ast: DeriveInput) -> TokenStream {
             fn is_branch(&self) -> bool {
                 #is_branch
             }
+
+            fn defs(&self) -> Vec<Register> {
+                vec![#(self.#defs,)* #(#implicit_defs,)*]
+            }
+
+            fn uses(&self) -> Vec<Register> {
+                vec![#(self.#uses,)* #(#implicit_uses,)*]
+            }
+
+            fn label(&self) -> Option<&str> {
+                #label
+            }
         }
     };
     gen.into()
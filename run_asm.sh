@@ -1,7 +1,7 @@
 #!/usr/bin/bash
 
 ./target/release/compiler -riscv temp.c -o temp.S
-clang temp.S -c -o temp.o -target riscv32-unknown-linux-elf -march=rv32im -mabi=ilp32
+clang temp.S -c -o temp.o -target riscv32-unknown-linux-elf -march=rv32imf -mabi=ilp32f
 ld.lld temp.o -L"$CDE_LIBRARY_PATH"/riscv32 -lsysy -o temp
 qemu-riscv32-static temp
 echo $?
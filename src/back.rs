@@ -1,7 +1,17 @@
+mod asm_cfg;
+mod asm_liveness;
 mod codegen;
 mod context;
+mod floats;
 mod inst;
+mod liveness;
+mod peephole;
 mod program;
+mod regalloc;
 mod register;
+mod sim;
 
 pub use codegen::generate_asm;
+pub use program::{AsmProgram, Assembly};
+pub use regalloc::RegAllocStrategy;
+pub use sim::{simulate, SimResult};
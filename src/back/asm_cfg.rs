@@ -0,0 +1,85 @@
+//! Control flow graph of generated assembly.
+//!
+//! Blocks are identified by their index in the function. The successors of a block are the
+//! targets of its branches, and the next block if the last instruction may fall through to it.
+
+use crate::back::inst::{AsmInst, Inst};
+use crate::back::program::AsmFunc;
+use std::collections::HashMap;
+
+pub struct AsmCfg {
+    labels: HashMap<String, usize>,
+    succs: Vec<Vec<usize>>,
+    preds: Vec<Vec<usize>>,
+    /// The block executed after the last instruction of every block, if it is not a jump.
+    fall_through: Vec<Option<usize>>,
+}
+
+impl AsmCfg {
+    pub fn new(func: &AsmFunc) -> Self {
+        let blocks = func.blocks();
+        let labels: HashMap<String, usize> = blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| (block.name().to_string(), i))
+            .collect();
+        let mut succs = vec![vec![]; blocks.len()];
+        let mut preds = vec![vec![]; blocks.len()];
+        let mut fall_through = vec![None; blocks.len()];
+        for (i, block) in blocks.iter().enumerate() {
+            let falls_through = !matches!(
+                block.insts().last(),
+                Some(AsmInst::Jmp(_)) | Some(AsmInst::Ret(_)) | Some(AsmInst::TailCall(_))
+            );
+            if falls_through && i + 1 < blocks.len() {
+                fall_through[i] = Some(i + 1);
+            }
+            let mut block_succs: Vec<usize> = vec![];
+            let targets = block
+                .insts()
+                .iter()
+                .filter_map(|inst| branch_target(&labels, inst));
+            for succ in targets.chain(fall_through[i]) {
+                if !block_succs.contains(&succ) {
+                    block_succs.push(succ);
+                }
+            }
+            for succ in &block_succs {
+                if !preds[*succ].contains(&i) {
+                    preds[*succ].push(i);
+                }
+            }
+            succs[i] = block_succs;
+        }
+        Self {
+            labels,
+            succs,
+            preds,
+            fall_through,
+        }
+    }
+
+    pub fn succs(&self, block: usize) -> &[usize] {
+        &self.succs[block]
+    }
+
+    pub fn preds(&self, block: usize) -> &[usize] {
+        &self.preds[block]
+    }
+
+    pub fn fall_through(&self, block: usize) -> Option<usize> {
+        self.fall_through[block]
+    }
+
+    /// Get the block a branch instruction jumps to, `None` for other instructions.
+    pub fn target(&self, inst: &AsmInst) -> Option<usize> {
+        branch_target(&self.labels, inst)
+    }
+}
+
+fn branch_target(labels: &HashMap<String, usize>, inst: &AsmInst) -> Option<usize> {
+    if !inst.is_branch() {
+        return None;
+    }
+    inst.label().and_then(|label| labels.get(label).copied())
+}
@@ -0,0 +1,254 @@
+//! Liveness analysis of registers in generated assembly.
+//!
+//! Uses the registers read and written by every instruction, including the implicit ones of
+//! calls and returns, so the result is only as precise as [`Inst::uses`] and [`Inst::defs`].
+
+use crate::back::asm_cfg::AsmCfg;
+use crate::back::inst::{AsmInst, Inst};
+use crate::back::program::AsmFunc;
+use crate::back::register::{Register, ZERO};
+use std::collections::HashSet;
+
+pub struct AsmLiveness {
+    live_in: Vec<HashSet<Register>>,
+    live_out: Vec<HashSet<Register>>,
+    /// Registers live after every instruction of every block.
+    live_after: Vec<Vec<HashSet<Register>>>,
+}
+
+impl AsmLiveness {
+    pub fn analyze(func: &AsmFunc, cfg: &AsmCfg) -> Self {
+        let blocks = func.blocks();
+        let mut live_in = vec![HashSet::new(); blocks.len()];
+
+        // Blocks are popped from the end, which is close to post order.
+        let mut worklist: Vec<usize> = (0..blocks.len()).collect();
+        let mut in_worklist = vec![true; blocks.len()];
+        while let Some(block) = worklist.pop() {
+            in_worklist[block] = false;
+            let new_live_in = scan(cfg, &live_in, block, blocks[block].insts(), |_, _| {});
+            if new_live_in != live_in[block] {
+                live_in[block] = new_live_in;
+                for pred in cfg.preds(block) {
+                    if !in_worklist[*pred] {
+                        in_worklist[*pred] = true;
+                        worklist.push(*pred);
+                    }
+                }
+            }
+        }
+
+        let live_out = (0..blocks.len())
+            .map(|block| {
+                cfg.succs(block)
+                    .iter()
+                    .flat_map(|succ| live_in[*succ].iter().copied())
+                    .collect()
+            })
+            .collect();
+        let live_after = blocks
+            .iter()
+            .enumerate()
+            .map(|(block, asm_block)| {
+                let mut live_after = vec![HashSet::new(); asm_block.insts().len()];
+                scan(cfg, &live_in, block, asm_block.insts(), |i, live| {
+                    live_after[i] = live.clone()
+                });
+                live_after
+            })
+            .collect();
+        Self {
+            live_in,
+            live_out,
+            live_after,
+        }
+    }
+
+    pub fn live_in(&self, block: usize) -> &HashSet<Register> {
+        &self.live_in[block]
+    }
+
+    pub fn live_out(&self, block: usize) -> &HashSet<Register> {
+        &self.live_out[block]
+    }
+
+    /// Get the registers live right after an instruction, given its index in the block.
+    pub fn live_after(&self, block: usize, inst: usize) -> &HashSet<Register> {
+        &self.live_after[block][inst]
+    }
+
+    /// Get the registers live everywhere in a function but after its returns, like the
+    /// callee-saved registers it leaves untouched, which are all read by the returns.
+    fn live_throughout(&self, func: &AsmFunc) -> HashSet<Register> {
+        let mut live = self.live_in[0].clone();
+        for (i, block) in func.blocks().iter().enumerate() {
+            live.retain(|reg| self.live_in(i).contains(reg));
+            for (j, inst) in block.insts().iter().enumerate() {
+                if !matches!(inst, AsmInst::Ret(_) | AsmInst::TailCall(_)) {
+                    live.retain(|reg| self.live_after(i, j).contains(reg));
+                }
+            }
+        }
+        live
+    }
+
+    /// Show the registers live on entry to and exit from every block, and after every
+    /// instruction. The registers live throughout the function are only shown once.
+    pub fn dump(&self, func: &AsmFunc) -> String {
+        let throughout = self.live_throughout(func);
+        let regs = |live: &HashSet<Register>| names(live.difference(&throughout));
+        let mut s = format!("{}:\n", func.name());
+        s.push_str(&format!(
+            "\t# live throughout: {}\n",
+            names(throughout.iter())
+        ));
+        for (i, block) in func.blocks().iter().enumerate() {
+            s.push_str(&format!("{}:\n", block.name()));
+            s.push_str(&format!("\t# live-in: {}\n", regs(self.live_in(i))));
+            for (j, inst) in block.insts().iter().enumerate() {
+                let live = regs(self.live_after(i, j));
+                s.push_str(&format!("\t{:<24} # {}\n", inst.dump(), live));
+            }
+            s.push_str(&format!("\t# live-out: {}\n", regs(self.live_out(i))));
+        }
+        s
+    }
+}
+
+/// Get the sorted names of registers, separated by commas.
+fn names<'a>(regs: impl Iterator<Item = &'a Register>) -> String {
+    let mut names: Vec<String> = regs.map(|reg| reg.to_string()).collect();
+    names.sort();
+    names.join(", ")
+}
+
+/// Walk a block backwards, calling `visit` with the registers live after every instruction, and
+/// return the registers live on entry.
+fn scan(
+    cfg: &AsmCfg,
+    live_in: &[HashSet<Register>],
+    block: usize,
+    insts: &[AsmInst],
+    mut visit: impl FnMut(usize, &HashSet<Register>),
+) -> HashSet<Register> {
+    let mut live = match cfg.fall_through(block) {
+        Some(next) => live_in[next].clone(),
+        None => HashSet::new(),
+    };
+    for (i, inst) in insts.iter().enumerate().rev() {
+        if let Some(target) = cfg.target(inst) {
+            // Nothing after a jump is executed.
+            if let AsmInst::Jmp(_) = inst {
+                live.clear();
+            }
+            live.extend(live_in[target].iter().copied());
+        }
+        visit(i, &live);
+        for def in inst.defs() {
+            live.remove(&def);
+        }
+        live.extend(inst.uses().into_iter().filter(|reg| *reg != ZERO));
+    }
+    live
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::back::inst::{Addi, Bnez, LoadImm, Move, Ret, Sub};
+    use crate::back::program::AsmBlock;
+    use crate::back::register::*;
+    use crate::back::RegAllocStrategy;
+    use crate::util::testing::{assemble, compile_at};
+
+    #[test]
+    fn test_asm_liveness() {
+        // Count `t1` up to `a1` in a loop and return it.
+        let mut func = AsmFunc::new("f".to_string());
+        let blocks: [(&str, Vec<AsmInst>); 3] = [
+            ("f", vec![LoadImm { rd: T1, imm: 0 }.into()]),
+            (
+                ".f_loop",
+                vec![
+                    Addi {
+                        rd: T1,
+                        rs: T1,
+                        imm: 1,
+                    }
+                    .into(),
+                    Sub {
+                        rd: T2,
+                        rs1: T1,
+                        rs2: A1,
+                    }
+                    .into(),
+                    Bnez {
+                        rs: T2,
+                        label: ".f_loop".to_string(),
+                    }
+                    .into(),
+                ],
+            ),
+            (".f_end", vec![Move { rd: A0, rs: T1 }.into(), Ret.into()]),
+        ];
+        for (name, insts) in blocks {
+            func.add_block(AsmBlock::new(name.to_string()))
+                .add_insts(insts);
+        }
+        let cfg = AsmCfg::new(&func);
+        assert_eq!(
+            (cfg.succs(0), cfg.succs(1), cfg.succs(2)),
+            (&[1][..], &[1, 2][..], &[][..])
+        );
+        assert_eq!(cfg.preds(1), [0, 1]);
+
+        let liveness = AsmLiveness::analyze(&func, &cfg);
+        assert!(liveness.live_in(0).contains(&A1) && !liveness.live_in(0).contains(&T1));
+        assert!(liveness.live_in(1).contains(&T1) && liveness.live_in(1).contains(&A1));
+        assert!(!liveness.live_in(1).contains(&T2));
+        assert!(liveness.live_out(1).contains(&T1));
+        // The return reads the callee-saved registers, but not `t1` after it is copied.
+        assert!(liveness.live_in(2).contains(&S1) && !liveness.live_after(2, 0).contains(&T1));
+
+        // The dump shows the registers left untouched once, and only the others per block.
+        let dump = liveness.dump(&func);
+        let throughout = dump.lines().nth(1).unwrap();
+        assert!(throughout.starts_with("\t# live throughout: fa0, fp,"));
+        assert!(["ra", "s1", "s11", "fs0", "sp"]
+            .iter()
+            .all(|reg| throughout.contains(reg)));
+        assert!(!throughout.contains("t1") && !throughout.contains("a1"));
+        assert!(dump.contains(".f_loop:\n\t# live-in: a1, t1\n"));
+    }
+
+    #[test]
+    fn test_dump_loop() {
+        let program = compile_at(
+            "int main() { int n = getint(); int s = 0; int i = 0;
+                while (i < n) { s = s + i; i = i + 1; } return s; }",
+            1,
+        );
+        let asm = assemble(program, RegAllocStrategy::LinearScan);
+        let main = asm.funcs().find(|func| func.name() == "main").unwrap();
+        let cfg = AsmCfg::new(main);
+        let liveness = AsmLiveness::analyze(main, &cfg);
+        // The loop header is the block jumped to from a later block.
+        let header = (0..main.blocks().len())
+            .find(|block| cfg.preds(*block).iter().any(|pred| pred > block))
+            .unwrap();
+        // `n`, `s` and `i` are live on entry to the header, and nothing else but the
+        // registers live throughout the function.
+        let dump = liveness.dump(main);
+        let live_in = dump
+            .split(&format!("{}:\n\t# live-in: ", main.blocks()[header].name()))
+            .nth(1)
+            .unwrap()
+            .lines()
+            .next()
+            .unwrap();
+        assert_eq!(live_in.split(", ").count(), 3);
+        assert!(live_in
+            .split(", ")
+            .all(|reg| !reg.starts_with('s') && !reg.starts_with('f')));
+    }
+}
@@ -1,9 +1,19 @@
-use crate::back::context::{variable_name, AsmError, Context, ValueLocation, PARAMETER_REGISTERS};
+use crate::back::asm_cfg::AsmCfg;
+use crate::back::asm_liveness::AsmLiveness;
+use crate::back::context::{
+    arg_locations, load_word, parallel_move, stack_args_size, store_word, variable_name, AsmError,
+    Context, ValueLocation,
+};
+use crate::back::floats::FloatValues;
 use crate::back::inst::*;
-use crate::back::program::{AsmBlock, AsmFunc, AsmProgram, AsmVarDecl, Assembly};
+use crate::back::peephole;
+use crate::back::program::{AsmBlock, AsmFunc, AsmProgram, AsmVarDecl};
+use crate::back::regalloc::{self, RegAllocStrategy, PARAM_POSITION};
 use crate::back::register::*;
 use crate::between;
-use crate::util::logger::show_error;
+use crate::front::ir::builtin::Intrinsic;
+use crate::util::ir::{is_tail_call, may_point_to_frame};
+use crate::util::logger::show_error_no_exit;
 use crate::util::remove_pointer;
 use koopa::ir::entities::ValueData;
 use koopa::ir::values::{Aggregate, Call as IRCall, GetElemPtr, GetPtr};
@@ -11,6 +21,7 @@ use koopa::ir::values::{Binary, Branch, Jump, Load, Return, Store};
 use koopa::ir::{
     BasicBlock, BinaryOp, Function, FunctionData, Program, Type, TypeKind, Value, ValueKind,
 };
+use std::process::exit;
 
 struct GlobalValue(Value);
 struct GlobalAggregate(Aggregate, usize);
@@ -42,12 +53,18 @@ fn get_type(value: Value, ctx: &Context, program: &Program) -> Result<Type, AsmE
     }
 }
 
-pub fn generate_asm(program: Program) -> String {
+pub fn generate_asm(
+    program: Program,
+    strategy: RegAllocStrategy,
+    dump_liveness: bool,
+) -> AsmProgram {
     let functions = program.func_layout().to_vec();
     let global_vars = program.inst_layout().to_vec();
-    let mut ctx = Context::default();
+    let mut ctx = Context::new(strategy);
+    ctx.floats = FloatValues::analyze(&program);
 
     let mut asm = AsmProgram::new();
+    let mut errors = vec![];
 
     // Global variables
     for global_var in global_vars {
@@ -57,12 +74,10 @@ pub fn generate_asm(program: Program) -> String {
             // Global variable name is always start with "@_0_"
             |name| name[4..].to_string(),
         );
-        let asm_var_decl = GlobalValue(global_var)
-            .to_asm(&mut ctx, &program)
-            .unwrap_or_else(|e| {
-                show_error(&format!("{:?}", e), 3);
-            });
-        asm.add_var_decl(asm_var_decl);
+        match GlobalValue(global_var).to_asm(&mut ctx, &program) {
+            Ok(asm_var_decl) => asm.add_var_decl(asm_var_decl),
+            Err(e) => errors.push(e),
+        }
         // Add global variable to the symbol table.
         ctx.symbol_table
             .insert(name.clone(), ValueLocation::GlobalValue(name));
@@ -75,12 +90,31 @@ pub fn generate_asm(program: Program) -> String {
         if func_data.dfg().bbs().is_empty() {
             continue;
         }
-        asm.add_func(func.to_asm(&mut ctx, &program).unwrap_or_else(|e| {
-            show_error(&format!("{:?}", e), 3);
-        }));
+        let mut asm_func = match func.to_asm(&mut ctx, &program) {
+            Ok(asm_func) => asm_func,
+            Err(e) => {
+                errors.push(e);
+                ctx.func = None;
+                continue;
+            }
+        };
+        peephole::optimize(&mut asm_func);
+        if dump_liveness {
+            let cfg = AsmCfg::new(&asm_func);
+            eprint!("{}", AsmLiveness::analyze(&asm_func, &cfg).dump(&asm_func));
+        }
+        asm.add_func(asm_func);
         ctx.func = None;
     }
-    asm.dump()
+
+    // Report the errors of all functions before giving up.
+    if !errors.is_empty() {
+        for e in &errors {
+            show_error_no_exit(&format!("{:?}", e));
+        }
+        exit(3);
+    }
+    asm
 }
 
 fn get_init_array(agg: &ValueData, ty: &Type, func_data: &FunctionData) -> Vec<i32> {
@@ -108,33 +142,48 @@ trait ToAsm {
     fn to_asm(&self, ctx: &mut Context, program: &Program) -> Result<Self::Output, AsmError>;
 }
 
-fn prologue_insts(ctx: &mut Context, program: &Program) -> Vec<Box<dyn Inst>> {
-    let func = ctx.func.expect("No function context.");
-    let func_data = program.func(func);
-    let param_num = func_data.params().len();
-    let mut insts: Vec<Box<dyn Inst>> = vec![];
+fn prologue_insts(ctx: &mut Context, program: &Program) -> Vec<AsmInst> {
+    let mut insts: Vec<AsmInst> = vec![];
 
     // Save used callee saved registers to stack.
     let used_callee_saved_registers = ctx.reg_allocator.used_callee_saved_registers();
     for reg in used_callee_saved_registers {
         let offset = ctx.stack_allocator.allocate(4);
-        insts.push(Box::new(Sw {
-            rs1: reg,
-            offset,
-            rs2: SP,
-        }));
+        insts.push(store_word(reg, offset, SP));
         ctx.reg_allocator.insert_callee_saved_register(reg, offset);
     }
 
-    let use_fp = param_num > 8;
+    // With a register allocation, the return address is saved once instead of around every call.
+    if ctx.allocation.is_some() && ctx.has_call(program) {
+        let offset = ctx.stack_allocator.allocate(4);
+        insts.push(
+            Sw {
+                rs1: RA,
+                offset,
+                rs2: SP,
+            }
+            .into(),
+        );
+        ctx.ra_offset = Some(offset);
+    }
+
+    let use_fp = ctx
+        .param_locations()
+        .iter()
+        .any(|location| matches!(location, ValueLocation::Parameter(_)));
 
     if use_fp {
         // We need to use frame pointer to access parameters.
-        insts.push(Box::new(Sw {
-            rs1: FP,
-            offset: ctx.stack_allocator.allocate(4),
-            rs2: SP,
-        }));
+        let offset = ctx.stack_allocator.allocate(4);
+        insts.push(
+            Sw {
+                rs1: FP,
+                offset,
+                rs2: SP,
+            }
+            .into(),
+        );
+        ctx.fp_offset = Some(offset);
     }
 
     ctx.stack_allocator.align();
@@ -146,84 +195,121 @@ fn prologue_insts(ctx: &mut Context, program: &Program) -> Vec<Box<dyn Inst>> {
     if stack_size <= 2048 {
         insts.insert(
             0,
-            Box::new(Addi {
+            Addi {
                 rd: SP,
                 rs: SP,
                 imm: -stack_size,
-            }),
+            }
+            .into(),
         );
         if use_fp {
-            insts.push(Box::new(Addi {
-                rd: FP,
-                rs: SP,
-                imm: stack_size,
-            }));
+            insts.push(
+                Addi {
+                    rd: FP,
+                    rs: SP,
+                    imm: stack_size,
+                }
+                .into(),
+            );
         }
     } else {
         insts.insert(
             0,
-            Box::new(Add {
+            Add {
                 rd: SP,
                 rs1: SP,
                 rs2: T0,
-            }),
+            }
+            .into(),
         );
         insts.insert(
             0,
-            Box::new(LoadImm {
+            LoadImm {
                 rd: T0,
                 imm: -stack_size,
-            }),
+            }
+            .into(),
         );
         if use_fp {
-            insts.push(Box::new(LoadImm {
-                rd: T0,
-                imm: stack_size,
-            }));
-            insts.push(Box::new(Add {
-                rd: FP,
-                rs1: SP,
-                rs2: T0,
-            }));
+            insts.push(
+                LoadImm {
+                    rd: T0,
+                    imm: stack_size,
+                }
+                .into(),
+            );
+            insts.push(
+                Add {
+                    rd: FP,
+                    rs1: SP,
+                    rs2: T0,
+                }
+                .into(),
+            );
         }
     }
     insts
 }
 
-fn epilogue_insts(ctx: &Context) -> Vec<Box<dyn Inst>> {
-    let mut insts: Vec<Box<dyn Inst>> = vec![];
+fn epilogue_insts(ctx: &Context) -> Vec<AsmInst> {
+    let mut insts: Vec<AsmInst> = vec![];
 
     // Restore callee saved registers.
     for reg in ctx.reg_allocator.used_callee_saved_registers() {
         if let Some(offset) = ctx.reg_allocator.get_callee_saved_register_offset(reg) {
-            insts.push(Box::new(Sw {
-                rs1: reg,
-                offset,
-                rs2: SP,
-            }));
+            insts.push(load_word(reg, offset, SP));
         }
     }
+    if let Some(offset) = ctx.ra_offset {
+        insts.push(
+            Lw {
+                rd: RA,
+                offset,
+                rs: SP,
+            }
+            .into(),
+        );
+    }
+    if let Some(offset) = ctx.fp_offset {
+        insts.push(
+            Lw {
+                rd: FP,
+                offset,
+                rs: SP,
+            }
+            .into(),
+        );
+    }
 
     let stack_size = ctx.stack_allocator.stack_size;
     if stack_size == 0 {
         return insts;
     }
     if stack_size <= 2047 {
-        insts.push(Box::new(Addi {
-            rd: SP,
-            rs: SP,
-            imm: stack_size,
-        }));
+        insts.push(
+            Addi {
+                rd: SP,
+                rs: SP,
+                imm: stack_size,
+            }
+            .into(),
+        );
     } else {
-        insts.push(Box::new(LoadImm {
-            rd: T0,
-            imm: stack_size,
-        }));
-        insts.push(Box::new(Add {
-            rd: SP,
-            rs1: SP,
-            rs2: T0,
-        }))
+        insts.push(
+            LoadImm {
+                rd: T0,
+                imm: stack_size,
+            }
+            .into(),
+        );
+        insts.push(
+            Add {
+                rd: SP,
+                rs1: SP,
+                rs2: T0,
+            }
+            .into(),
+        )
     };
     insts
 }
@@ -293,11 +379,26 @@ impl ToAsm for Function {
 
     fn to_asm(&self, ctx: &mut Context, program: &Program) -> Result<Self::Output, AsmError> {
         let func_data = program.func(*self);
-        let param_num = func_data.params().len();
         // Reset the register allocator.
-        ctx.function_default(param_num, program);
+        ctx.function_default(program);
+        if let Some(allocation) = regalloc::allocate(ctx.strategy, program, *self, &ctx.floats) {
+            ctx.apply_allocation(allocation, func_data);
+        }
         // Add parameters to the symbol table.
-        for (i, param) in func_data.params().iter().enumerate() {
+        let mut param_stores = vec![];
+        let param_locations = ctx.param_locations();
+        for (param, location) in func_data.params().iter().zip(param_locations) {
+            if ctx.allocation.is_some() {
+                // Parameters are tracked values with allocated locations.
+                break;
+            }
+            let entry = func_data.layout().entry_bb();
+            let used_outside_entry = func_data
+                .dfg()
+                .value(*param)
+                .used_by()
+                .iter()
+                .any(|user| func_data.layout().parent_bb(*user) != entry);
             let param = func_data.dfg().value(*param);
             let param_name = param
                 .name()
@@ -305,12 +406,30 @@ impl ToAsm for Function {
                 .map_or(ctx.name_generator.generate_indent_name(), |name| {
                     variable_name(&func_data.name(), &name)
                 });
-            if i < 8 {
-                ctx.symbol_table
-                    .insert(param_name, ValueLocation::Register(PARAMETER_REGISTERS[i]));
-            } else {
-                ctx.symbol_table
-                    .insert(param_name, ValueLocation::Parameter(((i - 8) * 4) as i32));
+            match location {
+                ValueLocation::Register(reg) if used_outside_entry || reg.is_float() => {
+                    // Registers are not tracked across blocks, and values are only kept in
+                    // f-registers with an allocation, so the parameter is kept in the stack.
+                    let slot = ValueLocation::Stack(ctx.stack_allocator.allocate(4));
+                    param_stores.push((slot.clone(), location));
+                    if !reg.is_float() {
+                        ctx.reg_allocator.set_unused(reg);
+                    }
+                    ctx.symbol_table.insert(param_name, slot);
+                }
+                location => {
+                    ctx.symbol_table.insert(param_name, location);
+                }
+            }
+        }
+        // Without an allocation, block parameters are passed in stack slots.
+        if ctx.allocation.is_none() {
+            for (bb, _) in func_data.layout().bbs() {
+                for param in func_data.dfg().bb(*bb).params() {
+                    let offset = ctx.stack_allocator.allocate(4);
+                    ctx.temp_value_table
+                        .insert(*param, ValueLocation::Stack(offset));
+                }
             }
         }
         // The first character of the function name should be deleted.
@@ -319,12 +438,20 @@ impl ToAsm for Function {
         for (block, _) in func_data.layout().bbs() {
             func.add_block(block.to_asm(ctx, program)?);
         }
+        for block in std::mem::take(&mut ctx.edge_blocks) {
+            func.add_block(block);
+        }
         ctx.current_bb = None;
 
         // Allocate stack space for the function and deallocate it when the function is done.
         let mut first_block = AsmBlock::new(format!(".{}_prologue", func_name));
         ctx.stack_allocator.align();
-        let prologue_insts = prologue_insts(ctx, program);
+        let mut prologue_insts = prologue_insts(ctx, program);
+        if ctx.allocation.is_some() {
+            prologue_insts.extend(param_moves(ctx, func_data));
+        } else {
+            prologue_insts.extend(parallel_move(param_stores));
+        }
         if !prologue_insts.is_empty() {
             first_block.add_insts(prologue_insts);
             func.add_first_block(first_block);
@@ -332,15 +459,33 @@ impl ToAsm for Function {
 
         // Add epilogue instructions to the end of the function.
         for block in func.blocks_mut() {
-            if let Some(ret_pos) = block.contains(&Ret) {
+            let exit_pos = block
+                .insts()
+                .iter()
+                .position(|inst| matches!(inst, AsmInst::Ret(_) | AsmInst::TailCall(_)));
+            if let Some(exit_pos) = exit_pos {
                 let leave_insts = epilogue_insts(ctx);
-                block.add_insts_in_pos(ret_pos, leave_insts);
+                block.add_insts_in_pos(exit_pos, leave_insts);
             }
         }
         Ok(func)
     }
 }
 
+/// Move parameters from the argument registers and the caller's stack to their allocated locations.
+fn param_moves(ctx: &Context, func_data: &FunctionData) -> Vec<AsmInst> {
+    let allocation = ctx.allocation.as_ref().unwrap();
+    let mut moves = vec![];
+    for (param, src) in func_data.params().iter().zip(ctx.param_locations()) {
+        if !allocation.is_allocated(*param) {
+            continue;
+        }
+        let reg = allocation.register_at(*param, PARAM_POSITION);
+        moves.push((ctx.allocated_location(*param, reg), src));
+    }
+    parallel_move(moves)
+}
+
 impl ToAsm for BasicBlock {
     type Output = AsmBlock;
 
@@ -354,9 +499,35 @@ impl ToAsm for BasicBlock {
         let node = func_data.layout().bbs().node(self).unwrap();
 
         let mut asm_block = AsmBlock::new(label_name);
+        if let Some((pred, args)) = ctx.entry_edge(*self, func_data) {
+            asm_block.add_insts(ctx.edge_moves(pred, *self, &args, program)?);
+        }
         for value in node.insts().keys() {
+            asm_block.add_insts(ctx.enter_instruction(*value, func_data));
             let insts = value.to_asm(ctx, program)?;
             asm_block.add_insts(insts);
+
+            // Registers are released after their first use, so a temp value used several times
+            // is stored in the stack.
+            if ctx.allocation.is_none() && use_count(func_data, *value) > 1 {
+                if let Some(ValueLocation::Register(reg)) = ctx.temp_value_table.get(value) {
+                    let reg = *reg;
+                    let offset = ctx.stack_allocator.allocate(4);
+                    ctx.temp_value_table
+                        .insert(*value, ValueLocation::Stack(offset));
+                    asm_block.add_insts(vec![Sw {
+                        rs1: reg,
+                        offset,
+                        rs2: SP,
+                    }
+                    .into()]);
+                    ctx.deallocate_reg(reg);
+                }
+            }
+        }
+
+        if ctx.allocation.is_some() {
+            return Ok(asm_block);
         }
 
         // If a temp value is used in another block, store it in the stack.
@@ -390,14 +561,30 @@ impl ToAsm for BasicBlock {
     }
 }
 
+/// Count the operands of all instructions that use a value.
+fn use_count(func_data: &FunctionData, value: Value) -> usize {
+    func_data
+        .dfg()
+        .value(value)
+        .used_by()
+        .iter()
+        .map(|user| {
+            let kind = func_data.dfg().value(*user).kind();
+            kind.value_uses().filter(|v| *v == value).count()
+        })
+        .sum()
+}
+
 impl ToAsm for Value {
-    type Output = Vec<Box<dyn Inst>>;
+    type Output = Vec<AsmInst>;
 
     fn to_asm(&self, ctx: &mut Context, program: &Program) -> Result<Self::Output, AsmError> {
         let func = ctx.func.ok_or(AsmError::UnknownFunction)?;
-        let value_data = program.func(func).dfg().value(*self);
+        let func_data = program.func(func);
+        let value_data = func_data.dfg().value(*self);
+        ctx.current_value = Some(*self);
 
-        match value_data.kind() {
+        let insts = match value_data.kind() {
             ValueKind::Integer(_) => Ok(vec![]),
             ValueKind::ZeroInit(_)
             | ValueKind::Undef(_)
@@ -405,6 +592,7 @@ impl ToAsm for Value {
             | ValueKind::FuncArgRef(_)
             | ValueKind::BlockArgRef(_)
             | ValueKind::GlobalAlloc(_) => unreachable!(),
+            ValueKind::Alloc(_) if ctx.is_promoted(*self) => Ok(vec![]),
             ValueKind::Alloc(_) => {
                 let func_data = program.func(ctx.func.ok_or(AsmError::UnknownFunction)?);
                 let data_type = remove_pointer(value_data.ty().clone());
@@ -420,44 +608,68 @@ impl ToAsm for Value {
                     .insert(data_name.clone(), ValueLocation::Stack(offset));
                 Ok(vec![])
             }
+            // Loads from and stores to promoted allocs are moves between locations.
+            ValueKind::Load(load) if ctx.is_promoted(load.src()) => {
+                let dest = ctx.get_location(*self, program)?;
+                let src = ctx.get_location(load.src(), program)?;
+                Ok(parallel_move(vec![(dest, src)]))
+            }
+            ValueKind::Store(store) if ctx.is_promoted(store.dest()) => {
+                let dest = ctx.get_location(store.dest(), program)?;
+                let src = ctx.get_location(store.value(), program)?;
+                Ok(parallel_move(vec![(dest, src)]))
+            }
             ValueKind::Load(load) => {
-                let (insts, temp_value) = load.to_asm(ctx, program)?;
-                ctx.temp_value_table.insert(*self, temp_value);
+                let (mut insts, temp_value) = load.to_asm(ctx, program)?;
+                insts.extend(ctx.define_value(*self, temp_value));
                 Ok(insts)
             }
             ValueKind::Store(store) => store.to_asm(ctx, program),
             ValueKind::GetPtr(get_ptr) => {
-                let (insts, temp_value) = get_ptr.to_asm(ctx, program)?;
-                ctx.temp_value_table.insert(*self, temp_value);
+                let (mut insts, temp_value) = get_ptr.to_asm(ctx, program)?;
+                insts.extend(ctx.define_value(*self, temp_value));
                 Ok(insts)
             }
             ValueKind::GetElemPtr(get_elem_ptr) => {
-                let (insts, temp_value) = get_elem_ptr.to_asm(ctx, program)?;
-                ctx.temp_value_table.insert(*self, temp_value);
+                let (mut insts, temp_value) = get_elem_ptr.to_asm(ctx, program)?;
+                insts.extend(ctx.define_value(*self, temp_value));
                 Ok(insts)
             }
             ValueKind::Binary(binary) => {
-                let (insts, temp_value) = binary.to_asm(ctx, program)?;
-                ctx.temp_value_table.insert(*self, temp_value);
+                let (mut insts, temp_value) = binary.to_asm(ctx, program)?;
+                insts.extend(ctx.define_value(*self, temp_value));
                 Ok(insts)
             }
             ValueKind::Branch(branch) => branch.to_asm(ctx, program),
             ValueKind::Jump(jump) => jump.to_asm(ctx, program),
             ValueKind::Call(call) => {
-                let (insts, ret_value) = call.to_asm(ctx, program)?;
-                let return_type = get_return_type(call.callee(), program);
-                if !return_type.is_unit() {
-                    ctx.temp_value_table.insert(*self, ret_value);
+                let callee_name = program.func(call.callee()).name();
+                if let Some(intrinsic) = Intrinsic::from_name(callee_name) {
+                    let (mut insts, temp_value) =
+                        intrinsic_to_asm(intrinsic, call.args(), ctx, program)?;
+                    insts.extend(ctx.define_value(*self, temp_value));
+                    Ok(insts)
+                } else {
+                    let (mut insts, ret_value) = call.to_asm(ctx, program)?;
+                    let return_type = get_return_type(call.callee(), program);
+                    if !return_type.is_unit() && !can_tail_call(*self, ctx, program) {
+                        insts.extend(ctx.define_value(*self, ret_value));
+                    }
+                    Ok(insts)
                 }
-                Ok(insts)
             }
+            // The callee returns in place of the function.
+            ValueKind::Return(_) if follows_tail_call(*self, ctx, program) => Ok(vec![]),
             ValueKind::Return(ret) => ret.to_asm(ctx, program),
-        }
+        };
+        ctx.release_scratch_regs();
+        ctx.current_value = None;
+        insts
     }
 }
 
 impl ToAsm for GetPtr {
-    type Output = (Vec<Box<dyn Inst>>, ValueLocation);
+    type Output = (Vec<AsmInst>, ValueLocation);
     fn to_asm(&self, ctx: &mut Context, program: &Program) -> Result<Self::Output, AsmError> {
         let src = self.src();
         let idx = self.index();
@@ -472,30 +684,40 @@ impl ToAsm for GetPtr {
         let idx_loc = ctx.get_location(idx, program)?;
         let (idx_insts, idx_reg) = ctx.load_value(&idx_loc, &[src_reg, imm_reg]);
         insts.extend(idx_insts);
-        let (temp_reg, alloc_insts) = ctx.allocate_reg(&[src_reg, idx_reg, imm_reg]);
-        insts.extend(alloc_insts);
 
-        if idx_reg != ZERO {
-            insts.push(Box::new(Mul {
-                rd: idx_reg,
-                rs1: imm_reg,
-                rs2: idx_reg,
-            }));
-        }
-        insts.push(Box::new(Add {
-            rd: temp_reg,
-            rs1: src_reg,
-            rs2: idx_reg,
-        }));
-        ctx.deallocate_reg(src_reg);
+        // The index may still be needed, so the offset is computed in the immediate register.
+        let offset_reg = if idx_reg != ZERO {
+            insts.push(
+                Mul {
+                    rd: imm_reg,
+                    rs1: imm_reg,
+                    rs2: idx_reg,
+                }
+                .into(),
+            );
+            imm_reg
+        } else {
+            ZERO
+        };
         ctx.deallocate_reg(idx_reg);
+        let (temp_reg, alloc_insts) = ctx.allocate_result_reg(&[src_reg, imm_reg]);
+        insts.extend(alloc_insts);
+        insts.push(
+            Add {
+                rd: temp_reg,
+                rs1: src_reg,
+                rs2: offset_reg,
+            }
+            .into(),
+        );
+        ctx.deallocate_reg(src_reg);
         ctx.deallocate_reg(imm_reg);
         Ok((insts, ValueLocation::Register(temp_reg)))
     }
 }
 
 impl ToAsm for GetElemPtr {
-    type Output = (Vec<Box<dyn Inst>>, ValueLocation);
+    type Output = (Vec<AsmInst>, ValueLocation);
     fn to_asm(&self, ctx: &mut Context, program: &Program) -> Result<Self::Output, AsmError> {
         let src = self.src();
         let idx = self.index();
@@ -512,121 +734,344 @@ impl ToAsm for GetElemPtr {
         insts.extend(imm_insts);
         let src_loc = ctx.get_location(src, program)?;
 
+        // The index may still be needed, so the offset is computed in the immediate register.
+        // This is done first since the result register may be the index register.
+        let offset_reg = if idx_reg != ZERO {
+            insts.push(
+                Mul {
+                    rd: imm_reg,
+                    rs1: imm_reg,
+                    rs2: idx_reg,
+                }
+                .into(),
+            );
+            imm_reg
+        } else {
+            ZERO
+        };
+        ctx.deallocate_reg(idx_reg);
+
         if is_symbol(src, ctx, program)? {
-            let (res_reg, alloc_insts) = ctx.allocate_reg(&[idx_reg, imm_reg]);
+            let (res_reg, alloc_insts) = ctx.allocate_result_reg(&[imm_reg]);
             insts.extend(alloc_insts);
             match src_loc {
                 ValueLocation::Stack(offset) => {
                     if between!(-2048, offset, 2047) {
-                        insts.push(Box::new(Addi {
-                            rd: res_reg,
-                            rs: SP,
-                            imm: offset,
-                        }));
+                        insts.push(
+                            Addi {
+                                rd: res_reg,
+                                rs: SP,
+                                imm: offset,
+                            }
+                            .into(),
+                        );
                     } else {
-                        insts.push(Box::new(LoadImm {
-                            rd: res_reg,
-                            imm: offset,
-                        }));
-                        insts.push(Box::new(Add {
-                            rd: res_reg,
-                            rs1: SP,
-                            rs2: res_reg,
-                        }));
+                        insts.push(
+                            LoadImm {
+                                rd: res_reg,
+                                imm: offset,
+                            }
+                            .into(),
+                        );
+                        insts.push(
+                            Add {
+                                rd: res_reg,
+                                rs1: SP,
+                                rs2: res_reg,
+                            }
+                            .into(),
+                        );
                     }
                 }
                 ValueLocation::GlobalValue(name) => {
-                    insts.push(Box::new(LoadLabel {
-                        rd: res_reg,
-                        label: name,
-                    }));
+                    insts.push(
+                        LoadLabel {
+                            rd: res_reg,
+                            label: name,
+                        }
+                        .into(),
+                    );
                 }
                 _ => return Err(AsmError::InvalidGetElemPtr),
             };
-            if idx_reg != ZERO {
-                insts.push(Box::new(Mul {
-                    rd: idx_reg,
-                    rs1: imm_reg,
-                    rs2: idx_reg,
-                }));
-            }
-            insts.push(Box::new(Add {
-                rd: res_reg,
-                rs1: res_reg,
-                rs2: idx_reg,
-            }));
-            ctx.deallocate_reg(idx_reg);
+            insts.push(
+                Add {
+                    rd: res_reg,
+                    rs1: res_reg,
+                    rs2: offset_reg,
+                }
+                .into(),
+            );
             ctx.deallocate_reg(imm_reg);
             Ok((insts, ValueLocation::Register(res_reg)))
         } else {
-            let (src_insts, src_reg) = ctx.load_value(&src_loc, &[idx_reg, imm_reg]);
+            let (src_insts, src_reg) = ctx.load_value(&src_loc, &[imm_reg]);
             insts.extend(src_insts);
-            let (temp_reg, alloc_insts) = ctx.allocate_reg(&[idx_reg, src_reg, imm_reg]);
+            let (temp_reg, alloc_insts) = ctx.allocate_result_reg(&[src_reg, imm_reg]);
             insts.extend(alloc_insts);
-            if idx_reg != ZERO {
-                insts.push(Box::new(Mul {
-                    rd: idx_reg,
-                    rs1: imm_reg,
-                    rs2: idx_reg,
-                }));
-            }
-            insts.push(Box::new(Add {
-                rd: temp_reg,
-                rs1: src_reg,
-                rs2: idx_reg,
-            }));
+            insts.push(
+                Add {
+                    rd: temp_reg,
+                    rs1: src_reg,
+                    rs2: offset_reg,
+                }
+                .into(),
+            );
             ctx.deallocate_reg(src_reg);
-            ctx.deallocate_reg(idx_reg);
             ctx.deallocate_reg(imm_reg);
             Ok((insts, ValueLocation::Register(temp_reg)))
         }
     }
 }
 
+/// Lower a call to an intrinsic to the instructions it stands for.
+///
+/// `float` operands are used in the f-registers they are allocated, or loaded to scratch
+/// f-registers, and `float` results are written to an f-register.
+fn intrinsic_to_asm(
+    intrinsic: Intrinsic,
+    args: &[Value],
+    ctx: &mut Context,
+    program: &Program,
+) -> Result<(Vec<AsmInst>, ValueLocation), AsmError> {
+    let mut insts: Vec<AsmInst> = vec![];
+    let mut arg_regs: Vec<Register> = vec![];
+    if !intrinsic.takes_float() {
+        for arg in args {
+            let arg_loc = ctx.get_location(*arg, program)?;
+            let (load, arg_reg) = ctx.load_value(&arg_loc, &arg_regs);
+            insts.extend(load);
+            arg_regs.push(arg_reg);
+        }
+        let result = if intrinsic == Intrinsic::MulHigh {
+            let (rd, alloc_insts) = ctx.allocate_result_reg(&arg_regs);
+            insts.extend(alloc_insts);
+            let (rs1, rs2) = (arg_regs[0], arg_regs[1]);
+            insts.push(Mulh { rd, rs1, rs2 }.into());
+            rd
+        } else {
+            let fd = ctx.allocate_float_result_reg(&[]);
+            insts.push(
+                FcvtSW {
+                    fd,
+                    rs: arg_regs[0],
+                }
+                .into(),
+            );
+            fd
+        };
+        for reg in arg_regs {
+            ctx.deallocate_reg(reg);
+        }
+        return Ok((insts, ValueLocation::Register(result)));
+    }
+
+    for arg in args {
+        let arg_loc = ctx.get_location(*arg, program)?;
+        let (load, arg_reg) = ctx.load_float(&arg_loc, &arg_regs);
+        insts.extend(load);
+        arg_regs.push(arg_reg);
+        // Without an allocation, `float`s are held in integer registers released after use.
+        if let ValueLocation::Register(reg) = arg_loc {
+            if !reg.is_float() {
+                ctx.deallocate_reg(reg);
+            }
+        }
+    }
+    let fs1 = arg_regs[0];
+    let fs2 = arg_regs.get(1).copied().unwrap_or(fs1);
+    let result = if intrinsic.returns_float() {
+        let fd = ctx.allocate_float_result_reg(&arg_regs);
+        let cal_inst: AsmInst = match intrinsic {
+            Intrinsic::FAdd => FAdd { fd, fs1, fs2 }.into(),
+            Intrinsic::FSub => FSub { fd, fs1, fs2 }.into(),
+            Intrinsic::FMul => FMul { fd, fs1, fs2 }.into(),
+            Intrinsic::FDiv => FDiv { fd, fs1, fs2 }.into(),
+            _ => unreachable!(),
+        };
+        insts.push(cal_inst);
+        fd
+    } else {
+        let (rd, alloc_insts) = ctx.allocate_result_reg(&[]);
+        insts.extend(alloc_insts);
+        let cal_inst: AsmInst = match intrinsic {
+            Intrinsic::FLt => FLt { rd, fs1, fs2 }.into(),
+            Intrinsic::FLe => FLe { rd, fs1, fs2 }.into(),
+            Intrinsic::FEq => FEq { rd, fs1, fs2 }.into(),
+            Intrinsic::FloatToInt => FcvtWS { rd, fs: fs1 }.into(),
+            _ => unreachable!(),
+        };
+        insts.push(cal_inst);
+        rd
+    };
+    for reg in arg_regs {
+        ctx.deallocate_reg(reg);
+    }
+    Ok((insts, ValueLocation::Register(result)))
+}
+
+/// Check if a call can jump to the callee after the epilogue instead: it is in tail position, the
+/// arguments passed in the stack fit in the slots of the parameters the function was passed in
+/// the stack, the result is returned in the same register, and none of the arguments points into
+/// the stack frame freed by the epilogue.
+fn can_tail_call(inst: Value, ctx: &Context, program: &Program) -> bool {
+    let func = ctx.func.expect("No function context.");
+    let func_data = program.func(func);
+    let ValueKind::Call(call) = func_data.dfg().value(inst).kind() else {
+        return false;
+    };
+    if Intrinsic::from_name(program.func(call.callee()).name()).is_some() {
+        return false;
+    }
+    let (caller, callee) = (
+        ctx.floats.signature(func),
+        ctx.floats.signature(call.callee()),
+    );
+    is_tail_call(func_data, inst)
+        && stack_args_size(&callee.params) <= stack_args_size(&caller.params)
+        && callee.ret == caller.ret
+        && !call
+            .args()
+            .iter()
+            .any(|arg| may_point_to_frame(func_data, *arg))
+}
+
+/// Check if a return follows a call lowered to a tail call.
+fn follows_tail_call(ret: Value, ctx: &Context, program: &Program) -> bool {
+    let func_data = program.func(ctx.func.expect("No function context."));
+    let bb = func_data.layout().parent_bb(ret).unwrap();
+    let insts = func_data.layout().bbs().node(&bb).unwrap().insts();
+    let cursor = insts.cursor(ret);
+    cursor
+        .prev_key()
+        .is_some_and(|prev| can_tail_call(*prev, ctx, program))
+}
+
 impl ToAsm for IRCall {
-    type Output = (Vec<Box<dyn Inst>>, ValueLocation);
+    type Output = (Vec<AsmInst>, ValueLocation);
 
     fn to_asm(&self, ctx: &mut Context, program: &Program) -> Result<Self::Output, AsmError> {
         let callee_name = &program.func(self.callee()).name()[1..];
         let return_type = get_return_type(self.callee(), program);
+        let tail = ctx
+            .current_value
+            .is_some_and(|call| can_tail_call(call, ctx, program));
+        let signature = ctx.floats.signature(self.callee());
+        let arg_locations = arg_locations(&signature.params);
+        let ret_reg = if signature.ret { FA0 } else { A0 };
+
+        // With a register allocation, no value in a caller saved register is live across the call,
+        // so only the arguments need to be moved.
+        if ctx.allocation.is_some() {
+            let mut moves = vec![];
+            for (arg, location) in self.args().iter().zip(arg_locations) {
+                let dest = match location {
+                    // A tail call passes the arguments where the function got its own.
+                    ValueLocation::Stack(offset) if tail => ValueLocation::Parameter(offset),
+                    location => location,
+                };
+                moves.push((dest, ctx.get_location(*arg, program)?));
+            }
+            let mut insts = parallel_move(moves);
+            let label = callee_name.to_string();
+            if tail {
+                insts.push(TailCall { label }.into());
+            } else {
+                insts.push(Call { label }.into());
+            }
+            return Ok((insts, ValueLocation::Register(ret_reg)));
+        }
 
-        let mut insts: Vec<Box<dyn Inst>> = vec![];
+        let mut insts: Vec<AsmInst> = vec![];
         let mut arg_regs: Vec<Register> = vec![];
-
-        let param_reg_num = PARAMETER_REGISTERS.len();
+        // Integer registers the arguments are passed in.
+        let param_regs: Vec<Register> = arg_locations
+            .iter()
+            .filter_map(|location| match location {
+                ValueLocation::Register(reg) if !reg.is_float() => Some(*reg),
+                _ => None,
+            })
+            .collect();
 
         // Load arguments.
-        for (i, arg) in self.args().iter().enumerate() {
+        for (arg, location) in self.args().iter().zip(&arg_locations) {
             let arg_loc = ctx.get_location(*arg, program)?;
             let (load, value_reg) = ctx.load_value(&arg_loc, &arg_regs);
             insts.extend(load);
-            if i < param_reg_num {
-                // Pass the argument in register.
-                let param_reg = PARAMETER_REGISTERS[i];
-                insts.extend(ctx.alloc_reg_from_name(param_reg));
-                arg_regs.push(param_reg);
-                if param_reg != value_reg {
-                    insts.push(Box::new(Move {
-                        rd: param_reg,
-                        rs: value_reg,
-                    }));
+            match location {
+                ValueLocation::Register(param_reg) if param_reg.is_float() => {
+                    // Pass the `float` in an f-register, it is held in an integer register here.
+                    insts.push(
+                        FmvWX {
+                            fd: *param_reg,
+                            rs: value_reg,
+                        }
+                        .into(),
+                    );
+                    ctx.deallocate_reg(value_reg);
+                }
+                ValueLocation::Register(param_reg) => {
+                    // Pass the argument in register.
+                    let param_reg = *param_reg;
+                    insts.extend(ctx.alloc_reg_from_name(param_reg));
+                    arg_regs.push(param_reg);
+                    if param_reg != value_reg {
+                        insts.push(
+                            Move {
+                                rd: param_reg,
+                                rs: value_reg,
+                            }
+                            .into(),
+                        );
+                    }
+                    if value_reg != param_reg {
+                        ctx.deallocate_reg(value_reg);
+                    }
                 }
-                if value_reg != param_reg {
+                ValueLocation::Stack(offset) => {
+                    // Pass the argument in stack.
+                    insts.push(
+                        Sw {
+                            rs1: value_reg,
+                            offset: *offset,
+                            rs2: SP,
+                        }
+                        .into(),
+                    );
                     ctx.deallocate_reg(value_reg);
                 }
-            } else {
-                // Pass the argument in stack.
-                insts.push(Box::new(Sw {
-                    rs1: value_reg,
-                    offset: 4 * (i - param_reg_num) as i32,
-                    rs2: SP,
-                }));
-                ctx.deallocate_reg(value_reg);
+                _ => unreachable!(),
+            }
+        }
+
+        if tail {
+            // The stack arguments are copied to the parameter slots only now, as the other
+            // arguments may have been loaded from them.
+            let copies = arg_locations
+                .iter()
+                .filter_map(|location| match location {
+                    ValueLocation::Stack(offset) => Some((
+                        ValueLocation::Parameter(*offset),
+                        ValueLocation::Stack(*offset),
+                    )),
+                    _ => None,
+                })
+                .collect();
+            insts.extend(parallel_move(copies));
+            insts.push(
+                TailCall {
+                    label: callee_name.to_string(),
+                }
+                .into(),
+            );
+            for reg in &param_regs {
+                ctx.reg_allocator.set_unused(*reg);
             }
+            return Ok((insts, ValueLocation::Register(A0)));
         }
 
         // Allocate register for the return value.
-        if self.args().len() == 0 && !return_type.is_unit() {
+        if !param_regs.contains(&A0) && !return_type.is_unit() {
             let alloc_insts = ctx.alloc_reg_from_name(A0);
             insts.extend(alloc_insts);
         }
@@ -636,8 +1081,7 @@ impl ToAsm for IRCall {
         caller_saved_reg.push(RA);
 
         // If the register is used for parameter or return value, we don't need to save it.
-        let arg_num = self.args().len();
-        for reg in PARAMETER_REGISTERS[..arg_num.min(param_reg_num)].iter() {
+        for reg in &param_regs {
             if let Some(pos) = caller_saved_reg.iter().position(|r| r == reg) {
                 caller_saved_reg.remove(pos);
             }
@@ -652,68 +1096,111 @@ impl ToAsm for IRCall {
 
         for reg in &caller_saved_reg {
             let offset = ctx.stack_allocator.allocate(4);
-            insts.push(Box::new(Sw {
-                rs1: *reg,
-                offset,
-                rs2: SP,
-            }));
+            insts.push(
+                Sw {
+                    rs1: *reg,
+                    offset,
+                    rs2: SP,
+                }
+                .into(),
+            );
             saved_reg_offset.push(offset);
         }
 
         // Call the function.
-        insts.push(Box::new(Call {
-            label: callee_name.to_string(),
-        }));
+        insts.push(
+            Call {
+                label: callee_name.to_string(),
+            }
+            .into(),
+        );
 
         // Restore caller saved registers.
         for (i, reg) in caller_saved_reg.iter().enumerate() {
-            insts.push(Box::new(Lw {
-                rd: *reg,
-                offset: saved_reg_offset[i],
-                rs: SP,
-            }));
+            insts.push(
+                Lw {
+                    rd: *reg,
+                    offset: saved_reg_offset[i],
+                    rs: SP,
+                }
+                .into(),
+            );
         }
 
-        // Deallocate the stack space for the arguments.
-        if arg_num > 1 {
-            for reg in &PARAMETER_REGISTERS[1..arg_num.min(param_reg_num)] {
+        // `float`s are held in integer registers without an allocation.
+        if ret_reg.is_float() {
+            insts.push(FmvXW { rd: A0, fs: FA0 }.into());
+        }
+
+        // Release the argument registers, except `a0` holding the return value.
+        for reg in &param_regs {
+            if *reg != A0 || return_type.is_unit() {
                 ctx.reg_allocator.set_unused(*reg);
             }
         }
-        if return_type.is_unit() && arg_num >= 1 {
-            ctx.reg_allocator.set_unused(A0);
-        }
 
         Ok((insts, ValueLocation::Register(A0)))
     }
 }
 
 impl ToAsm for Branch {
-    type Output = Vec<Box<dyn Inst>>;
+    type Output = Vec<AsmInst>;
 
     fn to_asm(&self, ctx: &mut Context, program: &Program) -> Result<Self::Output, AsmError> {
         let cond_loc = ctx.get_location(self.cond(), program)?;
         let (mut insts, reg) = ctx.load_value(&cond_loc, &[]);
+        let current_bb = ctx.current_bb.ok_or(AsmError::NoBasicBlock)?;
+        if ctx.allocation.is_none()
+            && !(self.true_args().is_empty() && self.false_args().is_empty())
+        {
+            // Edge blocks only run on one path, so they must find a free register instead of
+            // spilling one.
+            let (temp, spill) = ctx.allocate_reg(&[reg]);
+            insts.extend(spill);
+            ctx.deallocate_reg(temp);
+        }
         let true_target = ctx
             .get_label_name(self.true_bb(), program)
             .ok_or(AsmError::UnknownBranchTarget)?;
+        let true_target = ctx.branch_target(
+            current_bb,
+            self.true_bb(),
+            self.true_args(),
+            true_target,
+            program,
+        )?;
         let false_target = ctx
             .get_label_name(self.false_bb(), program)
             .ok_or(AsmError::UnknownBranchTarget)?;
-        insts.push(Box::new(Bnez {
-            rs: reg,
-            label: true_target,
-        }));
-        insts.push(Box::new(Jmp {
-            label: false_target,
-        }));
+        let false_target = ctx.branch_target(
+            current_bb,
+            self.false_bb(),
+            self.false_args(),
+            false_target,
+            program,
+        )?;
+        insts.push(
+            Bnez {
+                rs: reg,
+                label: true_target,
+            }
+            .into(),
+        );
+        insts.push(
+            Jmp {
+                label: false_target,
+            }
+            .into(),
+        );
         ctx.deallocate_reg(reg);
+        ctx.release_args(self.true_args());
+        ctx.release_args(self.false_args());
         Ok(insts)
     }
 }
 
 impl ToAsm for Jump {
-    type Output = Vec<Box<dyn Inst>>;
+    type Output = Vec<AsmInst>;
 
     fn to_asm(&self, ctx: &mut Context, program: &Program) -> Result<Self::Output, AsmError> {
         let target_name = ctx
@@ -728,16 +1215,17 @@ impl ToAsm for Jump {
                     .unwrap_or(false)
             })
             .unwrap_or(false);
+        let mut insts = ctx.edge_moves(current_bb, self.target(), self.args(), program)?;
+        ctx.release_args(self.args());
         if !target_next_bb {
-            Ok(vec![Box::new(Jmp { label: target_name })])
-        } else {
-            Ok(vec![])
+            insts.push(Jmp { label: target_name }.into());
         }
+        Ok(insts)
     }
 }
 
 impl ToAsm for Store {
-    type Output = Vec<Box<dyn Inst>>;
+    type Output = Vec<AsmInst>;
 
     fn to_asm(&self, ctx: &mut Context, program: &Program) -> Result<Self::Output, AsmError> {
         let func = ctx.func.ok_or(AsmError::UnknownFunction)?;
@@ -761,21 +1249,30 @@ impl ToAsm for Store {
                     for (i, value) in store_value.iter().enumerate() {
                         let offset = offset + i as i32 * 4;
                         if *value != 0 {
-                            insts.push(Box::new(LoadImm {
-                                rd: temp_reg,
-                                imm: *value,
-                            }));
-                            insts.push(Box::new(Sw {
-                                rs1: temp_reg,
-                                offset,
-                                rs2: SP,
-                            }));
+                            insts.push(
+                                LoadImm {
+                                    rd: temp_reg,
+                                    imm: *value,
+                                }
+                                .into(),
+                            );
+                            insts.push(
+                                Sw {
+                                    rs1: temp_reg,
+                                    offset,
+                                    rs2: SP,
+                                }
+                                .into(),
+                            );
                         } else {
-                            insts.push(Box::new(Sw {
-                                rs1: ZERO,
-                                offset,
-                                rs2: SP,
-                            }));
+                            insts.push(
+                                Sw {
+                                    rs1: ZERO,
+                                    offset,
+                                    rs2: SP,
+                                }
+                                .into(),
+                            );
                         }
                     }
                     ctx.deallocate_reg(temp_reg);
@@ -793,13 +1290,9 @@ impl ToAsm for Store {
             let dest_loc = ctx.get_location(dest, program)?;
             let (mut insts, dest_reg) = ctx.load_value(&dest_loc, &[]);
             let value_loc = ctx.get_location(value, program)?;
-            let (load, value_reg) = ctx.load_value(&value_loc, &[dest_reg]);
+            let (load, value_reg) = load_stored_value(ctx, &value_loc, &[dest_reg]);
             insts.extend(load);
-            insts.push(Box::new(Sw {
-                rs1: value_reg,
-                offset: 0,
-                rs2: dest_reg,
-            }));
+            insts.push(store_word(value_reg, 0, dest_reg));
             return Ok(insts);
         }
 
@@ -808,35 +1301,30 @@ impl ToAsm for Store {
         match dest {
             ValueLocation::Register(dest) => {
                 let (mut insts, reg) = ctx.load_value(&store_value, &[dest]);
-                insts.push(Box::new(Move { rd: dest, rs: reg }));
+                insts.push(Move { rd: dest, rs: reg }.into());
                 if reg != dest {
                     ctx.deallocate_reg(reg)
                 }
                 Ok(insts)
             }
             ValueLocation::Stack(offset) => {
-                let (mut insts, reg) = ctx.load_value(&store_value, &[]);
-                insts.push(Box::new(Sw {
-                    rs1: reg,
-                    offset,
-                    rs2: SP,
-                }));
+                let (mut insts, reg) = load_stored_value(ctx, &store_value, &[]);
+                insts.push(store_word(reg, offset, SP));
                 ctx.deallocate_reg(reg);
                 Ok(insts)
             }
             ValueLocation::GlobalValue(name) => {
-                let (mut insts, reg) = ctx.load_value(&store_value, &[]);
+                let (mut insts, reg) = load_stored_value(ctx, &store_value, &[]);
                 let (temp_reg, alloc_insts) = ctx.allocate_reg(&[reg]);
                 insts.extend(alloc_insts);
-                insts.push(Box::new(LoadLabel {
-                    rd: temp_reg,
-                    label: name,
-                }));
-                insts.push(Box::new(Sw {
-                    rs1: reg,
-                    offset: 0,
-                    rs2: temp_reg,
-                }));
+                insts.push(
+                    LoadLabel {
+                        rd: temp_reg,
+                        label: name,
+                    }
+                    .into(),
+                );
+                insts.push(store_word(reg, 0, temp_reg));
                 ctx.deallocate_reg(reg);
                 ctx.deallocate_reg(temp_reg);
                 Ok(insts)
@@ -846,39 +1334,85 @@ impl ToAsm for Store {
     }
 }
 
+/// Load a value to store, a `float` in an f-register being stored from there.
+fn load_stored_value(
+    ctx: &mut Context,
+    value_location: &ValueLocation,
+    used_regs: &[Register],
+) -> (Vec<AsmInst>, Register) {
+    match value_location {
+        ValueLocation::Register(reg) if reg.is_float() => (vec![], *reg),
+        _ => ctx.load_value(value_location, used_regs),
+    }
+}
+
 impl ToAsm for Load {
-    type Output = (Vec<Box<dyn Inst>>, ValueLocation);
+    type Output = (Vec<AsmInst>, ValueLocation);
 
     fn to_asm(&self, ctx: &mut Context, program: &Program) -> Result<Self::Output, AsmError> {
         let is_symbol = is_symbol(self.src(), ctx, program)?;
         let val_loc = ctx.get_location(self.src(), program)?;
 
         // A temp value generated by GetElemPtr or GetPtr.
+        // A `float` allocated an f-register is loaded to it.
+        let float_reg = ctx.float_result_reg();
         if !is_symbol {
             let (mut insts, temp_reg) = ctx.load_value(&val_loc, &[]);
-            let (res_reg, alloc_insts) = ctx.allocate_reg(&[temp_reg]);
-            insts.extend(alloc_insts);
-            insts.push(Box::new(Lw {
-                rd: res_reg,
-                offset: 0,
-                rs: temp_reg,
-            }));
+            let res_reg = match float_reg {
+                Some(reg) => reg,
+                None => {
+                    let (res_reg, alloc_insts) = ctx.allocate_result_reg(&[temp_reg]);
+                    insts.extend(alloc_insts);
+                    res_reg
+                }
+            };
+            insts.push(load_word(res_reg, 0, temp_reg));
             ctx.deallocate_reg(temp_reg);
             Ok((insts, ValueLocation::Register(res_reg)))
+        } else if let ValueLocation::Register(reg) = val_loc {
+            Ok((vec![], ValueLocation::Register(reg)))
         } else {
-            let (insts, reg) = ctx.load_value(&val_loc, &[]);
+            let (reg, mut insts) = match float_reg {
+                Some(reg) => (reg, vec![]),
+                None => ctx.allocate_result_reg(&[]),
+            };
+            let (rs, offset) = match val_loc {
+                ValueLocation::Stack(offset) => (SP, offset),
+                ValueLocation::Parameter(offset) => (FP, offset),
+                ValueLocation::GlobalValue(name) => {
+                    let (address, alloc_insts) = if reg.is_float() {
+                        ctx.allocate_reg(&[])
+                    } else {
+                        (reg, vec![])
+                    };
+                    insts.extend(alloc_insts);
+                    insts.push(
+                        LoadLabel {
+                            rd: address,
+                            label: name,
+                        }
+                        .into(),
+                    );
+                    (address, 0)
+                }
+                ValueLocation::Immediate(_) | ValueLocation::Register(_) => unreachable!(),
+            };
+            insts.push(load_word(reg, offset, rs));
+            if rs != reg && rs != SP && rs != FP {
+                ctx.deallocate_reg(rs);
+            }
             Ok((insts, ValueLocation::Register(reg)))
         }
     }
 }
 
 impl ToAsm for Binary {
-    type Output = (Vec<Box<dyn Inst>>, ValueLocation);
+    type Output = (Vec<AsmInst>, ValueLocation);
 
     fn to_asm(&self, ctx: &mut Context, program: &Program) -> Result<Self::Output, AsmError> {
         let lhs = self.lhs();
         let rhs = self.rhs();
-        let mut insts: Vec<Box<dyn Inst>> = vec![];
+        let mut insts: Vec<AsmInst> = vec![];
 
         fn generate_with_imm(
             lhs: Value,
@@ -886,27 +1420,26 @@ impl ToAsm for Binary {
             op: BinaryOp,
             ctx: &mut Context,
             program: &Program,
-        ) -> Option<(Vec<Box<dyn Inst>>, ValueLocation)> {
-            let mut insts: Vec<Box<dyn Inst>> = vec![];
+        ) -> Option<(Vec<AsmInst>, ValueLocation)> {
+            let mut insts: Vec<AsmInst> = vec![];
             if let Some(imm) = imm12(rhs, ctx, program) {
                 if has_imm(op) {
                     let lhs_loc = ctx.get_location(lhs, program).ok()?;
                     let (load_lhs, rs) = ctx.load_value(&lhs_loc, &[]);
-                    let (rd, temp_inst) = ctx.allocate_reg(&[rs]);
+                    let (rd, temp_inst) = ctx.allocate_result_reg(&[rs]);
                     insts.extend(load_lhs);
                     insts.extend(temp_inst);
-                    let cal_inst: Vec<Box<dyn Inst>> = match op {
-                        BinaryOp::Add => vec![Box::new(Addi { rd, rs, imm })],
-                        BinaryOp::Or => vec![Box::new(Ori { rd, rs, imm })],
-                        BinaryOp::And => vec![Box::new(Andi { rd, rs, imm })],
-                        BinaryOp::Xor => vec![Box::new(Xori { rd, rs, imm })],
-                        BinaryOp::Eq => vec![
-                            Box::new(Xori { rd, rs, imm }),
-                            Box::new(SetZero { rd, rs: rd }),
-                        ],
+                    let cal_inst: Vec<AsmInst> = match op {
+                        BinaryOp::Add => vec![Addi { rd, rs, imm }.into()],
+                        BinaryOp::Or => vec![Ori { rd, rs, imm }.into()],
+                        BinaryOp::And => vec![Andi { rd, rs, imm }.into()],
+                        BinaryOp::Xor => vec![Xori { rd, rs, imm }.into()],
+                        BinaryOp::Eq => {
+                            vec![Xori { rd, rs, imm }.into(), SetZero { rd, rs: rd }.into()]
+                        }
                         BinaryOp::NotEq => vec![
-                            Box::new(Xori { rd, rs, imm }),
-                            Box::new(SetNonZero { rd, rs: rd }),
+                            Xori { rd, rs, imm }.into(),
+                            SetNonZero { rd, rs: rd }.into(),
                         ],
                         _ => unreachable!(),
                     };
@@ -938,51 +1471,42 @@ impl ToAsm for Binary {
         insts.extend(load_rhs);
 
         // Allocate a temporary register for the result.
-        let (temp_reg, temp_inst) = ctx.allocate_reg(&[lhs_reg, rhs_reg]);
+        let (temp_reg, temp_inst) = ctx.allocate_result_reg(&[lhs_reg, rhs_reg]);
         insts.extend(temp_inst);
 
         let rs1 = lhs_reg;
         let rs2 = rhs_reg;
         let rd = temp_reg;
         // Calculate the result.
-        let cal_insts: Vec<Box<dyn Inst>> = match self.op() {
+        let cal_insts: Vec<AsmInst> = match self.op() {
             BinaryOp::NotEq => {
                 vec![
-                    Box::new(Xor { rd, rs1, rs2 }),
-                    Box::new(SetNonZero { rd, rs: rd }),
+                    Xor { rd, rs1, rs2 }.into(),
+                    SetNonZero { rd, rs: rd }.into(),
                 ]
             }
             BinaryOp::Eq => {
-                vec![
-                    Box::new(Xor { rd, rs1, rs2 }),
-                    Box::new(SetZero { rd, rs: rd }),
-                ]
+                vec![Xor { rd, rs1, rs2 }.into(), SetZero { rd, rs: rd }.into()]
             }
-            BinaryOp::Gt => vec![Box::new(SetGt { rd, rs1, rs2 })],
-            BinaryOp::Lt => vec![Box::new(SetLt { rd, rs1, rs2 })],
+            BinaryOp::Gt => vec![SetGt { rd, rs1, rs2 }.into()],
+            BinaryOp::Lt => vec![SetLt { rd, rs1, rs2 }.into()],
             BinaryOp::Ge => {
-                vec![
-                    Box::new(SetLt { rd, rs1, rs2 }),
-                    Box::new(SetZero { rd, rs: rd }),
-                ]
+                vec![SetLt { rd, rs1, rs2 }.into(), SetZero { rd, rs: rd }.into()]
             }
             BinaryOp::Le => {
-                vec![
-                    Box::new(SetGt { rd, rs1, rs2 }),
-                    Box::new(SetZero { rd, rs: rd }),
-                ]
+                vec![SetGt { rd, rs1, rs2 }.into(), SetZero { rd, rs: rd }.into()]
             }
-            BinaryOp::Add => vec![Box::new(Add { rd, rs1, rs2 })],
-            BinaryOp::Sub => vec![Box::new(Sub { rd, rs1, rs2 })],
-            BinaryOp::Mul => vec![Box::new(Mul { rd, rs1, rs2 })],
-            BinaryOp::Div => vec![Box::new(Div { rd, rs1, rs2 })],
-            BinaryOp::Mod => vec![Box::new(Rem { rd, rs1, rs2 })],
-            BinaryOp::And => vec![Box::new(And { rd, rs1, rs2 })],
-            BinaryOp::Or => vec![Box::new(Or { rd, rs1, rs2 })],
-            BinaryOp::Xor => vec![Box::new(Xor { rd, rs1, rs2 })],
-            BinaryOp::Shl => vec![Box::new(Sll { rd, rs1, rs2 })],
-            BinaryOp::Shr => vec![Box::new(Srl { rd, rs1, rs2 })],
-            BinaryOp::Sar => vec![Box::new(Sra { rd, rs1, rs2 })],
+            BinaryOp::Add => vec![Add { rd, rs1, rs2 }.into()],
+            BinaryOp::Sub => vec![Sub { rd, rs1, rs2 }.into()],
+            BinaryOp::Mul => vec![Mul { rd, rs1, rs2 }.into()],
+            BinaryOp::Div => vec![Div { rd, rs1, rs2 }.into()],
+            BinaryOp::Mod => vec![Rem { rd, rs1, rs2 }.into()],
+            BinaryOp::And => vec![And { rd, rs1, rs2 }.into()],
+            BinaryOp::Or => vec![Or { rd, rs1, rs2 }.into()],
+            BinaryOp::Xor => vec![Xor { rd, rs1, rs2 }.into()],
+            BinaryOp::Shl => vec![Sll { rd, rs1, rs2 }.into()],
+            BinaryOp::Shr => vec![Srl { rd, rs1, rs2 }.into()],
+            BinaryOp::Sar => vec![Sra { rd, rs1, rs2 }.into()],
         };
 
         insts.extend(cal_insts);
@@ -999,26 +1523,31 @@ impl ToAsm for Binary {
 }
 
 impl ToAsm for Return {
-    type Output = Vec<Box<dyn Inst>>;
+    type Output = Vec<AsmInst>;
 
     fn to_asm(&self, ctx: &mut Context, program: &Program) -> Result<Self::Output, AsmError> {
-        let mut insts: Vec<Box<dyn Inst>> = vec![];
+        let mut insts: Vec<AsmInst> = vec![];
         if let Some(val) = self.value() {
             let ret_val_loc = ctx.get_location(val, program)?;
-            if let ValueLocation::Immediate(n) = ret_val_loc {
-                insts.push(Box::new(LoadImm { rd: A0, imm: n }));
+            if ctx.return_register() == FA0 {
+                insts.extend(parallel_move(vec![(
+                    ValueLocation::Register(FA0),
+                    ret_val_loc,
+                )]));
+            } else if let ValueLocation::Immediate(n) = ret_val_loc {
+                insts.push(LoadImm { rd: A0, imm: n }.into());
             } else {
                 let (load, reg) = ctx.load_value(&ret_val_loc, &[]);
                 insts.extend(load);
                 if reg != A0 {
-                    insts.push(Box::new(Move { rd: A0, rs: reg }));
+                    insts.push(Move { rd: A0, rs: reg }.into());
                     if ctx.symbol_table.get_symbol_from_loc(&ret_val_loc).is_none() {
                         ctx.deallocate_reg(reg);
                     }
                 }
             }
         }
-        insts.push(Box::new(Ret));
+        insts.push(Ret.into());
         Ok(insts)
     }
 }
@@ -1050,3 +1579,131 @@ fn has_imm(op: BinaryOp) -> bool {
         _ => false,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::back::inst::AsmInst;
+    use crate::back::RegAllocStrategy;
+    use crate::util::testing::{assemble, compile, compile_at, interpret, simulate_asm};
+
+    #[test]
+    fn test_leaf_intrinsics() {
+        // `mulh` is an intrinsic, so `f` calls nothing and needs no stack frame.
+        let input = "int f(int x, int y) { return x / 7 + y; }
+            int main() { return f(getint(), 3); }";
+        for strategy in [
+            RegAllocStrategy::LinearScan,
+            RegAllocStrategy::GraphColoring,
+        ] {
+            let asm = assemble(compile_at(input, 2), strategy);
+            let f = asm.funcs().find(|func| func.name() == "f").unwrap();
+            let insts: Vec<&AsmInst> = f.blocks().iter().flat_map(|bb| bb.insts()).collect();
+            assert!(insts.iter().any(|inst| matches!(inst, AsmInst::Mulh(_))));
+            assert!(!insts
+                .iter()
+                .any(|inst| matches!(inst, AsmInst::Call(_) | AsmInst::Sw(_) | AsmInst::Lw(_))));
+        }
+    }
+
+    #[test]
+    fn test_float() {
+        let input = "const float h = 0x1.8p1; float a[2] = {1, 2.5};
+            float sq(float x) { return x * x; }
+            int main() { float x = getfloat(); a[0] = sq(x) - h; putfarray(2, a);
+                if (x > 1) putfloat(x / 2); return a[0] * 2; }";
+        let program = compile(input);
+        let (result, output) = interpret(&program, b"2.5");
+        assert_eq!(
+            (result, output.as_str()),
+            (Ok(6), "2: 0x1.ap+1 0x1.4p+1\n0x1.4p+0")
+        );
+
+        let (result, output) = simulate_asm(program, RegAllocStrategy::LinearScan, b"-0x1p+2");
+        assert_eq!(result.exit_code, 26);
+        assert_eq!(output, "2: 0x1.ap+3 0x1.4p+1\n");
+    }
+
+    #[test]
+    fn test_float_registers() {
+        // The allocators keep `float`s in f-registers, so no bits are moved between the files.
+        let input = "float f(float x, float y) { return x * y + x / y; }
+            int main() { putfloat(f(getfloat(), 3)); return 0; }";
+        for strategy in [
+            RegAllocStrategy::LinearScan,
+            RegAllocStrategy::GraphColoring,
+        ] {
+            let asm = assemble(compile_at(input, 2), strategy);
+            let f = asm.funcs().find(|func| func.name() == "f").unwrap();
+            let insts: Vec<&AsmInst> = f.blocks().iter().flat_map(|bb| bb.insts()).collect();
+            assert!(insts.iter().any(|inst| matches!(inst, AsmInst::FMul(_))));
+            assert!(!insts
+                .iter()
+                .any(|inst| matches!(inst, AsmInst::FmvWX(_) | AsmInst::FmvXW(_))));
+        }
+
+        // `float`s are passed in `fa0` to `fa7`, then in the integer registers left by the
+        // `int`s, then on the stack.
+        let params: Vec<String> = (0..14)
+            .map(|i| match i % 4 {
+                0 => format!("int i{}, float f{}", i, i),
+                _ => format!("float f{}", i),
+            })
+            .collect();
+        let terms: Vec<String> = (0..14)
+            .map(|i| match i % 4 {
+                0 => format!("f{} * i{}", i, i),
+                _ => format!("f{} * {}", i, i),
+            })
+            .collect();
+        let args: Vec<String> = (0..14)
+            .map(|i| match i % 4 {
+                0 => format!("{}, {}.5", i, i),
+                _ => format!("{}.5", i),
+            })
+            .collect();
+        let input = format!(
+            "float f({}) {{ return {}; }}
+            int main() {{ putfloat(f({})); return 0; }}",
+            params.join(", "),
+            terms.join(" + "),
+            args.join(", ")
+        );
+        let (_, expected) = interpret(&compile(&input), b"");
+        assert_eq!(expected, "0x1.b04p+9");
+        for strategy in [
+            RegAllocStrategy::Greedy,
+            RegAllocStrategy::LinearScan,
+            RegAllocStrategy::GraphColoring,
+        ] {
+            let (_, output) = simulate_asm(compile(&input), strategy, b"");
+            assert_eq!(output, expected);
+        }
+    }
+
+    #[test]
+    fn test_block_args_in_full_registers() {
+        // Without an allocation, the `float`s live across the loop fill the registers at its
+        // jumps, so loading an argument from the stack must not spill those holding the others.
+        let live: Vec<String> = (0..34).map(|i| format!("w{}", i)).collect();
+        let defs: Vec<String> = (0..34).map(|i| format!("w{} = a * {}", i, i)).collect();
+        let input = format!(
+            "float id(float x) {{ return x; }}
+            int main() {{
+              float a = getfloat(), {};
+              int i = 0;
+              while (i < 8) {{
+                float v = id(i * 0.5);
+                putfloat(v + {});
+                float s = w0; w0 = w33; w33 = w16; w16 = s;
+                i = i + 1;
+              }}
+              return 0;
+            }}",
+            defs.join(", "),
+            live.join(" + ")
+        );
+        let (_, expected) = interpret(&compile_at(&input, 2), b"1.5");
+        let (_, output) = simulate_asm(compile_at(&input, 2), RegAllocStrategy::Greedy, b"1.5");
+        assert_eq!(output, expected);
+    }
+}
@@ -1,6 +1,14 @@
-use crate::back::inst::{Inst, LoadImm, LoadLabel, Lw, Sw};
+use crate::back::floats::FloatValues;
+use crate::back::inst::{
+    AsmInst, FMove, Flw, FmvWX, FmvXW, Fsw, Jmp, LoadImm, LoadLabel, Lw, Move, Sw,
+};
+use crate::back::program::AsmBlock;
+use crate::back::regalloc::{
+    Allocation, RegAllocStrategy, FLOAT_SCRATCH_REGISTERS, SCRATCH_REGISTERS,
+};
 use crate::back::register::*;
-use koopa::ir::{BasicBlock, Function, Program, Value, ValueKind};
+use crate::front::ir::builtin::Intrinsic;
+use koopa::ir::{BasicBlock, Function, FunctionData, Program, Value, ValueKind};
 use std::collections::HashMap;
 
 macro_rules! hashmap {
@@ -50,13 +58,9 @@ impl StackAllocator {
         self.stack_size = (self.stack_size + 15) & !15;
     }
 
-    pub fn func_default(&mut self, param_num: usize) {
-        let initial_stack_size = if param_num <= PARAMETER_REGISTERS.len() {
-            0
-        } else {
-            (param_num - PARAMETER_REGISTERS.len()) as i32 * 4
-        };
-        self.stack_size = initial_stack_size;
+    /// Start the frame of a function with the space for the arguments its calls pass on the stack.
+    pub fn func_default(&mut self, stack_args_size: i32) {
+        self.stack_size = stack_args_size;
     }
 }
 
@@ -133,6 +137,53 @@ pub const CALLER_SAVED_REGISTERS: [Register; 14] =
 
 pub const PARAMETER_REGISTERS: [Register; 8] = [A0, A1, A2, A3, A4, A5, A6, A7];
 
+pub const FLOAT_CALLEE_SAVED_REGISTERS: [Register; 12] =
+    [FS0, FS1, FS2, FS3, FS4, FS5, FS6, FS7, FS8, FS9, FS10, FS11];
+
+pub const FLOAT_CALLER_SAVED_REGISTERS: [Register; 20] = [
+    FT0, FT1, FT2, FT3, FT4, FT5, FT6, FT7, FT8, FT9, FT10, FT11, FA0, FA1, FA2, FA3, FA4, FA5,
+    FA6, FA7,
+];
+
+pub const FLOAT_PARAMETER_REGISTERS: [Register; 8] = [FA0, FA1, FA2, FA3, FA4, FA5, FA6, FA7];
+
+/// # Get where the arguments of a call are passed, given which of them are `float`s.
+///
+/// As in the `ilp32f` ABI, `float`s are passed in `fa0` to `fa7` and the other arguments in `a0`
+/// to `a7`, `float`s taking the integer registers left when the f-registers run out. The other
+/// arguments are passed on the stack, at offsets from the stack pointer of the caller.
+pub fn arg_locations(floats: &[bool]) -> Vec<ValueLocation> {
+    let mut int_regs = PARAMETER_REGISTERS.iter();
+    let mut float_regs = FLOAT_PARAMETER_REGISTERS.iter();
+    let mut offset = 0;
+    floats
+        .iter()
+        .map(|float| {
+            let reg = if *float {
+                float_regs.next().or_else(|| int_regs.next())
+            } else {
+                int_regs.next()
+            };
+            match reg {
+                Some(reg) => ValueLocation::Register(*reg),
+                None => {
+                    offset += 4;
+                    ValueLocation::Stack(offset - 4)
+                }
+            }
+        })
+        .collect()
+}
+
+/// Get the size of the arguments of a call passed on the stack, given which of them are `float`s.
+pub fn stack_args_size(floats: &[bool]) -> i32 {
+    let locations = arg_locations(floats);
+    4 * locations
+        .iter()
+        .filter(|location| matches!(location, ValueLocation::Stack(_)))
+        .count() as i32
+}
+
 impl RegisterAllocator {
     pub fn new() -> Self {
         let hashmap = hashmap! {
@@ -182,12 +233,8 @@ impl RegisterAllocator {
     }
 
     /// Setup environment for a function.
-    pub fn function_default(&mut self, param_num: usize) {
-        let param_regs = if param_num < PARAMETER_REGISTERS.len() {
-            &PARAMETER_REGISTERS[..param_num]
-        } else {
-            &PARAMETER_REGISTERS
-        };
+    /// Reset the allocator for a function whose parameters are passed in `param_regs`.
+    pub fn function_default(&mut self, param_regs: &[Register]) {
         for (reg, used) in &mut self.used {
             if CALLEE_SAVED_REGISTERS.contains(reg) || param_regs.contains(reg) {
                 *used = (true, None);
@@ -195,6 +242,9 @@ impl RegisterAllocator {
                 *used = (false, None);
             }
         }
+        for used in self.callee_saved_registers.values_mut() {
+            *used = (false, 0);
+        }
     }
 
     /// Allocate a random unused register.
@@ -307,7 +357,6 @@ impl TempValueTable {
     }
 }
 
-#[derive(Default)]
 pub struct Context {
     pub func: Option<Function>,
 
@@ -325,24 +374,407 @@ pub struct Context {
     pub name_generator: NameGenerator,
 
     pub current_bb: Option<BasicBlock>,
+
+    /// The instruction whose code is being generated.
+    pub current_value: Option<Value>,
+
+    pub strategy: RegAllocStrategy,
+
+    /// Register allocation of the current function.
+    ///
+    /// `None` if registers are allocated greedily during code generation.
+    pub allocation: Option<Allocation>,
+
+    /// Stack slots of the values that are spilled at some point.
+    spill_slots: HashMap<Value, ValueLocation>,
+
+    /// Blocks for the moves on control flow edges that can not be put in either end of the edge.
+    pub edge_blocks: Vec<AsmBlock>,
+
+    /// Scratch registers that hold a value for the current instruction.
+    scratch_used: Vec<Register>,
+
+    /// Offset of the saved return address, if it is saved in the prologue.
+    pub ra_offset: Option<i32>,
+
+    /// Offset of the saved frame pointer, if it is saved in the prologue.
+    pub fp_offset: Option<i32>,
+
+    /// Values holding `float`s and the signatures of the functions.
+    pub floats: FloatValues,
 }
 
 impl Context {
+    pub fn new(strategy: RegAllocStrategy) -> Self {
+        Self {
+            func: None,
+            temp_value_table: TempValueTable::default(),
+            reg_allocator: RegisterAllocator::default(),
+            stack_allocator: StackAllocator::default(),
+            symbol_table: SymbolTable::default(),
+            name_generator: NameGenerator::default(),
+            current_bb: None,
+            current_value: None,
+            strategy,
+            allocation: None,
+            spill_slots: HashMap::new(),
+            edge_blocks: vec![],
+            scratch_used: vec![],
+            ra_offset: None,
+            fp_offset: None,
+            floats: FloatValues::default(),
+        }
+    }
+
     /// # Setup environment for a function.
-    pub fn function_default(&mut self, param_num: usize, program: &Program) {
-        self.reg_allocator.function_default(param_num);
-        if let Some(max_param_num) = self.max_param_num(program) {
-            self.stack_allocator.func_default(max_param_num);
+    pub fn function_default(&mut self, program: &Program) {
+        let param_regs: Vec<Register> = self
+            .param_locations()
+            .into_iter()
+            .filter_map(|location| match location {
+                ValueLocation::Register(reg) if !reg.is_float() => Some(reg),
+                _ => None,
+            })
+            .collect();
+        self.reg_allocator.function_default(&param_regs);
+        if let Some(size) = self.max_stack_args_size(program) {
+            self.stack_allocator.func_default(size);
         }
         self.symbol_table.clear();
         self.temp_value_table.clear();
         self.current_bb = None;
+        self.current_value = None;
+        self.allocation = None;
+        self.spill_slots.clear();
+        self.edge_blocks.clear();
+        self.scratch_used.clear();
+        self.ra_offset = None;
+        self.fp_offset = None;
+    }
+
+    /// # Use a register allocation for the current function.
+    ///
+    /// Spilled values get a stack slot, except parameters passed on the stack which stay where
+    /// the caller put them.
+    pub fn apply_allocation(&mut self, allocation: Allocation, func_data: &FunctionData) {
+        for reg in allocation.used_registers() {
+            if CALLEE_SAVED_REGISTERS.contains(&reg) || FLOAT_CALLEE_SAVED_REGISTERS.contains(&reg)
+            {
+                self.reg_allocator
+                    .callee_saved_registers
+                    .insert(reg, (true, 0));
+            }
+        }
+        let param_locations = self.param_locations();
+        for value in allocation.spilled_values() {
+            let param_pos = func_data.params().iter().position(|p| *p == value);
+            let location = match param_pos.map(|i| &param_locations[i]) {
+                Some(location @ ValueLocation::Parameter(_)) => location.clone(),
+                _ => ValueLocation::Stack(self.stack_allocator.allocate(4)),
+            };
+            self.spill_slots.insert(value, location);
+        }
+        self.allocation = Some(allocation);
+    }
+
+    /// Get where the parameters of the current function are passed, the ones passed on the stack
+    /// being at offsets from the frame pointer.
+    pub fn param_locations(&self) -> Vec<ValueLocation> {
+        let func = self.func.expect("No function context.");
+        arg_locations(&self.floats.signature(func).params)
+            .into_iter()
+            .map(|location| match location {
+                ValueLocation::Stack(offset) => ValueLocation::Parameter(offset),
+                location => location,
+            })
+            .collect()
+    }
+
+    /// Get the register the current function returns its value in.
+    pub fn return_register(&self) -> Register {
+        let func = self.func.expect("No function context.");
+        if self.floats.signature(func).ret {
+            FA0
+        } else {
+            A0
+        }
+    }
+
+    /// Get the location of a value in an allocated register, or its stack slot for `None`.
+    pub fn allocated_location(&self, value: Value, reg: Option<Register>) -> ValueLocation {
+        match reg {
+            Some(reg) => ValueLocation::Register(reg),
+            None => self.spill_slots[&value].clone(),
+        }
+    }
+
+    /// # Prepare the locations of values for an instruction.
+    ///
+    /// Values that change location before the instruction are moved, and the locations of the
+    /// operands and the result are put in the temp value table.
+    pub fn enter_instruction(&mut self, inst: Value, func_data: &FunctionData) -> Vec<AsmInst> {
+        let allocation = match &self.allocation {
+            Some(allocation) => allocation,
+            None => return vec![],
+        };
+        let moves = allocation
+            .moves_before(inst)
+            .iter()
+            .map(|m| {
+                (
+                    self.allocated_location(m.dest, m.dest_reg),
+                    self.allocated_location(m.src, m.src_reg),
+                )
+            })
+            .collect();
+        let position = allocation.numbering.position(inst);
+        let mut locations = vec![];
+        for value in allocation.liveness.uses(func_data, inst) {
+            let reg = allocation.register_at(value, position);
+            locations.push((value, self.allocated_location(value, reg)));
+        }
+        if let Some(value) = allocation.liveness.def(func_data, inst) {
+            let reg = allocation.register_at(value, position + 2);
+            locations.push((value, self.allocated_location(value, reg)));
+        }
+        for (value, location) in locations {
+            self.temp_value_table.insert(value, location);
+        }
+        parallel_move(moves)
+    }
+
+    /// # Get the moves on a control flow edge.
+    ///
+    /// The arguments are moved to the parameters of `succ`, together with the values whose
+    /// allocated location changes on the edge.
+    pub fn edge_moves(
+        &mut self,
+        pred: BasicBlock,
+        succ: BasicBlock,
+        args: &[Value],
+        program: &Program,
+    ) -> Result<Vec<AsmInst>, AsmError> {
+        let func_data = program.func(self.func.ok_or(AsmError::UnknownFunction)?);
+        let allocation = match &self.allocation {
+            Some(allocation) => allocation,
+            None => return self.block_arg_stores(succ, args, program),
+        };
+        let mut moves = vec![];
+        for m in allocation.edge_moves(func_data, pred, succ, args) {
+            let src = if allocation.liveness.is_tracked(func_data, m.src) {
+                self.allocated_location(m.src, m.src_reg)
+            } else {
+                self.get_location(m.src, program)?
+            };
+            moves.push((self.allocated_location(m.dest, m.dest_reg), src));
+        }
+        Ok(parallel_move(moves))
+    }
+
+    /// # Store the arguments of a branch to the stack slots of the block parameters.
+    ///
+    /// Used without an allocation. Arguments that are other parameters of `succ` are copied to
+    /// new slots first, so that all of them are read before any parameter is written.
+    fn block_arg_stores(
+        &mut self,
+        succ: BasicBlock,
+        args: &[Value],
+        program: &Program,
+    ) -> Result<Vec<AsmInst>, AsmError> {
+        let func_data = program.func(self.func.ok_or(AsmError::UnknownFunction)?);
+        let params = func_data.dfg().bb(succ).params();
+        let mut insts = vec![];
+        let mut srcs: Vec<ValueLocation> = vec![];
+        // Registers holding arguments must not be spilled to load the others.
+        let src_regs = |srcs: &[ValueLocation]| -> Vec<Register> {
+            srcs.iter()
+                .filter_map(|src| match src {
+                    ValueLocation::Register(reg) => Some(*reg),
+                    _ => None,
+                })
+                .collect()
+        };
+        for (i, arg) in args.iter().enumerate() {
+            let mut src = self.get_location(*arg, program)?;
+            if params.contains(arg) && params[i] != *arg {
+                let (mut load, reg) = self.load_value(&src, &src_regs(&srcs));
+                src = ValueLocation::Stack(self.stack_allocator.allocate(4));
+                load.extend(move_location(&src, &ValueLocation::Register(reg)));
+                insts.extend(load);
+                self.deallocate_reg(reg);
+            }
+            srcs.push(src);
+        }
+        let used_regs = src_regs(&srcs);
+        for (param, src) in params.iter().zip(srcs) {
+            let dest = self.get_location(*param, program)?;
+            if dest == src {
+                continue;
+            }
+            let (load, reg) = self.load_value(&src, &used_regs);
+            insts.extend(load);
+            insts.extend(move_location(&dest, &ValueLocation::Register(reg)));
+            // Arguments in registers may still be stored for other blocks.
+            if !matches!(src, ValueLocation::Register(_)) {
+                self.deallocate_reg(reg);
+            }
+        }
+        Ok(insts)
+    }
+
+    /// Release the registers of branch arguments after the branch.
+    ///
+    /// Without an allocation, arguments in registers are only used by the branch, since temp
+    /// values with several users are stored in the stack.
+    pub fn release_args(&mut self, args: &[Value]) {
+        if self.allocation.is_some() {
+            return;
+        }
+        for arg in args {
+            if let Some(ValueLocation::Register(reg)) = self.temp_value_table.get(arg) {
+                let reg = *reg;
+                self.deallocate_reg(reg);
+            }
+        }
+    }
+
+    /// Get the edge whose moves are put at the start of a block, and the arguments passed on it.
+    ///
+    /// This is the case when the block has a single predecessor which ends with a branch, and
+    /// the other target of the branch is a different block.
+    pub fn entry_edge(
+        &self,
+        bb: BasicBlock,
+        func_data: &FunctionData,
+    ) -> Option<(BasicBlock, Vec<Value>)> {
+        let allocation = self.allocation.as_ref()?;
+        match allocation.cfg.preds(bb) {
+            [pred] => {
+                let last = func_data.layout().bbs().node(pred)?.insts().back_key()?;
+                match func_data.dfg().value(*last).kind() {
+                    ValueKind::Branch(branch) if branch.true_bb() != branch.false_bb() => {
+                        let args = if branch.true_bb() == bb {
+                            branch.true_args()
+                        } else {
+                            branch.false_args()
+                        };
+                        Some((*pred, args.to_vec()))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// # Get the label to branch to for a control flow edge.
+    ///
+    /// If there are moves on the edge that cannot be put at the start of the target, the moves
+    /// are put in a new block and its label is returned.
+    pub fn branch_target(
+        &mut self,
+        pred: BasicBlock,
+        succ: BasicBlock,
+        args: &[Value],
+        label: String,
+        program: &Program,
+    ) -> Result<String, AsmError> {
+        let func_data = program.func(self.func.ok_or(AsmError::UnknownFunction)?);
+        if self.entry_edge(succ, func_data).is_some() {
+            return Ok(label);
+        }
+        let moves = self.edge_moves(pred, succ, args, program)?;
+        if moves.is_empty() {
+            return Ok(label);
+        }
+        let name = self.name_generator.generate_label_name();
+        let mut block = AsmBlock::new(name.clone());
+        block.add_insts(moves);
+        block.add_insts(vec![Jmp { label }.into()]);
+        self.edge_blocks.push(block);
+        Ok(name)
+    }
+
+    /// Check if an alloc is kept in a register or a spill slot instead of memory.
+    pub fn is_promoted(&self, value: Value) -> bool {
+        self.allocation
+            .as_ref()
+            .is_some_and(|a| a.liveness.is_promoted(value))
+    }
+
+    /// # Allocate a register for the result of the current instruction.
+    ///
+    /// If the result has been allocated a register, that register is returned.
+    pub fn allocate_result_reg(&mut self, used_regs: &[Register]) -> (Register, Vec<AsmInst>) {
+        if self.allocation.is_some() {
+            let location = self
+                .current_value
+                .and_then(|value| self.temp_value_table.get(&value));
+            if let Some(ValueLocation::Register(reg)) = location {
+                return (*reg, vec![]);
+            }
+        }
+        self.allocate_reg(used_regs)
+    }
+
+    /// # Allocate an f-register for the result of the current instruction.
+    ///
+    /// If the result has been allocated an f-register, that register is returned, otherwise a
+    /// scratch f-register that [`Self::define_value`] moves the result from.
+    pub fn allocate_float_result_reg(&mut self, used_regs: &[Register]) -> Register {
+        match self.float_result_reg() {
+            Some(reg) => reg,
+            None => self.allocate_float_reg(used_regs),
+        }
+    }
+
+    /// Get the f-register allocated to the result of the current instruction, if any.
+    pub fn float_result_reg(&self) -> Option<Register> {
+        self.allocation.as_ref()?;
+        let location = self.temp_value_table.get(&self.current_value?);
+        match location {
+            Some(ValueLocation::Register(reg)) if reg.is_float() => Some(*reg),
+            _ => None,
+        }
+    }
+
+    /// # Record the location of the result of an instruction.
+    ///
+    /// With a register allocation, the result is moved to its allocated location instead.
+    /// Without one, a result in an f-register is moved to an integer register, as values are only
+    /// kept in f-registers by the allocators.
+    pub fn define_value(&mut self, value: Value, location: ValueLocation) -> Vec<AsmInst> {
+        if self.allocation.is_none() {
+            if let ValueLocation::Register(fs) = location {
+                if fs.is_float() {
+                    let (rd, mut insts) = self.allocate_reg(&[]);
+                    insts.push(FmvXW { rd, fs }.into());
+                    self.deallocate_reg(fs);
+                    self.temp_value_table
+                        .insert(value, ValueLocation::Register(rd));
+                    return insts;
+                }
+            }
+            self.temp_value_table.insert(value, location);
+            return vec![];
+        }
+        let target = self.temp_value_table.get(&value).cloned().unwrap();
+        let insts = parallel_move(vec![(target, location.clone())]);
+        if let ValueLocation::Register(reg) = location {
+            self.deallocate_reg(reg);
+        }
+        insts
+    }
+
+    /// Release all scratch registers after an instruction.
+    pub fn release_scratch_regs(&mut self) {
+        self.scratch_used.clear();
     }
 
     /// # Move a register to the stack.
     ///
     /// Move a value stored in a register to the stack and update the symbol table, temp value table, and register allocator.
-    fn move_to_stack(&mut self, reg: Register) -> Vec<Box<dyn Inst>> {
+    fn move_to_stack(&mut self, reg: Register) -> Vec<AsmInst> {
         let offset = self.stack_allocator.allocate(4);
 
         let reg_loc = ValueLocation::Register(reg);
@@ -363,15 +795,16 @@ impl Context {
         } else {
             self.reg_allocator.used.insert(reg, (true, None));
         }
-        vec![Box::new(Sw {
+        vec![Sw {
             rs1: reg,
             offset,
             rs2: SP,
-        })]
+        }
+        .into()]
     }
 
     /// Force allocate certain register.
-    pub fn alloc_reg_from_name(&mut self, register: Register) -> Vec<Box<dyn Inst>> {
+    pub fn alloc_reg_from_name(&mut self, register: Register) -> Vec<AsmInst> {
         if let Some((used, _)) = self.reg_allocator.used.get(&register) {
             if !used {
                 self.reg_allocator.set_used(register);
@@ -389,7 +822,15 @@ impl Context {
     /// ## Panics
     ///
     /// If no parameter `used_registers` is all registers, it will panic.
-    pub fn allocate_reg(&mut self, used_regs: &[Register]) -> (Register, Vec<Box<dyn Inst>>) {
+    pub fn allocate_reg(&mut self, used_regs: &[Register]) -> (Register, Vec<AsmInst>) {
+        if self.allocation.is_some() {
+            let reg = *SCRATCH_REGISTERS
+                .iter()
+                .find(|r| !used_regs.contains(r) && !self.scratch_used.contains(r))
+                .expect("Out of scratch registers.");
+            self.scratch_used.push(reg);
+            return (reg, vec![]);
+        }
         self.reg_allocator
             .try_allocate(used_regs)
             .map(|r| (r, vec![]))
@@ -408,12 +849,28 @@ impl Context {
             })
     }
 
+    /// Allocate a scratch f-register for the current instruction.
+    pub fn allocate_float_reg(&mut self, used_regs: &[Register]) -> Register {
+        let reg = *FLOAT_SCRATCH_REGISTERS
+            .iter()
+            .find(|r| !used_regs.contains(r) && !self.scratch_used.contains(r))
+            .expect("Out of scratch registers.");
+        self.scratch_used.push(reg);
+        reg
+    }
+
     /// # Deallocate a register.
     pub fn deallocate_reg(&mut self, reg: Register) {
         if reg == ZERO {
             return;
         }
 
+        // f-registers are only scratch registers without an allocation.
+        if self.allocation.is_some() || reg.is_float() {
+            self.scratch_used.retain(|r| *r != reg);
+            return;
+        }
+
         // If a symbol is found, do nothing
         if self
             .symbol_table
@@ -444,25 +901,36 @@ impl Context {
         &mut self,
         value_location: &ValueLocation,
         used_regs: &[Register],
-    ) -> (Vec<Box<dyn Inst>>, Register) {
+    ) -> (Vec<AsmInst>, Register) {
         match value_location {
+            ValueLocation::Register(reg) if reg.is_float() => {
+                let (rd, mut insts) = self.allocate_reg(used_regs);
+                insts.push(FmvXW { rd, fs: *reg }.into());
+                (insts, rd)
+            }
             ValueLocation::Register(reg) => (vec![], *reg),
             ValueLocation::Stack(offset) => {
                 let (reg, mut insts) = self.allocate_reg(used_regs);
-                insts.push(Box::new(Lw {
-                    rd: reg,
-                    offset: *offset,
-                    rs: SP,
-                }));
+                insts.push(
+                    Lw {
+                        rd: reg,
+                        offset: *offset,
+                        rs: SP,
+                    }
+                    .into(),
+                );
                 (insts, reg)
             }
             ValueLocation::Parameter(offset) => {
                 let (reg, mut insts) = self.allocate_reg(used_regs);
-                insts.push(Box::new(Lw {
-                    rd: reg,
-                    offset: *offset,
-                    rs: FP,
-                }));
+                insts.push(
+                    Lw {
+                        rd: reg,
+                        offset: *offset,
+                        rs: FP,
+                    }
+                    .into(),
+                );
                 (insts, reg)
             }
             ValueLocation::Immediate(imm) => {
@@ -471,25 +939,59 @@ impl Context {
                     return (vec![], ZERO);
                 }
                 let (reg, mut insts) = self.allocate_reg(used_regs);
-                insts.push(Box::new(LoadImm { rd: reg, imm: *imm }));
+                insts.push(LoadImm { rd: reg, imm: *imm }.into());
                 (insts, reg)
             }
             ValueLocation::GlobalValue(name) => {
                 let (reg, mut insts) = self.allocate_reg(used_regs);
-                insts.push(Box::new(LoadLabel {
-                    rd: reg,
-                    label: name.clone(),
-                }));
-                insts.push(Box::new(Lw {
-                    rd: reg,
-                    offset: 0,
-                    rs: reg,
-                }));
+                insts.push(
+                    LoadLabel {
+                        rd: reg,
+                        label: name.clone(),
+                    }
+                    .into(),
+                );
+                insts.push(
+                    Lw {
+                        rd: reg,
+                        offset: 0,
+                        rs: reg,
+                    }
+                    .into(),
+                );
                 (insts, reg)
             }
         }
     }
 
+    /// # Load a value to an f-register.
+    ///
+    /// A value in an f-register is used in place, others are loaded to a scratch f-register.
+    pub fn load_float(
+        &mut self,
+        value_location: &ValueLocation,
+        used_regs: &[Register],
+    ) -> (Vec<AsmInst>, Register) {
+        if let ValueLocation::Register(reg) = value_location {
+            if reg.is_float() {
+                return (vec![], *reg);
+            }
+        }
+        let fd = self.allocate_float_reg(used_regs);
+        let insts = match value_location {
+            ValueLocation::Register(_) | ValueLocation::Stack(_) | ValueLocation::Parameter(_) => {
+                move_location(&ValueLocation::Register(fd), value_location)
+            }
+            ValueLocation::Immediate(_) | ValueLocation::GlobalValue(_) => {
+                let (mut insts, rs) = self.load_value(value_location, used_regs);
+                insts.push(FmvWX { fd, rs }.into());
+                self.deallocate_reg(rs);
+                insts
+            }
+        };
+        (insts, fd)
+    }
+
     pub fn next_block(&self, bb: BasicBlock, program: &Program) -> Option<BasicBlock> {
         let func = self.func?;
         let func_data = program.func(func);
@@ -531,6 +1033,7 @@ impl Context {
         };
         match value_data.kind() {
             ValueKind::Integer(c) => Ok(ValueLocation::Immediate(c.value())),
+            ValueKind::Undef(_) => Ok(ValueLocation::Immediate(0)),
             _ => {
                 // If the value is a temp value, get the location from the temp value table.
                 let temp_val = self.temp_value_table.get(&value).cloned();
@@ -553,19 +1056,180 @@ impl Context {
         }
     }
 
-    /// Get the maximum parameter number of all functions called in the current function.
-    fn max_param_num(&self, program: &Program) -> Option<usize> {
+    /// Check if the current function calls any function other than an intrinsic.
+    pub fn has_call(&self, program: &Program) -> bool {
+        let func_data = match self.func {
+            Some(func) => program.func(func),
+            None => return false,
+        };
+        func_data.layout().bbs().nodes().any(|node| {
+            node.insts()
+                .keys()
+                .any(|inst| is_call(program, func_data, *inst))
+        })
+    }
+
+    /// Get the maximum size of the arguments passed on the stack by the calls in the current
+    /// function.
+    fn max_stack_args_size(&self, program: &Program) -> Option<i32> {
         let func = self.func?;
         let func_data = program.func(func);
-        let mut max_param_num = 0usize;
+        let mut max_size = 0;
         for (_, bb_node) in func_data.layout().bbs() {
             for (inst, _) in bb_node.insts() {
                 let inst_data = func_data.dfg().value(*inst);
                 if let ValueKind::Call(call) = inst_data.kind() {
-                    max_param_num = max_param_num.max(call.args().len());
+                    if is_call(program, func_data, *inst) {
+                        let signature = self.floats.signature(call.callee());
+                        max_size = max_size.max(stack_args_size(&signature.params));
+                    }
                 }
             }
         }
-        Some(max_param_num)
+        Some(max_size)
+    }
+}
+
+/// Check if an instruction calls a function other than an intrinsic, which is lowered to
+/// ordinary instructions instead.
+pub fn is_call(program: &Program, func_data: &FunctionData, inst: Value) -> bool {
+    match func_data.dfg().value(inst).kind() {
+        ValueKind::Call(call) => Intrinsic::from_name(program.func(call.callee()).name()).is_none(),
+        _ => false,
+    }
+}
+
+/// # Generate a parallel move.
+///
+/// All sources are read before any destination is written. Cycles are broken with `t2`, or `ft0`
+/// for f-registers, and `t1` is used to move between memory locations and to move immediates to
+/// f-registers. Values are moved between integer and f-registers as bits.
+pub fn parallel_move(moves: Vec<(ValueLocation, ValueLocation)>) -> Vec<AsmInst> {
+    let mut pending: Vec<(ValueLocation, ValueLocation)> = moves
+        .into_iter()
+        .filter(|(dest, src)| dest != src)
+        .collect();
+    let mut insts = vec![];
+    while !pending.is_empty() {
+        let ready = pending
+            .iter()
+            .position(|(dest, _)| !pending.iter().any(|(_, src)| src == dest));
+        match ready {
+            Some(pos) => {
+                let (dest, src) = pending.remove(pos);
+                insts.extend(move_location(&dest, &src));
+            }
+            None => {
+                // Every destination is still needed as a source, so all moves are in cycles.
+                // Save one destination and read it from the temporary register instead.
+                let dest = pending[0].0.clone();
+                let cycle_temp = match dest {
+                    ValueLocation::Register(reg) if reg.is_float() => ValueLocation::Register(FT0),
+                    _ => ValueLocation::Register(T2),
+                };
+                insts.extend(move_location(&cycle_temp, &dest));
+                for (_, src) in pending.iter_mut() {
+                    if *src == dest {
+                        *src = cycle_temp.clone();
+                    }
+                }
+            }
+        }
+    }
+    insts
+}
+
+/// Move a value between two locations.
+fn move_location(dest: &ValueLocation, src: &ValueLocation) -> Vec<AsmInst> {
+    let dest_reg = match dest {
+        ValueLocation::Register(reg) => *reg,
+        ValueLocation::Stack(_) | ValueLocation::Parameter(_) => match src {
+            ValueLocation::Register(reg) => *reg,
+            ValueLocation::Immediate(0) => ZERO,
+            _ => T1,
+        },
+        _ => unreachable!("Invalid move destination {:?}.", dest),
+    };
+    let mut insts: Vec<AsmInst> = vec![];
+    match src {
+        ValueLocation::Register(reg) => {
+            if *reg != dest_reg {
+                insts.push(copy_register(dest_reg, *reg));
+            }
+        }
+        ValueLocation::Stack(offset) => insts.push(load_word(dest_reg, *offset, SP)),
+        ValueLocation::Parameter(offset) => insts.push(load_word(dest_reg, *offset, FP)),
+        ValueLocation::Immediate(imm) => {
+            if dest_reg.is_float() {
+                let rs = if *imm == 0 { ZERO } else { T1 };
+                if rs != ZERO {
+                    insts.push(LoadImm { rd: rs, imm: *imm }.into());
+                }
+                insts.push(FmvWX { fd: dest_reg, rs }.into());
+            } else if dest_reg != ZERO {
+                insts.push(
+                    LoadImm {
+                        rd: dest_reg,
+                        imm: *imm,
+                    }
+                    .into(),
+                );
+            }
+        }
+        ValueLocation::GlobalValue(name) => {
+            let address = if dest_reg.is_float() { T1 } else { dest_reg };
+            insts.push(
+                LoadLabel {
+                    rd: address,
+                    label: name.clone(),
+                }
+                .into(),
+            );
+            insts.push(load_word(dest_reg, 0, address));
+        }
+    }
+    match dest {
+        ValueLocation::Stack(offset) => insts.push(store_word(dest_reg, *offset, SP)),
+        ValueLocation::Parameter(offset) => insts.push(store_word(dest_reg, *offset, FP)),
+        _ => {}
+    }
+    insts
+}
+
+/// Copy a register to another one, moving the bits between integer and f-registers.
+pub fn copy_register(rd: Register, rs: Register) -> AsmInst {
+    match (rd.is_float(), rs.is_float()) {
+        (false, false) => Move { rd, rs }.into(),
+        (true, true) => FMove { fd: rd, fs: rs }.into(),
+        (true, false) => FmvWX { fd: rd, rs }.into(),
+        (false, true) => FmvXW { rd, fs: rs }.into(),
+    }
+}
+
+/// Load a word to an integer or an f-register.
+pub fn load_word(rd: Register, offset: i32, rs: Register) -> AsmInst {
+    if rd.is_float() {
+        Flw { fd: rd, offset, rs }.into()
+    } else {
+        Lw { rd, offset, rs }.into()
+    }
+}
+
+/// Store a word from an integer or an f-register, `reg -> offset(base)`.
+pub fn store_word(reg: Register, offset: i32, base: Register) -> AsmInst {
+    if reg.is_float() {
+        Fsw {
+            fs: reg,
+            offset,
+            rs: base,
+        }
+        .into()
+    } else {
+        Sw {
+            rs1: reg,
+            offset,
+            rs2: base,
+        }
+        .into()
     }
 }
@@ -0,0 +1,327 @@
+//! Inference of the values holding `float`s.
+//!
+//! Koopa IR has no float type, so `float`s are held as their bits in `i32`s and operations on
+//! them are calls to intrinsics. To keep `float`s in f-registers and pass them as the `ilp32f` ABI
+//! does, the backend infers which values hold them.
+//!
+//! Values that hold the same kind of number are put in a class: the arguments of a branch or a
+//! call and the parameters they are passed to, the values returned by a function and the results
+//! of the calls to it, and a promoted alloc with the values stored to and loaded from it. A class
+//! holds `float`s if one of its values is used or defined as a `float` by an intrinsic or a
+//! function of the runtime library, and none is used or defined as an `int`, e.g. by a binary
+//! instruction or as an address. The other values are `int`s.
+
+use crate::back::liveness::is_promotable;
+use crate::front::ir::builtin::{builtin_functions, IRType, Intrinsic};
+use koopa::ir::{Function, FunctionData, Program, TypeKind, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+/// Which parameters of a function hold `float`s, and whether its return value does.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Signature {
+    pub params: Vec<bool>,
+    pub ret: bool,
+}
+
+/// The values holding `float`s in all functions of a program, and the signatures of the
+/// functions other than the intrinsics.
+#[derive(Default)]
+pub struct FloatValues {
+    values: HashSet<Value>,
+    signatures: HashMap<Function, Signature>,
+}
+
+/// A value, or the return value of a function.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+enum Node {
+    Value(Value),
+    Return(Function),
+}
+
+/// Classes of nodes as a union-find forest.
+#[derive(Default)]
+struct Classes {
+    parent: HashMap<Node, Node>,
+    /// Whether a class is used as a `float`, and whether it is used as an `int`, at its root.
+    uses: HashMap<Node, (bool, bool)>,
+}
+
+impl Classes {
+    fn find(&mut self, node: Node) -> Node {
+        let mut root = node;
+        while let Some(parent) = self.parent.get(&root) {
+            root = *parent;
+        }
+        let mut node = node;
+        while let Some(parent) = self.parent.insert(node, root) {
+            node = parent;
+        }
+        self.parent.remove(&root);
+        root
+    }
+
+    fn union(&mut self, a: Node, b: Node) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parent.insert(b, a);
+            let (float, int) = self.uses.remove(&b).unwrap_or_default();
+            let uses = self.uses.entry(a).or_default();
+            *uses = (uses.0 || float, uses.1 || int);
+        }
+    }
+
+    /// Record that a node is used or defined as a `float` or as an `int`.
+    fn mark(&mut self, node: Node, float: bool) {
+        let root = self.find(node);
+        let uses = self.uses.entry(root).or_default();
+        if float {
+            uses.0 = true;
+        } else {
+            uses.1 = true;
+        }
+    }
+
+    fn is_float(&mut self, node: Node) -> bool {
+        let root = self.find(node);
+        self.uses.get(&root) == Some(&(true, false))
+    }
+}
+
+/// Get the node of a value, `None` for constants and global values.
+fn node(data: &FunctionData, value: Value) -> Option<Node> {
+    if value.is_global() {
+        return None;
+    }
+    match data.dfg().value(value).kind() {
+        ValueKind::Integer(_)
+        | ValueKind::ZeroInit(_)
+        | ValueKind::Undef(_)
+        | ValueKind::Aggregate(_) => None,
+        _ => Some(Node::Value(value)),
+    }
+}
+
+/// Check if a value is an alloc kept in a register by the register allocators.
+fn is_promoted(data: &FunctionData, value: Value) -> bool {
+    !value.is_global()
+        && matches!(data.dfg().value(value).kind(), ValueKind::Alloc(_))
+        && is_promotable(data, value)
+}
+
+impl FloatValues {
+    pub fn analyze(program: &Program) -> Self {
+        let builtins: HashMap<String, (&[IRType], IRType)> = builtin_functions()
+            .map(|(name, params, ret)| (format!("@{}", name), (params, ret)))
+            .collect();
+        let mut classes = Classes::default();
+        for (func, data) in program.funcs() {
+            for (_, node) in data.layout().bbs() {
+                for inst in node.insts().keys() {
+                    visit(program, *func, data, *inst, &builtins, &mut classes);
+                }
+            }
+        }
+
+        let mut floats = Self::default();
+        for (func, data) in program.funcs() {
+            if Intrinsic::from_name(data.name()).is_some() {
+                continue;
+            }
+            if let Some((params, ret)) = builtins.get(data.name()) {
+                let signature = Signature {
+                    params: params
+                        .iter()
+                        .map(|ty| matches!(ty, IRType::Float))
+                        .collect(),
+                    ret: matches!(ret, IRType::Float),
+                };
+                floats.signatures.insert(*func, signature);
+                continue;
+            }
+            for value in data.dfg().values().keys() {
+                if node(data, *value).is_some_and(|node| classes.is_float(node)) {
+                    floats.values.insert(*value);
+                }
+            }
+            // Functions declared without a body take and return `int`s.
+            let param_num = match data.ty().kind() {
+                TypeKind::Function(params, _) => params.len(),
+                _ => unreachable!(),
+            };
+            let signature = Signature {
+                params: (0..param_num)
+                    .map(|i| data.params().get(i).is_some_and(|p| floats.is_float(*p)))
+                    .collect(),
+                ret: classes.is_float(Node::Return(*func)),
+            };
+            floats.signatures.insert(*func, signature);
+        }
+        floats
+    }
+
+    /// Check if a value holds a `float`.
+    pub fn is_float(&self, value: Value) -> bool {
+        self.values.contains(&value)
+    }
+
+    /// Get the signature of a function other than an intrinsic.
+    pub fn signature(&self, func: Function) -> &Signature {
+        &self.signatures[&func]
+    }
+}
+
+/// Put the values of an instruction in the classes they share with other values, and record
+/// those used or defined as `float`s or `int`s.
+fn visit(
+    program: &Program,
+    func: Function,
+    data: &FunctionData,
+    inst: Value,
+    builtins: &HashMap<String, (&[IRType], IRType)>,
+    classes: &mut Classes,
+) {
+    let mut mark = |value: Value, float: bool| {
+        if let Some(node) = node(data, value) {
+            classes.mark(node, float);
+        }
+    };
+    match data.dfg().value(inst).kind() {
+        ValueKind::Binary(binary) => {
+            mark(binary.lhs(), false);
+            mark(binary.rhs(), false);
+            mark(inst, false);
+        }
+        ValueKind::GetPtr(get_ptr) => {
+            mark(get_ptr.src(), false);
+            mark(get_ptr.index(), false);
+            mark(inst, false);
+        }
+        ValueKind::GetElemPtr(get_elem_ptr) => {
+            mark(get_elem_ptr.src(), false);
+            mark(get_elem_ptr.index(), false);
+            mark(inst, false);
+        }
+        ValueKind::Load(load) if is_promoted(data, load.src()) => {
+            classes.union(Node::Value(load.src()), Node::Value(inst));
+        }
+        ValueKind::Load(load) => mark(load.src(), false),
+        ValueKind::Store(store) if is_promoted(data, store.dest()) => {
+            if let Some(value) = node(data, store.value()) {
+                classes.union(Node::Value(store.dest()), value);
+            }
+        }
+        ValueKind::Store(store) => mark(store.dest(), false),
+        ValueKind::Branch(branch) => {
+            mark(branch.cond(), false);
+            let edges = [
+                (branch.true_bb(), branch.true_args()),
+                (branch.false_bb(), branch.false_args()),
+            ];
+            for (target, args) in edges {
+                for (param, arg) in data.dfg().bb(target).params().iter().zip(args) {
+                    if let Some(arg) = node(data, *arg) {
+                        classes.union(Node::Value(*param), arg);
+                    }
+                }
+            }
+        }
+        ValueKind::Jump(jump) => {
+            for (param, arg) in data
+                .dfg()
+                .bb(jump.target())
+                .params()
+                .iter()
+                .zip(jump.args())
+            {
+                if let Some(arg) = node(data, *arg) {
+                    classes.union(Node::Value(*param), arg);
+                }
+            }
+        }
+        ValueKind::Return(ret) => {
+            if let Some(value) = ret.value().and_then(|value| node(data, value)) {
+                classes.union(Node::Return(func), value);
+            }
+        }
+        ValueKind::Call(call) => {
+            let callee = program.func(call.callee());
+            if let Some(intrinsic) = Intrinsic::from_name(callee.name()) {
+                for arg in call.args() {
+                    mark(*arg, intrinsic.takes_float());
+                }
+                mark(inst, intrinsic.returns_float());
+            } else if let Some((params, ret)) = builtins.get(callee.name()) {
+                for (arg, ty) in call.args().iter().zip(*params) {
+                    mark(*arg, matches!(ty, IRType::Float));
+                }
+                match ret {
+                    IRType::Void => {}
+                    ret => mark(inst, matches!(ret, IRType::Float)),
+                }
+            } else {
+                for (arg, param) in call.args().iter().zip(callee.params()) {
+                    if let Some(arg) = node(data, *arg) {
+                        classes.union(Node::Value(*param), arg);
+                    }
+                }
+                if !data.dfg().value(inst).ty().is_unit() {
+                    classes.union(Node::Return(call.callee()), Node::Value(inst));
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::testing::compile_at;
+
+    #[test]
+    fn test_signatures() {
+        let input = "float f(int n, float x) { while (n > 0) { x = x * 2; n = n - 1; } return x; }
+            int g(float x, int y) { return y; }
+            int main() { putfloat(f(getint(), 1.5)); return g(2, 3); }";
+        let program = compile_at(input, 1);
+        let floats = FloatValues::analyze(&program);
+        let function = |name: &str| {
+            let (func, _) = program
+                .funcs()
+                .iter()
+                .find(|(_, data)| data.name()[1..] == *name)
+                .unwrap();
+            *func
+        };
+
+        let f = function("f");
+        let signature = Signature {
+            params: vec![false, true],
+            ret: true,
+        };
+        assert_eq!(floats.signature(f), &signature);
+        // The loop carries `n` and `x` in the parameters of its header.
+        let data = program.func(f);
+        let block_params: Vec<bool> = data
+            .layout()
+            .bbs()
+            .keys()
+            .flat_map(|bb| data.dfg().bb(*bb).params())
+            .map(|param| floats.is_float(*param))
+            .collect();
+        assert_eq!(block_params.iter().filter(|float| **float).count(), 1);
+        assert_eq!(block_params.len(), 2);
+
+        // Nothing uses `x` in `g`, so both sides pass it as an `int`.
+        let signature = Signature {
+            params: vec![false, false],
+            ret: false,
+        };
+        assert_eq!(floats.signature(function("g")), &signature);
+        let putfloat = Signature {
+            params: vec![true],
+            ret: false,
+        };
+        assert_eq!(floats.signature(function("putfloat")), &putfloat);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::back::register::{Register, T0};
+use crate::back::register::*;
 use crate::between;
 use compiler_macro::Inst;
 use concat_idents::concat_idents;
@@ -6,6 +6,12 @@ use concat_idents::concat_idents;
 pub trait Inst {
     fn dump(&self) -> String;
     fn is_branch(&self) -> bool;
+    /// Get the registers written by the instruction.
+    fn defs(&self) -> Vec<Register>;
+    /// Get the registers read by the instruction.
+    fn uses(&self) -> Vec<Register>;
+    /// Get the label the instruction refers to.
+    fn label(&self) -> Option<&str>;
 }
 
 macro_rules! eval_inst {
@@ -57,14 +63,42 @@ pub struct Jmp {
     pub label: String,
 }
 
+/// Call a function
+///
+/// All argument registers are assumed to be read, and all caller saved registers are clobbered.
 #[derive(Debug, Clone, Inst, Eq, PartialEq)]
+#[implicit_defs(
+    RA, T0, T1, T2, T3, T4, T5, T6, A0, A1, A2, A3, A4, A5, A6, A7, FT0, FT1, FT2, FT3, FT4, FT5,
+    FT6, FT7, FT8, FT9, FT10, FT11, FA0, FA1, FA2, FA3, FA4, FA5, FA6, FA7
+)]
+#[implicit_uses(A0, A1, A2, A3, A4, A5, A6, A7, FA0, FA1, FA2, FA3, FA4, FA5, FA6, FA7)]
 pub struct Call {
     pub label: String,
 }
 
+/// Return from a function
+///
+/// The return value, the return address and the callee saved registers are read.
 #[derive(Debug, Clone, Inst, Eq, PartialEq)]
+#[implicit_uses(
+    A0, FA0, RA, SP, FP, S1, S2, S3, S4, S5, S6, S7, S8, S9, S10, S11, FS0, FS1, FS2, FS3, FS4,
+    FS5, FS6, FS7, FS8, FS9, FS10, FS11
+)]
 pub struct Ret;
 
+/// Jump to a function in place of calling it and returning, after the epilogue
+///
+/// The argument registers and the registers read by a return are read.
+#[derive(Debug, Clone, Inst, Eq, PartialEq)]
+#[asm_name = "j"]
+#[implicit_uses(
+    A0, A1, A2, A3, A4, A5, A6, A7, FA0, FA1, FA2, FA3, FA4, FA5, FA6, FA7, RA, SP, FP, S1, S2, S3,
+    S4, S5, S6, S7, S8, S9, S10, S11, FS0, FS1, FS2, FS3, FS4, FS5, FS6, FS7, FS8, FS9, FS10, FS11
+)]
+pub struct TailCall {
+    pub label: String,
+}
+
 /// Load word
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Lw {
@@ -99,6 +133,18 @@ impl Inst for Lw {
     fn is_branch(&self) -> bool {
         false
     }
+
+    fn defs(&self) -> Vec<Register> {
+        vec![self.rd]
+    }
+
+    fn uses(&self) -> Vec<Register> {
+        vec![self.rs]
+    }
+
+    fn label(&self) -> Option<&str> {
+        None
+    }
 }
 
 /// Store word
@@ -137,6 +183,131 @@ impl Inst for Sw {
     fn is_branch(&self) -> bool {
         false
     }
+
+    fn defs(&self) -> Vec<Register> {
+        // The address is computed in t0 when the offset does not fit.
+        if between!(-2048, self.offset, 2047) {
+            vec![]
+        } else {
+            vec![T0]
+        }
+    }
+
+    fn uses(&self) -> Vec<Register> {
+        vec![self.rs1, self.rs2]
+    }
+
+    fn label(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Load a word to a floating-point register
+///
+/// The address is computed in `t0` when the offset does not fit, as `fd` can not hold it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Flw {
+    pub fd: Register,
+    pub offset: i32,
+    pub rs: Register,
+}
+
+impl Inst for Flw {
+    fn dump(&self) -> String {
+        if between!(-2048, self.offset, 2047) {
+            format!("flw {}, {}({})", self.fd, self.offset, self.rs)
+        } else {
+            let li = LoadImm {
+                rd: T0,
+                imm: self.offset,
+            };
+            let add = Add {
+                rd: T0,
+                rs1: self.rs,
+                rs2: T0,
+            };
+            let flw = Flw {
+                fd: self.fd,
+                offset: 0,
+                rs: T0,
+            };
+            format!("{}\n{}\n{}", li.dump(), add.dump(), flw.dump())
+        }
+    }
+
+    fn is_branch(&self) -> bool {
+        false
+    }
+
+    fn defs(&self) -> Vec<Register> {
+        if between!(-2048, self.offset, 2047) {
+            vec![self.fd]
+        } else {
+            vec![self.fd, T0]
+        }
+    }
+
+    fn uses(&self) -> Vec<Register> {
+        vec![self.rs]
+    }
+
+    fn label(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Store a word from a floating-point register
+///
+/// fs -> offset(rs)
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Fsw {
+    pub fs: Register,
+    pub offset: i32,
+    pub rs: Register,
+}
+
+impl Inst for Fsw {
+    fn dump(&self) -> String {
+        if between!(-2048, self.offset, 2047) {
+            format!("fsw {}, {}({})", self.fs, self.offset, self.rs)
+        } else {
+            let li = LoadImm {
+                rd: T0,
+                imm: self.offset,
+            };
+            let add = Add {
+                rd: T0,
+                rs1: self.rs,
+                rs2: T0,
+            };
+            let fsw = Fsw {
+                fs: self.fs,
+                offset: 0,
+                rs: T0,
+            };
+            format!("{}\n{}\n{}", li.dump(), add.dump(), fsw.dump())
+        }
+    }
+
+    fn is_branch(&self) -> bool {
+        false
+    }
+
+    fn defs(&self) -> Vec<Register> {
+        if between!(-2048, self.offset, 2047) {
+            vec![]
+        } else {
+            vec![T0]
+        }
+    }
+
+    fn uses(&self) -> Vec<Register> {
+        vec![self.fs, self.rs]
+    }
+
+    fn label(&self) -> Option<&str> {
+        None
+    }
 }
 
 eval_inst_with_imm!(Add);
@@ -152,6 +323,15 @@ pub struct SetLt {
     pub rs2: Register,
 }
 
+/// If rs < imm, rd = 1; else rd = 0
+#[derive(Debug, Clone, Inst, Eq, PartialEq)]
+#[asm_name = "slti"]
+pub struct SetLtImm {
+    pub rd: Register,
+    pub rs: Register,
+    pub imm: i32,
+}
+
 /// If rs1 > rs2, rd = 1; else rd = 0
 #[derive(Debug, Clone, Inst, Eq, PartialEq)]
 #[asm_name = "sgt"]
@@ -181,11 +361,12 @@ eval_inst_with_imm!(Or);
 eval_inst_with_imm!(Xor);
 eval_inst_with_imm!(And);
 
-eval_inst!(Sll);
-eval_inst!(Srl);
-eval_inst!(Sra);
+eval_inst_with_imm!(Sll);
+eval_inst_with_imm!(Srl);
+eval_inst_with_imm!(Sra);
 
 eval_inst!(Mul);
+eval_inst!(Mulh);
 eval_inst!(Div);
 eval_inst!(Rem);
 
@@ -209,3 +390,219 @@ pub struct Move {
     pub rd: Register,
     pub rs: Register,
 }
+
+/// Move a floating-point register to another one
+#[derive(Debug, Clone, Inst, Eq, PartialEq)]
+#[asm_name = "fmv.s"]
+pub struct FMove {
+    pub fd: Register,
+    pub fs: Register,
+}
+
+/// Move the bits of an integer register to a floating-point register
+#[derive(Debug, Clone, Inst, Eq, PartialEq)]
+#[asm_name = "fmv.w.x"]
+pub struct FmvWX {
+    pub fd: Register,
+    pub rs: Register,
+}
+
+/// Move the bits of a floating-point register to an integer register
+#[derive(Debug, Clone, Inst, Eq, PartialEq)]
+#[asm_name = "fmv.x.w"]
+pub struct FmvXW {
+    pub rd: Register,
+    pub fs: Register,
+}
+
+macro_rules! float_inst {
+    ($name:ident, $asm_name:literal) => {
+        #[derive(Debug, Clone, Inst, Eq, PartialEq)]
+        #[asm_name = $asm_name]
+        pub struct $name {
+            pub fd: Register,
+            pub fs1: Register,
+            pub fs2: Register,
+        }
+    };
+}
+
+macro_rules! float_compare_inst {
+    ($name:ident, $asm_name:literal) => {
+        #[derive(Debug, Clone, Inst, Eq, PartialEq)]
+        #[asm_name = $asm_name]
+        pub struct $name {
+            pub rd: Register,
+            pub fs1: Register,
+            pub fs2: Register,
+        }
+    };
+}
+
+float_inst!(FAdd, "fadd.s");
+float_inst!(FSub, "fsub.s");
+float_inst!(FMul, "fmul.s");
+float_inst!(FDiv, "fdiv.s");
+
+float_compare_inst!(FLt, "flt.s");
+float_compare_inst!(FLe, "fle.s");
+float_compare_inst!(FEq, "feq.s");
+
+/// Convert an integer to a float
+#[derive(Debug, Clone, Inst, Eq, PartialEq)]
+#[asm_name = "fcvt.s.w"]
+pub struct FcvtSW {
+    pub fd: Register,
+    pub rs: Register,
+}
+
+/// Convert a float to an integer, rounding towards zero
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FcvtWS {
+    pub rd: Register,
+    pub fs: Register,
+}
+
+impl Inst for FcvtWS {
+    fn dump(&self) -> String {
+        format!("fcvt.w.s {}, {}, rtz", self.rd, self.fs)
+    }
+
+    fn is_branch(&self) -> bool {
+        false
+    }
+
+    fn defs(&self) -> Vec<Register> {
+        vec![self.rd]
+    }
+
+    fn uses(&self) -> Vec<Register> {
+        vec![self.fs]
+    }
+
+    fn label(&self) -> Option<&str> {
+        None
+    }
+}
+
+macro_rules! asm_inst {
+    ($($name:ident),* $(,)?) => {
+        /// A machine instruction.
+        #[derive(Debug, Clone, Eq, PartialEq)]
+        pub enum AsmInst {
+            $($name($name),)*
+        }
+
+        $(
+            impl From<$name> for AsmInst {
+                fn from(inst: $name) -> Self {
+                    AsmInst::$name(inst)
+                }
+            }
+        )*
+
+        impl AsmInst {
+            fn inner(&self) -> &dyn Inst {
+                match self {
+                    $(AsmInst::$name(inst) => inst,)*
+                }
+            }
+        }
+    };
+}
+
+asm_inst!(
+    Beqz, Bnez, Jmp, Call, Ret, TailCall, Lw, Sw, Flw, Fsw, Add, Addi, Sub, SetLt, SetLtImm, SetGt,
+    SetZero, SetNonZero, Or, Ori, Xor, Xori, And, Andi, Sll, Slli, Srl, Srli, Sra, Srai, Mul, Mulh,
+    Div, Rem, LoadImm, LoadLabel, Move, FMove, FmvWX, FmvXW, FAdd, FSub, FMul, FDiv, FLt, FLe, FEq,
+    FcvtSW, FcvtWS,
+);
+
+impl Inst for AsmInst {
+    fn dump(&self) -> String {
+        self.inner().dump()
+    }
+
+    fn is_branch(&self) -> bool {
+        self.inner().is_branch()
+    }
+
+    fn defs(&self) -> Vec<Register> {
+        self.inner().defs()
+    }
+
+    fn uses(&self) -> Vec<Register> {
+        self.inner().uses()
+    }
+
+    fn label(&self) -> Option<&str> {
+        self.inner().label()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defs_uses() {
+        let call: AsmInst = Call {
+            label: "f".to_string(),
+        }
+        .into();
+        assert_eq!(
+            call.uses(),
+            [A0, A1, A2, A3, A4, A5, A6, A7, FA0, FA1, FA2, FA3, FA4, FA5, FA6, FA7]
+        );
+        for reg in [RA, T0, T6, A0, A7, FT0, FT11, FA0] {
+            assert!(call.defs().contains(&reg));
+        }
+        for reg in [S1, SP, FS0, FS11] {
+            assert!(!call.defs().contains(&reg));
+        }
+
+        let ret: AsmInst = Ret.into();
+        assert!(ret.defs().is_empty());
+        for reg in [A0, FA0, RA, SP, S1, S11, FS0, FS11] {
+            assert!(ret.uses().contains(&reg));
+        }
+        assert!(!ret.uses().contains(&A1) && !ret.uses().contains(&FA1));
+
+        // A tail call reads the arguments and everything a return reads, and writes nothing.
+        let tail_call: AsmInst = TailCall {
+            label: "f".to_string(),
+        }
+        .into();
+        assert!(tail_call.defs().is_empty());
+        for reg in ret.uses().into_iter().chain(call.uses()) {
+            assert!(tail_call.uses().contains(&reg));
+        }
+
+        // Large offsets are added to the base address in a scratch register.
+        let sw = |offset| -> AsmInst {
+            Sw {
+                rs1: A1,
+                offset,
+                rs2: SP,
+            }
+            .into()
+        };
+        assert_eq!((sw(2047).defs(), sw(2047).uses()), (vec![], vec![A1, SP]));
+        assert_eq!((sw(2048).defs(), sw(2048).uses()), (vec![T0], vec![A1, SP]));
+        assert_eq!(sw(-4096).defs(), [T0]);
+        let lw: AsmInst = Lw {
+            rd: A1,
+            offset: 4096,
+            rs: SP,
+        }
+        .into();
+        assert_eq!((lw.defs(), lw.uses()), (vec![A1], vec![SP]));
+        let fsw: AsmInst = Fsw {
+            fs: FA1,
+            offset: 4096,
+            rs: SP,
+        }
+        .into();
+        assert_eq!((fsw.defs(), fsw.uses()), (vec![T0], vec![FA1, SP]));
+    }
+}
@@ -0,0 +1,208 @@
+//! Liveness analysis of Koopa IR values.
+//!
+//! Tracked values are the values that need a location at runtime: function and basic block
+//! parameters, instruction results and scalar allocs whose address never escapes. The latter are
+//! treated as variables that are defined by `store` and used by `load`, so that they can be
+//! kept in registers instead of on the stack.
+//!
+//! Calls to intrinsics are lowered to ordinary instructions, so only the other calls are
+//! recorded as calls, which clobber the caller-saved registers.
+
+use crate::back::context::is_call;
+use crate::util::cfg::Cfg;
+use koopa::ir::{BasicBlock, FunctionData, Program, TypeKind, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+pub struct Liveness {
+    promoted: HashSet<Value>,
+    calls: HashSet<Value>,
+    live_in: HashMap<BasicBlock, HashSet<Value>>,
+    live_out: HashMap<BasicBlock, HashSet<Value>>,
+}
+
+/// Check if an alloc can be kept in a register, i.e. it holds a scalar and is only used as
+/// the source of loads and the destination of stores.
+pub fn is_promotable(func_data: &FunctionData, alloc: Value) -> bool {
+    let alloc_data = func_data.dfg().value(alloc);
+    match alloc_data.ty().kind() {
+        TypeKind::Pointer(base) if !matches!(base.kind(), TypeKind::Array(..)) => {}
+        _ => return false,
+    }
+    alloc_data
+        .used_by()
+        .iter()
+        .all(|user| match func_data.dfg().value(*user).kind() {
+            ValueKind::Load(_) => true,
+            ValueKind::Store(store) => store.dest() == alloc && store.value() != alloc,
+            _ => false,
+        })
+}
+
+impl Liveness {
+    pub fn analyze(program: &Program, func_data: &FunctionData, cfg: &Cfg) -> Self {
+        let mut liveness = Self {
+            promoted: HashSet::new(),
+            calls: HashSet::new(),
+            live_in: HashMap::new(),
+            live_out: HashMap::new(),
+        };
+        for (_, node) in func_data.layout().bbs() {
+            for inst in node.insts().keys() {
+                match func_data.dfg().value(*inst).kind() {
+                    ValueKind::Alloc(_) if is_promotable(func_data, *inst) => {
+                        liveness.promoted.insert(*inst);
+                    }
+                    ValueKind::Call(_) if is_call(program, func_data, *inst) => {
+                        liveness.calls.insert(*inst);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Upward exposed uses and definitions of every basic block.
+        let mut gen_kill = HashMap::new();
+        for (bb, node) in func_data.layout().bbs() {
+            let mut uses = HashSet::new();
+            // Block parameters are defined on entry.
+            let mut defs: HashSet<Value> =
+                func_data.dfg().bb(*bb).params().iter().copied().collect();
+            for inst in node.insts().keys() {
+                for value in liveness.uses(func_data, *inst) {
+                    if !defs.contains(&value) {
+                        uses.insert(value);
+                    }
+                }
+                if let Some(def) = liveness.def(func_data, *inst) {
+                    defs.insert(def);
+                }
+            }
+            gen_kill.insert(*bb, (uses, defs));
+        }
+
+        // Visit blocks in post order first so that the fixed point is reached quickly.
+        let mut order: Vec<BasicBlock> = cfg.rpo().iter().rev().copied().collect();
+        for (bb, _) in func_data.layout().bbs() {
+            if !cfg.is_reachable(*bb) {
+                order.push(*bb);
+            }
+        }
+        for bb in &order {
+            liveness.live_in.insert(*bb, HashSet::new());
+            liveness.live_out.insert(*bb, HashSet::new());
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for bb in &order {
+                let mut live_out = HashSet::new();
+                for succ in cfg.succs(*bb) {
+                    live_out.extend(liveness.live_in[succ].iter().copied());
+                }
+                let (uses, defs) = &gen_kill[bb];
+                let mut live_in: HashSet<Value> = uses.clone();
+                live_in.extend(live_out.iter().filter(|v| !defs.contains(v)).copied());
+                if live_in.len() != liveness.live_in[bb].len() {
+                    changed = true;
+                    liveness.live_in.insert(*bb, live_in);
+                }
+                liveness.live_out.insert(*bb, live_out);
+            }
+        }
+        liveness
+    }
+
+    /// Check if an alloc is kept in a register.
+    pub fn is_promoted(&self, value: Value) -> bool {
+        self.promoted.contains(&value)
+    }
+
+    /// Check if an instruction is a call to a function other than an intrinsic.
+    pub fn is_call(&self, inst: Value) -> bool {
+        self.calls.contains(&inst)
+    }
+
+    /// Check if a value needs a location.
+    pub fn is_tracked(&self, func_data: &FunctionData, value: Value) -> bool {
+        if value.is_global() {
+            return false;
+        }
+        let value_data = func_data.dfg().value(value);
+        match value_data.kind() {
+            ValueKind::FuncArgRef(_) | ValueKind::BlockArgRef(_) => true,
+            ValueKind::Alloc(_) => self.is_promoted(value),
+            ValueKind::Load(_)
+            | ValueKind::Binary(_)
+            | ValueKind::GetPtr(_)
+            | ValueKind::GetElemPtr(_)
+            | ValueKind::Call(_) => !value_data.ty().is_unit(),
+            _ => false,
+        }
+    }
+
+    /// Get the value defined by an instruction.
+    pub fn def(&self, func_data: &FunctionData, inst: Value) -> Option<Value> {
+        match func_data.dfg().value(inst).kind() {
+            ValueKind::Store(store) if self.is_promoted(store.dest()) => Some(store.dest()),
+            _ if self.is_tracked(func_data, inst) && !self.is_promoted(inst) => Some(inst),
+            _ => None,
+        }
+    }
+
+    /// Get the values used by an instruction.
+    pub fn uses(&self, func_data: &FunctionData, inst: Value) -> Vec<Value> {
+        let inst_data = func_data.dfg().value(inst);
+        let promoted_dest = match inst_data.kind() {
+            ValueKind::Store(store) if self.is_promoted(store.dest()) => Some(store.dest()),
+            _ => None,
+        };
+        let mut uses = vec![];
+        for value in inst_data.kind().value_uses() {
+            if Some(value) != promoted_dest
+                && self.is_tracked(func_data, value)
+                && !uses.contains(&value)
+            {
+                uses.push(value);
+            }
+        }
+        uses
+    }
+
+    /// If an instruction only copies a tracked value to another one, get `(dest, src)`.
+    ///
+    /// Loads from and stores to promoted allocs are copies.
+    pub fn copy(&self, func_data: &FunctionData, inst: Value) -> Option<(Value, Value)> {
+        match func_data.dfg().value(inst).kind() {
+            ValueKind::Load(load) if self.is_promoted(load.src()) => Some((inst, load.src())),
+            ValueKind::Store(store)
+                if self.is_promoted(store.dest()) && self.is_tracked(func_data, store.value()) =>
+            {
+                Some((store.dest(), store.value()))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn live_in(&self, bb: BasicBlock) -> &HashSet<Value> {
+        &self.live_in[&bb]
+    }
+
+    pub fn live_out(&self, bb: BasicBlock) -> &HashSet<Value> {
+        &self.live_out[&bb]
+    }
+
+    /// All tracked values of a function, function parameters first and then in layout order.
+    pub fn tracked_values(&self, func_data: &FunctionData) -> Vec<Value> {
+        let mut values: Vec<Value> = func_data.params().to_vec();
+        for (bb, node) in func_data.layout().bbs() {
+            values.extend(func_data.dfg().bb(*bb).params());
+            for inst in node.insts().keys() {
+                if self.is_tracked(func_data, *inst) {
+                    values.push(*inst);
+                }
+            }
+        }
+        values
+    }
+}
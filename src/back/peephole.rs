@@ -0,0 +1,273 @@
+//! Peephole optimizations on generated assembly.
+//!
+//! Adjacent instructions are rewritten until nothing changes:
+//!
+//! - A `lw` from the slot written by the `sw` right before it becomes a `mv`.
+//! - `mv x, x` is removed.
+//! - A `li` whose register is only read by the next instruction is folded into the immediate
+//!   form of that instruction.
+//! - A `j` to the block that directly follows is removed.
+
+use crate::back::asm_cfg::AsmCfg;
+use crate::back::asm_liveness::AsmLiveness;
+use crate::back::inst::*;
+use crate::back::program::AsmFunc;
+use crate::back::register::Register;
+
+pub fn optimize(func: &mut AsmFunc) {
+    let cfg = AsmCfg::new(func);
+    let liveness = AsmLiveness::analyze(func, &cfg);
+    let blocks = func.blocks_mut();
+    for (i, block) in blocks.iter_mut().enumerate() {
+        let live = LiveAtExit {
+            cfg: &cfg,
+            liveness: &liveness,
+            block: i,
+        };
+        while optimize_block(block.insts_mut(), &live) {}
+    }
+    for i in 1..blocks.len() {
+        let next = blocks[i].name().to_string();
+        let insts = blocks[i - 1].insts_mut();
+        if matches!(insts.last(), Some(jmp @ AsmInst::Jmp(_)) if jmp.label() == Some(&next)) {
+            insts.pop();
+        }
+    }
+}
+
+/// Apply the rewrites to a block once, returning whether anything has changed.
+fn optimize_block(insts: &mut Vec<AsmInst>, live: &LiveAtExit) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i < insts.len() {
+        if let AsmInst::Move(mv) = &insts[i] {
+            if mv.rd == mv.rs {
+                insts.remove(i);
+                changed = true;
+                continue;
+            }
+        }
+        if i + 1 == insts.len() {
+            break;
+        }
+        match (&insts[i], &insts[i + 1]) {
+            (AsmInst::Sw(sw), AsmInst::Lw(lw)) if sw.offset == lw.offset && sw.rs2 == lw.rs => {
+                if lw.rd == sw.rs1 {
+                    insts.remove(i + 1);
+                } else {
+                    insts[i + 1] = Move {
+                        rd: lw.rd,
+                        rs: sw.rs1,
+                    }
+                    .into();
+                }
+                changed = true;
+                continue;
+            }
+            (AsmInst::LoadImm(li), inst) => {
+                let reg = li.rd;
+                if let Some(folded) = fold_imm(li, inst) {
+                    if folded.defs().contains(&reg) || is_dead_from(insts, i + 2, reg, live) {
+                        insts[i + 1] = folded;
+                        insts.remove(i);
+                        changed = true;
+                        continue;
+                    }
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    changed
+}
+
+/// Registers live when leaving a block, computed before the block is rewritten.
+struct LiveAtExit<'a> {
+    cfg: &'a AsmCfg,
+    liveness: &'a AsmLiveness,
+    block: usize,
+}
+
+impl LiveAtExit<'_> {
+    /// Check if a register is live when a branch is taken.
+    fn at_branch(&self, inst: &AsmInst, reg: Register) -> bool {
+        match self.cfg.target(inst) {
+            Some(target) => self.liveness.live_in(target).contains(&reg),
+            None => true,
+        }
+    }
+
+    /// Check if a register is live when falling through to the next block.
+    fn at_end(&self, reg: Register) -> bool {
+        self.cfg
+            .fall_through(self.block)
+            .is_some_and(|next| self.liveness.live_in(next).contains(&reg))
+    }
+}
+
+/// Check if a register is written before it is read, starting from the instruction at `start`.
+fn is_dead_from(insts: &[AsmInst], start: usize, reg: Register, live: &LiveAtExit) -> bool {
+    for inst in &insts[start..] {
+        if inst.uses().contains(&reg) || (inst.is_branch() && live.at_branch(inst, reg)) {
+            return false;
+        }
+        if matches!(inst, AsmInst::Jmp(_)) || inst.defs().contains(&reg) {
+            return true;
+        }
+    }
+    !live.at_end(reg)
+}
+
+/// If `reg` is one of the operands of a commutative instruction and the other one is not, get
+/// the other operand.
+fn other_operand(reg: Register, rs1: Register, rs2: Register) -> Option<Register> {
+    if rs2 == reg && rs1 != reg {
+        Some(rs1)
+    } else if rs1 == reg && rs2 != reg {
+        Some(rs2)
+    } else {
+        None
+    }
+}
+
+/// If `reg` is only the second operand of an instruction, get the first one.
+fn first_operand(reg: Register, rs1: Register, rs2: Register) -> Option<Register> {
+    (rs2 == reg && rs1 != reg).then_some(rs1)
+}
+
+/// Rewrite an instruction reading the register loaded by `li` into its immediate form.
+fn fold_imm(li: &LoadImm, inst: &AsmInst) -> Option<AsmInst> {
+    let (reg, imm) = (li.rd, li.imm);
+    let is_imm12 = (-2048..=2047).contains(&imm);
+    let is_shamt = (0..=31).contains(&imm);
+    let folded = match inst {
+        AsmInst::Add(add) if is_imm12 => Addi {
+            rd: add.rd,
+            rs: other_operand(reg, add.rs1, add.rs2)?,
+            imm,
+        }
+        .into(),
+        AsmInst::Sub(sub) => Addi {
+            rd: sub.rd,
+            rs: first_operand(reg, sub.rs1, sub.rs2)?,
+            imm: imm
+                .checked_neg()
+                .filter(|imm| (-2048..=2047).contains(imm))?,
+        }
+        .into(),
+        AsmInst::And(and) if is_imm12 => Andi {
+            rd: and.rd,
+            rs: other_operand(reg, and.rs1, and.rs2)?,
+            imm,
+        }
+        .into(),
+        AsmInst::Or(or) if is_imm12 => Ori {
+            rd: or.rd,
+            rs: other_operand(reg, or.rs1, or.rs2)?,
+            imm,
+        }
+        .into(),
+        AsmInst::Xor(xor) if is_imm12 => Xori {
+            rd: xor.rd,
+            rs: other_operand(reg, xor.rs1, xor.rs2)?,
+            imm,
+        }
+        .into(),
+        AsmInst::SetLt(slt) if is_imm12 => SetLtImm {
+            rd: slt.rd,
+            rs: first_operand(reg, slt.rs1, slt.rs2)?,
+            imm,
+        }
+        .into(),
+        AsmInst::Sll(sll) if is_shamt => Slli {
+            rd: sll.rd,
+            rs: first_operand(reg, sll.rs1, sll.rs2)?,
+            imm,
+        }
+        .into(),
+        AsmInst::Srl(srl) if is_shamt => Srli {
+            rd: srl.rd,
+            rs: first_operand(reg, srl.rs1, srl.rs2)?,
+            imm,
+        }
+        .into(),
+        AsmInst::Sra(sra) if is_shamt => Srai {
+            rd: sra.rd,
+            rs: first_operand(reg, sra.rs1, sra.rs2)?,
+            imm,
+        }
+        .into(),
+        _ => return None,
+    };
+    Some(folded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::back::program::AsmBlock;
+    use crate::back::register::*;
+
+    /// Build a function adding `li t1, 5` to `a1` and branching on the sum to a block starting
+    /// with `target`, optimized.
+    fn optimized(target: Vec<AsmInst>) -> AsmFunc {
+        let mut func = AsmFunc::new("f".to_string());
+        let blocks = [
+            (
+                "f",
+                vec![
+                    LoadImm { rd: T1, imm: 5 }.into(),
+                    Add {
+                        rd: A0,
+                        rs1: A1,
+                        rs2: T1,
+                    }
+                    .into(),
+                    Bnez {
+                        rs: A0,
+                        label: ".f_taken".to_string(),
+                    }
+                    .into(),
+                ],
+            ),
+            (
+                ".f_next",
+                vec![LoadImm { rd: A0, imm: 0 }.into(), Ret.into()],
+            ),
+            (".f_taken", target),
+        ];
+        for (name, insts) in blocks {
+            func.add_block(AsmBlock::new(name.to_string()))
+                .add_insts(insts);
+        }
+        optimize(&mut func);
+        func
+    }
+
+    #[test]
+    fn test_fold_li() {
+        let add_t1: AsmInst = Add {
+            rd: A0,
+            rs1: A0,
+            rs2: T1,
+        }
+        .into();
+
+        // `t1` is still read when the branch is taken.
+        let func = optimized(vec![add_t1.clone(), Ret.into()]);
+        assert!(matches!(func.blocks()[0].insts()[0], AsmInst::LoadImm(_)));
+
+        // `t1` is written before it is read when the branch is taken.
+        let func = optimized(vec![LoadImm { rd: T1, imm: 1 }.into(), add_t1, Ret.into()]);
+        assert_eq!(
+            func.blocks()[0].insts()[0],
+            Addi {
+                rd: A0,
+                rs: A1,
+                imm: 5
+            }
+            .into()
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use crate::back::inst::Inst;
+use crate::back::inst::{AsmInst, Inst};
 
 pub trait Assembly {
     fn dump(&self) -> String;
@@ -22,7 +22,7 @@ pub struct AsmFunc {
 
 pub struct AsmBlock {
     name: String,
-    items: Vec<Box<dyn Inst>>,
+    items: Vec<AsmInst>,
 }
 
 pub struct AsmProgram {
@@ -67,6 +67,20 @@ impl AsmProgram {
     pub fn add_var_decl(&mut self, var_decl: AsmVarDecl) {
         self.items.push(AsmProgramItem::VarDecl(var_decl));
     }
+
+    pub fn funcs(&self) -> impl Iterator<Item = &AsmFunc> {
+        self.items.iter().filter_map(|item| match item {
+            AsmProgramItem::FuncDecl(func_decl) => Some(func_decl),
+            AsmProgramItem::VarDecl(_) => None,
+        })
+    }
+
+    pub fn var_decls(&self) -> impl Iterator<Item = &AsmVarDecl> {
+        self.items.iter().filter_map(|item| match item {
+            AsmProgramItem::VarDecl(var_decl) => Some(var_decl),
+            AsmProgramItem::FuncDecl(_) => None,
+        })
+    }
 }
 
 impl Assembly for AsmFunc {
@@ -123,6 +137,14 @@ impl AsmFunc {
         self.body.first_mut().unwrap()
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn blocks(&self) -> &[AsmBlock] {
+        &self.body
+    }
+
     pub fn blocks_mut(&mut self) -> &mut [AsmBlock] {
         &mut self.body
     }
@@ -132,6 +154,19 @@ impl AsmVarDecl {
     pub fn new(name: String, size: usize, init: Option<Vec<i32>>) -> Self {
         Self { name, size, init }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the size of the variable in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn init(&self) -> Option<&[i32]> {
+        self.init.as_deref()
+    }
 }
 
 impl Assembly for AsmBlock {
@@ -153,7 +188,7 @@ impl AsmBlock {
         }
     }
 
-    pub fn add_inst_before_branch<T: Inst + 'static>(&mut self, inst: T) {
+    pub fn add_inst_before_branch<T: Into<AsmInst>>(&mut self, inst: T) {
         let mut insert_pos = self.items.len();
         for inst in self.items.iter().rev() {
             if inst.is_branch() {
@@ -162,23 +197,26 @@ impl AsmBlock {
                 break;
             }
         }
-        self.items.insert(insert_pos, Box::new(inst));
+        self.items.insert(insert_pos, inst.into());
     }
 
-    pub fn add_insts(&mut self, insts: Vec<Box<dyn Inst>>) {
-        self.items.extend(insts);
+    pub fn name(&self) -> &str {
+        &self.name
     }
 
-    pub fn add_insts_in_pos(&mut self, pos: usize, insts: Vec<Box<dyn Inst>>) {
-        self.items.splice(pos..pos, insts);
+    pub fn insts(&self) -> &[AsmInst] {
+        &self.items
     }
 
-    pub fn contains<T: Inst>(&self, inst: &T) -> Option<usize> {
-        for (i, item) in self.items.iter().enumerate() {
-            if item.dump().eq(&inst.dump()) {
-                return Some(i);
-            }
-        }
-        None
+    pub fn insts_mut(&mut self) -> &mut Vec<AsmInst> {
+        &mut self.items
+    }
+
+    pub fn add_insts(&mut self, insts: Vec<AsmInst>) {
+        self.items.extend(insts);
+    }
+
+    pub fn add_insts_in_pos(&mut self, pos: usize, insts: Vec<AsmInst>) {
+        self.items.splice(pos..pos, insts);
     }
 }
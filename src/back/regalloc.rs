@@ -0,0 +1,467 @@
+//! Register allocation for Koopa IR values.
+//!
+//! The greedy allocator in [`crate::back::context::RegisterAllocator`] assigns registers on the
+//! fly while generating code. The allocators here run before code generation instead, and assign
+//! every tracked value (see [`Liveness`]) a register or a stack slot at every point of the
+//! function. A value may move between locations in the middle of its lifetime, in which case the
+//! allocation also records the moves to insert.
+
+use crate::back::context::{
+    arg_locations, is_call, ValueLocation, CALLER_SAVED_REGISTERS, FLOAT_CALLER_SAVED_REGISTERS,
+};
+use crate::back::floats::FloatValues;
+use crate::back::liveness::Liveness;
+use crate::back::register::*;
+use crate::util::cfg::Cfg;
+use koopa::ir::{BasicBlock, Function, FunctionData, Program, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+mod coloring;
+mod linear_scan;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RegAllocStrategy {
+    /// Allocate registers on the fly while generating code.
+    Greedy,
+    /// Linear scan with interval splitting.
+    LinearScan,
+    /// Iterated register coalescing.
+    GraphColoring,
+}
+
+impl RegAllocStrategy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "greedy" => Some(Self::Greedy),
+            "linear" => Some(Self::LinearScan),
+            "coloring" => Some(Self::GraphColoring),
+            _ => None,
+        }
+    }
+}
+
+/// Registers that can be assigned to values, in the order of preference.
+pub const ALLOCATABLE_REGISTERS: [Register; 22] = [
+    T4, T5, T6, A0, A1, A2, A3, A4, A5, A6, A7, S1, S2, S3, S4, S5, S6, S7, S8, S9, S10, S11,
+];
+
+/// f-registers that can be assigned to `float`s, in the order of preference.
+pub const FLOAT_ALLOCATABLE_REGISTERS: [Register; 29] = [
+    FT3, FT4, FT5, FT6, FT7, FT8, FT9, FT10, FT11, FA0, FA1, FA2, FA3, FA4, FA5, FA6, FA7, FS0,
+    FS1, FS2, FS3, FS4, FS5, FS6, FS7, FS8, FS9, FS10, FS11,
+];
+
+/// Registers used to access spilled values and immediates, they are never assigned to values.
+///
+/// `t0` is not in the list since it is used to expand loads and stores with large offsets.
+pub const SCRATCH_REGISTERS: [Register; 3] = [T1, T2, T3];
+
+/// f-registers used to operate on spilled `float`s and immediates.
+pub const FLOAT_SCRATCH_REGISTERS: [Register; 3] = [FT0, FT1, FT2];
+
+/// Position where function parameters are defined, before the first instruction.
+pub const PARAM_POSITION: u32 = 2;
+
+/// Positions of the instructions of a function in layout order.
+///
+/// Every instruction takes four positions: it reads its operands at `4k`, clobbers the caller
+/// saved registers at `4k + 1` if it is a call, and writes its result at `4k + 2`. The first
+/// instruction is at position 4, so that function parameters can be defined at
+/// [`PARAM_POSITION`]. Basic block parameters are defined two positions before the first
+/// instruction of their block, where the terminator of the previous block defines nothing.
+pub struct Numbering {
+    /// Definition positions of function and basic block parameters, and their indices.
+    params: HashMap<Value, (u32, usize)>,
+    insts: Vec<Value>,
+    positions: HashMap<Value, u32>,
+    /// Position of the first instruction of every basic block and the position after its last one.
+    block_ranges: HashMap<BasicBlock, (u32, u32)>,
+}
+
+impl Numbering {
+    pub fn new(func_data: &FunctionData) -> Self {
+        let mut numbering = Self {
+            params: HashMap::new(),
+            insts: vec![],
+            positions: HashMap::new(),
+            block_ranges: HashMap::new(),
+        };
+        for (i, param) in func_data.params().iter().enumerate() {
+            numbering.params.insert(*param, (PARAM_POSITION, i));
+        }
+        for (bb, node) in func_data.layout().bbs() {
+            let from = 4 * (numbering.insts.len() as u32 + 1);
+            for (i, param) in func_data.dfg().bb(*bb).params().iter().enumerate() {
+                numbering.params.insert(*param, (from - 2, i));
+            }
+            for inst in node.insts().keys() {
+                numbering.insts.push(*inst);
+                numbering
+                    .positions
+                    .insert(*inst, 4 * numbering.insts.len() as u32);
+            }
+            let to = 4 * (numbering.insts.len() as u32 + 1);
+            numbering.block_ranges.insert(*bb, (from, to));
+        }
+        numbering
+    }
+
+    /// Get the position where an instruction reads its operands.
+    pub fn position(&self, inst: Value) -> u32 {
+        self.positions[&inst]
+    }
+
+    /// Get the position where a parameter or an instruction defines its value, and the index of
+    /// a parameter to order the parameters defined together.
+    fn def_position(&self, value: Value) -> (u32, usize) {
+        match self.positions.get(&value) {
+            Some(position) => (position + 2, 0),
+            None => self.params[&value],
+        }
+    }
+
+    /// Get the instruction at a position.
+    pub fn inst_at(&self, position: u32) -> Option<Value> {
+        let index = (position / 4).checked_sub(1)?;
+        self.insts.get(index as usize).copied()
+    }
+
+    /// Get the position of the first instruction of a basic block and the position after its
+    /// last one.
+    pub fn block_range(&self, bb: BasicBlock) -> (u32, u32) {
+        self.block_ranges[&bb]
+    }
+}
+
+/// Locations of a value over its lifetime, as `(start position, register)` pairs sorted by
+/// position. `None` is the stack slot of the value.
+type Segments = Vec<(u32, Option<Register>)>;
+
+/// A move between the allocated locations of two values.
+pub struct AllocatedMove {
+    pub dest: Value,
+    /// `None` is the stack slot of the destination.
+    pub dest_reg: Option<Register>,
+    pub src: Value,
+    /// `None` is the stack slot of the source, or the source is not tracked, e.g. a constant.
+    pub src_reg: Option<Register>,
+}
+
+pub struct Allocation {
+    pub liveness: Liveness,
+    pub cfg: Cfg,
+    pub numbering: Numbering,
+    segments: HashMap<Value, Segments>,
+    /// Moves of values that change location before an instruction.
+    split_moves: HashMap<Value, Vec<AllocatedMove>>,
+}
+
+impl Allocation {
+    fn new(
+        liveness: Liveness,
+        cfg: Cfg,
+        numbering: Numbering,
+        segments: HashMap<Value, Segments>,
+        splits: Vec<(u32, Value)>,
+    ) -> Self {
+        let mut allocation = Self {
+            liveness,
+            cfg,
+            numbering,
+            segments,
+            split_moves: HashMap::new(),
+        };
+        // Values split at the start of a block are moved on the incoming edges instead.
+        let block_starts: HashSet<u32> = allocation
+            .numbering
+            .block_ranges
+            .iter()
+            .filter(|(bb, _)| !allocation.cfg.preds(**bb).is_empty())
+            .map(|(_, (from, _))| *from)
+            .collect();
+        for (position, value) in splits {
+            if block_starts.contains(&position) {
+                continue;
+            }
+            let from = allocation.register_at(value, position - 1);
+            let to = allocation.register_at(value, position);
+            if from != to {
+                let inst = allocation.numbering.inst_at(position).unwrap();
+                allocation
+                    .split_moves
+                    .entry(inst)
+                    .or_default()
+                    .push(AllocatedMove {
+                        dest: value,
+                        dest_reg: to,
+                        src: value,
+                        src_reg: from,
+                    });
+            }
+        }
+        allocation
+    }
+
+    /// Check if a value has been allocated a location.
+    pub fn is_allocated(&self, value: Value) -> bool {
+        self.segments.contains_key(&value)
+    }
+
+    /// Get the register of a value at a position, `None` if it is in its stack slot.
+    pub fn register_at(&self, value: Value, position: u32) -> Option<Register> {
+        let segments = &self.segments[&value];
+        let i = segments.partition_point(|(start, _)| *start <= position);
+        segments[i.saturating_sub(1)].1
+    }
+
+    /// All registers assigned to values.
+    pub fn used_registers(&self) -> HashSet<Register> {
+        self.segments
+            .values()
+            .flatten()
+            .filter_map(|(_, reg)| *reg)
+            .collect()
+    }
+
+    /// Values that need a stack slot.
+    pub fn spilled_values(&self) -> Vec<Value> {
+        self.segments
+            .iter()
+            .filter(|(_, segments)| segments.iter().any(|(_, reg)| reg.is_none()))
+            .map(|(value, _)| *value)
+            .collect()
+    }
+
+    /// Get the moves to insert before an instruction.
+    pub fn moves_before(&self, inst: Value) -> &[AllocatedMove] {
+        self.split_moves.get(&inst).map_or(&[], |m| m.as_slice())
+    }
+
+    /// Get the moves to insert on a control flow edge.
+    ///
+    /// The arguments are moved to the parameters of `succ`, and the values whose location at the
+    /// end of `pred` differs from their location at the start of `succ` are moved as well.
+    pub fn edge_moves(
+        &self,
+        func_data: &FunctionData,
+        pred: BasicBlock,
+        succ: BasicBlock,
+        args: &[Value],
+    ) -> Vec<AllocatedMove> {
+        // The terminator of `pred` is the last point where its locations are known.
+        let (_, pred_end) = self.numbering.block_range(pred);
+        let pred_end = pred_end - 4;
+        let (succ_start, _) = self.numbering.block_range(succ);
+        let src_reg = |value: Value| {
+            self.segments
+                .contains_key(&value)
+                .then(|| self.register_at(value, pred_end))
+                .flatten()
+        };
+
+        let mut moves = vec![];
+        let params = func_data.dfg().bb(succ).params();
+        for (param, arg) in params.iter().zip(args) {
+            if self.is_allocated(*param) {
+                moves.push(AllocatedMove {
+                    dest: *param,
+                    dest_reg: self.register_at(*param, succ_start),
+                    src: *arg,
+                    src_reg: src_reg(*arg),
+                });
+            }
+        }
+        let mut live_in: Vec<Value> = self.liveness.live_in(succ).iter().copied().collect();
+        // Keep the generated code deterministic.
+        live_in.sort_by_key(|value| self.numbering.def_position(*value));
+        for value in live_in {
+            let from = self.register_at(value, pred_end);
+            let to = self.register_at(value, succ_start);
+            if from != to {
+                moves.push(AllocatedMove {
+                    dest: value,
+                    dest_reg: to,
+                    src: value,
+                    src_reg: from,
+                });
+            }
+        }
+        moves
+    }
+}
+
+/// Registers the calling convention passes values in, which the allocators try to assign to
+/// these values to avoid moves.
+pub struct AbiRegisters {
+    /// Registers of the arguments of every call, `None` for the arguments passed on the stack.
+    args: HashMap<Value, Vec<Option<Register>>>,
+    /// Register of the result of every call.
+    results: HashMap<Value, Register>,
+    /// Registers of the parameters, `None` for the parameters passed on the stack.
+    pub params: Vec<Option<Register>>,
+    /// Register of the return value.
+    pub ret: Register,
+}
+
+impl AbiRegisters {
+    fn new(program: &Program, func: Function, floats: &FloatValues) -> Self {
+        let registers = |floats: &[bool]| -> Vec<Option<Register>> {
+            arg_locations(floats)
+                .into_iter()
+                .map(|location| match location {
+                    ValueLocation::Register(reg) => Some(reg),
+                    _ => None,
+                })
+                .collect()
+        };
+        let return_register = |ret: bool| if ret { FA0 } else { A0 };
+        let func_data = program.func(func);
+        let mut args = HashMap::new();
+        let mut results = HashMap::new();
+        for (_, node) in func_data.layout().bbs() {
+            for inst in node.insts().keys() {
+                if let ValueKind::Call(call) = func_data.dfg().value(*inst).kind() {
+                    if is_call(program, func_data, *inst) {
+                        let signature = floats.signature(call.callee());
+                        args.insert(*inst, registers(&signature.params));
+                        results.insert(*inst, return_register(signature.ret));
+                    }
+                }
+            }
+        }
+        let signature = floats.signature(func);
+        Self {
+            args,
+            results,
+            params: registers(&signature.params),
+            ret: return_register(signature.ret),
+        }
+    }
+
+    /// Get the registers of the arguments of a call.
+    pub fn args(&self, call: Value) -> &[Option<Register>] {
+        &self.args[&call]
+    }
+
+    /// Get the register of the result of a call.
+    pub fn result(&self, call: Value) -> Register {
+        self.results[&call]
+    }
+}
+
+/// Check if a register is clobbered by calls.
+pub fn is_caller_saved(reg: Register) -> bool {
+    CALLER_SAVED_REGISTERS.contains(&reg) || FLOAT_CALLER_SAVED_REGISTERS.contains(&reg)
+}
+
+/// Allocate registers for all tracked values of a function.
+///
+/// `float`s are allocated f-registers and the other values integer registers, separately.
+/// Returns `None` for [`RegAllocStrategy::Greedy`], which allocates during code generation.
+pub fn allocate(
+    strategy: RegAllocStrategy,
+    program: &Program,
+    func: Function,
+    floats: &FloatValues,
+) -> Option<Allocation> {
+    if strategy == RegAllocStrategy::Greedy {
+        return None;
+    }
+    let func_data = program.func(func);
+    let cfg = Cfg::new(func_data);
+    let liveness = Liveness::analyze(program, func_data, &cfg);
+    let numbering = Numbering::new(func_data);
+    let abi = AbiRegisters::new(program, func, floats);
+    let (float_values, int_values): (Vec<Value>, Vec<Value>) = liveness
+        .tracked_values(func_data)
+        .into_iter()
+        .partition(|value| floats.is_float(*value));
+    let mut segments = HashMap::new();
+    let mut splits = vec![];
+    for (registers, values) in [
+        (&ALLOCATABLE_REGISTERS[..], int_values),
+        (&FLOAT_ALLOCATABLE_REGISTERS[..], float_values),
+    ] {
+        match strategy {
+            RegAllocStrategy::Greedy => unreachable!(),
+            RegAllocStrategy::LinearScan => {
+                let (class_segments, class_splits) = linear_scan::allocate(
+                    func_data, &liveness, &numbering, &abi, registers, &values,
+                );
+                segments.extend(class_segments);
+                splits.extend(class_splits);
+            }
+            RegAllocStrategy::GraphColoring => {
+                let (colored, spilled) =
+                    coloring::allocate(func_data, &cfg, &liveness, &abi, registers, &values);
+                segments.extend(
+                    colored
+                        .into_iter()
+                        .map(|(value, reg)| (value, vec![(0, Some(reg))])),
+                );
+                segments.extend(spilled.into_iter().map(|value| (value, vec![(0, None)])));
+            }
+        }
+    }
+    Some(Allocation::new(liveness, cfg, numbering, segments, splits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::testing::{compile_at, func, simulate_asm};
+    use koopa::ir::ValueKind;
+
+    #[test]
+    fn test_spill_across_calls() {
+        // More values than registers are live across the calls to `getint` and `f`.
+        let n = 30;
+        let decls: String = (0..n)
+            .map(|i| format!("int v{} = getint() * {};", i, i + 2))
+            .collect();
+        let sum: Vec<String> = (0..n).map(|i| format!("v{}", i)).collect();
+        let input = format!(
+            "int f(int x) {{ putint(x); return x + 1; }}
+            int main() {{ {} return f(1) + {}; }}",
+            decls,
+            sum.join(" + ")
+        );
+        let stdin: String = (1..=n).map(|i| format!("{} ", i)).collect();
+        let expected = 2 + (1..=n as i32).map(|i| i * (i + 1)).sum::<i32>();
+
+        for strategy in [
+            RegAllocStrategy::GraphColoring,
+            RegAllocStrategy::LinearScan,
+        ] {
+            let program = compile_at(&input, 1);
+            let main = func(&program, "main");
+            let calls: Vec<Value> = main
+                .layout()
+                .bbs()
+                .nodes()
+                .flat_map(|node| node.insts().keys().copied())
+                .filter(|inst| matches!(main.dfg().value(*inst).kind(), ValueKind::Call(_)))
+                .collect();
+            let (main_func, _) = program
+                .funcs()
+                .iter()
+                .find(|(_, data)| data.name() == "@main")
+                .unwrap();
+            let floats = FloatValues::analyze(&program);
+            let allocation = allocate(strategy, &program, *main_func, &floats).unwrap();
+            assert!(!allocation.spilled_values().is_empty());
+            // The products are live across the call to `f`, which clobbers the caller-saved
+            // registers.
+            let clobber = allocation.numbering.position(calls[n]) + 1;
+            for getint in &calls[..n] {
+                let product = main.dfg().value(*getint).used_by().iter().next().unwrap();
+                assert!(allocation
+                    .register_at(*product, clobber)
+                    .is_none_or(|reg| !is_caller_saved(reg)));
+            }
+
+            let (result, output) = simulate_asm(program, strategy, stdin.as_bytes());
+            assert_eq!((result.exit_code, output.as_str()), (expected, "1"));
+        }
+    }
+}
@@ -0,0 +1,612 @@
+//! Graph coloring register allocation.
+//!
+//! This is the iterated register coalescing algorithm from George and Appel,
+//! "Iterated Register Coalescing". Physical registers are precolored nodes of the interference
+//! graph, values that are live across calls interfere with all caller-saved registers, and
+//! argument, return value and parameter registers are move-related to the values they hold, so
+//! coalescing removes most of the moves around calls. Integer values and `float`s are colored
+//! separately, with the registers of their class.
+//!
+//! Spilled values are not rewritten into new short-lived values. They are kept on the stack and
+//! accessed through [`SCRATCH_REGISTERS`](super::SCRATCH_REGISTERS) instead, which never take part
+//! in the coloring, so a single pass is enough.
+
+use super::{is_caller_saved, AbiRegisters};
+use crate::back::liveness::Liveness;
+use crate::back::register::Register;
+use crate::util::cfg::Cfg;
+use koopa::ir::{BasicBlock, FunctionData, Value, ValueKind};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum NodeState {
+    Precolored,
+    Initial,
+    Simplify,
+    Freeze,
+    Spill,
+    Spilled,
+    Coalesced,
+    Colored,
+    Select,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum MoveState {
+    Worklist,
+    Active,
+    Coalesced,
+    Constrained,
+    Frozen,
+}
+
+/// Interference graph and the state of the algorithm.
+///
+/// The first `k` nodes are the physical registers that can be assigned, the others are the values
+/// to color. Worklists are stacks that may contain stale entries, a node is only in a worklist if
+/// its state says so.
+struct Graph<'a> {
+    registers: &'a [Register],
+    k: usize,
+    adj_set: HashSet<(usize, usize)>,
+    adj_list: Vec<Vec<usize>>,
+    degree: Vec<usize>,
+    move_list: Vec<Vec<usize>>,
+    /// `(dest, src)` of every move.
+    moves: Vec<(usize, usize)>,
+    move_weight: Vec<f64>,
+    move_state: Vec<MoveState>,
+    state: Vec<NodeState>,
+    alias: Vec<usize>,
+    color: Vec<Option<usize>>,
+    spill_cost: Vec<f64>,
+    simplify_worklist: Vec<usize>,
+    freeze_worklist: Vec<usize>,
+    spill_worklist: Vec<usize>,
+    move_worklist: Vec<usize>,
+    select_stack: Vec<usize>,
+}
+
+/// Allocate registers to some of the tracked values of a function.
+///
+/// Returns the register of every colored value and the values that must be spilled.
+pub fn allocate(
+    func_data: &FunctionData,
+    cfg: &Cfg,
+    liveness: &Liveness,
+    abi: &AbiRegisters,
+    registers: &[Register],
+    values: &[Value],
+) -> (HashMap<Value, Register>, Vec<Value>) {
+    let k = registers.len();
+    let index: HashMap<Value, usize> = values
+        .iter()
+        .enumerate()
+        .map(|(i, v)| (*v, k + i))
+        .collect();
+    let mut graph = Graph::new(registers, k + values.len());
+    graph.build(func_data, cfg, liveness, abi, &index);
+    graph.make_worklist();
+    loop {
+        if let Some(n) = graph.pop_node(NodeState::Simplify) {
+            graph.simplify(n);
+        } else if let Some(m) = graph.pop_move() {
+            graph.coalesce(m);
+        } else if let Some(n) = graph.pop_node(NodeState::Freeze) {
+            graph.freeze(n);
+        } else if !graph.select_spill() {
+            break;
+        }
+    }
+    graph.assign_colors();
+
+    let mut colored = HashMap::new();
+    let mut spilled = vec![];
+    for (i, value) in values.iter().enumerate() {
+        match graph.color[k + i] {
+            Some(color) => {
+                colored.insert(*value, registers[color]);
+            }
+            None => spilled.push(*value),
+        }
+    }
+    (colored, spilled)
+}
+
+impl<'a> Graph<'a> {
+    fn new(registers: &'a [Register], node_num: usize) -> Self {
+        let mut graph = Self {
+            registers,
+            k: registers.len(),
+            adj_set: HashSet::new(),
+            adj_list: vec![vec![]; node_num],
+            degree: vec![0; node_num],
+            move_list: vec![vec![]; node_num],
+            moves: vec![],
+            move_weight: vec![],
+            move_state: vec![],
+            state: vec![NodeState::Initial; node_num],
+            alias: (0..node_num).collect(),
+            color: vec![None; node_num],
+            spill_cost: vec![0.0; node_num],
+            simplify_worklist: vec![],
+            freeze_worklist: vec![],
+            spill_worklist: vec![],
+            move_worklist: vec![],
+            select_stack: vec![],
+        };
+        for reg in 0..graph.k {
+            graph.state[reg] = NodeState::Precolored;
+            graph.color[reg] = Some(reg);
+            graph.degree[reg] = usize::MAX / 2;
+        }
+        graph
+    }
+
+    fn is_precolored(&self, n: usize) -> bool {
+        n < self.k
+    }
+
+    /// Get the node of a register, `None` if it is in another class.
+    fn register_node(&self, reg: Register) -> Option<usize> {
+        self.registers.iter().position(|r| *r == reg)
+    }
+
+    fn add_edge(&mut self, u: usize, v: usize) {
+        if u == v || self.adj_set.contains(&(u, v)) {
+            return;
+        }
+        self.adj_set.insert((u, v));
+        self.adj_set.insert((v, u));
+        if !self.is_precolored(u) {
+            self.adj_list[u].push(v);
+            self.degree[u] += 1;
+        }
+        if !self.is_precolored(v) {
+            self.adj_list[v].push(u);
+            self.degree[v] += 1;
+        }
+    }
+
+    fn add_move(&mut self, dest: usize, src: usize, weight: f64) {
+        if dest == src {
+            return;
+        }
+        let m = self.moves.len();
+        self.moves.push((dest, src));
+        self.move_weight.push(weight);
+        self.move_state.push(MoveState::Worklist);
+        self.move_list[dest].push(m);
+        self.move_list[src].push(m);
+    }
+
+    /// Add the moves from the arguments of a branch to the parameters of its target.
+    fn add_block_arg_moves(
+        &mut self,
+        func_data: &FunctionData,
+        index: &HashMap<Value, usize>,
+        target: BasicBlock,
+        args: &[Value],
+        weight: f64,
+    ) {
+        for (param, arg) in func_data.dfg().bb(target).params().iter().zip(args) {
+            if let Some(arg) = index.get(arg) {
+                self.add_move(index[param], *arg, weight);
+            }
+        }
+    }
+
+    /// Build the interference graph by walking every basic block backwards from its live-out set.
+    fn build(
+        &mut self,
+        func_data: &FunctionData,
+        cfg: &Cfg,
+        liveness: &Liveness,
+        abi: &AbiRegisters,
+        index: &HashMap<Value, usize>,
+    ) {
+        let depths = cfg.loop_depths(&cfg.dominators());
+        let caller_saved: Vec<usize> = (0..self.k)
+            .filter(|r| is_caller_saved(self.registers[*r]))
+            .collect();
+
+        for (bb, node) in func_data.layout().bbs() {
+            let depth = depths.get(bb).copied().unwrap_or(0).min(8);
+            let weight = 10f64.powi(depth as i32);
+            // Values of the other class are not in the graph.
+            let mut live: BTreeSet<usize> = liveness
+                .live_out(*bb)
+                .iter()
+                .filter_map(|v| index.get(v).copied())
+                .collect();
+
+            let insts: Vec<Value> = node.insts().keys().copied().collect();
+            for inst in insts.into_iter().rev() {
+                let def = liveness
+                    .def(func_data, inst)
+                    .and_then(|v| index.get(&v).copied());
+                let uses: Vec<usize> = liveness
+                    .uses(func_data, inst)
+                    .iter()
+                    .filter_map(|v| index.get(v).copied())
+                    .collect();
+
+                if let Some((dest, src)) = liveness.copy(func_data, inst) {
+                    if let (Some(dest), Some(src)) = (index.get(&dest), index.get(&src)) {
+                        // The source and the destination of a copy hold the same value, so they
+                        // do not interfere here.
+                        live.remove(src);
+                        self.add_move(*dest, *src, weight);
+                    }
+                }
+
+                match func_data.dfg().value(inst).kind() {
+                    ValueKind::Call(call) if liveness.is_call(inst) => {
+                        for value in live.iter().filter(|v| Some(**v) != def) {
+                            for reg in &caller_saved {
+                                self.add_edge(*value, *reg);
+                            }
+                        }
+                        for (arg, reg) in call.args().iter().zip(abi.args(inst)) {
+                            let reg = reg.and_then(|reg| self.register_node(reg));
+                            if let (Some(arg), Some(reg)) = (index.get(arg), reg) {
+                                self.add_move(reg, *arg, weight);
+                            }
+                        }
+                        if let (Some(def), Some(reg)) = (def, self.register_node(abi.result(inst)))
+                        {
+                            self.add_move(def, reg, weight);
+                        }
+                    }
+                    ValueKind::Return(ret) => {
+                        let value = ret.value().and_then(|v| index.get(&v));
+                        if let (Some(value), Some(reg)) = (value, self.register_node(abi.ret)) {
+                            self.add_move(reg, *value, weight);
+                        }
+                    }
+                    ValueKind::Jump(jump) => {
+                        self.add_block_arg_moves(
+                            func_data,
+                            index,
+                            jump.target(),
+                            jump.args(),
+                            weight,
+                        );
+                    }
+                    ValueKind::Branch(branch) => {
+                        self.add_block_arg_moves(
+                            func_data,
+                            index,
+                            branch.true_bb(),
+                            branch.true_args(),
+                            weight,
+                        );
+                        self.add_block_arg_moves(
+                            func_data,
+                            index,
+                            branch.false_bb(),
+                            branch.false_args(),
+                            weight,
+                        );
+                    }
+                    _ => {}
+                }
+
+                if let Some(def) = def {
+                    for value in &live {
+                        self.add_edge(def, *value);
+                    }
+                    self.spill_cost[def] += weight;
+                    live.remove(&def);
+                }
+                for value in uses {
+                    live.insert(value);
+                    self.spill_cost[value] += weight;
+                }
+            }
+
+            // Block parameters are all defined on entry, even if they are never used.
+            let params: Vec<usize> = func_data
+                .dfg()
+                .bb(*bb)
+                .params()
+                .iter()
+                .filter_map(|v| index.get(v).copied())
+                .collect();
+            for param in &params {
+                live.remove(param);
+            }
+            for (i, param) in params.iter().enumerate() {
+                for value in live.iter().chain(&params[i + 1..]) {
+                    self.add_edge(*param, *value);
+                }
+                self.spill_cost[*param] += weight;
+            }
+
+            // All parameters are defined at the entry of the function.
+            if Some(*bb) == cfg.entry {
+                for (param, reg) in func_data.params().iter().zip(&abi.params) {
+                    let param = match index.get(param) {
+                        Some(param) => *param,
+                        None => continue,
+                    };
+                    for value in &live {
+                        self.add_edge(param, *value);
+                    }
+                    if let Some(reg) = reg.and_then(|reg| self.register_node(reg)) {
+                        self.add_move(param, reg, 1.0);
+                    }
+                }
+            }
+        }
+    }
+
+    fn set_state(&mut self, n: usize, state: NodeState) {
+        self.state[n] = state;
+        match state {
+            NodeState::Simplify => self.simplify_worklist.push(n),
+            NodeState::Freeze => self.freeze_worklist.push(n),
+            NodeState::Spill => self.spill_worklist.push(n),
+            _ => {}
+        }
+    }
+
+    fn pop_node(&mut self, state: NodeState) -> Option<usize> {
+        loop {
+            let n = match state {
+                NodeState::Simplify => self.simplify_worklist.pop()?,
+                NodeState::Freeze => self.freeze_worklist.pop()?,
+                _ => unreachable!(),
+            };
+            if self.state[n] == state {
+                return Some(n);
+            }
+        }
+    }
+
+    fn pop_move(&mut self) -> Option<usize> {
+        while let Some(m) = self.move_worklist.pop() {
+            if self.move_state[m] == MoveState::Worklist {
+                return Some(m);
+            }
+        }
+        None
+    }
+
+    fn make_worklist(&mut self) {
+        // Try to coalesce the most frequently executed moves first.
+        let mut moves: Vec<usize> = (0..self.moves.len()).collect();
+        moves.sort_by(|a, b| self.move_weight[*a].total_cmp(&self.move_weight[*b]));
+        self.move_worklist = moves;
+
+        for n in self.k..self.state.len() {
+            if self.degree[n] >= self.k {
+                self.set_state(n, NodeState::Spill);
+            } else if self.is_move_related(n) {
+                self.set_state(n, NodeState::Freeze);
+            } else {
+                self.set_state(n, NodeState::Simplify);
+            }
+        }
+    }
+
+    fn adjacent(&self, n: usize) -> Vec<usize> {
+        self.adj_list[n]
+            .iter()
+            .filter(|m| !matches!(self.state[**m], NodeState::Select | NodeState::Coalesced))
+            .copied()
+            .collect()
+    }
+
+    fn node_moves(&self, n: usize) -> Vec<usize> {
+        self.move_list[n]
+            .iter()
+            .filter(|m| {
+                matches!(
+                    self.move_state[**m],
+                    MoveState::Active | MoveState::Worklist
+                )
+            })
+            .copied()
+            .collect()
+    }
+
+    fn is_move_related(&self, n: usize) -> bool {
+        !self.node_moves(n).is_empty()
+    }
+
+    fn simplify(&mut self, n: usize) {
+        self.state[n] = NodeState::Select;
+        self.select_stack.push(n);
+        for m in self.adjacent(n) {
+            self.decrement_degree(m);
+        }
+    }
+
+    fn decrement_degree(&mut self, m: usize) {
+        if self.is_precolored(m) {
+            return;
+        }
+        let d = self.degree[m];
+        self.degree[m] = d - 1;
+        if d == self.k && self.state[m] == NodeState::Spill {
+            let mut nodes = self.adjacent(m);
+            nodes.push(m);
+            self.enable_moves(&nodes);
+            if self.is_move_related(m) {
+                self.set_state(m, NodeState::Freeze);
+            } else {
+                self.set_state(m, NodeState::Simplify);
+            }
+        }
+    }
+
+    fn enable_moves(&mut self, nodes: &[usize]) {
+        for n in nodes {
+            for m in self.node_moves(*n) {
+                if self.move_state[m] == MoveState::Active {
+                    self.move_state[m] = MoveState::Worklist;
+                    self.move_worklist.push(m);
+                }
+            }
+        }
+    }
+
+    fn get_alias(&self, mut n: usize) -> usize {
+        while self.state[n] == NodeState::Coalesced {
+            n = self.alias[n];
+        }
+        n
+    }
+
+    fn add_worklist(&mut self, u: usize) {
+        if !self.is_precolored(u)
+            && !self.is_move_related(u)
+            && self.degree[u] < self.k
+            && self.state[u] == NodeState::Freeze
+        {
+            self.set_state(u, NodeState::Simplify);
+        }
+    }
+
+    /// George's test for coalescing with a precolored node.
+    fn ok(&self, t: usize, r: usize) -> bool {
+        self.degree[t] < self.k || self.is_precolored(t) || self.adj_set.contains(&(t, r))
+    }
+
+    /// Briggs' test: the merged node has fewer than `k` neighbors of significant degree.
+    fn conservative(&self, nodes: &[usize]) -> bool {
+        let nodes: BTreeSet<usize> = nodes.iter().copied().collect();
+        nodes.iter().filter(|n| self.degree[**n] >= self.k).count() < self.k
+    }
+
+    fn coalesce(&mut self, m: usize) {
+        let (x, y) = self.moves[m];
+        let (x, y) = (self.get_alias(x), self.get_alias(y));
+        let (u, v) = if self.is_precolored(y) {
+            (y, x)
+        } else {
+            (x, y)
+        };
+
+        if u == v {
+            self.move_state[m] = MoveState::Coalesced;
+            self.add_worklist(u);
+        } else if self.is_precolored(v) || self.adj_set.contains(&(u, v)) {
+            self.move_state[m] = MoveState::Constrained;
+            self.add_worklist(u);
+            self.add_worklist(v);
+        } else if (self.is_precolored(u) && self.adjacent(v).iter().all(|t| self.ok(*t, u)))
+            || (!self.is_precolored(u) && {
+                let mut nodes = self.adjacent(u);
+                nodes.extend(self.adjacent(v));
+                self.conservative(&nodes)
+            })
+        {
+            self.move_state[m] = MoveState::Coalesced;
+            self.combine(u, v);
+            self.add_worklist(u);
+        } else {
+            self.move_state[m] = MoveState::Active;
+        }
+    }
+
+    fn combine(&mut self, u: usize, v: usize) {
+        self.state[v] = NodeState::Coalesced;
+        self.alias[v] = u;
+        self.spill_cost[u] += self.spill_cost[v];
+        let v_moves = self.move_list[v].clone();
+        self.move_list[u].extend(v_moves);
+        self.enable_moves(&[v]);
+        for t in self.adjacent(v) {
+            self.add_edge(t, u);
+            self.decrement_degree(t);
+        }
+        if self.degree[u] >= self.k && self.state[u] == NodeState::Freeze {
+            self.set_state(u, NodeState::Spill);
+        }
+    }
+
+    fn freeze(&mut self, u: usize) {
+        self.set_state(u, NodeState::Simplify);
+        self.freeze_moves(u);
+    }
+
+    fn freeze_moves(&mut self, u: usize) {
+        for m in self.node_moves(u) {
+            let (x, y) = self.moves[m];
+            let v = if self.get_alias(y) == self.get_alias(u) {
+                self.get_alias(x)
+            } else {
+                self.get_alias(y)
+            };
+            self.move_state[m] = MoveState::Frozen;
+            if !self.is_precolored(v)
+                && self.state[v] == NodeState::Freeze
+                && !self.is_move_related(v)
+                && self.degree[v] < self.k
+            {
+                self.set_state(v, NodeState::Simplify);
+            }
+        }
+    }
+
+    /// Pick the node with the lowest spill cost per degree as a potential spill.
+    ///
+    /// Returns `false` if there is no node to spill.
+    fn select_spill(&mut self) -> bool {
+        let mut candidates = std::mem::take(&mut self.spill_worklist);
+        candidates.retain(|n| self.state[*n] == NodeState::Spill);
+        candidates.dedup();
+        let best = candidates.iter().copied().min_by(|a, b| {
+            let cost_a = self.spill_cost[*a] / self.degree[*a] as f64;
+            let cost_b = self.spill_cost[*b] / self.degree[*b] as f64;
+            cost_a.total_cmp(&cost_b)
+        });
+        self.spill_worklist = candidates;
+        match best {
+            Some(n) => {
+                self.set_state(n, NodeState::Simplify);
+                self.freeze_moves(n);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn assign_colors(&mut self) {
+        while let Some(n) = self.select_stack.pop() {
+            let mut ok = vec![true; self.k];
+            for w in &self.adj_list[n] {
+                let a = self.get_alias(*w);
+                if matches!(self.state[a], NodeState::Colored | NodeState::Precolored) {
+                    ok[self.color[a].unwrap()] = false;
+                }
+            }
+
+            // Prefer the color of a move-related node, so that the move can be removed.
+            let biased = self.move_list[n].iter().find_map(|m| {
+                let (x, y) = self.moves[*m];
+                let other = if self.get_alias(x) == n {
+                    self.get_alias(y)
+                } else {
+                    self.get_alias(x)
+                };
+                self.color[other].filter(|c| ok[*c] && self.state[other] != NodeState::Select)
+            });
+            match biased.or_else(|| (0..self.k).find(|c| ok[*c])) {
+                Some(color) => {
+                    self.state[n] = NodeState::Colored;
+                    self.color[n] = Some(color);
+                }
+                None => self.state[n] = NodeState::Spilled,
+            }
+        }
+        for n in self.k..self.state.len() {
+            if self.state[n] == NodeState::Coalesced {
+                let a = self.get_alias(n);
+                self.color[n] = self.color[a];
+            }
+        }
+    }
+}
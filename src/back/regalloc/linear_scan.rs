@@ -0,0 +1,504 @@
+//! Linear scan register allocation with interval splitting.
+//!
+//! Based on Wimmer and Mössenböck, "Optimized Interval Splitting in a Linear Scan Register
+//! Allocator". Every tracked value has a live interval over the positions of [`Numbering`], and
+//! intervals are allocated in the order of their start positions. When no register is free for a
+//! whole interval, the interval is split: the first part keeps a register and the rest is
+//! allocated later, or spilled to the stack until its next use.
+//!
+//! Calls clobber all caller saved registers, which is modelled by fixed intervals covering the
+//! clobber position of every call other than to an intrinsic. An interval live across a call
+//! therefore gets a callee saved register or is split before the call.
+//!
+//! Integer values and `float`s are scanned separately, with the registers of their class.
+
+use super::{is_caller_saved, AbiRegisters, Numbering, Segments, PARAM_POSITION};
+use crate::back::liveness::Liveness;
+use crate::back::register::Register;
+use koopa::ir::{BasicBlock, FunctionData, Value, ValueKind};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+/// Half-open ranges `[from, to)` of positions, disjoint and sorted.
+type Ranges = Vec<(u32, u32)>;
+
+struct Interval {
+    value: Value,
+    ranges: Ranges,
+    /// Positions where the value is used or defined, sorted.
+    uses: Vec<u32>,
+    /// Position from which this interval holds the value. Intervals created by splitting may
+    /// start with a lifetime hole.
+    split_pos: u32,
+    reg: Option<Register>,
+}
+
+/// A register that is good for a value, because the value is moved from or to it.
+enum Hint {
+    Register(Register),
+    Value(Value),
+}
+
+struct LinearScan<'a> {
+    /// Registers that can be assigned, in the order of preference.
+    registers: &'a [Register],
+    intervals: Vec<Interval>,
+    /// Positions where the caller saved registers are clobbered, indexed like `registers`.
+    fixed: Vec<Ranges>,
+    hints: HashMap<Value, Vec<Hint>>,
+    /// The last register assigned to every value.
+    assigned: HashMap<Value, Register>,
+    unhandled: BinaryHeap<Reverse<(u32, usize)>>,
+    active: Vec<usize>,
+    inactive: Vec<usize>,
+    /// Positions where a value is split while it is live, as `(position, value)`.
+    splits: Vec<(u32, Value)>,
+}
+
+/// Get the first position covered by both ranges.
+fn next_intersection(a: &[(u32, u32)], b: &[(u32, u32)]) -> Option<u32> {
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        let from = a[i].0.max(b[j].0);
+        if from < a[i].1.min(b[j].1) {
+            return Some(from);
+        }
+        if a[i].1 <= b[j].1 {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    None
+}
+
+/// Align a position down to the start of an instruction, where moves can be inserted.
+fn align(position: u32) -> u32 {
+    position & !3
+}
+
+/// Allocate registers to some of the tracked values of a function by linear scan.
+///
+/// Returns the locations of every value and the positions where values are split while live.
+pub fn allocate(
+    func_data: &FunctionData,
+    liveness: &Liveness,
+    numbering: &Numbering,
+    abi: &AbiRegisters,
+    registers: &[Register],
+    values: &[Value],
+) -> (HashMap<Value, Segments>, Vec<(u32, Value)>) {
+    let mut scan = LinearScan::new(func_data, liveness, numbering, abi, registers, values);
+    scan.run();
+
+    let mut segments: HashMap<Value, Segments> = HashMap::new();
+    for interval in &scan.intervals {
+        segments
+            .entry(interval.value)
+            .or_default()
+            .push((interval.split_pos, interval.reg));
+    }
+    for value_segments in segments.values_mut() {
+        value_segments.sort_by_key(|(pos, _)| *pos);
+    }
+    (segments, scan.splits)
+}
+
+/// Hint the parameters of a block and the arguments passed to them to share registers.
+fn block_arg_hints(
+    func_data: &FunctionData,
+    hints: &mut HashMap<Value, Vec<Hint>>,
+    target: BasicBlock,
+    args: &[Value],
+) {
+    for (param, arg) in func_data.dfg().bb(target).params().iter().zip(args) {
+        hints.entry(*param).or_default().push(Hint::Value(*arg));
+        hints.entry(*arg).or_default().push(Hint::Value(*param));
+    }
+}
+
+impl<'a> LinearScan<'a> {
+    fn new(
+        func_data: &FunctionData,
+        liveness: &Liveness,
+        numbering: &Numbering,
+        abi: &AbiRegisters,
+        registers: &'a [Register],
+        values: &[Value],
+    ) -> Self {
+        let mut ranges: HashMap<Value, Ranges> = HashMap::new();
+        let mut uses: HashMap<Value, Vec<u32>> = HashMap::new();
+        let mut fixed = vec![vec![]; registers.len()];
+        let mut hints: HashMap<Value, Vec<Hint>> = HashMap::new();
+
+        for (bb, node) in func_data.layout().bbs() {
+            let (from, to) = numbering.block_range(*bb);
+            // End positions of the values live at the current position.
+            let mut open: HashMap<Value, u32> =
+                liveness.live_out(*bb).iter().map(|v| (*v, to)).collect();
+            let insts: Vec<Value> = node.insts().keys().copied().collect();
+            for inst in insts.iter().rev() {
+                let pos = numbering.position(*inst);
+                if let Some(def) = liveness.def(func_data, *inst) {
+                    let end = open.remove(&def).unwrap_or(pos + 3);
+                    ranges.entry(def).or_default().push((pos + 2, end));
+                    uses.entry(def).or_default().push(pos + 2);
+                }
+                match func_data.dfg().value(*inst).kind() {
+                    ValueKind::Call(call) if liveness.is_call(*inst) => {
+                        for (i, reg) in registers.iter().enumerate() {
+                            if is_caller_saved(*reg) {
+                                fixed[i].push((pos + 1, pos + 2));
+                            }
+                        }
+                        for (arg, reg) in call.args().iter().zip(abi.args(*inst)) {
+                            if let Some(reg) = reg {
+                                hints.entry(*arg).or_default().push(Hint::Register(*reg));
+                            }
+                        }
+                        let result = abi.result(*inst);
+                        hints.entry(*inst).or_default().push(Hint::Register(result));
+                    }
+                    ValueKind::Return(ret) => {
+                        if let Some(value) = ret.value() {
+                            hints
+                                .entry(value)
+                                .or_default()
+                                .push(Hint::Register(abi.ret));
+                        }
+                    }
+                    ValueKind::Jump(jump) => {
+                        block_arg_hints(func_data, &mut hints, jump.target(), jump.args());
+                    }
+                    ValueKind::Branch(branch) => {
+                        block_arg_hints(
+                            func_data,
+                            &mut hints,
+                            branch.true_bb(),
+                            branch.true_args(),
+                        );
+                        block_arg_hints(
+                            func_data,
+                            &mut hints,
+                            branch.false_bb(),
+                            branch.false_args(),
+                        );
+                    }
+                    _ => {}
+                }
+                if let Some((dest, src)) = liveness.copy(func_data, *inst) {
+                    hints.entry(dest).or_default().push(Hint::Value(src));
+                    hints.entry(src).or_default().push(Hint::Value(dest));
+                }
+                for value in liveness.uses(func_data, *inst) {
+                    open.entry(value).or_insert(pos + 1);
+                    uses.entry(value).or_default().push(pos);
+                }
+            }
+            // Block parameters are defined right before the block.
+            for param in func_data.dfg().bb(*bb).params() {
+                if let Some(end) = open.remove(param) {
+                    ranges.entry(*param).or_default().push((from - 2, end));
+                    uses.entry(*param).or_default().push(from - 2);
+                }
+            }
+            for (value, end) in open {
+                ranges.entry(value).or_default().push((from, end));
+            }
+        }
+
+        // Parameters are defined before the entry block.
+        if let Some(entry) = func_data.layout().entry_bb() {
+            let (entry_start, _) = numbering.block_range(entry);
+            for (param, reg) in func_data.params().iter().zip(&abi.params) {
+                if let Some(reg) = reg {
+                    hints.entry(*param).or_default().push(Hint::Register(*reg));
+                }
+            }
+            for param in func_data.params() {
+                if let Some(param_ranges) = ranges.get_mut(param) {
+                    param_ranges.push((PARAM_POSITION, entry_start));
+                    uses.entry(*param).or_default().push(PARAM_POSITION);
+                }
+            }
+        }
+
+        for reg_ranges in &mut fixed {
+            reg_ranges.sort();
+        }
+
+        let mut scan = Self {
+            registers,
+            intervals: vec![],
+            fixed,
+            hints,
+            assigned: HashMap::new(),
+            unhandled: BinaryHeap::new(),
+            active: vec![],
+            inactive: vec![],
+            splits: vec![],
+        };
+        for value in values.iter().copied() {
+            let mut value_ranges = match ranges.remove(&value) {
+                Some(value_ranges) => value_ranges,
+                None => continue,
+            };
+            value_ranges.sort();
+            let mut merged: Ranges = vec![];
+            for (from, to) in value_ranges {
+                match merged.last_mut() {
+                    Some(last) if from <= last.1 => last.1 = last.1.max(to),
+                    _ => merged.push((from, to)),
+                }
+            }
+            let mut value_uses = uses.remove(&value).unwrap_or_default();
+            value_uses.sort();
+            value_uses.dedup();
+            let index = scan.intervals.len();
+            scan.unhandled.push(Reverse((merged[0].0, index)));
+            scan.intervals.push(Interval {
+                value,
+                ranges: merged,
+                uses: value_uses,
+                split_pos: 0,
+                reg: None,
+            });
+        }
+        scan
+    }
+
+    /// Get the index of a register, `None` if it is in another class.
+    fn reg_index(&self, reg: Register) -> Option<usize> {
+        self.registers.iter().position(|r| *r == reg)
+    }
+
+    fn start(&self, i: usize) -> u32 {
+        self.intervals[i].ranges[0].0
+    }
+
+    /// Get the index of the register assigned to an interval.
+    fn index_of(&self, i: usize) -> usize {
+        self.reg_index(self.intervals[i].reg.unwrap()).unwrap()
+    }
+
+    fn end(&self, i: usize) -> u32 {
+        self.intervals[i].ranges.last().unwrap().1
+    }
+
+    fn covers(&self, i: usize, position: u32) -> bool {
+        self.intervals[i]
+            .ranges
+            .iter()
+            .any(|(from, to)| *from <= position && position < *to)
+    }
+
+    /// Get the first use of an interval at or after a position.
+    fn next_use(&self, i: usize, position: u32) -> u32 {
+        let uses = &self.intervals[i].uses;
+        let k = uses.partition_point(|p| *p < position);
+        uses.get(k).copied().unwrap_or(u32::MAX)
+    }
+
+    fn run(&mut self) {
+        while let Some(Reverse((position, current))) = self.unhandled.pop() {
+            let active = std::mem::take(&mut self.active);
+            let inactive = std::mem::take(&mut self.inactive);
+            for i in active.into_iter().chain(inactive) {
+                if self.end(i) <= position {
+                    continue;
+                }
+                if self.covers(i, position) {
+                    self.active.push(i);
+                } else {
+                    self.inactive.push(i);
+                }
+            }
+
+            if !self.try_allocate_free_reg(current) {
+                self.allocate_blocked_reg(current);
+            }
+            if let Some(reg) = self.intervals[current].reg {
+                self.assigned.insert(self.intervals[current].value, reg);
+                self.active.push(current);
+            }
+        }
+    }
+
+    /// Get the registers of the class of an interval hinted for it.
+    fn hinted_registers(&self, i: usize) -> Vec<Register> {
+        let hints = match self.hints.get(&self.intervals[i].value) {
+            Some(hints) => hints,
+            None => return vec![],
+        };
+        hints
+            .iter()
+            .filter_map(|hint| match hint {
+                Hint::Register(reg) => Some(*reg),
+                Hint::Value(value) => self.assigned.get(value).copied(),
+            })
+            .filter(|reg| self.registers.contains(reg))
+            .collect()
+    }
+
+    /// Split an interval at a position and return the new interval for the rest.
+    fn split(&mut self, i: usize, position: u32) -> usize {
+        let interval = &mut self.intervals[i];
+        let k = interval
+            .ranges
+            .iter()
+            .position(|(_, to)| *to > position)
+            .unwrap();
+        let (from, to) = interval.ranges[k];
+        let ranges = if from < position {
+            // The value is live across the split position, so it has to be moved there.
+            self.splits.push((position, interval.value));
+            interval.ranges[k].1 = position;
+            let mut ranges = vec![(position, to)];
+            ranges.extend(interval.ranges.drain(k + 1..));
+            ranges
+        } else {
+            interval.ranges.drain(k..).collect()
+        };
+        let k = interval.uses.partition_point(|p| *p < position);
+        let uses = interval.uses.split_off(k);
+        let child = Interval {
+            value: interval.value,
+            ranges,
+            uses,
+            split_pos: position,
+            reg: None,
+        };
+        self.intervals.push(child);
+        self.intervals.len() - 1
+    }
+
+    /// Spill an interval until its first use after a position, the rest is allocated again.
+    fn spill(&mut self, i: usize, position: u32) {
+        self.intervals[i].reg = None;
+        let next_use = self.next_use(i, position + 1);
+        if next_use != u32::MAX {
+            let child = self.split(i, next_use);
+            self.unhandled.push(Reverse((self.start(child), child)));
+        }
+    }
+
+    /// Try to find a register that is free at the start of an interval.
+    ///
+    /// If the register is taken before the interval ends, the interval is split there.
+    fn try_allocate_free_reg(&mut self, current: usize) -> bool {
+        let mut free_until = vec![u32::MAX; self.registers.len()];
+        for i in &self.active {
+            free_until[self.index_of(*i)] = 0;
+        }
+        let ranges = &self.intervals[current].ranges;
+        for i in &self.inactive {
+            if let Some(pos) = next_intersection(&self.intervals[*i].ranges, ranges) {
+                let r = self.index_of(*i);
+                free_until[r] = free_until[r].min(pos);
+            }
+        }
+        for (r, fixed) in self.fixed.iter().enumerate() {
+            if let Some(pos) = next_intersection(fixed, ranges) {
+                free_until[r] = free_until[r].min(pos);
+            }
+        }
+
+        let start = self.start(current);
+        let end = self.end(current);
+        let whole = self
+            .hinted_registers(current)
+            .into_iter()
+            .chain(self.registers.iter().copied())
+            .find(|reg| free_until[self.reg_index(*reg).unwrap()] >= end);
+        if let Some(reg) = whole {
+            self.intervals[current].reg = Some(reg);
+            return true;
+        }
+
+        // Use the register that is free for the longest time for the first part.
+        let mut best = 0;
+        for r in 1..self.registers.len() {
+            if free_until[r] > free_until[best] {
+                best = r;
+            }
+        }
+        let split_pos = align(free_until[best]);
+        if split_pos <= start {
+            return false;
+        }
+        self.intervals[current].reg = Some(self.registers[best]);
+        let child = self.split(current, split_pos);
+        self.unhandled.push(Reverse((self.start(child), child)));
+        true
+    }
+
+    /// Allocate a register when none is free, by spilling the interval whose next use is the
+    /// furthest away.
+    fn allocate_blocked_reg(&mut self, current: usize) {
+        let start = self.start(current);
+        let mut use_pos = vec![u32::MAX; self.registers.len()];
+        let mut block_pos = vec![u32::MAX; self.registers.len()];
+        for i in &self.active {
+            let r = self.index_of(*i);
+            use_pos[r] = use_pos[r].min(self.next_use(*i, start));
+        }
+        let ranges = &self.intervals[current].ranges;
+        for i in &self.inactive {
+            if next_intersection(&self.intervals[*i].ranges, ranges).is_some() {
+                let r = self.index_of(*i);
+                use_pos[r] = use_pos[r].min(self.next_use(*i, start));
+            }
+        }
+        for (r, fixed) in self.fixed.iter().enumerate() {
+            if let Some(pos) = next_intersection(fixed, ranges) {
+                block_pos[r] = block_pos[r].min(pos);
+                use_pos[r] = use_pos[r].min(pos);
+            }
+        }
+
+        let mut best = 0;
+        for r in 1..self.registers.len() {
+            if use_pos[r] > use_pos[best] {
+                best = r;
+            }
+        }
+        if use_pos[best] < self.next_use(current, start) || align(block_pos[best]) <= start {
+            // All other intervals are used first.
+            self.spill(current, start);
+            return;
+        }
+
+        let reg = self.registers[best];
+        self.intervals[current].reg = Some(reg);
+        // Intervals in the register are spilled from the start of the current one.
+        let split_pos = align(start);
+        let ranges = &self.intervals[current].ranges;
+        let evicted: Vec<usize> = self
+            .active
+            .iter()
+            .chain(
+                self.inactive
+                    .iter()
+                    .filter(|i| next_intersection(&self.intervals[**i].ranges, ranges).is_some()),
+            )
+            .copied()
+            .filter(|i| self.intervals[*i].reg == Some(reg))
+            .collect();
+        self.active.retain(|i| !evicted.contains(i));
+        self.inactive.retain(|i| !evicted.contains(i));
+        for i in evicted {
+            if self.start(i) >= split_pos {
+                self.spill(i, start);
+            } else {
+                let child = self.split(i, split_pos);
+                self.spill(child, start);
+            }
+        }
+
+        // The register is clobbered by a call before the interval ends.
+        if block_pos[best] < self.end(current) {
+            let child = self.split(current, align(block_pos[best]));
+            self.unhandled.push(Reverse((self.start(child), child)));
+        }
+    }
+}
@@ -2,6 +2,7 @@
 
 use std::fmt::Display;
 
+/// An integer or a floating-point register.
 #[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
 pub struct Register {
     name: &'static str,
@@ -60,4 +61,66 @@ regs!(
     T4, t4;
     T5, t5;
     T6, t6;
+    FT0, ft0;
+    FT1, ft1;
+    FT2, ft2;
+    FT3, ft3;
+    FT4, ft4;
+    FT5, ft5;
+    FT6, ft6;
+    FT7, ft7;
+    FS0, fs0;
+    FS1, fs1;
+    FA0, fa0;
+    FA1, fa1;
+    FA2, fa2;
+    FA3, fa3;
+    FA4, fa4;
+    FA5, fa5;
+    FA6, fa6;
+    FA7, fa7;
+    FS2, fs2;
+    FS3, fs3;
+    FS4, fs4;
+    FS5, fs5;
+    FS6, fs6;
+    FS7, fs7;
+    FS8, fs8;
+    FS9, fs9;
+    FS10, fs10;
+    FS11, fs11;
+    FT8, ft8;
+    FT9, ft9;
+    FT10, ft10;
+    FT11, ft11;
 );
+
+/// Integer registers in the order of their numbers.
+const REGISTERS: [Register; 32] = [
+    ZERO, RA, SP, GP, TP, T0, T1, T2, FP, S1, A0, A1, A2, A3, A4, A5, A6, A7, S2, S3, S4, S5, S6,
+    S7, S8, S9, S10, S11, T3, T4, T5, T6,
+];
+
+/// Floating-point registers in the order of their numbers.
+const F_REGISTERS: [Register; 32] = [
+    FT0, FT1, FT2, FT3, FT4, FT5, FT6, FT7, FS0, FS1, FA0, FA1, FA2, FA3, FA4, FA5, FA6, FA7, FS2,
+    FS3, FS4, FS5, FS6, FS7, FS8, FS9, FS10, FS11, FT8, FT9, FT10, FT11,
+];
+
+impl Register {
+    /// Get the number of the register in its register file, from 0 for `zero` to 31 for `t6`,
+    /// and from 0 for `ft0` to 31 for `ft11`.
+    pub fn index(self) -> usize {
+        let file = if self.is_float() {
+            &F_REGISTERS
+        } else {
+            &REGISTERS
+        };
+        file.iter().position(|reg| *reg == self).unwrap()
+    }
+
+    /// Check if the register is a floating-point register.
+    pub fn is_float(self) -> bool {
+        F_REGISTERS.contains(&self)
+    }
+}
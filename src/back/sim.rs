@@ -0,0 +1,476 @@
+//! Simulator of RV32IMF assembly.
+//!
+//! Runs an [`AsmProgram`] in-process, executing its instruction objects rather than assembling
+//! their text. Global variables are laid out from address [`DATA_BASE`] in the order they are
+//! declared, the stack grows down from the end of the memory, and calls to the runtime library
+//! are handled by the host.
+//!
+//! Besides the exit code, the simulator counts the executed instructions, which is a
+//! deterministic measure of the performance of the generated code. Pseudo-instructions count as
+//! the instructions they expand to: `la` and `call` as two, `li` as one or two depending on its
+//! immediate, and loads and stores with an offset out of 12 bits as the `li`, `add` and access
+//! they are dumped as. Calls to the runtime library count as the `call` only, and overwrite the
+//! caller saved registers other than `a0` and `fa0`, which code must not expect to be preserved.
+//!
+//! Floating-point instructions are evaluated like the intrinsics they are lowered from, see
+//! [`Intrinsic::eval`].
+
+use crate::back::inst::*;
+use crate::back::program::AsmProgram;
+use crate::back::register::*;
+use crate::front::ir::builtin::Intrinsic;
+use crate::util::runtime::Runtime;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+/// Address of the first global variable. Lower addresses are never valid.
+const DATA_BASE: u32 = 0x1000;
+/// Size of the memory in bytes, the stack pointer starting at its end.
+const MEMORY_SIZE: u32 = 1 << 28;
+/// Return address of `main`, which is not the address of any instruction.
+const EXIT_ADDRESS: i32 = -1;
+/// Value left in the caller saved registers by calls to the runtime library.
+const CLOBBERED: i32 = 0x0bad_0bad;
+const CALLER_SAVED: [Register; 33] = [
+    T0, T1, T2, T3, T4, T5, T6, A1, A2, A3, A4, A5, A6, A7, FT0, FT1, FT2, FT3, FT4, FT5, FT6, FT7,
+    FT8, FT9, FT10, FT11, FA1, FA2, FA3, FA4, FA5, FA6, FA7,
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimResult {
+    /// The value returned by `main`.
+    pub exit_code: i32,
+    /// The number of instructions executed.
+    pub insts: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimError {
+    NoMain,
+    UnknownLabel(String),
+    /// A load or store at an address out of the memory, in the given function.
+    OutOfBounds(String, u32),
+    /// A load or store at an address that is not a multiple of 4, in the given function.
+    Misaligned(String, u32),
+    Output(String),
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimError::NoMain => write!(f, "the program has no `main` function"),
+            SimError::UnknownLabel(label) => write!(f, "unknown label `{}`", label),
+            SimError::OutOfBounds(func, address) => {
+                write!(
+                    f,
+                    "memory access out of bounds at {:#x} in {}",
+                    address, func
+                )
+            }
+            SimError::Misaligned(func, address) => {
+                write!(f, "misaligned memory access at {:#x} in {}", address, func)
+            }
+            SimError::Output(e) => write!(f, "failed to write the output: {}", e),
+        }
+    }
+}
+
+/// Run the `main` function of a program.
+pub fn simulate(
+    program: &AsmProgram,
+    input: &[u8],
+    output: &mut impl Write,
+) -> Result<SimResult, SimError> {
+    let mut sim = Simulator::new(program, input, output);
+    let result = sim.run();
+    sim.runtime.report_total_time();
+    result
+}
+
+struct Simulator<'a, W: Write> {
+    /// Instructions of all functions, a code address being an index in it.
+    code: Vec<&'a AsmInst>,
+    /// Code address of every function and block.
+    labels: HashMap<&'a str, usize>,
+    /// Function starting at every code address that is a function entry.
+    funcs: Vec<(usize, &'a str)>,
+    /// Address of every global variable.
+    symbols: HashMap<&'a str, u32>,
+    regs: [i32; 32],
+    /// The bits of the floating-point registers.
+    fregs: [i32; 32],
+    memory: Vec<i32>,
+    pc: usize,
+    insts: u64,
+    runtime: Runtime<'a, W>,
+}
+
+impl<'a, W: Write> Simulator<'a, W> {
+    fn new(program: &'a AsmProgram, input: &'a [u8], output: &'a mut W) -> Self {
+        let mut code = vec![];
+        let mut labels = HashMap::new();
+        let mut funcs = vec![];
+        for func in program.funcs() {
+            labels.insert(func.name(), code.len());
+            funcs.push((code.len(), func.name()));
+            for block in func.blocks() {
+                labels.insert(block.name(), code.len());
+                code.extend(block.insts());
+            }
+        }
+
+        let mut memory = vec![0; (MEMORY_SIZE / 4) as usize];
+        let mut symbols = HashMap::new();
+        let mut address = DATA_BASE;
+        for var in program.var_decls() {
+            symbols.insert(var.name(), address);
+            for (i, value) in var.init().unwrap_or(&[]).iter().enumerate() {
+                memory[address as usize / 4 + i] = *value;
+            }
+            address += (var.size() as u32).next_multiple_of(4);
+        }
+
+        let mut regs = [0; 32];
+        regs[SP.index()] = MEMORY_SIZE as i32;
+        regs[RA.index()] = EXIT_ADDRESS;
+        Self {
+            code,
+            labels,
+            funcs,
+            symbols,
+            regs,
+            fregs: [0; 32],
+            memory,
+            pc: 0,
+            insts: 0,
+            runtime: Runtime::new(input, output),
+        }
+    }
+
+    fn run(&mut self) -> Result<SimResult, SimError> {
+        self.pc = *self.labels.get("main").ok_or(SimError::NoMain)?;
+        loop {
+            if self.pc as i32 == EXIT_ADDRESS {
+                return Ok(SimResult {
+                    exit_code: self.reg(A0),
+                    insts: self.insts,
+                });
+            }
+            let inst = self.code[self.pc];
+            self.pc += 1;
+            self.step(inst)?;
+        }
+    }
+
+    fn step(&mut self, inst: &'a AsmInst) -> Result<(), SimError> {
+        self.insts += 1;
+        match inst {
+            AsmInst::Beqz(beqz) => {
+                if self.reg(beqz.rs) == 0 {
+                    self.pc = self.label(&beqz.label)?;
+                }
+            }
+            AsmInst::Bnez(bnez) => {
+                if self.reg(bnez.rs) != 0 {
+                    self.pc = self.label(&bnez.label)?;
+                }
+            }
+            AsmInst::Jmp(jmp) => self.pc = self.label(&jmp.label)?,
+            AsmInst::Call(call) => {
+                self.insts += 1;
+                self.set_reg(RA, self.pc as i32);
+                match self.labels.get(call.label.as_str()) {
+                    Some(target) => self.pc = *target,
+                    None => self.call_runtime(&call.label)?,
+                }
+            }
+            AsmInst::Ret(_) => self.pc = self.reg(RA) as usize,
+            AsmInst::TailCall(tail_call) => match self.labels.get(tail_call.label.as_str()) {
+                Some(target) => self.pc = *target,
+                None => {
+                    self.call_runtime(&tail_call.label)?;
+                    self.pc = self.reg(RA) as usize;
+                }
+            },
+            AsmInst::Lw(lw) => {
+                self.insts += offset_insts(lw.offset);
+                let address = self.reg(lw.rs).wrapping_add(lw.offset);
+                let value = self.load(address)?;
+                self.set_reg(lw.rd, value);
+            }
+            AsmInst::Sw(sw) => {
+                self.insts += offset_insts(sw.offset);
+                let address = self.reg(sw.rs2).wrapping_add(sw.offset);
+                if offset_insts(sw.offset) > 0 {
+                    // The address is computed in t0.
+                    self.set_reg(T0, address);
+                }
+                self.store(address, self.reg(sw.rs1))?;
+            }
+            AsmInst::Flw(flw) => {
+                self.insts += offset_insts(flw.offset);
+                let address = self.reg(flw.rs).wrapping_add(flw.offset);
+                if offset_insts(flw.offset) > 0 {
+                    self.set_reg(T0, address);
+                }
+                let value = self.load(address)?;
+                self.set_reg(flw.fd, value);
+            }
+            AsmInst::Fsw(fsw) => {
+                self.insts += offset_insts(fsw.offset);
+                let address = self.reg(fsw.rs).wrapping_add(fsw.offset);
+                if offset_insts(fsw.offset) > 0 {
+                    self.set_reg(T0, address);
+                }
+                self.store(address, self.reg(fsw.fs))?;
+            }
+            AsmInst::Add(add) => self.binary(add.rd, add.rs1, add.rs2, i32::wrapping_add),
+            AsmInst::Addi(addi) => self.imm(addi.rd, addi.rs, addi.imm, i32::wrapping_add),
+            AsmInst::Sub(sub) => self.binary(sub.rd, sub.rs1, sub.rs2, i32::wrapping_sub),
+            AsmInst::SetLt(slt) => self.binary(slt.rd, slt.rs1, slt.rs2, |a, b| (a < b) as i32),
+            AsmInst::SetLtImm(slti) => self.imm(slti.rd, slti.rs, slti.imm, |a, b| (a < b) as i32),
+            AsmInst::SetGt(sgt) => self.binary(sgt.rd, sgt.rs1, sgt.rs2, |a, b| (a > b) as i32),
+            AsmInst::SetZero(seqz) => self.set_reg(seqz.rd, (self.reg(seqz.rs) == 0) as i32),
+            AsmInst::SetNonZero(snez) => self.set_reg(snez.rd, (self.reg(snez.rs) != 0) as i32),
+            AsmInst::Or(or) => self.binary(or.rd, or.rs1, or.rs2, |a, b| a | b),
+            AsmInst::Ori(ori) => self.imm(ori.rd, ori.rs, ori.imm, |a, b| a | b),
+            AsmInst::Xor(xor) => self.binary(xor.rd, xor.rs1, xor.rs2, |a, b| a ^ b),
+            AsmInst::Xori(xori) => self.imm(xori.rd, xori.rs, xori.imm, |a, b| a ^ b),
+            AsmInst::And(and) => self.binary(and.rd, and.rs1, and.rs2, |a, b| a & b),
+            AsmInst::Andi(andi) => self.imm(andi.rd, andi.rs, andi.imm, |a, b| a & b),
+            AsmInst::Sll(sll) => self.binary(sll.rd, sll.rs1, sll.rs2, shl),
+            AsmInst::Slli(slli) => self.imm(slli.rd, slli.rs, slli.imm, shl),
+            AsmInst::Srl(srl) => self.binary(srl.rd, srl.rs1, srl.rs2, shr),
+            AsmInst::Srli(srli) => self.imm(srli.rd, srli.rs, srli.imm, shr),
+            AsmInst::Sra(sra) => self.binary(sra.rd, sra.rs1, sra.rs2, sar),
+            AsmInst::Srai(srai) => self.imm(srai.rd, srai.rs, srai.imm, sar),
+            AsmInst::Mul(mul) => self.binary(mul.rd, mul.rs1, mul.rs2, i32::wrapping_mul),
+            AsmInst::Mulh(mulh) => self.binary(mulh.rd, mulh.rs1, mulh.rs2, |a, b| {
+                Intrinsic::MulHigh.eval(&[a, b])
+            }),
+            AsmInst::Div(div) => self.binary(div.rd, div.rs1, div.rs2, |a, b| match b {
+                0 => -1,
+                _ => a.wrapping_div(b),
+            }),
+            AsmInst::Rem(rem) => self.binary(rem.rd, rem.rs1, rem.rs2, |a, b| match b {
+                0 => a,
+                _ => a.wrapping_rem(b),
+            }),
+            AsmInst::LoadImm(li) => {
+                self.insts += li_insts(li.imm) - 1;
+                self.set_reg(li.rd, li.imm);
+            }
+            AsmInst::LoadLabel(la) => {
+                self.insts += 1;
+                let address = *self
+                    .symbols
+                    .get(la.label.as_str())
+                    .ok_or_else(|| SimError::UnknownLabel(la.label.clone()))?;
+                self.set_reg(la.rd, address as i32);
+            }
+            AsmInst::Move(mv) => self.set_reg(mv.rd, self.reg(mv.rs)),
+            AsmInst::FMove(fmv) => self.set_reg(fmv.fd, self.reg(fmv.fs)),
+            AsmInst::FmvWX(fmv) => self.set_reg(fmv.fd, self.reg(fmv.rs)),
+            AsmInst::FmvXW(fmv) => self.set_reg(fmv.rd, self.reg(fmv.fs)),
+            AsmInst::FAdd(fadd) => self.float(fadd.fd, fadd.fs1, fadd.fs2, Intrinsic::FAdd),
+            AsmInst::FSub(fsub) => self.float(fsub.fd, fsub.fs1, fsub.fs2, Intrinsic::FSub),
+            AsmInst::FMul(fmul) => self.float(fmul.fd, fmul.fs1, fmul.fs2, Intrinsic::FMul),
+            AsmInst::FDiv(fdiv) => self.float(fdiv.fd, fdiv.fs1, fdiv.fs2, Intrinsic::FDiv),
+            AsmInst::FLt(flt) => self.compare(flt.rd, flt.fs1, flt.fs2, Intrinsic::FLt),
+            AsmInst::FLe(fle) => self.compare(fle.rd, fle.fs1, fle.fs2, Intrinsic::FLe),
+            AsmInst::FEq(feq) => self.compare(feq.rd, feq.fs1, feq.fs2, Intrinsic::FEq),
+            AsmInst::FcvtSW(fcvt) => {
+                let value = Intrinsic::IntToFloat.eval(&[self.reg(fcvt.rs)]);
+                self.set_reg(fcvt.fd, value);
+            }
+            AsmInst::FcvtWS(fcvt) => {
+                let value = Intrinsic::FloatToInt.eval(&[self.reg(fcvt.fs)]);
+                self.set_reg(fcvt.rd, value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the value of an integer register or the bits of a floating-point register.
+    fn reg(&self, reg: Register) -> i32 {
+        if reg.is_float() {
+            self.fregs[reg.index()]
+        } else {
+            self.regs[reg.index()]
+        }
+    }
+
+    fn set_reg(&mut self, reg: Register, value: i32) {
+        if reg.is_float() {
+            self.fregs[reg.index()] = value;
+        } else if reg != ZERO {
+            self.regs[reg.index()] = value;
+        }
+    }
+
+    fn float(&mut self, fd: Register, fs1: Register, fs2: Register, op: Intrinsic) {
+        self.set_reg(fd, op.eval(&[self.reg(fs1), self.reg(fs2)]));
+    }
+
+    fn compare(&mut self, rd: Register, fs1: Register, fs2: Register, op: Intrinsic) {
+        self.set_reg(rd, op.eval(&[self.reg(fs1), self.reg(fs2)]));
+    }
+
+    fn binary(&mut self, rd: Register, rs1: Register, rs2: Register, op: fn(i32, i32) -> i32) {
+        self.set_reg(rd, op(self.reg(rs1), self.reg(rs2)));
+    }
+
+    fn imm(&mut self, rd: Register, rs: Register, imm: i32, op: fn(i32, i32) -> i32) {
+        self.set_reg(rd, op(self.reg(rs), imm));
+    }
+
+    fn label(&self, label: &str) -> Result<usize, SimError> {
+        self.labels
+            .get(label)
+            .copied()
+            .ok_or_else(|| SimError::UnknownLabel(label.to_string()))
+    }
+
+    /// Get the name of the function being executed.
+    fn func_name(&self) -> String {
+        let index = self.funcs.partition_point(|(start, _)| *start < self.pc);
+        self.funcs[index.saturating_sub(1)].1.to_string()
+    }
+
+    fn word_index(&self, address: i32) -> Result<usize, SimError> {
+        let address = address as u32;
+        if !(DATA_BASE..MEMORY_SIZE).contains(&address) {
+            return Err(SimError::OutOfBounds(self.func_name(), address));
+        }
+        if !address.is_multiple_of(4) {
+            return Err(SimError::Misaligned(self.func_name(), address));
+        }
+        Ok(address as usize / 4)
+    }
+
+    fn load(&self, address: i32) -> Result<i32, SimError> {
+        Ok(self.memory[self.word_index(address)?])
+    }
+
+    fn store(&mut self, address: i32, value: i32) -> Result<(), SimError> {
+        let index = self.word_index(address)?;
+        self.memory[index] = value;
+        Ok(())
+    }
+
+    /// Call a function of the runtime library, which takes its arguments from `a0`, `a1` and `fa0`
+    /// and returns its result in `a0` or `fa0`, as in the `ilp32f` ABI.
+    fn call_runtime(&mut self, name: &str) -> Result<(), SimError> {
+        let output_error = |e: io::Error| SimError::Output(e.to_string());
+        let (a0, a1, fa0) = (self.reg(A0), self.reg(A1), self.reg(FA0));
+        for reg in CALLER_SAVED {
+            self.set_reg(reg, CLOBBERED);
+        }
+        match name {
+            "getint" => {
+                let value = self.runtime.read_int();
+                self.set_reg(A0, value);
+            }
+            "getch" => {
+                let value = self.runtime.read_char();
+                self.set_reg(A0, value);
+            }
+            "getarray" => {
+                let values = self.runtime.read_array();
+                for (i, value) in values.iter().enumerate() {
+                    self.store(a0.wrapping_add(i as i32 * 4), *value)?;
+                }
+                self.set_reg(A0, values.len() as i32);
+            }
+            "getfloat" => {
+                let value = self.runtime.read_float();
+                self.set_reg(FA0, value.to_bits() as i32);
+            }
+            "getfarray" => {
+                let values = self.runtime.read_float_array();
+                for (i, value) in values.iter().enumerate() {
+                    self.store(a0.wrapping_add(i as i32 * 4), value.to_bits() as i32)?;
+                }
+                self.set_reg(A0, values.len() as i32);
+            }
+            "putint" => self.runtime.put_int(a0).map_err(output_error)?,
+            "putch" => self.runtime.put_char(a0).map_err(output_error)?,
+            "putarray" => {
+                let values = (0..a0.max(0))
+                    .map(|i| self.load(a1.wrapping_add(i * 4)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.runtime.put_array(&values).map_err(output_error)?;
+            }
+            "putfloat" => {
+                let value = f32::from_bits(fa0 as u32);
+                self.runtime.put_float(value).map_err(output_error)?;
+            }
+            "putfarray" => {
+                let values = (0..a0.max(0))
+                    .map(|i| Ok(f32::from_bits(self.load(a1.wrapping_add(i * 4))? as u32)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.runtime
+                    .put_float_array(&values)
+                    .map_err(output_error)?;
+            }
+            "starttime" => self.runtime.start_timer(),
+            "stoptime" => self.runtime.stop_timer(),
+            _ => return Err(SimError::UnknownLabel(name.to_string())),
+        }
+        Ok(())
+    }
+}
+
+/// Get the number of instructions of a `li`.
+fn li_insts(imm: i32) -> u64 {
+    if (-2048..=2047).contains(&imm) || imm & 0xfff == 0 {
+        1
+    } else {
+        2
+    }
+}
+
+/// Get the number of instructions added to a load or store to compute its address.
+fn offset_insts(offset: i32) -> u64 {
+    if (-2048..=2047).contains(&offset) {
+        0
+    } else {
+        li_insts(offset) + 1
+    }
+}
+
+fn shl(a: i32, b: i32) -> i32 {
+    a.wrapping_shl(b as u32)
+}
+
+fn shr(a: i32, b: i32) -> i32 {
+    (a as u32).wrapping_shr(b as u32) as i32
+}
+
+fn sar(a: i32, b: i32) -> i32 {
+    a.wrapping_shr(b as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::back::RegAllocStrategy;
+    use crate::util::testing::{compile, simulate_asm};
+
+    #[test]
+    fn test_sim() {
+        let input = "int a[3] = {1, 2};
+            int fib(int n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+            int main() { int n = getint(); a[2] = fib(n); putarray(3, a); return a[2] % 256; }";
+        for strategy in [
+            RegAllocStrategy::Greedy,
+            RegAllocStrategy::LinearScan,
+            RegAllocStrategy::GraphColoring,
+        ] {
+            let (result, output) = simulate_asm(compile(input), strategy, b"10");
+            assert_eq!(result.exit_code, 55);
+            assert!(result.insts > 177);
+            assert_eq!(output, "3: 1 2 55\n");
+        }
+    }
+}
@@ -1,27 +1,41 @@
 use crate::front::ast::CompUnit;
+use crate::front::check::check;
+use crate::front::diagnostic::Diagnostics;
 use crate::front::ir::builtin::generate_builtin_decl;
 use crate::front::ir::scope::Scope;
 use crate::front::ir::GenerateIR;
-use crate::util::logger::show_error;
+use crate::front::lint::Lints;
 use ir::context;
 use koopa::ir::Program;
 use lalrpop_util::lalrpop_mod;
 
 pub mod ast;
+pub mod check;
+pub mod diagnostic;
 pub mod ident;
 pub mod ir;
+pub mod lint;
 pub mod opt;
 pub mod parser_context;
 
 lalrpop_mod!(pub parser);
 
-pub fn generate_ir(comp_unit: CompUnit) -> Program {
+/// Check a parsed program and generate IR for it, along with the errors and warnings found in it.
+/// The IR is incomplete if there are errors.
+pub fn generate_ir(comp_unit: CompUnit, lints: &Lints) -> (Program, Diagnostics) {
+    let diagnostics = check(&comp_unit, lints);
+    if diagnostics.has_errors() {
+        return (Program::new(), diagnostics);
+    }
     let scope = Scope::new();
     let mut ctx = context::Context::new(scope);
-    generate_builtin_decl(&mut ctx.program, &mut ctx.func_table);
-    comp_unit.generate_ir(&mut ctx).unwrap_or_else(|e| {
-        show_error(&format!("{:?}", e), 2);
-    });
-    ctx.delete_and_link();
-    ctx.program()
+    ctx.diagnostics = diagnostics;
+    generate_builtin_decl(&mut ctx);
+    if let Err(e) = comp_unit.generate_ir(&mut ctx) {
+        ctx.diagnostics.push(e);
+    }
+    if !ctx.diagnostics.has_errors() {
+        ctx.delete_and_link();
+    }
+    ctx.finish()
 }
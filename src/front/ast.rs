@@ -1,6 +1,24 @@
 use koopa::ir::{BinaryOp, Type};
 use std::rc::Rc;
 
+/// Byte range of a node in the source file, as given by the parser.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// Get the range from the start of this span to the end of another one.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start, other.end)
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct CompUnit {
     pub items: Vec<GlobalItem>,
@@ -30,20 +48,27 @@ pub enum ConstDef {
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct NormalConstDef {
     pub name: String,
+    pub ty: DataType,
     pub value: ConstExpr,
+    /// Span of the name.
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct ArrayConstDef {
     pub name: String,
+    /// Type of the elements.
+    pub ty: DataType,
     pub shape: Vec<ConstExpr>,
     pub values: ConstArray,
+    /// Span of the name.
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum ConstArray {
     Val(ConstExpr),
-    Array(Vec<ConstArray>),
+    Array(Vec<ConstArray>, Span),
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
@@ -55,20 +80,27 @@ pub enum VarDef {
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct NormalVarDef {
     pub name: String,
+    pub ty: DataType,
     pub value: Option<Expr>,
+    /// Span of the name.
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct ArrayVarDef {
     pub name: String,
+    /// Type of the elements.
+    pub ty: DataType,
     pub shape: Vec<ConstExpr>,
     pub values: Option<ExprArray>,
+    /// Span of the name.
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum ExprArray {
     Val(Expr),
-    Array(Vec<ExprArray>),
+    Array(Vec<ExprArray>, Span),
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
@@ -80,6 +112,8 @@ pub struct FuncDef {
     pub params: Vec<Rc<FuncFParam>>,
     pub ret_type: DataType,
     pub body: Block,
+    /// Span of the return type, name and parameters, without the body.
+    pub span: Span,
 }
 
 /// Represent function parameter in declaration.
@@ -92,26 +126,35 @@ pub enum FuncFParam {
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct NormalFParam {
     pub name: String,
+    pub ty: DataType,
+    /// Span of the name.
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct ArrayFParam {
     pub name: String,
+    /// Type of the elements.
+    pub ty: DataType,
     /// Whether the array has a placeholder. For example, `int a[]` has a placeholder.
     pub placeholder: bool,
     pub shape: Vec<ConstExpr>,
+    /// Span of the name and the shape.
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum DataType {
     Void,
     Int,
+    Float,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct Block {
     pub id: i32,
     pub items: Vec<BlockItem>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
@@ -121,10 +164,20 @@ pub enum BlockItem {
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
-pub struct Break;
+pub struct Break {
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Continue {
+    pub span: Span,
+}
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
-pub struct Continue;
+pub struct Return {
+    pub value: Option<Expr>,
+    pub span: Span,
+}
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum Stmt {
@@ -133,21 +186,22 @@ pub enum Stmt {
     Block(Block),
     If(If),
     While(While),
-    Return(Option<Expr>),
+    Return(Return),
     Break(Break),
     Continue(Continue),
-    Empty,
+    Empty(Span),
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct Assign {
     pub target: LVal,
     pub value: Expr,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum LVal {
-    Var(String),
+    Var(String, Span),
     ArrayElem(ArrayElem),
 }
 
@@ -155,6 +209,7 @@ pub enum LVal {
 pub struct ArrayElem {
     pub name: String,
     pub indices: Vec<Expr>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
@@ -162,12 +217,14 @@ pub struct If {
     pub cond: LOrExpr,
     pub then_stmt: Rc<Stmt>,
     pub else_stmt: Option<Rc<Stmt>>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct While {
     pub cond: LOrExpr,
     pub body: Rc<Stmt>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
@@ -248,25 +305,200 @@ pub enum UnaryOp {
 pub enum UnaryExpr {
     PrimaryExpr(PrimaryExpr),
     FuncCall(FuncCall),
-    Unary(UnaryOp, Rc<UnaryExpr>),
+    Unary(UnaryOp, Rc<UnaryExpr>, Span),
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum PrimaryExpr {
-    Expr(Expr),
+    /// Parenthesized expression, with the span including the parentheses.
+    Expr(Expr, Span),
     LVal(LVal),
-    Number(i32),
+    Number(i32, Span),
+    /// Float literal, holding the bits of its value so that the tree can be compared and hashed.
+    FloatNumber(u32, Span),
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct FuncCall {
     pub name: String,
     pub args: Vec<Expr>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct ConstExpr(pub Rc<LOrExpr>);
 
+impl Decl {
+    /// Get the span of the name of the first definition.
+    pub fn span(&self) -> Span {
+        match self {
+            Decl::ConstDecl(defs) => match defs[0].as_ref() {
+                ConstDef::NormalConstDef(def) => def.span,
+                ConstDef::ArrayConstDef(def) => def.span,
+            },
+            Decl::VarDecl(defs) => match defs[0].as_ref() {
+                VarDef::NormalVarDef(def) => def.span,
+                VarDef::ArrayVarDef(def) => def.span,
+            },
+        }
+    }
+}
+
+impl ConstDef {
+    /// Set the type of the definition, which is written once for all the definitions of a
+    /// declaration.
+    pub fn with_type(mut self, ty: DataType) -> Self {
+        match &mut self {
+            ConstDef::NormalConstDef(def) => def.ty = ty,
+            ConstDef::ArrayConstDef(def) => def.ty = ty,
+        }
+        self
+    }
+}
+
+impl VarDef {
+    /// Set the type of the definition, which is written once for all the definitions of a
+    /// declaration.
+    pub fn with_type(mut self, ty: DataType) -> Self {
+        match &mut self {
+            VarDef::NormalVarDef(def) => def.ty = ty,
+            VarDef::ArrayVarDef(def) => def.ty = ty,
+        }
+        self
+    }
+}
+
+impl Stmt {
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Assign(assign) => assign.span,
+            Stmt::Expr(expr) => expr.span(),
+            Stmt::Block(block) => block.span,
+            Stmt::If(if_stmt) => if_stmt.span,
+            Stmt::While(while_stmt) => while_stmt.span,
+            Stmt::Return(ret) => ret.span,
+            Stmt::Break(break_stmt) => break_stmt.span,
+            Stmt::Continue(continue_stmt) => continue_stmt.span,
+            Stmt::Empty(span) => *span,
+        }
+    }
+}
+
+impl FuncFParam {
+    pub fn span(&self) -> Span {
+        match self {
+            FuncFParam::NormalFParam(param) => param.span,
+            FuncFParam::ArrayFParam(param) => param.span,
+        }
+    }
+
+    /// Get the type of the parameter, or of its elements for an array.
+    pub fn ty(&self) -> DataType {
+        match self {
+            FuncFParam::NormalFParam(param) => param.ty,
+            FuncFParam::ArrayFParam(param) => param.ty,
+        }
+    }
+}
+
+impl LVal {
+    pub fn span(&self) -> Span {
+        match self {
+            LVal::Var(_, span) => *span,
+            LVal::ArrayElem(array_elem) => array_elem.span,
+        }
+    }
+}
+
+impl Expr {
+    pub fn span(&self) -> Span {
+        self.0.span()
+    }
+}
+
+impl ConstExpr {
+    pub fn span(&self) -> Span {
+        self.0.span()
+    }
+}
+
+// Binary expressions have no tokens around their operands, so their spans are computed from the
+// operands instead of being stored.
+
+impl LOrExpr {
+    pub fn span(&self) -> Span {
+        match self {
+            LOrExpr::LAndExpr(expr) => expr.span(),
+            LOrExpr::Or(lhs, rhs) => lhs.span().to(rhs.span()),
+        }
+    }
+}
+
+impl LAndExpr {
+    pub fn span(&self) -> Span {
+        match self {
+            LAndExpr::EqExpr(expr) => expr.span(),
+            LAndExpr::And(lhs, rhs) => lhs.span().to(rhs.span()),
+        }
+    }
+}
+
+impl EqExpr {
+    pub fn span(&self) -> Span {
+        match self {
+            EqExpr::RelExpr(expr) => expr.span(),
+            EqExpr::Eq(lhs, _, rhs) => lhs.span().to(rhs.span()),
+        }
+    }
+}
+
+impl RelExpr {
+    pub fn span(&self) -> Span {
+        match self {
+            RelExpr::AddExpr(expr) => expr.span(),
+            RelExpr::Rel(lhs, _, rhs) => lhs.span().to(rhs.span()),
+        }
+    }
+}
+
+impl AddExpr {
+    pub fn span(&self) -> Span {
+        match self {
+            AddExpr::MulExpr(expr) => expr.span(),
+            AddExpr::Add(lhs, _, rhs) => lhs.span().to(rhs.span()),
+        }
+    }
+}
+
+impl MulExpr {
+    pub fn span(&self) -> Span {
+        match self {
+            MulExpr::UnaryExpr(expr) => expr.span(),
+            MulExpr::Mul(lhs, _, rhs) => lhs.span().to(rhs.span()),
+        }
+    }
+}
+
+impl UnaryExpr {
+    pub fn span(&self) -> Span {
+        match self {
+            UnaryExpr::PrimaryExpr(expr) => expr.span(),
+            UnaryExpr::FuncCall(call) => call.span,
+            UnaryExpr::Unary(_, _, span) => *span,
+        }
+    }
+}
+
+impl PrimaryExpr {
+    pub fn span(&self) -> Span {
+        match self {
+            PrimaryExpr::Expr(_, span) => *span,
+            PrimaryExpr::LVal(lval) => lval.span(),
+            PrimaryExpr::Number(_, span) | PrimaryExpr::FloatNumber(_, span) => *span,
+        }
+    }
+}
+
 impl Into<BinaryOp> for RelOp {
     fn into(self) -> BinaryOp {
         match self {
@@ -310,7 +542,8 @@ impl Into<Type> for DataType {
     fn into(self) -> Type {
         match self {
             DataType::Void => Type::get_unit(),
-            DataType::Int => Type::get_i32(),
+            // Koopa IR has no float type, `float`s are held as their bits in `i32`s.
+            DataType::Int | DataType::Float => Type::get_i32(),
         }
     }
 }
@@ -319,7 +552,7 @@ impl Default for Expr {
     fn default() -> Self {
         Expr(Rc::new(LOrExpr::LAndExpr(LAndExpr::EqExpr(
             EqExpr::RelExpr(RelExpr::AddExpr(AddExpr::MulExpr(MulExpr::UnaryExpr(
-                UnaryExpr::PrimaryExpr(PrimaryExpr::Number(0)),
+                UnaryExpr::PrimaryExpr(PrimaryExpr::Number(0, Span::default())),
             )))),
         ))))
     }
@@ -6,7 +6,7 @@ use crate::parser;
 fn test_number() {
     let number_parser = parser::NumberParser::new();
 
-    let context = ParserContext::new("", "");
+    let context = ParserContext::new();
 
     assert_eq!(number_parser.parse(&mut context.clone(), "123"), Ok(123));
     assert_eq!(number_parser.parse(&mut context.clone(), "0"), Ok(0));
@@ -20,7 +20,7 @@ fn test_number() {
 fn test_identifier() {
     let identifier_parser = parser::IdentParser::new();
 
-    let context = ParserContext::new("", "");
+    let context = ParserContext::new();
 
     assert_eq!(
         identifier_parser.parse(&mut context.clone(), "a"),
@@ -40,7 +40,7 @@ fn test_identifier() {
 #[test]
 fn test_expr() {
     let expr_parser = parser::ExprParser::new();
-    let mut context = ParserContext::new("", "");
+    let mut context = ParserContext::new();
 
     let input = "1 + 2 * -a";
     let result = expr_parser.parse(&mut context, input);
@@ -49,30 +49,58 @@ fn test_expr() {
         Ok(Expr(Rc::new(LOrExpr::LAndExpr(LAndExpr::EqExpr(
             EqExpr::RelExpr(RelExpr::AddExpr(AddExpr::Add(
                 Rc::new(AddExpr::MulExpr(MulExpr::UnaryExpr(
-                    UnaryExpr::PrimaryExpr(PrimaryExpr::Number(1))
+                    UnaryExpr::PrimaryExpr(PrimaryExpr::Number(1, Span::new(0, 1)))
                 ))),
                 AddOp::Add,
                 Rc::new(MulExpr::Mul(
                     Rc::new(MulExpr::UnaryExpr(UnaryExpr::PrimaryExpr(
-                        PrimaryExpr::Number(2)
+                        PrimaryExpr::Number(2, Span::new(4, 5))
                     ))),
                     MulOp::Mul,
                     Rc::new(UnaryExpr::Unary(
                         UnaryOp::Neg,
                         Rc::new(UnaryExpr::PrimaryExpr(PrimaryExpr::LVal(LVal::Var(
-                            "a".to_string()
-                        ))))
+                            "a".to_string(),
+                            Span::new(9, 10)
+                        )))),
+                        Span::new(8, 10)
                     ))
                 ))
             )))
         )))))
     );
+    assert_eq!(result.unwrap().span(), Span::new(0, 10));
 }
 
-fn build_number(n: i32) -> LOrExpr {
+#[test]
+fn test_span() {
+    let expr_parser = parser::ExprParser::new();
+    let mut context = ParserContext::new();
+
+    let input = "(a + f(1)) * b[2]";
+    let result = expr_parser.parse(&mut context, input).unwrap();
+    assert_eq!(result.span(), Span::new(0, 17));
+    match result.0.as_ref() {
+        LOrExpr::LAndExpr(LAndExpr::EqExpr(EqExpr::RelExpr(RelExpr::AddExpr(
+            AddExpr::MulExpr(MulExpr::Mul(lhs, _, rhs)),
+        )))) => {
+            assert_eq!(lhs.span(), Span::new(0, 10));
+            assert_eq!(rhs.span(), Span::new(13, 17));
+        }
+        _ => panic!("unexpected expression {:?}", result),
+    }
+}
+
+/// Get the span of `s` in the first occurrence of `pattern` in `input`.
+fn span_of(input: &str, pattern: &str, s: &str) -> Span {
+    let start = input.find(pattern).unwrap() + pattern.find(s).unwrap();
+    Span::new(start, start + s.len())
+}
+
+fn build_number(n: i32, span: Span) -> LOrExpr {
     LOrExpr::LAndExpr(LAndExpr::EqExpr(EqExpr::RelExpr(RelExpr::AddExpr(
         AddExpr::MulExpr(MulExpr::UnaryExpr(UnaryExpr::PrimaryExpr(
-            PrimaryExpr::Number(n),
+            PrimaryExpr::Number(n, span),
         ))),
     ))))
 }
@@ -80,7 +108,7 @@ fn build_number(n: i32) -> LOrExpr {
 #[test]
 fn test_comp_unit() {
     let comp_unit_parser = parser::CompUnitParser::new();
-    let mut context = ParserContext::new("", "");
+    let mut context = ParserContext::new();
 
     let input = r#"
     // This is a comment.
@@ -109,7 +137,12 @@ fn test_comp_unit() {
         GlobalItem::Decl(Decl::VarDecl(vec![Rc::new(VarDef::NormalVarDef(
             NormalVarDef {
                 name: "a".to_string(),
-                value: Some(Expr(Rc::new(build_number(10)))),
+                ty: DataType::Int,
+                value: Some(Expr(Rc::new(build_number(
+                    10,
+                    span_of(input, "a = 10", "10")
+                )))),
+                span: span_of(input, "a = 10", "a"),
             }
         ))]))
     );
@@ -118,14 +151,43 @@ fn test_comp_unit() {
         GlobalItem::Decl(Decl::ConstDecl(vec![Rc::new(ConstDef::ArrayConstDef(
             ArrayConstDef {
                 name: "b".to_string(),
+                ty: DataType::Int,
                 shape: vec![
-                    ConstExpr(Rc::new(build_number(10))),
-                    ConstExpr(Rc::new(build_number(15)))
+                    ConstExpr(Rc::new(build_number(10, span_of(input, "b[10]", "10")))),
+                    ConstExpr(Rc::new(build_number(15, span_of(input, "[15]", "15"))))
                 ],
-                values: ConstArray::Array(vec![ConstArray::Val(ConstExpr(Rc::new(build_number(
-                    1
-                ))))]),
+                values: ConstArray::Array(
+                    vec![ConstArray::Val(ConstExpr(Rc::new(build_number(
+                        1,
+                        span_of(input, "{1}", "1")
+                    ))))],
+                    span_of(input, "{1}", "{1}")
+                ),
+                span: span_of(input, "b[10]", "b"),
             }
         ))]))
     );
 }
+
+#[test]
+fn test_recovery() {
+    let comp_unit_parser = parser::CompUnitParser::new();
+    let mut context = ParserContext::new();
+    let input = "int a = ; int f() { a = 1 +; if (a) return 1 else return 2; return 0; }";
+    let comp_unit = comp_unit_parser.parse(&mut context, input).unwrap();
+    assert_eq!(comp_unit.items.len(), 1);
+    assert_eq!(context.diagnostics.error_count(), 3);
+    let spans: Vec<Span> = context
+        .diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.span.unwrap())
+        .collect();
+    assert_eq!(
+        spans,
+        vec![
+            span_of(input, "a = ;", ";"),
+            span_of(input, "1 +;", ";"),
+            span_of(input, "1 else", "else"),
+        ]
+    );
+}
@@ -0,0 +1,1208 @@
+//! Semantic analysis of the AST.
+//!
+//! Symbols are resolved with a [`Scope`] of their types, so that undefined names, mismatched
+//! types, wrong numbers of arguments, invalid array accesses and initializers, and assignments to
+//! constants are all reported before IR generation. IR generation only runs on programs without
+//! errors.
+//!
+//! The warnings enabled by [`Lints`] are reported along the way.
+
+use crate::front::ast::*;
+use crate::front::diagnostic::{Diagnostic, Diagnostics, ErrorCode};
+use crate::front::ir::builtin::{builtin_functions, IRType};
+use crate::front::ir::eval::{Constant, Eval, Number};
+use crate::front::ir::eval_array_len;
+use crate::front::ir::initial_list::InitializeList;
+use crate::front::ir::scope::Scope;
+use crate::front::lint::{Lint, Lints};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::iter;
+
+/// Types of values in SysY.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Float,
+    Void,
+    /// Array of `int`s or `float`s, with the length of every dimension. A length is `None` if it
+    /// is unknown, like the first one of an array parameter.
+    Array(DataType, Vec<Option<i32>>),
+}
+
+impl Type {
+    /// Check if a value of this type can be used where `expected` is expected. `int`s and
+    /// `float`s are converted to each other. Arrays are passed as pointers, so their first
+    /// lengths are not compared.
+    fn fits(&self, expected: &Type) -> bool {
+        match (self, expected) {
+            (Type::Array(found_ty, found), Type::Array(expected_ty, expected)) => {
+                found_ty == expected_ty
+                    && found.len() == expected.len()
+                    && found.iter().zip(expected).skip(1).all(|(found, expected)| {
+                        found.is_none() || expected.is_none() || found == expected
+                    })
+            }
+            (Type::Int | Type::Float, Type::Int | Type::Float) => true,
+            _ => self == expected,
+        }
+    }
+}
+
+impl From<DataType> for Type {
+    fn from(ty: DataType) -> Self {
+        match ty {
+            DataType::Void => Type::Void,
+            DataType::Int => Type::Int,
+            DataType::Float => Type::Float,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Void => write!(f, "void"),
+            Type::Array(ty, shape) => {
+                write!(f, "{}", Type::from(*ty))?;
+                for len in shape {
+                    match len {
+                        Some(len) => write!(f, "[{}]", len)?,
+                        None => write!(f, "[]")?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl From<IRType> for Type {
+    fn from(ty: IRType) -> Self {
+        match ty {
+            IRType::Void => Type::Void,
+            IRType::Int => Type::Int,
+            IRType::Float => Type::Float,
+            IRType::IntPointer => Type::Array(DataType::Int, vec![None]),
+            IRType::FloatPointer => Type::Array(DataType::Float, vec![None]),
+        }
+    }
+}
+
+/// What the type checker knows about an identifier.
+#[derive(Debug, Clone)]
+enum Symbol {
+    Variable(Type),
+    /// A constant, with its type and its value if it could be evaluated.
+    Constant(DataType, Option<Number>),
+    /// A constant array, with the type of its elements, its shape and its values if they could
+    /// be evaluated.
+    ConstArray(DataType, Vec<Option<i32>>, Option<InitializeList<Number>>),
+}
+
+impl Constant for Symbol {
+    fn value(&self) -> Option<Number> {
+        match self {
+            Symbol::Constant(_, value) => *value,
+            _ => None,
+        }
+    }
+
+    fn array_values(&self) -> Option<&InitializeList<Number>> {
+        match self {
+            Symbol::ConstArray(_, _, values) => values.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+struct Signature {
+    params: Vec<Type>,
+    ret_type: Type,
+    /// Span of the definition, `None` for the runtime library.
+    span: Option<Span>,
+}
+
+struct Checker {
+    scope: Scope<Symbol>,
+    functions: HashMap<String, Signature>,
+    /// Return type of the function being checked, `None` for global declarations.
+    ret_type: Option<Type>,
+    /// Whether each of the loops around the current statement has a `break`.
+    loops: Vec<bool>,
+    /// Spans of the definitions of the identifiers that are used.
+    used: HashSet<Span>,
+    /// Functions that are called somewhere.
+    called: HashSet<String>,
+    lints: Lints,
+    /// Lints that have already been reported, so that how to disable them is only shown once.
+    reported: HashSet<Lint>,
+    diagnostics: Diagnostics,
+}
+
+/// Check a parsed program, returning the errors and warnings found in it.
+pub fn check(comp_unit: &CompUnit, lints: &Lints) -> Diagnostics {
+    let mut checker = Checker::new(lints.clone());
+    comp_unit.check(&mut checker);
+    if lints.werror {
+        checker.diagnostics.deny_warnings();
+    }
+    checker.diagnostics
+}
+
+impl Checker {
+    fn new(lints: Lints) -> Self {
+        let functions = builtin_functions()
+            .map(|(name, params, ret_type)| {
+                let signature = Signature {
+                    params: params.iter().copied().map(Into::into).collect(),
+                    ret_type: ret_type.into(),
+                    span: None,
+                };
+                (name.to_string(), signature)
+            })
+            .collect();
+        Self {
+            scope: Scope::new(),
+            functions,
+            ret_type: None,
+            loops: vec![],
+            used: HashSet::new(),
+            called: HashSet::new(),
+            lints,
+            reported: HashSet::new(),
+            diagnostics: Diagnostics::new(),
+        }
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol, span: Span) {
+        let outer = self.scope.get_definition(name).map(|(_, span)| *span);
+        match self.scope.add_identifier(name.to_string(), symbol, span) {
+            Ok(()) => {
+                if let Some(outer) = outer {
+                    self.warn(
+                        Lint::Shadow,
+                        format!("`{}` shadows a definition of an outer scope", name),
+                        span,
+                        |diagnostic| {
+                            diagnostic.with_note(outer, format!("`{}` is first defined here", name))
+                        },
+                    );
+                }
+            }
+            Err(previous) => {
+                self.diagnostics
+                    .push(Diagnostic::multiple_definition(name, span, Some(previous)));
+            }
+        }
+    }
+
+    /// Get the definition of an identifier, marking it as used.
+    fn use_identifier(&mut self, name: &str) -> Option<(Symbol, Span)> {
+        let (symbol, span) = self.scope.get_definition(name)?.clone();
+        self.used.insert(span);
+        Some((symbol, span))
+    }
+
+    /// Report a warning if its lint is enabled, adding details to it with `build`.
+    fn warn(
+        &mut self,
+        lint: Lint,
+        message: String,
+        span: Span,
+        build: impl FnOnce(Diagnostic) -> Diagnostic,
+    ) {
+        if !self.lints.is_enabled(lint) {
+            return;
+        }
+        let mut diagnostic = build(Diagnostic::warning(lint.code(), message).with_span(span));
+        if self.reported.insert(lint) {
+            diagnostic = diagnostic.with_plain_note(format!(
+                "`-W{}` is enabled, use `-Wno-{}` to disable it",
+                lint.name(),
+                lint.name()
+            ));
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Report the identifiers of the current scope that are never used. Names starting with an
+    /// underscore are never reported.
+    fn report_unused(&mut self) {
+        let mut unused: Vec<(String, &'static str, Span)> = self
+            .scope
+            .current_identifiers()
+            .filter(|(name, (_, span))| !name.starts_with('_') && !self.used.contains(span))
+            .map(|(name, (symbol, span))| {
+                let kind = match symbol {
+                    Symbol::Variable(_) => "variable",
+                    Symbol::Constant(_, _) | Symbol::ConstArray(_, _, _) => "constant",
+                };
+                (name.clone(), kind, *span)
+            })
+            .collect();
+        unused.sort_by_key(|(_, _, span)| span.start);
+        for (name, kind, span) in unused {
+            self.warn(
+                Lint::UnusedVariable,
+                format!("unused {} `{}`", kind, name),
+                span,
+                |diagnostic| {
+                    diagnostic.with_help(format!(
+                        "if this is intentional, prefix it with an underscore: `_{}`",
+                        name
+                    ))
+                },
+            );
+        }
+    }
+
+    /// Check that an expression is an `int`, returning whether it is. Nothing is reported if the
+    /// type is `None`, as the error has already been reported.
+    fn expect_int(&mut self, ty: Option<Type>, span: Span) -> bool {
+        match ty {
+            Some(Type::Int) => true,
+            Some(ty) => {
+                self.diagnostics
+                    .push(mismatched_types(&Type::Int, &ty, span));
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Check that an expression is an `int` or a `float`, returning its type if it is. Nothing
+    /// is reported if the type is `None`, as the error has already been reported.
+    fn expect_scalar(&mut self, ty: Option<Type>, span: Span) -> Option<Type> {
+        match ty {
+            Some(Type::Int) => Some(Type::Int),
+            Some(Type::Float) => Some(Type::Float),
+            Some(ty) => {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        ErrorCode::MismatchedTypes,
+                        format!("expected `int` or `float`, found `{}`", ty),
+                    )
+                    .with_span(span),
+                );
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Check the operands of an arithmetic operator, returning the type of the result, which is
+    /// `float` if one of the operands is.
+    fn check_arith(&mut self, lhs: (Option<Type>, Span), rhs: (Option<Type>, Span)) -> Type {
+        let lhs = self.expect_scalar(lhs.0, lhs.1);
+        let rhs = self.expect_scalar(rhs.0, rhs.1);
+        if lhs == Some(Type::Float) || rhs == Some(Type::Float) {
+            Type::Float
+        } else {
+            Type::Int
+        }
+    }
+
+    /// Evaluate an expression that must be constant.
+    fn eval_const(&mut self, expr: &impl ConstValue) -> Option<Number> {
+        let ty = expr.check(self);
+        self.expect_scalar(ty, expr.span())?;
+        expr.eval(&mut self.scope)
+            .map_err(|e| {
+                self.diagnostics
+                    .push(Diagnostic::not_constant(e, expr.span()))
+            })
+            .ok()
+    }
+
+    /// Get the lengths of the dimensions of an array, `None` for the ones that are invalid.
+    fn shape(&mut self, shape: &[ConstExpr]) -> Vec<Option<i32>> {
+        shape
+            .iter()
+            .map(|len| {
+                let ty = len.check(self);
+                if !self.expect_int(ty, len.span()) {
+                    return None;
+                }
+                eval_array_len(len, &mut self.scope)
+                    .map_err(|e| self.diagnostics.push(e))
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// Check the values of an initializer list, and that they fit in an array of the given
+    /// shape when nested lists are placed the way [`InitializeList`] does. The shape is `None` if
+    /// it is unknown. Returns whether the list is valid.
+    fn check_list<I: Initializer>(
+        &mut self,
+        shape: Option<&[i32]>,
+        items: &[I],
+        constant: bool,
+    ) -> bool {
+        let size = shape.map(|shape| shape.iter().product::<i32>());
+        let mut len = 0;
+        let mut valid = true;
+        for item in items {
+            if size.is_some_and(|size| len >= size) {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        ErrorCode::InvalidInitializer,
+                        "excess elements in array initializer",
+                    )
+                    .with_span(item.span()),
+                );
+                return false;
+            }
+            match (item.as_list(), shape) {
+                (None, _) => {
+                    valid &= item.check_value(self, constant);
+                    len += 1;
+                }
+                (Some(_), Some([])) => {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            ErrorCode::InvalidInitializer,
+                            "initializer list is nested too deeply",
+                        )
+                        .with_span(item.span()),
+                    );
+                    valid = false;
+                    len += 1;
+                }
+                (Some(list), Some(shape)) => {
+                    let align = InitializeList::<i32>::align(len, shape) as usize;
+                    let inner = &shape[shape.len() - align..];
+                    valid &= self.check_list(Some(inner), list, constant);
+                    len += inner.iter().product::<i32>();
+                }
+                (Some(list), None) => valid &= self.check_list(None, list, constant),
+            }
+        }
+        valid
+    }
+
+    /// Check the items of a block, which is already in its own scope. Returns whether control
+    /// may reach the end of the block.
+    fn check_items(&mut self, block: &Block) -> bool {
+        // The last statement that does not complete normally, if any.
+        let mut terminator: Option<Span> = None;
+        let mut warned = false;
+        for item in &block.items {
+            if let Some(terminator) = terminator.filter(|_| !warned) {
+                // Only warn about the first unreachable statement.
+                let next = match item {
+                    BlockItem::Stmt(Stmt::Empty(_)) => None,
+                    BlockItem::Stmt(stmt) => Some(stmt.span()),
+                    BlockItem::Decl(decl) => Some(decl.span()),
+                };
+                if let Some(next) = next {
+                    self.warn(
+                        Lint::UnreachableCode,
+                        "unreachable statement".to_string(),
+                        next,
+                        |diagnostic| {
+                            diagnostic.with_note(
+                                terminator,
+                                "any code following this statement is unreachable",
+                            )
+                        },
+                    );
+                    warned = true;
+                }
+            }
+            match item {
+                BlockItem::Stmt(stmt) => {
+                    if !stmt.check(self) && terminator.is_none() {
+                        terminator = Some(stmt.span());
+                    }
+                }
+                BlockItem::Decl(decl) => decl.check(self),
+            }
+        }
+        terminator.is_none()
+    }
+}
+
+fn mismatched_types(expected: &Type, found: &Type, span: Span) -> Diagnostic {
+    Diagnostic::error(
+        ErrorCode::MismatchedTypes,
+        format!("expected `{}`, found `{}`", expected, found),
+    )
+    .with_span(span)
+}
+
+trait Check {
+    type Output;
+    fn check(&self, checker: &mut Checker) -> Self::Output;
+}
+
+/// Expressions that may be evaluated at compile time.
+trait ConstValue: Check<Output = Option<Type>> + Eval {
+    fn span(&self) -> Span;
+}
+
+impl ConstValue for ConstExpr {
+    fn span(&self) -> Span {
+        self.span()
+    }
+}
+
+impl ConstValue for Expr {
+    fn span(&self) -> Span {
+        self.span()
+    }
+}
+
+/// Initializer lists of constant arrays and of variable arrays.
+trait Initializer: Sized {
+    /// Get the items of a nested list, `None` for a single value.
+    fn as_list(&self) -> Option<&[Self]>;
+    fn span(&self) -> Span;
+    /// Check a single value, which must be constant if `constant` is set.
+    fn check_value(&self, checker: &mut Checker, constant: bool) -> bool;
+}
+
+impl Initializer for ConstArray {
+    fn as_list(&self) -> Option<&[Self]> {
+        match self {
+            ConstArray::Val(_) => None,
+            ConstArray::Array(list, _) => Some(list),
+        }
+    }
+
+    fn span(&self) -> Span {
+        match self {
+            ConstArray::Val(expr) => expr.span(),
+            ConstArray::Array(_, span) => *span,
+        }
+    }
+
+    fn check_value(&self, checker: &mut Checker, _: bool) -> bool {
+        match self {
+            ConstArray::Val(expr) => checker.eval_const(expr).is_some(),
+            ConstArray::Array(_, _) => unreachable!(),
+        }
+    }
+}
+
+impl Initializer for ExprArray {
+    fn as_list(&self) -> Option<&[Self]> {
+        match self {
+            ExprArray::Val(_) => None,
+            ExprArray::Array(list, _) => Some(list),
+        }
+    }
+
+    fn span(&self) -> Span {
+        match self {
+            ExprArray::Val(expr) => expr.span(),
+            ExprArray::Array(_, span) => *span,
+        }
+    }
+
+    fn check_value(&self, checker: &mut Checker, constant: bool) -> bool {
+        match self {
+            ExprArray::Val(expr) if constant => checker.eval_const(expr).is_some(),
+            ExprArray::Val(expr) => {
+                let ty = expr.check(checker);
+                checker.expect_scalar(ty, expr.span()).is_some()
+            }
+            ExprArray::Array(_, _) => unreachable!(),
+        }
+    }
+}
+
+impl Check for CompUnit {
+    type Output = ();
+
+    fn check(&self, checker: &mut Checker) {
+        for item in &self.items {
+            match item {
+                GlobalItem::Decl(decl) => decl.check(checker),
+                GlobalItem::FuncDef(func_def) => func_def.check(checker),
+            }
+        }
+        checker.report_unused();
+
+        let mut unused: Vec<(String, Span)> = checker
+            .functions
+            .iter()
+            .filter(|(name, _)| *name != "main" && !checker.called.contains(*name))
+            .filter_map(|(name, signature)| Some((name.clone(), signature.span?)))
+            .collect();
+        unused.sort_by_key(|(_, span)| span.start);
+        for (name, span) in unused {
+            checker.warn(
+                Lint::UnusedFunction,
+                format!("function `{}` is never used", name),
+                span,
+                |diagnostic| diagnostic,
+            );
+        }
+    }
+}
+
+impl Check for Decl {
+    type Output = ();
+
+    fn check(&self, checker: &mut Checker) {
+        match self {
+            Decl::ConstDecl(const_decl) => {
+                for const_def in const_decl {
+                    const_def.check(checker);
+                }
+            }
+            Decl::VarDecl(var_decl) => {
+                for var_def in var_decl {
+                    var_def.check(checker);
+                }
+            }
+        }
+    }
+}
+
+impl Check for ConstDef {
+    type Output = ();
+
+    fn check(&self, checker: &mut Checker) {
+        match self {
+            ConstDef::NormalConstDef(normal) => {
+                let value = checker
+                    .eval_const(&normal.value)
+                    .map(|value| value.convert(normal.ty));
+                checker.define(
+                    &normal.name,
+                    Symbol::Constant(normal.ty, value),
+                    normal.span,
+                );
+            }
+            ConstDef::ArrayConstDef(const_array) => {
+                let shape = checker.shape(&const_array.shape);
+                let known_shape: Option<Vec<i32>> = shape.iter().copied().collect();
+                let values = match &const_array.values {
+                    ConstArray::Val(val) => {
+                        checker.diagnostics.push(Diagnostic::invalid_initializer(
+                            &const_array.name,
+                            val.span(),
+                        ));
+                        None
+                    }
+                    ConstArray::Array(list, _) => {
+                        let valid = checker.check_list(known_shape.as_deref(), list, true);
+                        match known_shape {
+                            Some(shape) if valid => InitializeList::from_const_array(
+                                &shape,
+                                const_array.ty,
+                                list,
+                                &mut checker.scope,
+                            )
+                            .ok(),
+                            _ => None,
+                        }
+                    }
+                };
+                checker.define(
+                    &const_array.name,
+                    Symbol::ConstArray(const_array.ty, shape, values),
+                    const_array.span,
+                );
+            }
+        }
+    }
+}
+
+impl Check for VarDef {
+    type Output = ();
+
+    fn check(&self, checker: &mut Checker) {
+        let global = checker.ret_type.is_none();
+        match self {
+            VarDef::NormalVarDef(normal) => {
+                if let Some(init) = &normal.value {
+                    if global {
+                        checker.eval_const(init);
+                    } else {
+                        let ty = init.check(checker);
+                        checker.expect_scalar(ty, init.span());
+                    }
+                }
+                checker.define(
+                    &normal.name,
+                    Symbol::Variable(normal.ty.into()),
+                    normal.span,
+                );
+            }
+            VarDef::ArrayVarDef(array_var) => {
+                let shape = checker.shape(&array_var.shape);
+                let known_shape: Option<Vec<i32>> = shape.iter().copied().collect();
+                match &array_var.values {
+                    Some(ExprArray::Val(val)) => {
+                        checker
+                            .diagnostics
+                            .push(Diagnostic::invalid_initializer(&array_var.name, val.span()));
+                    }
+                    Some(ExprArray::Array(list, _)) => {
+                        checker.check_list(known_shape.as_deref(), list, global);
+                    }
+                    None => {}
+                }
+                checker.define(
+                    &array_var.name,
+                    Symbol::Variable(Type::Array(array_var.ty, shape)),
+                    array_var.span,
+                );
+            }
+        }
+    }
+}
+
+impl Check for FuncDef {
+    type Output = ();
+
+    fn check(&self, checker: &mut Checker) {
+        let params: Vec<Type> = self
+            .params
+            .iter()
+            .map(|param| match param.as_ref() {
+                FuncFParam::NormalFParam(param) => param.ty.into(),
+                FuncFParam::ArrayFParam(array_param) => {
+                    let shape = if array_param.placeholder {
+                        &array_param.shape[..]
+                    } else {
+                        &array_param.shape[1..]
+                    };
+                    let shape = checker.shape(shape);
+                    Type::Array(array_param.ty, iter::once(None).chain(shape).collect())
+                }
+            })
+            .collect();
+        let ret_type = Type::from(self.ret_type);
+        match checker.functions.get(&self.name) {
+            Some(previous) => {
+                let previous = previous.span;
+                checker.diagnostics.push(Diagnostic::multiple_definition(
+                    &self.name, self.span, previous,
+                ));
+            }
+            None => {
+                let signature = Signature {
+                    params: params.clone(),
+                    ret_type: ret_type.clone(),
+                    span: Some(self.span),
+                };
+                checker.functions.insert(self.name.clone(), signature);
+            }
+        }
+
+        // Parameters are in the same scope as the body.
+        checker.scope.go_into_scoop(self.body.id);
+        for (param, ty) in self.params.iter().zip(params) {
+            let name = match param.as_ref() {
+                FuncFParam::NormalFParam(param) => &param.name,
+                FuncFParam::ArrayFParam(param) => &param.name,
+            };
+            checker.define(name, Symbol::Variable(ty), param.span());
+        }
+        checker.ret_type = Some(ret_type.clone());
+        let falls_through = checker.check_items(&self.body);
+        checker.ret_type = None;
+        checker.report_unused();
+        checker.scope.go_out_scoop();
+
+        if falls_through && ret_type != Type::Void {
+            checker.warn(
+                Lint::MissingReturn,
+                format!(
+                    "function `{}` may reach its end without returning a value",
+                    self.name
+                ),
+                self.span,
+                |diagnostic| {
+                    diagnostic
+                        .with_plain_note("`0` is returned in that case")
+                        .with_help("add a `return` statement at the end of the function")
+                },
+            );
+        }
+    }
+}
+
+/// Statements return whether control may reach their end.
+impl Check for Stmt {
+    type Output = bool;
+
+    fn check(&self, checker: &mut Checker) -> bool {
+        match self {
+            Stmt::Assign(assign) => {
+                assign.check(checker);
+                true
+            }
+            Stmt::Expr(expr) => {
+                expr.check(checker);
+                true
+            }
+            Stmt::Block(block) => {
+                checker.scope.go_into_scoop(block.id);
+                let falls_through = checker.check_items(block);
+                checker.report_unused();
+                checker.scope.go_out_scoop();
+                falls_through
+            }
+            Stmt::If(if_stmt) => {
+                let ty = if_stmt.cond.check(checker);
+                checker.expect_scalar(ty, if_stmt.cond.span());
+                let then_falls_through = if_stmt.then_stmt.check(checker);
+                match &if_stmt.else_stmt {
+                    Some(else_stmt) => else_stmt.check(checker) || then_falls_through,
+                    None => true,
+                }
+            }
+            Stmt::While(while_stmt) => {
+                let ty = while_stmt.cond.check(checker);
+                checker.expect_scalar(ty, while_stmt.cond.span());
+                checker.loops.push(false);
+                while_stmt.body.check(checker);
+                let has_break = checker.loops.pop().unwrap();
+                // A loop whose condition is always true can only be left with `break`.
+                let endless = while_stmt
+                    .cond
+                    .eval(&mut checker.scope)
+                    .is_ok_and(|cond| cond.to_bool());
+                has_break || !endless
+            }
+            Stmt::Return(ret) => {
+                ret.check(checker);
+                false
+            }
+            Stmt::Break(break_stmt) => {
+                match checker.loops.last_mut() {
+                    Some(has_break) => *has_break = true,
+                    None => checker.diagnostics.push(
+                        Diagnostic::error(ErrorCode::BreakOutsideLoop, "`break` outside of a loop")
+                            .with_span(break_stmt.span),
+                    ),
+                }
+                false
+            }
+            Stmt::Continue(continue_stmt) => {
+                if checker.loops.is_empty() {
+                    checker.diagnostics.push(
+                        Diagnostic::error(
+                            ErrorCode::ContinueOutsideLoop,
+                            "`continue` outside of a loop",
+                        )
+                        .with_span(continue_stmt.span),
+                    );
+                }
+                false
+            }
+            Stmt::Empty(_) => true,
+        }
+    }
+}
+
+impl Check for Return {
+    type Output = ();
+
+    fn check(&self, checker: &mut Checker) {
+        let ty = self.value.as_ref().map(|value| value.check(checker));
+        match (checker.ret_type.clone(), ty) {
+            (Some(Type::Void), Some(_)) => checker.diagnostics.push(
+                Diagnostic::error(
+                    ErrorCode::MismatchedTypes,
+                    "`return` with a value in a function returning `void`",
+                )
+                .with_span(self.span),
+            ),
+            (Some(ret_type), None) if ret_type != Type::Void => checker.diagnostics.push(
+                Diagnostic::error(
+                    ErrorCode::MismatchedTypes,
+                    format!(
+                        "`return` without a value in a function returning `{}`",
+                        ret_type
+                    ),
+                )
+                .with_span(self.span),
+            ),
+            (_, Some(ty)) => {
+                let span = self.value.as_ref().unwrap().span();
+                checker.expect_scalar(ty, span);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Check for Assign {
+    type Output = ();
+
+    fn check(&self, checker: &mut Checker) {
+        let name = match &self.target {
+            LVal::Var(name, _) => name,
+            LVal::ArrayElem(array_elem) => &array_elem.name,
+        };
+        let span = self.target.span();
+        match checker.scope.get_definition(name) {
+            Some((Symbol::Constant(_, _) | Symbol::ConstArray(_, _, _), def_span)) => {
+                let def_span = *def_span;
+                checker.diagnostics.push(
+                    Diagnostic::error(
+                        ErrorCode::AssignToConstant,
+                        format!("cannot assign to constant `{}`", name),
+                    )
+                    .with_span(span)
+                    .with_note(
+                        def_span,
+                        format!("`{}` is defined as a constant here", name),
+                    ),
+                );
+            }
+            _ => {
+                if let Some(Type::Array(_, _)) = self.target.check(checker) {
+                    checker.diagnostics.push(
+                        Diagnostic::error(
+                            ErrorCode::MismatchedTypes,
+                            format!("cannot assign to array `{}`", name),
+                        )
+                        .with_span(span),
+                    );
+                }
+            }
+        }
+        let ty = self.value.check(checker);
+        checker.expect_scalar(ty, self.value.span());
+    }
+}
+
+impl Check for LVal {
+    type Output = Option<Type>;
+
+    fn check(&self, checker: &mut Checker) -> Option<Type> {
+        match self {
+            LVal::Var(name, span) => match checker.use_identifier(name) {
+                Some((Symbol::Variable(ty), _)) => Some(ty),
+                Some((Symbol::Constant(ty, _), _)) => Some(ty.into()),
+                Some((Symbol::ConstArray(_, _, _), _)) => {
+                    checker.diagnostics.push(
+                        Diagnostic::error(
+                            ErrorCode::InvalidValue,
+                            format!("constant array `{}` cannot be used as a value", name),
+                        )
+                        .with_span(*span)
+                        .with_help("index the array to read one of its elements"),
+                    );
+                    None
+                }
+                None => {
+                    checker
+                        .diagnostics
+                        .push(Diagnostic::unknown_identifier(name, *span));
+                    None
+                }
+            },
+            LVal::ArrayElem(array_elem) => array_elem.check(checker),
+        }
+    }
+}
+
+impl Check for ArrayElem {
+    type Output = Option<Type>;
+
+    fn check(&self, checker: &mut Checker) -> Option<Type> {
+        for index in &self.indices {
+            let ty = index.check(checker);
+            checker.expect_int(ty, index.span());
+        }
+        let (ty, shape, def_span) = match checker.use_identifier(&self.name) {
+            Some((Symbol::Variable(Type::Array(ty, shape)), def_span))
+            | Some((Symbol::ConstArray(ty, shape, _), def_span)) => (ty, shape, def_span),
+            Some((_, def_span)) => {
+                checker.diagnostics.push(
+                    Diagnostic::error(
+                        ErrorCode::NotAnArray,
+                        format!("cannot index into `{}`", self.name),
+                    )
+                    .with_span(self.span)
+                    .with_note(
+                        def_span,
+                        format!("`{}` is defined as a scalar here", self.name),
+                    ),
+                );
+                return None;
+            }
+            None => {
+                checker
+                    .diagnostics
+                    .push(Diagnostic::unknown_identifier(&self.name, self.span));
+                return None;
+            }
+        };
+        if self.indices.len() > shape.len() {
+            checker.diagnostics.push(
+                Diagnostic::error(
+                    ErrorCode::NotAnArray,
+                    format!("too many indices for array `{}`", self.name),
+                )
+                .with_span(self.span)
+                .with_note(
+                    def_span,
+                    format!(
+                        "`{}` is defined with {} dimension{} here",
+                        self.name,
+                        shape.len(),
+                        if shape.len() == 1 { "" } else { "s" }
+                    ),
+                ),
+            );
+            return None;
+        }
+        if self.indices.len() == shape.len() {
+            Some(ty.into())
+        } else {
+            Some(Type::Array(ty, shape[self.indices.len()..].to_vec()))
+        }
+    }
+}
+
+impl Check for FuncCall {
+    type Output = Option<Type>;
+
+    fn check(&self, checker: &mut Checker) -> Option<Type> {
+        let args: Vec<Option<Type>> = self.args.iter().map(|arg| arg.check(checker)).collect();
+        checker.called.insert(self.name.clone());
+        let Some(signature) = checker.functions.get(&self.name) else {
+            checker.diagnostics.push(
+                Diagnostic::error(
+                    ErrorCode::UnknownFunction,
+                    format!("cannot find function `{}`", self.name),
+                )
+                .with_span(self.span),
+            );
+            return None;
+        };
+        let mut diagnostics = vec![];
+        if args.len() != signature.params.len() {
+            let arguments = |n: usize| format!("{} argument{}", n, if n == 1 { "" } else { "s" });
+            let diagnostic = Diagnostic::error(
+                ErrorCode::WrongArgumentCount,
+                format!(
+                    "function `{}` takes {} but {} supplied",
+                    self.name,
+                    arguments(signature.params.len()),
+                    if args.len() == 1 {
+                        "1 argument was".to_string()
+                    } else {
+                        format!("{} were", arguments(args.len()))
+                    }
+                ),
+            )
+            .with_span(self.span);
+            diagnostics.push(match signature.span {
+                Some(span) => {
+                    diagnostic.with_note(span, format!("`{}` is defined here", self.name))
+                }
+                None => diagnostic.with_plain_note(format!(
+                    "`{}` is a function of the runtime library",
+                    self.name
+                )),
+            });
+        } else {
+            for ((arg, ty), expected) in self.args.iter().zip(args).zip(&signature.params) {
+                if let Some(ty) = ty {
+                    if !ty.fits(expected) {
+                        diagnostics.push(mismatched_types(expected, &ty, arg.span()));
+                    }
+                }
+            }
+        }
+        let ret_type = signature.ret_type.clone();
+        for diagnostic in diagnostics {
+            checker.diagnostics.push(diagnostic);
+        }
+        Some(ret_type)
+    }
+}
+
+impl Check for ConstExpr {
+    type Output = Option<Type>;
+
+    fn check(&self, checker: &mut Checker) -> Option<Type> {
+        self.0.check(checker)
+    }
+}
+
+impl Check for Expr {
+    type Output = Option<Type>;
+
+    fn check(&self, checker: &mut Checker) -> Option<Type> {
+        self.0.check(checker)
+    }
+}
+
+impl Check for PrimaryExpr {
+    type Output = Option<Type>;
+
+    fn check(&self, checker: &mut Checker) -> Option<Type> {
+        match self {
+            PrimaryExpr::Expr(expr, _) => expr.check(checker),
+            PrimaryExpr::LVal(lval) => lval.check(checker),
+            PrimaryExpr::Number(_, _) => Some(Type::Int),
+            PrimaryExpr::FloatNumber(_, _) => Some(Type::Float),
+        }
+    }
+}
+
+impl Check for UnaryExpr {
+    type Output = Option<Type>;
+
+    fn check(&self, checker: &mut Checker) -> Option<Type> {
+        match self {
+            UnaryExpr::PrimaryExpr(expr) => expr.check(checker),
+            UnaryExpr::FuncCall(func_call) => func_call.check(checker),
+            UnaryExpr::Unary(op, expr, _) => {
+                let ty = expr.check(checker);
+                let ty = checker.expect_scalar(ty, expr.span());
+                match op {
+                    UnaryOp::Not => Some(Type::Int),
+                    UnaryOp::Pos | UnaryOp::Neg => Some(ty.unwrap_or(Type::Int)),
+                }
+            }
+        }
+    }
+}
+
+impl Check for MulExpr {
+    type Output = Option<Type>;
+
+    fn check(&self, checker: &mut Checker) -> Option<Type> {
+        match self {
+            MulExpr::UnaryExpr(expr) => expr.check(checker),
+            MulExpr::Mul(lhs, MulOp::Mod, rhs) => {
+                let ty = lhs.check(checker);
+                checker.expect_int(ty, lhs.span());
+                let ty = rhs.check(checker);
+                checker.expect_int(ty, rhs.span());
+                Some(Type::Int)
+            }
+            MulExpr::Mul(lhs, _, rhs) => {
+                let lhs = (lhs.check(checker), lhs.span());
+                let rhs = (rhs.check(checker), rhs.span());
+                Some(checker.check_arith(lhs, rhs))
+            }
+        }
+    }
+}
+
+impl Check for AddExpr {
+    type Output = Option<Type>;
+
+    fn check(&self, checker: &mut Checker) -> Option<Type> {
+        match self {
+            AddExpr::MulExpr(expr) => expr.check(checker),
+            AddExpr::Add(lhs, _, rhs) => {
+                let lhs = (lhs.check(checker), lhs.span());
+                let rhs = (rhs.check(checker), rhs.span());
+                Some(checker.check_arith(lhs, rhs))
+            }
+        }
+    }
+}
+
+impl Check for RelExpr {
+    type Output = Option<Type>;
+
+    fn check(&self, checker: &mut Checker) -> Option<Type> {
+        match self {
+            RelExpr::AddExpr(expr) => expr.check(checker),
+            RelExpr::Rel(lhs, _, rhs) => {
+                let ty = lhs.check(checker);
+                checker.expect_scalar(ty, lhs.span());
+                let ty = rhs.check(checker);
+                checker.expect_scalar(ty, rhs.span());
+                Some(Type::Int)
+            }
+        }
+    }
+}
+
+impl Check for EqExpr {
+    type Output = Option<Type>;
+
+    fn check(&self, checker: &mut Checker) -> Option<Type> {
+        match self {
+            EqExpr::RelExpr(expr) => expr.check(checker),
+            EqExpr::Eq(lhs, _, rhs) => {
+                let ty = lhs.check(checker);
+                checker.expect_scalar(ty, lhs.span());
+                let ty = rhs.check(checker);
+                checker.expect_scalar(ty, rhs.span());
+                Some(Type::Int)
+            }
+        }
+    }
+}
+
+impl Check for LAndExpr {
+    type Output = Option<Type>;
+
+    fn check(&self, checker: &mut Checker) -> Option<Type> {
+        match self {
+            LAndExpr::EqExpr(expr) => expr.check(checker),
+            LAndExpr::And(lhs, rhs) => {
+                let ty = lhs.check(checker);
+                checker.expect_scalar(ty, lhs.span());
+                let ty = rhs.check(checker);
+                checker.expect_scalar(ty, rhs.span());
+                Some(Type::Int)
+            }
+        }
+    }
+}
+
+impl Check for LOrExpr {
+    type Output = Option<Type>;
+
+    fn check(&self, checker: &mut Checker) -> Option<Type> {
+        match self {
+            LOrExpr::LAndExpr(expr) => expr.check(checker),
+            LOrExpr::Or(lhs, rhs) => {
+                let ty = lhs.check(checker);
+                checker.expect_scalar(ty, lhs.span());
+                let ty = rhs.check(checker);
+                checker.expect_scalar(ty, rhs.span());
+                Some(Type::Int)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::testing::parse;
+
+    #[test]
+    fn test_check() {
+        let comp_unit = parse(
+            "const int c = 1; void f(int _a[][2]) {}
+            int main() { int b[3][2]; f(b); f(c); f(b, 1); c = f(b); b[0][0][0] = 1; return 0; }",
+        );
+        let codes: Vec<ErrorCode> = check(&comp_unit, &Lints::default())
+            .iter()
+            .map(|d| d.code)
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                ErrorCode::MismatchedTypes,
+                ErrorCode::WrongArgumentCount,
+                ErrorCode::AssignToConstant,
+                ErrorCode::MismatchedTypes,
+                ErrorCode::NotAnArray,
+            ]
+        );
+    }
+}
@@ -0,0 +1,332 @@
+//! Diagnostics of errors and warnings found in the source code.
+//!
+//! A diagnostic has an error code, a message, the span of the offending node, notes that may
+//! point at other places in the source, and a help text. They are collected in [`Diagnostics`]
+//! during parsing and IR generation, and rendered together by
+//! [`show_diagnostics`](crate::util::logger::show_diagnostics).
+
+use crate::front::ast::Span;
+use crate::front::ir::eval::EvalError;
+use lalrpop_util::lexer::Token;
+use lalrpop_util::ParseError;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// A bug of the compiler rather than of the source code.
+    Internal,
+    Syntax,
+    NumberOutOfRange,
+    InvalidType,
+    UnknownIdentifier,
+    UnknownFunction,
+    MultipleDefinition,
+    InvalidValue,
+    NotAnArray,
+    NotConstant,
+    AssignToConstant,
+    BreakOutsideLoop,
+    ContinueOutsideLoop,
+    InvalidInitializer,
+    InvalidArraySize,
+    UnreachableCode,
+    MismatchedTypes,
+    WrongArgumentCount,
+    UnusedVariable,
+    UnusedFunction,
+    MissingReturn,
+    ShadowedIdentifier,
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "E{:04}", *self as u32)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Note {
+    pub span: Option<Span>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub code: ErrorCode,
+    pub message: String,
+    pub span: Option<Span>,
+    pub notes: Vec<Note>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        Diagnostic {
+            level: Level::Error,
+            code,
+            message: message.into(),
+            span: None,
+            notes: vec![],
+            help: None,
+        }
+    }
+
+    pub fn warning(code: ErrorCode, message: impl Into<String>) -> Self {
+        Diagnostic {
+            level: Level::Warning,
+            ..Self::error(code, message)
+        }
+    }
+
+    /// Create an error that is not caused by the source code.
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::error(ErrorCode::Internal, message)
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    /// Add a note pointing at another place in the source.
+    pub fn with_note(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.notes.push(Note {
+            span: Some(span),
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Add a note without a place in the source.
+    pub fn with_plain_note(mut self, message: impl Into<String>) -> Self {
+        self.notes.push(Note {
+            span: None,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn unknown_identifier(name: &str, span: Span) -> Self {
+        Self::error(
+            ErrorCode::UnknownIdentifier,
+            format!("cannot find `{}` in this scope", name),
+        )
+        .with_span(span)
+    }
+
+    /// Create an error for a name defined twice, `previous` being the span of the first
+    /// definition if it is in the source.
+    pub fn multiple_definition(name: &str, span: Span, previous: Option<Span>) -> Self {
+        let diagnostic = Self::error(
+            ErrorCode::MultipleDefinition,
+            format!("`{}` is defined multiple times", name),
+        )
+        .with_span(span);
+        match previous {
+            Some(previous) => {
+                diagnostic.with_note(previous, format!("previous definition of `{}` here", name))
+            }
+            None => diagnostic
+                .with_plain_note(format!("`{}` is a function of the runtime library", name)),
+        }
+    }
+
+    /// Create an error for an expression that should be constant but cannot be evaluated.
+    pub fn not_constant(error: EvalError, span: Span) -> Self {
+        let diagnostic = Self::error(ErrorCode::NotConstant, "expression is not constant");
+        let diagnostic = match error {
+            EvalError::DivisionByZero => {
+                diagnostic.with_plain_note("the expression divides by zero")
+            }
+            EvalError::Overflow => diagnostic.with_plain_note("the expression overflows"),
+            EvalError::IndexOutOfBounds => {
+                diagnostic.with_plain_note("an index of a constant array is out of bounds")
+            }
+            EvalError::NotSupportedVariable => {
+                diagnostic.with_help("only constants can be used in a constant expression")
+            }
+            EvalError::FunctionNotSupported => {
+                diagnostic.with_help("functions cannot be called in a constant expression")
+            }
+            EvalError::FloatRemainder => {
+                diagnostic.with_plain_note("`%` cannot be applied to `float`s")
+            }
+        };
+        diagnostic.with_span(span)
+    }
+
+    /// Create an error for an array initialized with a single value instead of a list.
+    pub fn invalid_initializer(name: &str, span: Span) -> Self {
+        Self::error(
+            ErrorCode::InvalidInitializer,
+            format!("array `{}` must be initialized with a list", name),
+        )
+        .with_span(span)
+        .with_help("surround the initial values with braces")
+    }
+
+    /// Create an error from a syntax error reported by the parser.
+    pub fn syntax_error(error: ParseError<usize, Token, &str>) -> Self {
+        match error {
+            ParseError::InvalidToken { location } => {
+                Self::error(ErrorCode::Syntax, "invalid token")
+                    .with_span(Span::new(location, location + 1))
+            }
+            ParseError::UnrecognizedEof { location, expected } => {
+                Self::error(ErrorCode::Syntax, "unexpected end of file")
+                    .with_span(Span::new(location, location))
+                    .with_expected(&expected)
+            }
+            ParseError::UnrecognizedToken {
+                token: (start, token, end),
+                expected,
+            } => Self::error(ErrorCode::Syntax, format!("unexpected token `{}`", token.1))
+                .with_span(Span::new(start, end))
+                .with_expected(&expected),
+            ParseError::ExtraToken {
+                token: (start, token, end),
+            } => Self::error(ErrorCode::Syntax, format!("extra token `{}`", token.1))
+                .with_span(Span::new(start, end)),
+            ParseError::User { error } => Self::error(ErrorCode::Syntax, error),
+        }
+    }
+
+    /// Add the tokens expected by the parser as help, naming the regular expressions of the
+    /// grammar by what they match.
+    fn with_expected(self, expected: &[String]) -> Self {
+        let mut names: Vec<&str> = vec![];
+        for token in expected {
+            let name = if !token.starts_with("r#") {
+                token.as_str()
+            } else if token.contains("a-zA-Z") {
+                "identifier"
+            } else {
+                "number"
+            };
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        match names[..] {
+            [] => self,
+            [name] => self.with_help(format!("expected {}", name)),
+            _ => self.with_help(format!("expected one of {}", names.join(", "))),
+        }
+    }
+}
+
+/// Collects the diagnostics of a compilation, so that all of them can be reported at the end
+/// instead of stopping at the first error.
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    /// Report all warnings as errors.
+    pub fn deny_warnings(&mut self) {
+        for diagnostic in &mut self.diagnostics {
+            diagnostic.level = Level::Error;
+        }
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.error_count() > 0
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.count(Level::Error)
+    }
+
+    pub fn warning_count(&self) -> usize {
+        self.count(Level::Warning)
+    }
+
+    fn count(&self, level: Level) -> usize {
+        self.diagnostics.iter().filter(|d| d.level == level).count()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::logger::render_diagnostic;
+
+    #[test]
+    fn test_render() {
+        colored::control::set_override(false);
+        let input = "int a;\nint main() {\n  int a = 10 % 0;\n  return a;\n}";
+        let span = |s: &str| {
+            let start = input.rfind(s).unwrap();
+            Span::new(start, start + s.len())
+        };
+        let diagnostic = Diagnostic::multiple_definition("a", span("a ="), Some(Span::new(4, 5)))
+            .with_plain_note("a local variable may shadow a global one")
+            .with_help("rename one of them");
+        assert_eq!(
+            render_diagnostic(&diagnostic, input, "a.c"),
+            "\
+Error[E0006]: `a` is defined multiple times
+ --> a.c:3:7
+  |
+3 |   int a = 10 % 0;
+  |       ^^^
+Note: previous definition of `a` here
+ --> a.c:1:5
+  |
+1 | int a;
+  |     -
+  = note: a local variable may shadow a global one
+  = help: rename one of them
+"
+        );
+
+        let diagnostic = Diagnostic::not_constant(EvalError::DivisionByZero, span("10 % 0"));
+        assert_eq!(
+            render_diagnostic(&diagnostic, input, "a.c"),
+            "\
+Error[E0009]: expression is not constant
+ --> a.c:3:11
+  |
+3 |   int a = 10 % 0;
+  |           ^^^^^^
+  = note: the expression divides by zero
+"
+        );
+
+        let diagnostic = Diagnostic::warning(ErrorCode::UnusedVariable, "unused variable `a`")
+            .with_span(Span::new(4, 5));
+        assert_eq!(
+            render_diagnostic(&diagnostic, input, "a.c"),
+            "Warning[E0018]: unused variable `a`\n --> a.c:1:5\n  |\n1 | int a;\n  |     ^\n"
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use crate::front::ast::FuncDef;
+use crate::front::ir::eval::Number;
 use crate::front::ir::initial_list::InitializeList;
 use koopa::ir::Value;
 
@@ -9,13 +10,13 @@ pub struct Variable {
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct Constant {
-    pub value: i32,
+    pub value: Number,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct ConstArray {
     pub koopa_def: Value,
-    pub values: InitializeList<i32>,
+    pub values: InitializeList<Number>,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
@@ -41,19 +42,11 @@ impl Identifier {
         Identifier::Variable(Variable { koopa_def })
     }
 
-    pub fn from_constant(value: i32) -> Self {
+    pub fn from_constant(value: Number) -> Self {
         Identifier::Constant(Constant { value })
     }
 
-    pub fn from_const_array(koopa_def: Value, values: InitializeList<i32>) -> Self {
+    pub fn from_const_array(koopa_def: Value, values: InitializeList<Number>) -> Self {
         Identifier::ConstArray(ConstArray { koopa_def, values })
     }
-
-    pub fn koopa_def(&self) -> Option<Value> {
-        match self {
-            Identifier::Variable(var) => Some(var.koopa_def),
-            Identifier::ConstArray(arr) => Some(arr.koopa_def),
-            _ => None,
-        }
-    }
 }
@@ -5,21 +5,20 @@ pub mod initial_list;
 pub mod scope;
 
 use crate::front::ast::*;
+use crate::front::diagnostic::{Diagnostic, ErrorCode};
 use crate::front::ident::Identifier;
-use crate::util::logger::show_error;
 use crate::util::remove_pointer;
 use crate::{add_bb, add_inst, new_value};
-use context::Context;
-use eval::Eval;
+use builtin::Intrinsic;
+use context::{Context, Signature};
+use eval::{Constant, Eval, Number};
 use initial_list::InitializeList;
 use koopa::ir::builder::{GlobalInstBuilder, LocalInstBuilder, ValueBuilder};
 use koopa::ir::{BinaryOp, FunctionData, Type, TypeKind, Value};
 use scope::Scope;
 use std::rc::Rc;
 
-type Return = Option<Expr>;
-
-fn get_type(value: Value, ctx: &Context) -> Result<Type, ParseError> {
+fn get_type(value: Value, ctx: &Context) -> Result<Type, Diagnostic> {
     let ty = if ctx.func_data().is_ok() && ctx.func_data()?.dfg().values().contains_key(&value) {
         ctx.func_data()?.dfg().value(value).ty().clone()
     } else {
@@ -28,19 +27,39 @@ fn get_type(value: Value, ctx: &Context) -> Result<Type, ParseError> {
     Ok(ty)
 }
 
-fn get_array_type<T: Eval>(shape: &[T], scope: &mut Scope) -> Type {
+fn get_array_type(shape: &[i32]) -> Type {
     let mut param_type = Type::get_i32();
-    for i in shape.iter().rev() {
-        let v = i.eval(scope).unwrap_or(0);
-        if v <= 0 {
-            show_error("Array size must be greater than 0", 2);
-        }
-        param_type = Type::get_array(param_type, v as usize);
+    for len in shape.iter().rev() {
+        param_type = Type::get_array(param_type, *len as usize);
     }
     param_type
 }
 
-fn get_array_pos(array_elem: &ArrayElem, ctx: &mut Context) -> Result<Value, ParseError> {
+/// Evaluate the lengths of the dimensions of an array, which must be positive constants.
+fn eval_shape(shape: &[ConstExpr], scope: &mut Scope) -> Result<Vec<i32>, Diagnostic> {
+    shape.iter().map(|len| eval_array_len(len, scope)).collect()
+}
+
+/// Evaluate the length of one dimension of an array.
+pub fn eval_array_len<T: Constant>(
+    len: &ConstExpr,
+    scope: &mut Scope<T>,
+) -> Result<i32, Diagnostic> {
+    let value = len
+        .eval(scope)
+        .map_err(|e| Diagnostic::not_constant(e, len.span()))?
+        .as_int();
+    if value <= 0 {
+        return Err(Diagnostic::error(
+            ErrorCode::InvalidArraySize,
+            format!("array size must be greater than 0, found {}", value),
+        )
+        .with_span(len.span()));
+    }
+    Ok(value)
+}
+
+fn get_array_pos(array_elem: &ArrayElem, ctx: &mut Context) -> Result<Value, Diagnostic> {
     let indices = array_elem
         .indices
         .iter()
@@ -48,13 +67,23 @@ fn get_array_pos(array_elem: &ArrayElem, ctx: &mut Context) -> Result<Value, Par
         .collect::<Result<Vec<_>, _>>()?;
     let array = ctx
         .scope
-        .get_identifier(&array_elem.name)
-        .ok_or(ParseError::UnknownIdentifier)?
+        .get_definition(&array_elem.name)
+        .ok_or_else(|| Diagnostic::unknown_identifier(&array_elem.name, array_elem.span))?
         .clone();
     let array = match array {
-        Identifier::Variable(var) => var.koopa_def,
-        Identifier::ConstArray(const_array) => const_array.koopa_def,
-        _ => return Err(ParseError::InvalidExpr),
+        (Identifier::Variable(var), _) => var.koopa_def,
+        (Identifier::ConstArray(const_array), _) => const_array.koopa_def,
+        (_, def_span) => {
+            return Err(Diagnostic::error(
+                ErrorCode::NotAnArray,
+                format!("cannot index into `{}`", array_elem.name),
+            )
+            .with_span(array_elem.span)
+            .with_note(
+                def_span,
+                format!("`{}` is defined as a scalar here", array_elem.name),
+            ))
+        }
     };
 
     // Get offset
@@ -72,58 +101,105 @@ fn get_array_pos(array_elem: &ArrayElem, ctx: &mut Context) -> Result<Value, Par
             new_value!(ctx.func_data_mut()?).get_elem_ptr(result, index)
         };
         add_inst!(ctx.func_data_mut()?, bb, array_elem);
+        ctx.inherit_float(array_elem, result);
         result = array_elem;
     }
 
     Ok(result)
 }
 
-#[derive(Debug)]
-pub enum ParseError {
-    InvalidExpr,
-    FunctionNotFound,
-    BasicBlockNotFound,
-    UnknownIdentifier,
-    ConstExprError,
-    BreakOutsideLoop,
-    ContinueOutsideLoop,
-    MultipleDefinition,
+/// Generate a binary operation, which is on `float`s if one of the operands is a `float`.
+fn generate_binary(
+    op: BinaryOp,
+    lhs: Value,
+    rhs: Value,
+    ctx: &mut Context,
+) -> Result<Value, Diagnostic> {
+    if ctx.is_float(lhs) || ctx.is_float(rhs) {
+        let lhs = ctx.convert(lhs, DataType::Float)?;
+        let rhs = ctx.convert(rhs, DataType::Float)?;
+        return match op {
+            BinaryOp::Add => ctx.call_intrinsic(Intrinsic::FAdd, vec![lhs, rhs]),
+            BinaryOp::Sub => ctx.call_intrinsic(Intrinsic::FSub, vec![lhs, rhs]),
+            BinaryOp::Mul => ctx.call_intrinsic(Intrinsic::FMul, vec![lhs, rhs]),
+            BinaryOp::Div => ctx.call_intrinsic(Intrinsic::FDiv, vec![lhs, rhs]),
+            BinaryOp::Lt => ctx.call_intrinsic(Intrinsic::FLt, vec![lhs, rhs]),
+            BinaryOp::Gt => ctx.call_intrinsic(Intrinsic::FLt, vec![rhs, lhs]),
+            BinaryOp::Le => ctx.call_intrinsic(Intrinsic::FLe, vec![lhs, rhs]),
+            BinaryOp::Ge => ctx.call_intrinsic(Intrinsic::FLe, vec![rhs, lhs]),
+            BinaryOp::Eq => ctx.call_intrinsic(Intrinsic::FEq, vec![lhs, rhs]),
+            BinaryOp::NotEq => {
+                let equal = ctx.call_intrinsic(Intrinsic::FEq, vec![lhs, rhs])?;
+                let zero = 0.generate_ir(ctx)?;
+                generate_binary(BinaryOp::Eq, equal, zero, ctx)
+            }
+            _ => Err(Diagnostic::internal(format!(
+                "`{}` cannot be applied to `float`s",
+                op
+            ))),
+        };
+    }
+    let current_bb = ctx.get_bb()?;
+    let func_data = ctx.func_data_mut()?;
+    let value = new_value!(func_data).binary(op, lhs, rhs);
+    add_inst!(func_data, current_bb, value);
+    Ok(value)
+}
+
+/// Get whether a value is not zero, as 0 or 1.
+fn generate_bool(value: Value, ctx: &mut Context) -> Result<Value, Diagnostic> {
+    if ctx.is_float(value) {
+        return ctx.condition(value);
+    }
+    let zero = 0.generate_ir(ctx)?;
+    generate_binary(BinaryOp::NotEq, value, zero, ctx)
 }
 
 pub trait GenerateIR {
     type Output;
-    fn generate_ir(&self, ctx: &mut Context) -> Result<Self::Output, ParseError>;
+    fn generate_ir(&self, ctx: &mut Context) -> Result<Self::Output, Diagnostic>;
 }
 
 impl GenerateIR for i32 {
     type Output = Value;
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, Diagnostic> {
         Ok(new_value!(ctx.func_data_mut()?).integer(*self))
     }
 }
 
-impl GenerateIR for ConstExpr {
+impl GenerateIR for Number {
     type Output = Value;
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, ParseError> {
-        let val = self
-            .0
-            .eval(&mut ctx.scope)
-            .map_err(|_| ParseError::InvalidExpr)?;
-        if let Ok(_) = ctx.get_func() {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, Diagnostic> {
+        let value = if let Ok(_) = ctx.get_func() {
             let func_data = ctx.func_data_mut()?;
-            Ok(new_value!(func_data).integer(val))
+            new_value!(func_data).integer(self.bits())
         } else {
-            Ok(ctx.program.new_value().integer(val))
+            ctx.program.new_value().integer(self.bits())
+        };
+        if self.is_float() {
+            ctx.floats.insert(value);
         }
+        Ok(value)
+    }
+}
+
+impl GenerateIR for ConstExpr {
+    type Output = Value;
+
+    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, Diagnostic> {
+        self.0
+            .eval(&mut ctx.scope)
+            .map_err(|e| Diagnostic::not_constant(e, self.span()))?
+            .generate_ir(ctx)
     }
 }
 
 impl GenerateIR for Expr {
     type Output = Value;
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, Diagnostic> {
         self.0.generate_ir(ctx)
     }
 }
@@ -131,7 +207,7 @@ impl GenerateIR for Expr {
 impl GenerateIR for VarDef {
     type Output = Value;
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, Diagnostic> {
         let scope_id = ctx.scope.current_scope_id();
         match self {
             VarDef::NormalVarDef(normal_var_def) => {
@@ -147,10 +223,14 @@ impl GenerateIR for VarDef {
                         .dfg_mut()
                         .set_value_name(var_alloc, Some(var_name));
                     add_inst!(func_data, bb, var_alloc);
+                    if normal_var_def.ty == DataType::Float {
+                        ctx.floats.insert(var_alloc);
+                    }
 
                     if let Some(init) = &normal_var_def.value {
                         // store initial value
                         let init = init.generate_ir(ctx)?;
+                        let init = ctx.convert(init, normal_var_def.ty)?;
                         let store = new_value!(ctx.func_data_mut()?).store(init, var_alloc);
                         let current_bb = ctx.get_bb()?;
                         add_inst!(ctx.func_data_mut()?, current_bb, store);
@@ -160,85 +240,140 @@ impl GenerateIR for VarDef {
                         .add_identifier(
                             normal_var_def.name.clone(),
                             Identifier::from_variable(var_alloc),
+                            normal_var_def.span,
                         )
-                        .map_err(|_| ParseError::MultipleDefinition)?;
+                        .map_err(|previous| {
+                            Diagnostic::multiple_definition(
+                                &normal_var_def.name,
+                                normal_var_def.span,
+                                Some(previous),
+                            )
+                        })?;
                     Ok(var_alloc)
                 } else {
                     // global variable
-                    let val = normal_var_def
-                        .value
-                        .as_ref()
-                        .map(|x| x.eval(&mut ctx.scope).unwrap_or(0))
-                        .unwrap_or(0);
-                    let val = ctx.program.new_value().integer(val);
+                    let val = match &normal_var_def.value {
+                        Some(init) => init
+                            .eval(&mut ctx.scope)
+                            .map_err(|e| Diagnostic::not_constant(e, init.span()))?,
+                        None => Number::Int(0),
+                    };
+                    let val = ctx
+                        .program
+                        .new_value()
+                        .integer(val.convert(normal_var_def.ty).bits());
                     let var_alloc = ctx.program.new_value().global_alloc(val);
                     ctx.program.set_value_name(var_alloc, Some(var_name));
+                    if normal_var_def.ty == DataType::Float {
+                        ctx.floats.insert(var_alloc);
+                    }
                     ctx.scope
                         .add_identifier(
                             normal_var_def.name.clone(),
                             Identifier::from_variable(var_alloc),
+                            normal_var_def.span,
                         )
-                        .map_err(|_| ParseError::MultipleDefinition)?;
+                        .map_err(|previous| {
+                            Diagnostic::multiple_definition(
+                                &normal_var_def.name,
+                                normal_var_def.span,
+                                Some(previous),
+                            )
+                        })?;
                     Ok(var_alloc)
                 }
             }
 
             VarDef::ArrayVarDef(array_var) => {
                 let var_name = format!("@_{}_{}", scope_id, array_var.name);
-                let shape = array_var
-                    .shape
-                    .iter()
-                    .map(|x| x.eval(&mut ctx.scope).unwrap_or(0))
-                    .collect::<Vec<_>>();
+                let shape = eval_shape(&array_var.shape, &mut ctx.scope)?;
 
                 if ctx.is_global() {
                     // global array
                     let initial_list = if let Some(initial) = &array_var.values {
                         match initial {
-                            ExprArray::Val(_) => {
-                                show_error("Invalid array initialization", 2);
+                            ExprArray::Val(val) => {
+                                return Err(Diagnostic::invalid_initializer(
+                                    &array_var.name,
+                                    val.span(),
+                                ));
                             }
-                            ExprArray::Array(array) => {
-                                InitializeList::from_expr_array(&shape, array, ctx)
+                            ExprArray::Array(array, _) => {
+                                InitializeList::from_expr_array(&shape, array)
                             }
                         }
                     } else {
                         InitializeList::zero(&shape)
                     };
+                    if let InitializeList::NonZero(data, _) = &initial_list {
+                        for expr in data {
+                            expr.eval(&mut ctx.scope)
+                                .map_err(|e| Diagnostic::not_constant(e, expr.span()))?;
+                        }
+                    }
 
-                    let init_value = initial_list.to_global_value(ctx);
+                    let init_value = initial_list.to_global_value(array_var.ty, ctx);
                     let alloc = ctx.program.new_value().global_alloc(init_value);
                     ctx.program.set_value_name(alloc, Some(var_name));
+                    if array_var.ty == DataType::Float {
+                        ctx.floats.insert(alloc);
+                    }
                     ctx.scope
-                        .add_identifier(array_var.name.clone(), Identifier::from_variable(alloc))
-                        .map_err(|_| ParseError::MultipleDefinition)?;
+                        .add_identifier(
+                            array_var.name.clone(),
+                            Identifier::from_variable(alloc),
+                            array_var.span,
+                        )
+                        .map_err(|previous| {
+                            Diagnostic::multiple_definition(
+                                &array_var.name,
+                                array_var.span,
+                                Some(previous),
+                            )
+                        })?;
 
                     Ok(alloc)
                 } else {
                     // local array
-                    let array_type = get_array_type(&shape, &mut ctx.scope);
+                    let array_type = get_array_type(&shape);
                     let bb = ctx.get_bb()?;
                     let func_data = ctx.func_data_mut()?;
                     let alloc = new_value!(func_data).alloc(array_type);
                     func_data.dfg_mut().set_value_name(alloc, Some(var_name));
                     add_inst!(func_data, bb, alloc);
+                    if array_var.ty == DataType::Float {
+                        ctx.floats.insert(alloc);
+                    }
                     if let Some(initial) = &array_var.values {
                         let initial_list = match initial {
-                            ExprArray::Val(_) => {
-                                show_error("Invalid array initialization", 2);
+                            ExprArray::Val(val) => {
+                                return Err(Diagnostic::invalid_initializer(
+                                    &array_var.name,
+                                    val.span(),
+                                ));
                             }
-                            ExprArray::Array(array) => {
-                                InitializeList::from_expr_array(&shape, array, ctx)
+                            ExprArray::Array(array, _) => {
+                                InitializeList::from_expr_array(&shape, array)
                             }
                         };
                         // Get initial value and store it
-                        let init_value = initial_list.to_local_value(ctx);
+                        let init_value = initial_list.to_local_value(array_var.ty, ctx)?;
                         let store = new_value!(ctx.func_data_mut()?).store(init_value, alloc);
                         add_inst!(ctx.func_data_mut()?, bb, store);
                     }
                     ctx.scope
-                        .add_identifier(array_var.name.clone(), Identifier::from_variable(alloc))
-                        .map_err(|_| ParseError::MultipleDefinition)?;
+                        .add_identifier(
+                            array_var.name.clone(),
+                            Identifier::from_variable(alloc),
+                            array_var.span,
+                        )
+                        .map_err(|previous| {
+                            Diagnostic::multiple_definition(
+                                &array_var.name,
+                                array_var.span,
+                                Some(previous),
+                            )
+                        })?;
                     Ok(alloc)
                 }
             }
@@ -249,40 +384,50 @@ impl GenerateIR for VarDef {
 impl GenerateIR for ConstDef {
     type Output = ();
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<(), ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<(), Diagnostic> {
         match self {
             ConstDef::NormalConstDef(normal) => {
                 let val = normal
                     .value
                     .eval(&mut ctx.scope)
-                    .map_err(|_| ParseError::ConstExprError)?;
+                    .map_err(|e| Diagnostic::not_constant(e, normal.value.span()))?
+                    .convert(normal.ty);
                 ctx.scope
-                    .add_identifier(normal.name.clone(), Identifier::from_constant(val))
-                    .unwrap_or_else(|e| {
-                        show_error(&format!("{:?}", e), 2);
-                    });
+                    .add_identifier(
+                        normal.name.clone(),
+                        Identifier::from_constant(val),
+                        normal.span,
+                    )
+                    .map_err(|previous| {
+                        Diagnostic::multiple_definition(&normal.name, normal.span, Some(previous))
+                    })?;
                 Ok(())
             }
             ConstDef::ArrayConstDef(const_array) => {
                 let init = match &const_array.values {
-                    ConstArray::Val(_) => {
-                        show_error("Invalid array initialization", 2);
+                    ConstArray::Val(val) => {
+                        return Err(Diagnostic::invalid_initializer(
+                            &const_array.name,
+                            val.span(),
+                        ));
                     }
-                    ConstArray::Array(array) => array,
+                    ConstArray::Array(array, _) => array,
                 };
-                let initial_list = InitializeList::from_const_array(&const_array.shape, init, ctx);
+                let shape = eval_shape(&const_array.shape, &mut ctx.scope)?;
+                let initial_list =
+                    InitializeList::from_const_array(&shape, const_array.ty, init, &mut ctx.scope)?;
 
                 // Add const array to IR
                 let scope_id = ctx.scope.current_scope_id();
                 let array_name = format!("@_{}_{}", scope_id, const_array.name);
 
                 let koopa_def = if ctx.is_global() {
-                    let init_value = initial_list.to_global_value(ctx);
+                    let init_value = initial_list.to_global_value(const_array.ty, ctx);
                     let alloc = ctx.program.new_value().global_alloc(init_value);
                     ctx.program.set_value_name(alloc, Some(array_name));
                     alloc
                 } else {
-                    let array_type = get_array_type(&const_array.shape, &mut ctx.scope);
+                    let array_type = get_array_type(&shape);
                     let bb = ctx.get_bb()?;
                     let func_data = ctx.func_data_mut()?;
                     // allocate array
@@ -290,21 +435,29 @@ impl GenerateIR for ConstDef {
                     func_data.dfg_mut().set_value_name(alloc, Some(array_name));
                     add_inst!(func_data, bb, alloc);
                     // store initial value
-                    let init_value = initial_list.to_local_value(ctx);
+                    let init_value = initial_list.to_local_value(const_array.ty, ctx)?;
                     let store = new_value!(ctx.func_data_mut()?).store(init_value, alloc);
                     add_inst!(ctx.func_data_mut()?, bb, store);
                     alloc
                 };
+                if const_array.ty == DataType::Float {
+                    ctx.floats.insert(koopa_def);
+                }
 
                 // Add const array to identifier table
                 ctx.scope
                     .add_identifier(
                         const_array.name.clone(),
                         Identifier::from_const_array(koopa_def, initial_list),
+                        const_array.span,
                     )
-                    .unwrap_or_else(|e| {
-                        show_error(&format!("{:?}", e), 2);
-                    });
+                    .map_err(|previous| {
+                        Diagnostic::multiple_definition(
+                            &const_array.name,
+                            const_array.span,
+                            Some(previous),
+                        )
+                    })?;
 
                 Ok(())
             }
@@ -315,14 +468,14 @@ impl GenerateIR for ConstDef {
 impl GenerateIR for LVal {
     type Output = Value;
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, Diagnostic> {
         match self {
             // Normal variable
-            LVal::Var(var) => {
+            LVal::Var(var, span) => {
                 let ident = ctx
                     .scope
                     .get_identifier(var)
-                    .ok_or(ParseError::UnknownIdentifier)?
+                    .ok_or_else(|| Diagnostic::unknown_identifier(var, *span))?
                     .clone();
 
                 let val = match ident {
@@ -345,10 +498,18 @@ impl GenerateIR for LVal {
                                 load
                             }
                         };
+                        ctx.inherit_float(load, var_def);
                         load
                     }
                     Identifier::Constant(ref constant) => constant.value.generate_ir(ctx)?,
-                    _ => return Err(ParseError::InvalidExpr),
+                    Identifier::ConstArray(_) => {
+                        return Err(Diagnostic::error(
+                            ErrorCode::InvalidValue,
+                            format!("constant array `{}` cannot be used as a value", var),
+                        )
+                        .with_span(*span)
+                        .with_help("index the array to read one of its elements"))
+                    }
                 };
                 Ok(val)
             }
@@ -358,21 +519,23 @@ impl GenerateIR for LVal {
                 let pos_type = ctx.func_data_mut()?.dfg().value(pos).ty().clone();
                 let target_type = remove_pointer(pos_type);
 
-                match target_type.kind() {
+                let load = match target_type.kind() {
                     TypeKind::Int32 => {
                         let load = new_value!(ctx.func_data_mut()?).load(pos);
                         let bb = ctx.get_bb()?;
                         add_inst!(ctx.func_data_mut()?, bb, load);
-                        Ok(load)
+                        load
                     }
                     _ => {
                         let zero = 0.generate_ir(ctx)?;
                         let load = new_value!(ctx.func_data_mut()?).get_elem_ptr(pos, zero);
                         let bb = ctx.get_bb()?;
                         add_inst!(ctx.func_data_mut()?, bb, load);
-                        Ok(load)
+                        load
                     }
-                }
+                };
+                ctx.inherit_float(load, pos);
+                Ok(load)
             }
         }
     }
@@ -381,11 +544,14 @@ impl GenerateIR for LVal {
 impl GenerateIR for PrimaryExpr {
     type Output = Value;
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, Diagnostic> {
         match self {
-            PrimaryExpr::Expr(expr) => expr.generate_ir(ctx),
+            PrimaryExpr::Expr(expr, _) => expr.generate_ir(ctx),
             PrimaryExpr::LVal(lval) => lval.generate_ir(ctx),
-            PrimaryExpr::Number(n) => n.generate_ir(ctx),
+            PrimaryExpr::Number(n, _) => n.generate_ir(ctx),
+            PrimaryExpr::FloatNumber(bits, _) => {
+                Number::Float(f32::from_bits(*bits)).generate_ir(ctx)
+            }
         }
     }
 }
@@ -393,12 +559,31 @@ impl GenerateIR for PrimaryExpr {
 impl GenerateIR for UnaryExpr {
     type Output = Value;
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, Diagnostic> {
         match self {
             UnaryExpr::PrimaryExpr(expr) => expr.generate_ir(ctx),
             UnaryExpr::FuncCall(func_call) => func_call.generate_ir(ctx),
-            UnaryExpr::Unary(op, expr) => {
+            UnaryExpr::Unary(op, expr, _) => {
                 let expr = expr.generate_ir(ctx)?;
+                if ctx.is_float(expr) {
+                    return match op {
+                        UnaryOp::Pos => Ok(expr),
+                        UnaryOp::Neg => {
+                            // Flip the sign bit.
+                            let sign = i32::MIN.generate_ir(ctx)?;
+                            let current_bb = ctx.get_bb()?;
+                            let func_data = ctx.func_data_mut()?;
+                            let value = new_value!(func_data).binary(BinaryOp::Xor, expr, sign);
+                            add_inst!(func_data, current_bb, value);
+                            ctx.floats.insert(value);
+                            Ok(value)
+                        }
+                        UnaryOp::Not => {
+                            let zero = 0.generate_ir(ctx)?;
+                            ctx.call_intrinsic(Intrinsic::FEq, vec![expr, zero])
+                        }
+                    };
+                }
                 let current_bb = ctx.get_bb()?;
                 let func_data = ctx.func_data_mut()?;
                 let zero = new_value!(func_data).integer(0);
@@ -424,21 +609,30 @@ impl GenerateIR for UnaryExpr {
 impl GenerateIR for FuncCall {
     type Output = Value;
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, ParseError> {
-        let param_values = self
-            .args
-            .iter()
-            .map(|arg| arg.generate_ir(ctx))
-            .collect::<Result<Vec<Value>, ParseError>>()?;
+    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, Diagnostic> {
         let func_name = &self.name;
-        let func = ctx
-            .func_table
-            .get(func_name)
-            .copied()
-            .ok_or(ParseError::FunctionNotFound)?;
+        let func = ctx.func_table.get(func_name).copied().ok_or_else(|| {
+            Diagnostic::error(
+                ErrorCode::UnknownFunction,
+                format!("cannot find function `{}`", func_name),
+            )
+            .with_span(self.span)
+        })?;
+        let signature = ctx.signatures[func_name].clone();
+        let mut param_values = vec![];
+        for (arg, ty) in self.args.iter().zip(&signature.params) {
+            let value = arg.generate_ir(ctx)?;
+            param_values.push(match ty {
+                Some(ty) => ctx.convert(value, *ty)?,
+                None => value,
+            });
+        }
         let ret_val = new_value!(ctx.func_data_mut()?).call(func, param_values);
         let current_bb = ctx.get_bb()?;
         add_inst!(ctx.func_data_mut()?, current_bb, ret_val);
+        if signature.ret_type == DataType::Float {
+            ctx.floats.insert(ret_val);
+        }
         Ok(ret_val)
     }
 }
@@ -446,17 +640,13 @@ impl GenerateIR for FuncCall {
 impl GenerateIR for MulExpr {
     type Output = Value;
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, Diagnostic> {
         match self {
             MulExpr::UnaryExpr(expr) => expr.generate_ir(ctx),
             MulExpr::Mul(lhs, op, rhs) => {
                 let lhs = lhs.generate_ir(ctx)?;
                 let rhs = rhs.generate_ir(ctx)?;
-                let current_bb = ctx.get_bb()?;
-                let func_data = ctx.func_data_mut()?;
-                let value = new_value!(func_data).binary((*op).into(), lhs, rhs);
-                add_inst!(func_data, current_bb, value);
-                Ok(value)
+                generate_binary((*op).into(), lhs, rhs, ctx)
             }
         }
     }
@@ -465,17 +655,13 @@ impl GenerateIR for MulExpr {
 impl GenerateIR for AddExpr {
     type Output = Value;
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, Diagnostic> {
         match self {
             AddExpr::MulExpr(expr) => expr.generate_ir(ctx),
             AddExpr::Add(lhs, op, rhs) => {
                 let lhs = lhs.generate_ir(ctx)?;
                 let rhs = rhs.generate_ir(ctx)?;
-                let current_bb = ctx.get_bb()?;
-                let func_data = ctx.func_data_mut()?;
-                let value = new_value!(func_data).binary((*op).into(), lhs, rhs);
-                add_inst!(func_data, current_bb, value);
-                Ok(value)
+                generate_binary((*op).into(), lhs, rhs, ctx)
             }
         }
     }
@@ -484,17 +670,13 @@ impl GenerateIR for AddExpr {
 impl GenerateIR for RelExpr {
     type Output = Value;
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, Diagnostic> {
         match self {
             RelExpr::AddExpr(expr) => expr.generate_ir(ctx),
             RelExpr::Rel(lhs, op, rhs) => {
                 let lhs = lhs.generate_ir(ctx)?;
                 let rhs = rhs.generate_ir(ctx)?;
-                let current_bb = ctx.get_bb()?;
-                let func_data = ctx.func_data_mut()?;
-                let value = new_value!(func_data).binary((*op).into(), lhs, rhs);
-                add_inst!(func_data, current_bb, value);
-                Ok(value)
+                generate_binary((*op).into(), lhs, rhs, ctx)
             }
         }
     }
@@ -503,17 +685,13 @@ impl GenerateIR for RelExpr {
 impl GenerateIR for EqExpr {
     type Output = Value;
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, Diagnostic> {
         match self {
             EqExpr::RelExpr(expr) => expr.generate_ir(ctx),
             EqExpr::Eq(lhs, op, rhs) => {
                 let lhs = lhs.generate_ir(ctx)?;
                 let rhs = rhs.generate_ir(ctx)?;
-                let current_bb = ctx.get_bb()?;
-                let func_data = ctx.func_data_mut()?;
-                let value = new_value!(func_data).binary((*op).into(), lhs, rhs);
-                add_inst!(func_data, current_bb, value);
-                Ok(value)
+                generate_binary((*op).into(), lhs, rhs, ctx)
             }
         }
     }
@@ -522,7 +700,7 @@ impl GenerateIR for EqExpr {
 impl GenerateIR for LAndExpr {
     type Output = Value;
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, Diagnostic> {
         match self {
             LAndExpr::EqExpr(eq_expr) => eq_expr.generate_ir(ctx),
             LAndExpr::And(lhs, rhs) => {
@@ -536,9 +714,7 @@ impl GenerateIR for LAndExpr {
                     .set_value_name(result, Some(result_name));
                 let current_bb = ctx.get_bb()?;
                 add_inst!(ctx.func_data_mut()?, current_bb, result);
-                let zero = 0.generate_ir(ctx)?;
-                let lhs = new_value!(ctx.func_data_mut()?).binary(BinaryOp::NotEq, lhs, zero);
-                add_inst!(ctx.func_data_mut()?, current_bb, lhs);
+                let lhs = generate_bool(lhs, ctx)?;
                 let store = new_value!(ctx.func_data_mut()?).store(lhs, result);
                 add_inst!(ctx.func_data_mut()?, current_bb, store);
 
@@ -555,10 +731,8 @@ impl GenerateIR for LAndExpr {
                 add_bb!(ctx.func_data_mut()?, true_bb);
                 ctx.current_bb = Some(true_bb);
                 let rhs = rhs.generate_ir(ctx)?;
+                let rhs = generate_bool(rhs, ctx)?;
                 let current_bb = ctx.get_bb()?;
-                let zero = 0.generate_ir(ctx)?;
-                let rhs = new_value!(ctx.func_data_mut()?).binary(BinaryOp::NotEq, rhs, zero);
-                add_inst!(ctx.func_data_mut()?, current_bb, rhs);
                 let store = new_value!(ctx.func_data_mut()?).store(rhs, result);
                 add_inst!(ctx.func_data_mut()?, current_bb, store);
                 // Jump to end_bb
@@ -578,7 +752,7 @@ impl GenerateIR for LAndExpr {
 impl GenerateIR for LOrExpr {
     type Output = Value;
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<Value, Diagnostic> {
         match self {
             LOrExpr::LAndExpr(and_expr) => and_expr.generate_ir(ctx),
             LOrExpr::Or(lhs, rhs) => {
@@ -591,9 +765,7 @@ impl GenerateIR for LOrExpr {
                     .set_value_name(result, Some(result_name));
                 let current_bb = ctx.get_bb()?;
                 add_inst!(ctx.func_data_mut()?, current_bb, result);
-                let zero = 0.generate_ir(ctx)?;
-                let lhs = new_value!(ctx.func_data_mut()?).binary(BinaryOp::NotEq, lhs, zero);
-                add_inst!(ctx.func_data_mut()?, current_bb, lhs);
+                let lhs = generate_bool(lhs, ctx)?;
                 let store = new_value!(ctx.func_data_mut()?).store(lhs, result);
                 add_inst!(ctx.func_data_mut()?, current_bb, store);
 
@@ -610,10 +782,8 @@ impl GenerateIR for LOrExpr {
                 add_bb!(ctx.func_data_mut()?, false_bb);
                 ctx.current_bb = Some(false_bb);
                 let rhs = rhs.generate_ir(ctx)?;
+                let rhs = generate_bool(rhs, ctx)?;
                 let current_bb = ctx.get_bb()?;
-                let zero = 0.generate_ir(ctx)?;
-                let rhs = new_value!(ctx.func_data_mut()?).binary(BinaryOp::NotEq, rhs, zero);
-                add_inst!(ctx.func_data_mut()?, current_bb, rhs);
                 let store = new_value!(ctx.func_data_mut()?).store(rhs, result);
                 add_inst!(ctx.func_data_mut()?, current_bb, store);
                 // Jump to end_bb
@@ -633,13 +803,32 @@ impl GenerateIR for LOrExpr {
 impl GenerateIR for FuncDef {
     type Output = ();
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<(), ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<(), Diagnostic> {
+        if ctx.func_table.contains_key(&self.name) {
+            let previous = ctx.func_spans.get(&self.name).copied();
+            return Err(Diagnostic::multiple_definition(
+                &self.name, self.span, previous,
+            ));
+        }
         let ret_type = self.ret_type.into();
-        let func_params = get_func_param(&self.params, &mut ctx.scope);
+        let func_params = get_func_param(&self.params, &mut ctx.scope)?;
         let func_data =
             FunctionData::with_param_names("@".to_string() + &self.name, func_params, ret_type);
         let func = ctx.program.new_func(func_data);
         ctx.func_table.insert(self.name.clone(), func);
+        let signature = Signature {
+            params: self
+                .params
+                .iter()
+                .map(|param| match param.as_ref() {
+                    FuncFParam::NormalFParam(param) => Some(param.ty),
+                    FuncFParam::ArrayFParam(_) => None,
+                })
+                .collect(),
+            ret_type: self.ret_type,
+        };
+        ctx.signatures.insert(self.name.clone(), signature);
+        ctx.func_spans.insert(self.name.clone(), self.span);
         ctx.func = Some(func);
 
         // Restore parameters because they may be modified in function body
@@ -648,7 +837,7 @@ impl GenerateIR for FuncDef {
         ctx.scope.go_into_scoop(self.body.id);
         add_bb!(func_data, store_bb);
         let params = func_data.params().iter().copied().collect::<Vec<_>>();
-        for param in params {
+        for (param, param_def) in params.into_iter().zip(&self.params) {
             // TODO: When a parameter is not reassigned, we don't need to allocate a new space
             let param_data = func_data.dfg().value(param);
             let param_name = param_data
@@ -665,37 +854,48 @@ impl GenerateIR for FuncDef {
             let store = new_value!(func_data).store(param, alloc_param);
             add_inst!(func_data, store_bb, alloc_param);
             add_inst!(func_data, store_bb, store);
-            ctx.scope
-                .add_identifier(param_name, Identifier::from_variable(alloc_param))
-                .map_err(|e| show_error(&format!("{:?}", e), 2))?;
+            if param_def.ty() == DataType::Float {
+                ctx.floats.insert(alloc_param);
+            }
+            if let Err(previous) = ctx.scope.add_identifier(
+                param_name.clone(),
+                Identifier::from_variable(alloc_param),
+                param_def.span(),
+            ) {
+                ctx.diagnostics.push(Diagnostic::multiple_definition(
+                    &param_name,
+                    param_def.span(),
+                    Some(previous),
+                ));
+            }
         }
 
-        self.body.generate_ir(ctx)?;
+        let result = self.body.generate_ir(ctx);
         ctx.scope.go_out_scoop();
         ctx.func = None;
-        Ok(())
+        result
     }
 }
 
 impl GenerateIR for Stmt {
     type Output = ();
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<Self::Output, ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<Self::Output, Diagnostic> {
         match self {
             Stmt::Assign(assign) => assign.generate_ir(ctx),
             Stmt::Expr(expr) => expr.generate_ir(ctx).map(|_| ()),
             Stmt::Block(block) => {
                 ctx.scope.go_into_scoop(block.id);
-                block.generate_ir(ctx)?;
+                let result = block.generate_ir(ctx);
                 ctx.scope.go_out_scoop();
-                Ok(())
+                result
             }
             Stmt::If(if_stmt) => if_stmt.generate_ir(ctx),
             Stmt::While(while_stmt) => while_stmt.generate_ir(ctx),
             Stmt::Return(ret) => ret.generate_ir(ctx),
             Stmt::Break(break_stmt) => break_stmt.generate_ir(ctx),
             Stmt::Continue(continue_stmt) => continue_stmt.generate_ir(ctx),
-            Stmt::Empty => Ok(()),
+            Stmt::Empty(_) => Ok(()),
         }
     }
 }
@@ -703,10 +903,13 @@ impl GenerateIR for Stmt {
 impl GenerateIR for Break {
     type Output = ();
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<(), ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<(), Diagnostic> {
         let end_bb = ctx
             .get_while_info()
-            .ok_or(ParseError::BreakOutsideLoop)?
+            .ok_or_else(|| {
+                Diagnostic::error(ErrorCode::BreakOutsideLoop, "`break` outside of a loop")
+                    .with_span(self.span)
+            })?
             .end_bb;
         let jump = new_value!(ctx.func_data_mut()?).jump(end_bb);
         let bb = ctx.get_bb()?;
@@ -718,10 +921,16 @@ impl GenerateIR for Break {
 impl GenerateIR for Continue {
     type Output = ();
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<(), ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<(), Diagnostic> {
         let start_bb = ctx
             .get_while_info()
-            .ok_or(ParseError::ContinueOutsideLoop)?
+            .ok_or_else(|| {
+                Diagnostic::error(
+                    ErrorCode::ContinueOutsideLoop,
+                    "`continue` outside of a loop",
+                )
+                .with_span(self.span)
+            })?
             .start_bb;
         let jump = new_value!(ctx.func_data_mut()?).jump(start_bb);
         let bb = ctx.get_bb()?;
@@ -732,13 +941,14 @@ impl GenerateIR for Continue {
 
 impl GenerateIR for While {
     type Output = ();
-    fn generate_ir(&self, ctx: &mut Context) -> Result<Self::Output, ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<Self::Output, Diagnostic> {
         let body_bb = ctx.new_bb()?;
         let end_bb = ctx.new_bb()?;
         let start_bb = ctx.new_bb()?;
         add_bb!(ctx.func_data_mut()?, start_bb);
         ctx.current_bb = Some(start_bb);
         let cond_value = self.cond.generate_ir(ctx)?;
+        let cond_value = ctx.condition(cond_value)?;
         let start_branch_bb = ctx.get_bb()?;
         // branch to body or end
         let branch = new_value!(ctx.func_data_mut()?).branch(cond_value, body_bb, end_bb);
@@ -748,7 +958,9 @@ impl GenerateIR for While {
         // generate body
         ctx.add_while_info(start_bb, end_bb);
         ctx.current_bb = Some(body_bb);
-        self.body.generate_ir(ctx)?;
+        let body = self.body.generate_ir(ctx);
+        ctx.pop_while_info();
+        body?;
 
         // jump to start_bb
         let body_bb_end = ctx.get_bb()?;
@@ -762,7 +974,6 @@ impl GenerateIR for While {
         }
         add_bb!(ctx.func_data_mut()?, end_bb);
         ctx.current_bb = Some(end_bb);
-        ctx.pop_while_info();
         Ok(())
     }
 }
@@ -770,22 +981,24 @@ impl GenerateIR for While {
 impl GenerateIR for Block {
     type Output = ();
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<(), ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<(), Diagnostic> {
         let bb = ctx.new_bb()?;
         let func_data = ctx.func_data_mut()?;
         add_bb!(func_data, bb);
         ctx.current_bb = Some(bb);
         for block_item in &self.items {
-            match block_item {
-                BlockItem::Stmt(stmt) => match stmt {
-                    Stmt::Return(_) | Stmt::Break(_) | Stmt::Continue(_) => {
-                        stmt.generate_ir(ctx)?;
-                        // Once return, break or continue is called, we don't need to generate any more IR
-                        break;
-                    }
-                    _ => stmt.generate_ir(ctx)?,
-                },
-                BlockItem::Decl(decl) => decl.generate_ir(ctx)?,
+            let result = match block_item {
+                BlockItem::Stmt(stmt) => stmt.generate_ir(ctx),
+                BlockItem::Decl(decl) => decl.generate_ir(ctx),
+            };
+            if let Err(diagnostic) = result {
+                ctx.diagnostics.push(diagnostic);
+            }
+            // Once return, break or continue is called, we don't need to generate any more IR
+            if let BlockItem::Stmt(Stmt::Return(_) | Stmt::Break(_) | Stmt::Continue(_)) =
+                block_item
+            {
+                break;
             }
         }
         let bb = ctx.new_bb()?;
@@ -799,16 +1012,20 @@ impl GenerateIR for Block {
 impl GenerateIR for Decl {
     type Output = ();
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<(), ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<(), Diagnostic> {
         match self {
             Decl::ConstDecl(const_decl) => {
                 for const_def in const_decl {
-                    const_def.generate_ir(ctx)?;
+                    if let Err(diagnostic) = const_def.generate_ir(ctx) {
+                        ctx.diagnostics.push(diagnostic);
+                    }
                 }
             }
             Decl::VarDecl(var_decl) => {
                 for var_def in var_decl {
-                    var_def.generate_ir(ctx)?;
+                    if let Err(diagnostic) = var_def.generate_ir(ctx) {
+                        ctx.diagnostics.push(diagnostic);
+                    }
                 }
             }
         }
@@ -819,9 +1036,10 @@ impl GenerateIR for Decl {
 impl GenerateIR for If {
     type Output = ();
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<Self::Output, ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<Self::Output, Diagnostic> {
         // TODO: Modify cond
         let cond = self.cond.generate_ir(ctx)?;
+        let cond = ctx.condition(cond)?;
         let current_bb = ctx.get_bb()?;
         let then_bb = ctx.new_bb()?;
         add_bb!(ctx.func_data_mut()?, then_bb);
@@ -858,15 +1076,18 @@ impl GenerateIR for If {
 impl GenerateIR for Return {
     type Output = ();
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<(), ParseError> {
-        if let Some(expr) = self {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<(), Diagnostic> {
+        if let Some(expr) = &self.value {
+            let ret_type = ctx.ret_type()?;
             if let Ok(ret_val) = expr.eval(&mut ctx.scope) {
-                let ret_val = new_value!(ctx.func_data_mut()?).integer(ret_val);
+                let ret_val =
+                    new_value!(ctx.func_data_mut()?).integer(ret_val.convert(ret_type).bits());
                 let ret = new_value!(ctx.func_data_mut()?).ret(Some(ret_val));
                 let bb = ctx.get_bb()?;
                 add_inst!(ctx.func_data_mut()?, bb, ret);
             } else {
                 let val = expr.generate_ir(ctx)?;
+                let val = ctx.convert(val, ret_type)?;
                 let ret = new_value!(ctx.func_data_mut()?).ret(Some(val));
                 let bb = ctx.get_bb()?;
                 add_inst!(ctx.func_data_mut()?, bb, ret);
@@ -883,16 +1104,26 @@ impl GenerateIR for Return {
 impl GenerateIR for Assign {
     type Output = ();
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<(), ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<(), Diagnostic> {
         match self.target {
-            LVal::Var(ref var) => {
+            LVal::Var(ref var, span) => {
                 let val = self.value.generate_ir(ctx)?;
-                let var_decl = ctx
-                    .scope
-                    .get_identifier(var)
-                    .map(|x| x.koopa_def())
-                    .ok_or(ParseError::UnknownIdentifier)?
-                    .ok_or(ParseError::UnknownIdentifier)?;
+                let var_decl = match ctx.scope.get_definition(var) {
+                    Some((Identifier::Variable(variable), _)) => variable.koopa_def,
+                    Some((_, def_span)) => {
+                        return Err(Diagnostic::error(
+                            ErrorCode::AssignToConstant,
+                            format!("cannot assign to constant `{}`", var),
+                        )
+                        .with_span(span)
+                        .with_note(
+                            *def_span,
+                            format!("`{}` is defined as a constant here", var),
+                        ))
+                    }
+                    None => return Err(Diagnostic::unknown_identifier(var, span)),
+                };
+                let val = ctx.convert(val, ctx.data_type(var_decl))?;
                 let store = new_value!(ctx.func_data_mut()?).store(val, var_decl);
                 let bb = ctx.get_bb()?;
                 add_inst!(ctx.func_data_mut()?, bb, store);
@@ -903,6 +1134,7 @@ impl GenerateIR for Assign {
 
                 // Store value
                 let val = self.value.generate_ir(ctx)?;
+                let val = ctx.convert(val, ctx.data_type(pos))?;
                 let store = new_value!(ctx.func_data_mut()?).store(val, pos);
                 let bb = ctx.get_bb()?;
                 add_inst!(ctx.func_data_mut()?, bb, store);
@@ -915,22 +1147,24 @@ impl GenerateIR for Assign {
 impl GenerateIR for CompUnit {
     type Output = ();
 
-    fn generate_ir(&self, ctx: &mut Context) -> Result<(), ParseError> {
+    fn generate_ir(&self, ctx: &mut Context) -> Result<(), Diagnostic> {
         for item in &self.items {
-            match item {
-                GlobalItem::Decl(decl) => {
-                    decl.generate_ir(ctx)?;
-                }
-                GlobalItem::FuncDef(func_def) => {
-                    func_def.generate_ir(ctx)?;
-                }
+            let result = match item {
+                GlobalItem::Decl(decl) => decl.generate_ir(ctx),
+                GlobalItem::FuncDef(func_def) => func_def.generate_ir(ctx),
+            };
+            if let Err(diagnostic) = result {
+                ctx.diagnostics.push(diagnostic);
             }
         }
         Ok(())
     }
 }
 
-fn get_func_param(params: &Vec<Rc<FuncFParam>>, scope: &mut Scope) -> Vec<(Option<String>, Type)> {
+fn get_func_param(
+    params: &Vec<Rc<FuncFParam>>,
+    scope: &mut Scope,
+) -> Result<Vec<(Option<String>, Type)>, Diagnostic> {
     let mut func_params = vec![];
     for param in params {
         match param.as_ref() {
@@ -943,11 +1177,12 @@ fn get_func_param(params: &Vec<Rc<FuncFParam>>, scope: &mut Scope) -> Vec<(Optio
                 } else {
                     &array_param.shape[1..]
                 };
-                let param_type = Type::get_pointer(get_array_type(shape, scope));
+                let shape = eval_shape(shape, scope)?;
+                let param_type = Type::get_pointer(get_array_type(&shape));
                 func_params.push((Some("@".to_string() + &array_param.name), param_type));
             }
         }
     }
 
-    func_params
+    Ok(func_params)
 }
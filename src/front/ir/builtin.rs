@@ -1,19 +1,34 @@
+use crate::front::ast::DataType;
+use crate::front::ir::context::{Context, Signature};
 use koopa::ir::{Function, FunctionData, Program, Type};
-use std::collections::HashMap;
 
 #[derive(Copy, Clone)]
-enum IRType {
+pub enum IRType {
     Void,
     Int,
+    Float,
     IntPointer,
+    FloatPointer,
+}
+
+impl IRType {
+    /// Get the type of a scalar or of the return value, `None` for pointers.
+    pub fn data_type(self) -> Option<DataType> {
+        match self {
+            IRType::Void => Some(DataType::Void),
+            IRType::Int => Some(DataType::Int),
+            IRType::Float => Some(DataType::Float),
+            IRType::IntPointer | IRType::FloatPointer => None,
+        }
+    }
 }
 
 impl Into<Type> for IRType {
     fn into(self) -> Type {
         match self {
             IRType::Void => Type::get_unit(),
-            IRType::Int => Type::get_i32(),
-            IRType::IntPointer => Type::get_pointer(Type::get_i32()),
+            IRType::Int | IRType::Float => Type::get_i32(),
+            IRType::IntPointer | IRType::FloatPointer => Type::get_pointer(Type::get_i32()),
         }
     }
 }
@@ -24,7 +39,7 @@ struct FuncDecl {
     ret: IRType,
 }
 
-const BUILTIN_FUNCTIONS: [FuncDecl; 8] = [
+const BUILTIN_FUNCTIONS: [FuncDecl; 12] = [
     FuncDecl {
         name: "getint",
         params: &[],
@@ -35,11 +50,21 @@ const BUILTIN_FUNCTIONS: [FuncDecl; 8] = [
         params: &[],
         ret: IRType::Int,
     },
+    FuncDecl {
+        name: "getfloat",
+        params: &[],
+        ret: IRType::Float,
+    },
     FuncDecl {
         name: "getarray",
         params: &[IRType::IntPointer],
         ret: IRType::Int,
     },
+    FuncDecl {
+        name: "getfarray",
+        params: &[IRType::FloatPointer],
+        ret: IRType::Int,
+    },
     FuncDecl {
         name: "putint",
         params: &[IRType::Int],
@@ -50,11 +75,21 @@ const BUILTIN_FUNCTIONS: [FuncDecl; 8] = [
         params: &[IRType::Int],
         ret: IRType::Void,
     },
+    FuncDecl {
+        name: "putfloat",
+        params: &[IRType::Float],
+        ret: IRType::Void,
+    },
     FuncDecl {
         name: "putarray",
         params: &[IRType::Int, IRType::IntPointer],
         ret: IRType::Void,
     },
+    FuncDecl {
+        name: "putfarray",
+        params: &[IRType::Int, IRType::FloatPointer],
+        ret: IRType::Void,
+    },
     FuncDecl {
         name: "starttime",
         params: &[],
@@ -67,7 +102,7 @@ const BUILTIN_FUNCTIONS: [FuncDecl; 8] = [
     },
 ];
 
-pub fn generate_builtin_decl(program: &mut Program, func_table: &mut HashMap<String, Function>) {
+pub fn generate_builtin_decl(ctx: &mut Context) {
     for builtin_func in BUILTIN_FUNCTIONS {
         let func_data = FunctionData::new_decl(
             "@".to_string() + builtin_func.name,
@@ -79,7 +114,160 @@ pub fn generate_builtin_decl(program: &mut Program, func_table: &mut HashMap<Str
                 .collect(),
             builtin_func.ret.into(),
         );
-        let func = program.new_func(func_data);
-        func_table.insert(builtin_func.name.to_string(), func);
+        let func = ctx.program.new_func(func_data);
+        ctx.func_table.insert(builtin_func.name.to_string(), func);
+        let signature = Signature {
+            params: builtin_func
+                .params
+                .iter()
+                .map(|param| param.data_type())
+                .collect(),
+            ret_type: builtin_func.ret.data_type().unwrap(),
+        };
+        ctx.signatures
+            .insert(builtin_func.name.to_string(), signature);
+    }
+}
+
+/// Get the names, parameter types and return types of the runtime library functions.
+pub fn builtin_functions() -> impl Iterator<Item = (&'static str, &'static [IRType], IRType)> {
+    BUILTIN_FUNCTIONS
+        .iter()
+        .map(|builtin_func| (builtin_func.name, builtin_func.params, builtin_func.ret))
+}
+
+/// Check if a function is one of the runtime library functions, given its Koopa IR name.
+pub fn is_builtin(name: &str) -> bool {
+    BUILTIN_FUNCTIONS
+        .iter()
+        .any(|builtin_func| name.strip_prefix('@') == Some(builtin_func.name))
+}
+
+/// Operations Koopa IR has no instruction for.
+///
+/// Koopa IR has no float type, so `float`s are held as their bits in `i32`s, and operations on
+/// them are calls to these functions, as is the high word of a product, used to divide by
+/// constants. The functions are declared when they are first used, and the backend lowers the
+/// calls to RV32F and RV32M instructions. Koopa IR using them can only be run by the interpreter,
+/// or linked with an implementation of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Intrinsic {
+    FAdd,
+    FSub,
+    FMul,
+    FDiv,
+    FLt,
+    FLe,
+    FEq,
+    IntToFloat,
+    FloatToInt,
+    /// The high 32 bits of the 64-bit product of two `int`s.
+    MulHigh,
+}
+
+const INTRINSICS: [Intrinsic; 10] = [
+    Intrinsic::FAdd,
+    Intrinsic::FSub,
+    Intrinsic::FMul,
+    Intrinsic::FDiv,
+    Intrinsic::FLt,
+    Intrinsic::FLe,
+    Intrinsic::FEq,
+    Intrinsic::IntToFloat,
+    Intrinsic::FloatToInt,
+    Intrinsic::MulHigh,
+];
+
+/// Bits of the NaN produced by RV32F operations.
+const CANONICAL_NAN: u32 = 0x7fc0_0000;
+
+impl Intrinsic {
+    pub fn name(self) -> &'static str {
+        match self {
+            Intrinsic::FAdd => "__sysy_fadd",
+            Intrinsic::FSub => "__sysy_fsub",
+            Intrinsic::FMul => "__sysy_fmul",
+            Intrinsic::FDiv => "__sysy_fdiv",
+            Intrinsic::FLt => "__sysy_flt",
+            Intrinsic::FLe => "__sysy_fle",
+            Intrinsic::FEq => "__sysy_feq",
+            Intrinsic::IntToFloat => "__sysy_itof",
+            Intrinsic::FloatToInt => "__sysy_ftoi",
+            Intrinsic::MulHigh => "__sysy_mulh",
+        }
+    }
+
+    /// Get an intrinsic from the Koopa IR name of its function.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.strip_prefix('@')?;
+        INTRINSICS
+            .into_iter()
+            .find(|intrinsic| intrinsic.name() == name)
+    }
+
+    pub fn arity(self) -> usize {
+        match self {
+            Intrinsic::IntToFloat | Intrinsic::FloatToInt => 1,
+            _ => 2,
+        }
+    }
+
+    /// Whether the arguments are `float`s rather than `int`s.
+    pub fn takes_float(self) -> bool {
+        !matches!(self, Intrinsic::IntToFloat | Intrinsic::MulHigh)
+    }
+
+    /// Whether the result is a `float` rather than an `int`.
+    pub fn returns_float(self) -> bool {
+        matches!(
+            self,
+            Intrinsic::FAdd
+                | Intrinsic::FSub
+                | Intrinsic::FMul
+                | Intrinsic::FDiv
+                | Intrinsic::IntToFloat
+        )
+    }
+
+    /// Evaluate the intrinsic on the bits of its arguments, with the results of RV32F and RV32M.
+    pub fn eval(self, args: &[i32]) -> i32 {
+        let float = |i: usize| f32::from_bits(args[i] as u32);
+        let result = |value: f32| {
+            if value.is_nan() {
+                CANONICAL_NAN as i32
+            } else {
+                value.to_bits() as i32
+            }
+        };
+        match self {
+            Intrinsic::FAdd => result(float(0) + float(1)),
+            Intrinsic::FSub => result(float(0) - float(1)),
+            Intrinsic::FMul => result(float(0) * float(1)),
+            Intrinsic::FDiv => result(float(0) / float(1)),
+            Intrinsic::FLt => (float(0) < float(1)) as i32,
+            Intrinsic::FLe => (float(0) <= float(1)) as i32,
+            Intrinsic::FEq => (float(0) == float(1)) as i32,
+            Intrinsic::IntToFloat => result(args[0] as f32),
+            Intrinsic::FloatToInt => float_to_int(float(0)),
+            Intrinsic::MulHigh => ((args[0] as i64 * args[1] as i64) >> 32) as i32,
+        }
+    }
+
+    /// Declare the function of the intrinsic in a program.
+    pub fn declare(self, program: &mut Program) -> Function {
+        let params = vec![Type::get_i32(); self.arity()];
+        let func_data =
+            FunctionData::new_decl("@".to_string() + self.name(), params, Type::get_i32());
+        program.new_func(func_data)
+    }
+}
+
+/// Convert a `float` to an `int` like `fcvt.w.s` rounding toward zero does, saturating out of
+/// range values and giving the largest `int` for NaN.
+pub fn float_to_int(value: f32) -> i32 {
+    if value.is_nan() {
+        i32::MAX
+    } else {
+        value as i32
     }
 }
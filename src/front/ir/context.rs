@@ -1,9 +1,13 @@
+use crate::front::ast::{DataType, Span};
+use crate::front::diagnostic::{Diagnostic, Diagnostics};
+use crate::front::ir::builtin::Intrinsic;
 use crate::front::ir::scope::Scope;
-use crate::front::ir::ParseError;
 use crate::{add_inst, new_bb, new_value};
 use koopa::ir::builder::{BasicBlockBuilder, LocalInstBuilder, ValueBuilder};
-use koopa::ir::{BasicBlock, Function, FunctionData, Program, TypeKind, ValueKind};
-use std::collections::HashMap;
+use koopa::ir::{
+    BasicBlock, BinaryOp, Function, FunctionData, Program, TypeKind, Value, ValueKind,
+};
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Clone)]
 pub struct WhileInfo {
@@ -11,6 +15,15 @@ pub struct WhileInfo {
     pub end_bb: BasicBlock,
 }
 
+/// Types of the parameters and of the return value of a function, which Koopa IR does not tell
+/// apart since `float`s are held in `i32`s.
+#[derive(Debug, Clone)]
+pub struct Signature {
+    /// Types of the parameters, `None` for arrays, which are passed as pointers.
+    pub params: Vec<Option<DataType>>,
+    pub ret_type: DataType,
+}
+
 /// Context of the IR generation
 pub struct Context {
     pub program: Program,
@@ -21,6 +34,14 @@ pub struct Context {
     pub max_temp_value_id: usize,
     pub while_info: Vec<WhileInfo>,
     pub func_table: HashMap<String, Function>,
+    pub signatures: HashMap<String, Signature>,
+    /// Values that are `float`s, or pointers to `float`s at any depth.
+    pub floats: HashSet<Value>,
+    /// Functions of the intrinsics used so far.
+    pub intrinsics: HashMap<Intrinsic, Function>,
+    /// Spans of the functions defined in the source, which are not in the runtime library.
+    pub func_spans: HashMap<String, Span>,
+    pub diagnostics: Diagnostics,
 }
 
 impl Context {
@@ -34,35 +55,44 @@ impl Context {
             max_temp_value_id: 0,
             while_info: vec![],
             func_table: HashMap::new(),
+            signatures: HashMap::new(),
+            floats: HashSet::new(),
+            intrinsics: HashMap::new(),
+            func_spans: HashMap::new(),
+            diagnostics: Diagnostics::new(),
         }
     }
 
-    pub fn new_bb(&mut self) -> Result<BasicBlock, ParseError> {
+    pub fn new_bb(&mut self) -> Result<BasicBlock, Diagnostic> {
         self.max_basic_block_id += 1;
-        self.func.ok_or(ParseError::FunctionNotFound).map(|func| {
-            let func_data = self.program.func_mut(func);
-            new_bb!(func_data).basic_block(Some(format!("%bb{}", self.max_basic_block_id)))
-        })
+        self.func
+            .ok_or_else(|| Diagnostic::internal("not inside a function"))
+            .map(|func| {
+                let func_data = self.program.func_mut(func);
+                new_bb!(func_data).basic_block(Some(format!("%bb{}", self.max_basic_block_id)))
+            })
     }
 
-    pub fn get_func(&self) -> Result<Function, ParseError> {
-        self.func.ok_or(ParseError::FunctionNotFound)
+    pub fn get_func(&self) -> Result<Function, Diagnostic> {
+        self.func
+            .ok_or_else(|| Diagnostic::internal("not inside a function"))
     }
 
-    pub fn get_bb(&self) -> Result<BasicBlock, ParseError> {
-        self.current_bb.ok_or(ParseError::BasicBlockNotFound)
+    pub fn get_bb(&self) -> Result<BasicBlock, Diagnostic> {
+        self.current_bb
+            .ok_or_else(|| Diagnostic::internal("not inside a basic block"))
     }
 
-    pub fn program(self) -> Program {
-        self.program
+    pub fn finish(self) -> (Program, Diagnostics) {
+        (self.program, self.diagnostics)
     }
 
-    pub fn func_data(&self) -> Result<&FunctionData, ParseError> {
+    pub fn func_data(&self) -> Result<&FunctionData, Diagnostic> {
         let func = self.get_func()?;
         Ok(self.program.func(func))
     }
 
-    pub fn func_data_mut(&mut self) -> Result<&mut FunctionData, ParseError> {
+    pub fn func_data_mut(&mut self) -> Result<&mut FunctionData, Diagnostic> {
         let func = self.get_func()?;
         Ok(self.program.func_mut(func))
     }
@@ -169,7 +199,7 @@ impl Context {
     }
 
     /// Whether the block is end with a jump, branch or return instruction
-    pub fn block_ended(&mut self, bb: BasicBlock) -> Result<bool, ParseError> {
+    pub fn block_ended(&mut self, bb: BasicBlock) -> Result<bool, Diagnostic> {
         let inst = self
             .func_data_mut()?
             .layout_mut()
@@ -189,7 +219,7 @@ impl Context {
     }
 
     /// If a block is not ended, end the block with a jump instruction
-    pub fn end_block(&mut self, bb: BasicBlock, target: BasicBlock) -> Result<(), ParseError> {
+    pub fn end_block(&mut self, bb: BasicBlock, target: BasicBlock) -> Result<(), Diagnostic> {
         if !self.block_ended(bb)? {
             let func_data = self.func_data_mut()?;
             let jump = new_value!(func_data).jump(target);
@@ -203,6 +233,88 @@ impl Context {
         format!("@__t{}", self.max_temp_value_id)
     }
 
+    /// Get the return type of the current function.
+    pub fn ret_type(&self) -> Result<DataType, Diagnostic> {
+        let name = &self.func_data()?.name()[1..];
+        self.signatures
+            .get(name)
+            .map(|signature| signature.ret_type)
+            .ok_or_else(|| Diagnostic::internal("unknown function"))
+    }
+
+    pub fn is_float(&self, value: Value) -> bool {
+        self.floats.contains(&value)
+    }
+
+    /// Get the type of a scalar value.
+    pub fn data_type(&self, value: Value) -> DataType {
+        if self.is_float(value) {
+            DataType::Float
+        } else {
+            DataType::Int
+        }
+    }
+
+    /// Mark a value derived from a pointer as a `float` if the pointer is to `float`s.
+    pub fn inherit_float(&mut self, value: Value, src: Value) {
+        if self.is_float(src) {
+            self.floats.insert(value);
+        }
+    }
+
+    /// Call an intrinsic at the end of the current block.
+    pub fn call_intrinsic(
+        &mut self,
+        intrinsic: Intrinsic,
+        args: Vec<Value>,
+    ) -> Result<Value, Diagnostic> {
+        let func = *self
+            .intrinsics
+            .entry(intrinsic)
+            .or_insert_with(|| intrinsic.declare(&mut self.program));
+        let bb = self.get_bb()?;
+        let func_data = self.func_data_mut()?;
+        let call = new_value!(func_data).call(func, args);
+        add_inst!(func_data, bb, call);
+        if intrinsic.returns_float() {
+            self.floats.insert(call);
+        }
+        Ok(call)
+    }
+
+    /// Convert a scalar value to `int` or `float`. Constants are converted at compile time.
+    pub fn convert(&mut self, value: Value, ty: DataType) -> Result<Value, Diagnostic> {
+        let intrinsic = match (self.is_float(value), ty) {
+            (false, DataType::Float) => Intrinsic::IntToFloat,
+            (true, DataType::Int) => Intrinsic::FloatToInt,
+            _ => return Ok(value),
+        };
+        if let ValueKind::Integer(int) = self.func_data()?.dfg().value(value).kind() {
+            let bits = intrinsic.eval(&[int.value()]);
+            let result = new_value!(self.func_data_mut()?).integer(bits);
+            if intrinsic.returns_float() {
+                self.floats.insert(result);
+            }
+            return Ok(result);
+        }
+        self.call_intrinsic(intrinsic, vec![value])
+    }
+
+    /// Get whether a scalar value is not zero, as 0 or 1 for `float`s and as it is for `int`s.
+    pub fn condition(&mut self, value: Value) -> Result<Value, Diagnostic> {
+        if !self.is_float(value) {
+            return Ok(value);
+        }
+        let zero = new_value!(self.func_data_mut()?).integer(0);
+        let is_zero = self.call_intrinsic(Intrinsic::FEq, vec![value, zero])?;
+        let zero = new_value!(self.func_data_mut()?).integer(0);
+        let bb = self.get_bb()?;
+        let func_data = self.func_data_mut()?;
+        let not_zero = new_value!(func_data).binary(BinaryOp::Eq, is_zero, zero);
+        add_inst!(func_data, bb, not_zero);
+        Ok(not_zero)
+    }
+
     pub fn add_while_info(&mut self, start: BasicBlock, end: BasicBlock) {
         self.while_info.push(WhileInfo {
             start_bb: start,
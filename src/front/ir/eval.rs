@@ -1,6 +1,10 @@
 use crate::front::ast::*;
 use crate::front::ident::Identifier;
+use crate::front::ir::builtin::float_to_int;
+use crate::front::ir::initial_list::InitializeList;
 use crate::front::ir::scope::Scope;
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
 
 #[derive(Debug)]
 pub enum EvalError {
@@ -8,126 +12,285 @@ pub enum EvalError {
     Overflow,
     NotSupportedVariable,
     FunctionNotSupported,
+    IndexOutOfBounds,
+    FloatRemainder,
 }
 
-fn to_bool(x: i32) -> bool {
-    x != 0
+/// Value of a constant expression.
+///
+/// `float`s are compared and hashed by their bits, so that constants can be used as keys.
+#[derive(Debug, Clone, Copy)]
+pub enum Number {
+    Int(i32),
+    Float(f32),
 }
 
-type EvalResult = Result<i32, EvalError>;
+impl Number {
+    /// Get the zero of a type, which must be `int` or `float`.
+    pub fn zero(ty: DataType) -> Self {
+        Number::Int(0).convert(ty)
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, Number::Float(_))
+    }
+
+    /// Convert the value to an `int`, truncating `float`s toward zero.
+    pub fn as_int(self) -> i32 {
+        match self {
+            Number::Int(value) => value,
+            Number::Float(value) => float_to_int(value),
+        }
+    }
+
+    pub fn as_float(self) -> f32 {
+        match self {
+            Number::Int(value) => value as f32,
+            Number::Float(value) => value,
+        }
+    }
+
+    /// Convert the value to a type, which must be `int` or `float`.
+    pub fn convert(self, ty: DataType) -> Self {
+        match ty {
+            DataType::Float => Number::Float(self.as_float()),
+            _ => Number::Int(self.as_int()),
+        }
+    }
+
+    /// Get the bits of the value, which is how `float`s are held in Koopa IR.
+    pub fn bits(self) -> i32 {
+        match self {
+            Number::Int(value) => value,
+            Number::Float(value) => value.to_bits() as i32,
+        }
+    }
+
+    pub fn to_bool(self) -> bool {
+        match self {
+            Number::Int(value) => value != 0,
+            Number::Float(value) => value != 0.0,
+        }
+    }
+}
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        self.is_float() == other.is_float() && self.bits() == other.bits()
+    }
+}
+
+impl Eq for Number {}
+
+impl Hash for Number {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.is_float().hash(state);
+        self.bits().hash(state);
+    }
+}
+
+impl Default for Number {
+    fn default() -> Self {
+        Number::Int(0)
+    }
+}
+
+/// Identifiers whose values may be known at compile time.
+pub trait Constant {
+    fn value(&self) -> Option<Number>;
+    fn array_values(&self) -> Option<&InitializeList<Number>>;
+}
+
+impl Constant for Identifier {
+    fn value(&self) -> Option<Number> {
+        match self {
+            Identifier::Constant(constant) => Some(constant.value),
+            _ => None,
+        }
+    }
+
+    fn array_values(&self) -> Option<&InitializeList<Number>> {
+        match self {
+            Identifier::ConstArray(const_array) => Some(&const_array.values),
+            _ => None,
+        }
+    }
+}
+
+type EvalResult = Result<Number, EvalError>;
+
+/// Apply an arithmetic operator, converting both operands to `float` if one of them is.
+fn arith(
+    left: Number,
+    right: Number,
+    int_op: impl FnOnce(i32, i32) -> Result<i32, EvalError>,
+    float_op: impl FnOnce(f32, f32) -> f32,
+) -> EvalResult {
+    match (left, right) {
+        (Number::Int(left), Number::Int(right)) => int_op(left, right).map(Number::Int),
+        _ => Ok(Number::Float(float_op(left.as_float(), right.as_float()))),
+    }
+}
+
+/// Compare two values, converting both to `float` if one of them is. `None` means that one of
+/// them is NaN.
+fn compare(left: Number, right: Number) -> Option<Ordering> {
+    match (left, right) {
+        (Number::Int(left), Number::Int(right)) => Some(left.cmp(&right)),
+        _ => left.as_float().partial_cmp(&right.as_float()),
+    }
+}
+
+fn from_bool(value: bool) -> Number {
+    Number::Int(value as i32)
+}
 
 pub trait Eval {
-    fn eval(&self, scope: &mut Scope) -> EvalResult;
+    fn eval<T: Constant>(&self, scope: &mut Scope<T>) -> EvalResult;
 }
 
 impl Eval for i32 {
-    fn eval(&self, _: &mut Scope) -> EvalResult {
+    fn eval<T: Constant>(&self, _: &mut Scope<T>) -> EvalResult {
+        Ok(Number::Int(*self))
+    }
+}
+
+impl Eval for Number {
+    fn eval<T: Constant>(&self, _: &mut Scope<T>) -> EvalResult {
         Ok(*self)
     }
 }
 
 impl Eval for ConstExpr {
-    fn eval(&self, scope: &mut Scope) -> EvalResult {
+    fn eval<T: Constant>(&self, scope: &mut Scope<T>) -> EvalResult {
         self.0.eval(scope)
     }
 }
 
 impl Eval for LOrExpr {
-    fn eval(&self, scope: &mut Scope) -> EvalResult {
+    fn eval<T: Constant>(&self, scope: &mut Scope<T>) -> EvalResult {
         match self {
             LOrExpr::LAndExpr(and) => and.eval(scope),
-            LOrExpr::Or(left, right) => {
-                Ok((left.eval(scope).map(to_bool)? || right.eval(scope).map(to_bool)?) as i32)
-            }
+            LOrExpr::Or(left, right) => Ok(from_bool(
+                left.eval(scope)?.to_bool() || right.eval(scope)?.to_bool(),
+            )),
         }
     }
 }
 
 impl Eval for LAndExpr {
-    fn eval(&self, scope: &mut Scope) -> EvalResult {
+    fn eval<T: Constant>(&self, scope: &mut Scope<T>) -> EvalResult {
         match self {
             LAndExpr::EqExpr(eq) => eq.eval(scope),
-            LAndExpr::And(left, right) => {
-                Ok((left.eval(scope).map(to_bool)? && right.eval(scope).map(to_bool)?) as i32)
-            }
+            LAndExpr::And(left, right) => Ok(from_bool(
+                left.eval(scope)?.to_bool() && right.eval(scope)?.to_bool(),
+            )),
         }
     }
 }
 
 impl Eval for EqExpr {
-    fn eval(&self, scope: &mut Scope) -> EvalResult {
+    fn eval<T: Constant>(&self, scope: &mut Scope<T>) -> EvalResult {
         match self {
             EqExpr::RelExpr(rel) => rel.eval(scope),
-            EqExpr::Eq(left, op, right) => match op {
-                EqOp::Eq => Ok((left.eval(scope)? == right.eval(scope)?) as i32),
-                EqOp::Ne => Ok((left.eval(scope)? != right.eval(scope)?) as i32),
-            },
+            EqExpr::Eq(left, op, right) => {
+                let equal = compare(left.eval(scope)?, right.eval(scope)?) == Some(Ordering::Equal);
+                match op {
+                    EqOp::Eq => Ok(from_bool(equal)),
+                    EqOp::Ne => Ok(from_bool(!equal)),
+                }
+            }
         }
     }
 }
 
 impl Eval for RelExpr {
-    fn eval(&self, scope: &mut Scope) -> EvalResult {
+    fn eval<T: Constant>(&self, scope: &mut Scope<T>) -> EvalResult {
         match self {
             RelExpr::AddExpr(add) => add.eval(scope),
-            RelExpr::Rel(left, op, right) => match op {
-                RelOp::Lt => Ok((left.eval(scope)? < right.eval(scope)?) as i32),
-                RelOp::Gt => Ok((left.eval(scope)? > right.eval(scope)?) as i32),
-                RelOp::Le => Ok((left.eval(scope)? <= right.eval(scope)?) as i32),
-                RelOp::Ge => Ok((left.eval(scope)? >= right.eval(scope)?) as i32),
-            },
+            RelExpr::Rel(left, op, right) => {
+                let ordering = compare(left.eval(scope)?, right.eval(scope)?);
+                Ok(from_bool(match op {
+                    RelOp::Lt => ordering == Some(Ordering::Less),
+                    RelOp::Gt => ordering == Some(Ordering::Greater),
+                    RelOp::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                    RelOp::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                }))
+            }
         }
     }
 }
 
 impl Eval for AddExpr {
-    fn eval(&self, scope: &mut Scope) -> EvalResult {
+    fn eval<T: Constant>(&self, scope: &mut Scope<T>) -> EvalResult {
         match self {
             AddExpr::MulExpr(mul_expr) => mul_expr.eval(scope),
-            AddExpr::Add(left, op, right) => match op {
-                AddOp::Add => left
-                    .eval(scope)?
-                    .checked_add(right.eval(scope)?)
-                    .ok_or(EvalError::Overflow),
-                AddOp::Sub => left
-                    .eval(scope)?
-                    .checked_sub(right.eval(scope)?)
-                    .ok_or(EvalError::Overflow),
-            },
+            AddExpr::Add(left, op, right) => {
+                let (left, right) = (left.eval(scope)?, right.eval(scope)?);
+                match op {
+                    AddOp::Add => arith(
+                        left,
+                        right,
+                        |l, r| l.checked_add(r).ok_or(EvalError::Overflow),
+                        |l, r| l + r,
+                    ),
+                    AddOp::Sub => arith(
+                        left,
+                        right,
+                        |l, r| l.checked_sub(r).ok_or(EvalError::Overflow),
+                        |l, r| l - r,
+                    ),
+                }
+            }
         }
     }
 }
 
 impl Eval for MulExpr {
-    fn eval(&self, scope: &mut Scope) -> EvalResult {
+    fn eval<T: Constant>(&self, scope: &mut Scope<T>) -> EvalResult {
         match self {
             MulExpr::UnaryExpr(unary_expr) => unary_expr.eval(scope),
-            MulExpr::Mul(left, op, right) => match op {
-                MulOp::Div => left
-                    .eval(scope)?
-                    .checked_div(right.eval(scope)?)
-                    .ok_or(EvalError::DivisionByZero),
-                MulOp::Mod => left
-                    .eval(scope)?
-                    .checked_rem(right.eval(scope)?)
-                    .ok_or(EvalError::DivisionByZero),
-                MulOp::Mul => left
-                    .eval(scope)?
-                    .checked_mul(right.eval(scope)?)
-                    .ok_or(EvalError::Overflow),
-            },
+            MulExpr::Mul(left, op, right) => {
+                let (left, right) = (left.eval(scope)?, right.eval(scope)?);
+                match op {
+                    MulOp::Div => arith(
+                        left,
+                        right,
+                        |l, r| l.checked_div(r).ok_or(EvalError::DivisionByZero),
+                        |l, r| l / r,
+                    ),
+                    MulOp::Mod if left.is_float() || right.is_float() => {
+                        Err(EvalError::FloatRemainder)
+                    }
+                    MulOp::Mod => arith(
+                        left,
+                        right,
+                        |l, r| l.checked_rem(r).ok_or(EvalError::DivisionByZero),
+                        |_, _| unreachable!(),
+                    ),
+                    MulOp::Mul => arith(
+                        left,
+                        right,
+                        |l, r| l.checked_mul(r).ok_or(EvalError::Overflow),
+                        |l, r| l * r,
+                    ),
+                }
+            }
         }
     }
 }
 
 impl Eval for UnaryExpr {
-    fn eval(&self, scope: &mut Scope) -> EvalResult {
+    fn eval<T: Constant>(&self, scope: &mut Scope<T>) -> EvalResult {
         match self {
             UnaryExpr::PrimaryExpr(primary_expr) => primary_expr.eval(scope),
             UnaryExpr::FuncCall(_) => Err(EvalError::FunctionNotSupported),
-            UnaryExpr::Unary(op, unary_expr) => match op {
-                UnaryOp::Neg => unary_expr.eval(scope).map(|x| -x),
-                UnaryOp::Not => unary_expr.eval(scope).map(|x| if x == 0 { 1 } else { 0 }),
+            UnaryExpr::Unary(op, unary_expr, _) => match op {
+                UnaryOp::Neg => unary_expr.eval(scope).map(|x| match x {
+                    Number::Int(x) => Number::Int(-x),
+                    Number::Float(x) => Number::Float(-x),
+                }),
+                UnaryOp::Not => unary_expr.eval(scope).map(|x| from_bool(!x.to_bool())),
                 UnaryOp::Pos => unary_expr.eval(scope),
             },
         }
@@ -135,50 +298,52 @@ impl Eval for UnaryExpr {
 }
 
 impl Eval for PrimaryExpr {
-    fn eval(&self, scope: &mut Scope) -> EvalResult {
+    fn eval<T: Constant>(&self, scope: &mut Scope<T>) -> EvalResult {
         match self {
-            PrimaryExpr::Expr(expr) => expr.eval(scope),
+            PrimaryExpr::Expr(expr, _) => expr.eval(scope),
             PrimaryExpr::LVal(lval) => lval.eval(scope),
-            PrimaryExpr::Number(num) => num.eval(scope),
+            PrimaryExpr::Number(num, _) => num.eval(scope),
+            PrimaryExpr::FloatNumber(bits, _) => Ok(Number::Float(f32::from_bits(*bits))),
         }
     }
 }
 
 impl Eval for Expr {
-    fn eval(&self, scope: &mut Scope) -> EvalResult {
+    fn eval<T: Constant>(&self, scope: &mut Scope<T>) -> EvalResult {
         self.0.eval(scope)
     }
 }
 
 impl Eval for LVal {
-    fn eval(&self, scope: &mut Scope) -> EvalResult {
+    fn eval<T: Constant>(&self, scope: &mut Scope<T>) -> EvalResult {
         match self {
-            LVal::Var(var) => {
-                let id = scope
-                    .get_identifier(var)
-                    .ok_or(EvalError::NotSupportedVariable)?;
-                let id = id.clone();
-                match id {
-                    Identifier::Constant(constant) => Ok(constant.value),
-                    _ => Err(EvalError::NotSupportedVariable),
-                }
-            }
+            LVal::Var(var, _) => scope
+                .get_identifier(var)
+                .and_then(Constant::value)
+                .ok_or(EvalError::NotSupportedVariable),
             LVal::ArrayElem(array_elem) => {
-                let id = scope
+                let values = scope
                     .get_identifier(&array_elem.name)
+                    .and_then(Constant::array_values)
                     .ok_or(EvalError::NotSupportedVariable)?
                     .clone();
-                match id {
-                    Identifier::ConstArray(const_array) => {
-                        let indices = array_elem
-                            .indices
-                            .iter()
-                            .map(|x| x.eval(scope))
-                            .collect::<Result<Vec<_>, _>>()?;
-                        Ok(const_array.values.get_element(&indices))
-                    }
-                    _ => Err(EvalError::NotSupportedVariable),
+                let indices = array_elem
+                    .indices
+                    .iter()
+                    .map(|x| x.eval(scope).map(Number::as_int))
+                    .collect::<Result<Vec<_>, _>>()?;
+                // An element is only constant if all the indices are given.
+                if indices.len() != values.shape().len() {
+                    return Err(EvalError::NotSupportedVariable);
+                }
+                if indices
+                    .iter()
+                    .zip(values.shape())
+                    .any(|(index, len)| !(0..*len).contains(index))
+                {
+                    return Err(EvalError::IndexOutOfBounds);
                 }
+                Ok(values.get_element(&indices))
             }
         }
     }
@@ -1,7 +1,9 @@
-use crate::front::ast::{ConstArray, Expr, ExprArray};
+use crate::front::ast::{ConstArray, DataType, Expr, ExprArray};
+use crate::front::diagnostic::Diagnostic;
 use crate::front::ir::context::Context;
-use crate::front::ir::eval::Eval;
+use crate::front::ir::eval::{Constant, Eval, Number};
 use crate::front::ir::initial_list::InitializeList::{NonZero, Zero};
+use crate::front::ir::scope::Scope;
 use crate::front::ir::{get_array_type, GenerateIR};
 use crate::new_value;
 use koopa::ir::builder::ValueBuilder;
@@ -13,26 +15,32 @@ pub enum InitializeList<T: Default + Clone> {
     NonZero(Vec<T>, Vec<i32>),
 }
 
-impl InitializeList<i32> {
-    pub fn from_const_array<T: Eval>(
-        shape: &[T],
+impl InitializeList<Number> {
+    /// Evaluate the values of a constant array, converting them to the type of its elements.
+    pub fn from_const_array<T: Constant>(
+        shape: &[i32],
+        ty: DataType,
         const_array: &[ConstArray],
-        ctx: &mut Context,
-    ) -> Self {
-        let shape = shape
-            .iter()
-            .map(|x| x.eval(&mut ctx.scope).unwrap_or(0))
-            .collect::<Vec<_>>();
+        scope: &mut Scope<T>,
+    ) -> Result<Self, Diagnostic> {
         let size: i32 = shape.iter().product();
         let mut data = Vec::with_capacity(size as usize);
 
         for constant in const_array {
             match constant {
-                ConstArray::Val(expr) => data.push(expr.eval(&mut ctx.scope).unwrap_or(0)),
-                ConstArray::Array(array) => {
-                    let align = Self::align(data.len() as i32, &shape);
-                    let inner =
-                        Self::from_const_array(&shape[shape.len() - align as usize..], array, ctx);
+                ConstArray::Val(expr) => data.push(
+                    expr.eval(scope)
+                        .map_err(|e| Diagnostic::not_constant(e, expr.span()))?
+                        .convert(ty),
+                ),
+                ConstArray::Array(array, _) => {
+                    let align = Self::align(data.len() as i32, shape);
+                    let inner = Self::from_const_array(
+                        &shape[shape.len() - align as usize..],
+                        ty,
+                        array,
+                        scope,
+                    )?;
                     if let NonZero(inner_data, _) = inner {
                         data.extend(inner_data);
                     }
@@ -41,36 +49,25 @@ impl InitializeList<i32> {
         }
 
         for _ in data.len()..size as usize {
-            data.push(0);
+            data.push(Number::zero(ty));
         }
 
-        NonZero(data, shape)
+        Ok(NonZero(data, shape.to_vec()))
     }
 }
 
 impl InitializeList<Expr> {
-    pub fn from_expr_array<T: Eval>(
-        shape: &[T],
-        expr_array: &[ExprArray],
-        ctx: &mut Context,
-    ) -> Self {
-        let shape = shape
-            .iter()
-            .map(|x| x.eval(&mut ctx.scope).unwrap_or(0))
-            .collect::<Vec<_>>();
+    pub fn from_expr_array(shape: &[i32], expr_array: &[ExprArray]) -> Self {
         let size: i32 = shape.iter().product();
         let mut data = Vec::with_capacity(size as usize);
 
         for expr in expr_array {
             match expr {
                 ExprArray::Val(expr) => data.push(expr.clone()),
-                ExprArray::Array(expr_array) => {
-                    let align = Self::align(data.len() as i32, &shape);
-                    let inner = Self::from_expr_array(
-                        &shape[shape.len() - align as usize..],
-                        expr_array,
-                        ctx,
-                    );
+                ExprArray::Array(expr_array, _) => {
+                    let align = Self::align(data.len() as i32, shape);
+                    let inner =
+                        Self::from_expr_array(&shape[shape.len() - align as usize..], expr_array);
                     if let NonZero(inner_data, _) = inner {
                         data.extend(inner_data);
                     }
@@ -82,7 +79,7 @@ impl InitializeList<Expr> {
             data.push(Expr::default());
         }
 
-        NonZero(data, shape)
+        NonZero(data, shape.to_vec())
     }
 }
 
@@ -91,7 +88,9 @@ impl<T: Default + Clone + Eval + GenerateIR<Output = Value> + PartialEq> Initial
         Zero(shape.to_vec())
     }
 
-    fn align(len: i32, shape: &[i32]) -> i32 {
+    /// Get the number of trailing dimensions filled by a nested list that starts after `len`
+    /// values.
+    pub fn align(len: i32, shape: &[i32]) -> i32 {
         let mut align = 0;
         let mut product = 1;
         if len == 0 {
@@ -107,23 +106,20 @@ impl<T: Default + Clone + Eval + GenerateIR<Output = Value> + PartialEq> Initial
         align
     }
 
-    pub fn to_global_value(&self, ctx: &mut Context) -> Value {
+    /// Generate the initial value of a global array whose elements have type `ty`.
+    pub fn to_global_value(&self, ty: DataType, ctx: &mut Context) -> Value {
         match self {
-            Zero(shape) => ctx
-                .program
-                .new_value()
-                .zero_init(get_array_type(shape, &mut ctx.scope)),
+            Zero(shape) => ctx.program.new_value().zero_init(get_array_type(shape)),
             NonZero(data, shape) => {
                 if data.iter().all(|x| *x == T::default()) {
-                    return ctx
-                        .program
-                        .new_value()
-                        .zero_init(get_array_type(shape, &mut ctx.scope));
+                    return ctx.program.new_value().zero_init(get_array_type(shape));
                 }
                 let mut values = vec![];
                 if shape.len() == 1 {
                     for expr in data {
-                        let value = expr.eval(&mut ctx.scope).unwrap_or(0);
+                        let value = expr
+                            .eval(&mut ctx.scope)
+                            .map_or(0, |value| value.convert(ty).bits());
                         values.push(ctx.program.new_value().integer(value));
                     }
                 } else {
@@ -135,7 +131,7 @@ impl<T: Default + Clone + Eval + GenerateIR<Output = Value> + PartialEq> Initial
                             data[i as usize * inner_size..(i + 1) as usize * inner_size].to_vec(),
                             inner_shape.clone(),
                         );
-                        values.push(inner.to_global_value(ctx));
+                        values.push(inner.to_global_value(ty, ctx));
                     }
                 }
                 ctx.program.new_value().aggregate(values)
@@ -143,22 +139,23 @@ impl<T: Default + Clone + Eval + GenerateIR<Output = Value> + PartialEq> Initial
         }
     }
 
-    pub fn to_local_value(&self, ctx: &mut Context) -> Value {
+    /// Generate the initial value of a local array whose elements have type `ty`.
+    pub fn to_local_value(&self, ty: DataType, ctx: &mut Context) -> Result<Value, Diagnostic> {
         match self {
             Zero(shape) => {
-                let ty = get_array_type(shape, &mut ctx.scope);
-                new_value!(ctx.func_data_mut().unwrap()).zero_init(ty)
+                let ty = get_array_type(shape);
+                Ok(new_value!(ctx.func_data_mut()?).zero_init(ty))
             }
             NonZero(data, shape) => {
                 if data.iter().all(|x| *x == T::default()) {
-                    let ty = get_array_type(shape, &mut ctx.scope);
-                    return new_value!(ctx.func_data_mut().unwrap()).zero_init(ty);
+                    let ty = get_array_type(shape);
+                    return Ok(new_value!(ctx.func_data_mut()?).zero_init(ty));
                 }
                 let mut values = vec![];
                 if shape.len() == 1 {
                     for expr in data {
-                        let value = expr.generate_ir(ctx).unwrap();
-                        values.push(value);
+                        let value = expr.generate_ir(ctx)?;
+                        values.push(ctx.convert(value, ty)?);
                     }
                 } else {
                     let outer_shape = shape[0];
@@ -169,14 +166,20 @@ impl<T: Default + Clone + Eval + GenerateIR<Output = Value> + PartialEq> Initial
                             data[i as usize * inner_size..(i + 1) as usize * inner_size].to_vec(),
                             inner_shape.clone(),
                         );
-                        values.push(inner.to_local_value(ctx));
+                        values.push(inner.to_local_value(ty, ctx)?);
                     }
                 }
-                new_value!(ctx.func_data_mut().unwrap()).aggregate(values)
+                Ok(new_value!(ctx.func_data_mut()?).aggregate(values))
             }
         }
     }
 
+    pub fn shape(&self) -> &[i32] {
+        match self {
+            Zero(shape) | NonZero(_, shape) => shape,
+        }
+    }
+
     pub fn get_element(&self, index: &[i32]) -> T {
         if let NonZero(data, shape) = self {
             let mut offset = 0;
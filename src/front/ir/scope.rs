@@ -1,17 +1,22 @@
+use crate::front::ast::Span;
 use crate::front::ident::Identifier;
 use std::collections::HashMap;
 
-pub type Result = std::result::Result<(), String>;
+/// On failure, holds the span of the previous definition.
+pub type Result = std::result::Result<(), Span>;
 
-type IdentifierMap = HashMap<String, Identifier>;
+/// Identifiers with the spans of their definitions.
+type IdentifierMap<T> = HashMap<String, (T, Span)>;
 
+/// Nested scopes of identifiers. IR generation stores [`Identifier`]s, while the type checker
+/// stores its own symbols.
 #[derive(Debug)]
-pub struct Scope {
-    stack: Vec<(i32, IdentifierMap)>,
+pub struct Scope<T = Identifier> {
+    stack: Vec<(i32, IdentifierMap<T>)>,
 }
 
-impl Scope {
-    pub fn new() -> Scope {
+impl<T> Scope<T> {
+    pub fn new() -> Self {
         let mut scope = Scope { stack: Vec::new() };
         scope.go_into_scoop(0);
         scope
@@ -29,23 +34,29 @@ impl Scope {
         self.stack.last().unwrap().0
     }
 
-    pub fn get_identifier(&self, name: &str) -> Option<&Identifier> {
+    pub fn get_identifier(&self, name: &str) -> Option<&T> {
+        self.get_definition(name).map(|(identifier, _)| identifier)
+    }
+
+    /// Get an identifier and the span of its definition.
+    pub fn get_definition(&self, name: &str) -> Option<&(T, Span)> {
         self.stack
             .iter()
             .rev()
             .find_map(|(_, identifiers)| identifiers.get(name))
     }
 
-    pub fn add_identifier(&mut self, name: String, identifier: Identifier) -> Result {
-        let scope_id = self.current_scope_id();
+    /// Get the identifiers of the innermost scope with the spans of their definitions.
+    pub fn current_identifiers(&self) -> impl Iterator<Item = (&String, &(T, Span))> {
+        self.stack.last().unwrap().1.iter()
+    }
+
+    pub fn add_identifier(&mut self, name: String, identifier: T, span: Span) -> Result {
         let (_, identifiers) = self.stack.last_mut().unwrap();
-        if let Some(_) = identifiers.get(&name) {
-            return Err(format!(
-                "Identifier {} is already defined in scope {}",
-                name, scope_id
-            ));
+        if let Some((_, previous)) = identifiers.get(&name) {
+            return Err(*previous);
         }
-        identifiers.insert(name, identifier);
+        identifiers.insert(name, (identifier, span));
         Ok(())
     }
 }
@@ -0,0 +1,172 @@
+//! Warnings that can be turned on and off from the command line.
+//!
+//! Every lint has a name used in `-W<name>` to enable it and `-Wno-<name>` to disable it. `-Wall`
+//! enables all lints, `-w` disables all of them, and `-Werror` turns the warnings that are
+//! emitted into errors.
+
+use crate::front::diagnostic::ErrorCode;
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// Local variables, parameters, constants and global variables that are never used.
+    UnusedVariable,
+    /// Functions that are never called.
+    UnusedFunction,
+    /// Non-void functions that can reach their end without returning a value.
+    MissingReturn,
+    /// Statements after `return`, `break` or `continue`.
+    UnreachableCode,
+    /// Identifiers that hide another one of an outer scope.
+    Shadow,
+}
+
+const LINTS: [Lint; 5] = [
+    Lint::UnusedVariable,
+    Lint::UnusedFunction,
+    Lint::MissingReturn,
+    Lint::UnreachableCode,
+    Lint::Shadow,
+];
+
+impl Lint {
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused-variable",
+            Lint::UnusedFunction => "unused-function",
+            Lint::MissingReturn => "missing-return",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::Shadow => "shadow",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        LINTS.into_iter().find(|lint| lint.name() == name)
+    }
+
+    pub fn code(self) -> ErrorCode {
+        match self {
+            Lint::UnusedVariable => ErrorCode::UnusedVariable,
+            Lint::UnusedFunction => ErrorCode::UnusedFunction,
+            Lint::MissingReturn => ErrorCode::MissingReturn,
+            Lint::UnreachableCode => ErrorCode::UnreachableCode,
+            Lint::Shadow => ErrorCode::ShadowedIdentifier,
+        }
+    }
+
+    /// Shadowing is common in correct code, so it is only reported when asked for.
+    fn is_default(self) -> bool {
+        self != Lint::Shadow
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Lints {
+    enabled: HashSet<Lint>,
+    /// Whether warnings are reported as errors.
+    pub werror: bool,
+}
+
+impl Default for Lints {
+    fn default() -> Self {
+        Self {
+            enabled: LINTS.into_iter().filter(|lint| lint.is_default()).collect(),
+            werror: false,
+        }
+    }
+}
+
+impl Lints {
+    pub fn is_enabled(&self, lint: Lint) -> bool {
+        self.enabled.contains(&lint)
+    }
+
+    /// Apply a `-W` flag, given without the `-W` prefix. Returns `false` if the flag is unknown.
+    pub fn apply_flag(&mut self, flag: &str) -> bool {
+        match flag {
+            "all" => self.enabled.extend(LINTS),
+            "error" => self.werror = true,
+            "no-error" => self.werror = false,
+            _ => match flag.strip_prefix("no-") {
+                Some(name) => match Lint::from_name(name) {
+                    Some(lint) => {
+                        self.enabled.remove(&lint);
+                    }
+                    None => return false,
+                },
+                None => match Lint::from_name(flag) {
+                    Some(lint) => {
+                        self.enabled.insert(lint);
+                    }
+                    None => return false,
+                },
+            },
+        }
+        true
+    }
+
+    pub fn disable_all(&mut self) {
+        self.enabled.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::front::check::check;
+    use crate::front::diagnostic::Level;
+    use crate::util::testing::parse;
+
+    #[test]
+    fn test_lints() {
+        let comp_unit = parse(
+            "int g; int f(int x) { int x1; { int x = 1; putint(x); } if (x) return 1; }
+            int main() { return 0; putint(1); }",
+        );
+        let codes = |lints: &Lints| -> Vec<ErrorCode> {
+            check(&comp_unit, lints).iter().map(|d| d.code).collect()
+        };
+        let defaults = vec![
+            ErrorCode::UnusedVariable,
+            ErrorCode::MissingReturn,
+            ErrorCode::UnreachableCode,
+            ErrorCode::UnusedVariable,
+            ErrorCode::UnusedFunction,
+        ];
+        assert_eq!(codes(&Lints::default()), defaults);
+        let diagnostics = check(&comp_unit, &Lints::default());
+        assert_eq!(diagnostics.warning_count(), defaults.len());
+        assert!(!diagnostics.has_errors());
+
+        let mut lints = Lints::default();
+        assert!(lints.apply_flag("shadow"));
+        assert!(lints.apply_flag("no-unused-variable"));
+        assert!(!lints.apply_flag("no-such-lint"));
+        assert_eq!(
+            codes(&lints),
+            vec![
+                ErrorCode::ShadowedIdentifier,
+                ErrorCode::MissingReturn,
+                ErrorCode::UnreachableCode,
+                ErrorCode::UnusedFunction,
+            ]
+        );
+
+        // `-Werror` reports the same diagnostics, all as errors.
+        let mut lints = Lints::default();
+        assert!(lints.apply_flag("error"));
+        let diagnostics = check(&comp_unit, &lints);
+        assert_eq!(codes(&lints), defaults);
+        assert!(diagnostics.iter().all(|d| d.level == Level::Error));
+        assert_eq!(diagnostics.error_count(), defaults.len());
+        assert!(lints.apply_flag("no-error"));
+        assert!(!check(&comp_unit, &lints).has_errors());
+
+        // `-w` disables all lints, even with `-Wall` before it, and leaves nothing to promote.
+        let mut lints = Lints::default();
+        assert!(lints.apply_flag("all"));
+        assert!(lints.apply_flag("error"));
+        lints.disable_all();
+        assert!(check(&comp_unit, &lints).is_empty());
+    }
+}
@@ -1,14 +1,312 @@
-use crate::front::opt::const_fold::ConstFold;
+use crate::front::opt::dce::DeadCodeElim;
+use crate::front::opt::gvn::Gvn;
+use crate::front::opt::inline::{Inline, DEFAULT_THRESHOLD};
+use crate::front::opt::licm::Licm;
+use crate::front::opt::mem2reg::Mem2Reg;
 use crate::front::opt::mul_div::MulDiv;
+use crate::front::opt::pure_calls::PureCalls;
+use crate::front::opt::sccp::Sccp;
+use crate::front::opt::tail_rec::TailRec;
+use crate::util::verify::assert_valid;
+use koopa::back::KoopaGenerator;
 use koopa::ir::Program;
 use koopa::opt::{Pass, PassManager};
+use std::collections::HashSet;
+use std::io;
+use std::time::{Duration, Instant};
 
 mod const_fold;
+mod dce;
+mod gvn;
+mod inline;
+mod licm;
+mod mem2reg;
 mod mul_div;
+mod pure_calls;
+mod sccp;
+mod tail_rec;
 
-pub fn opt(program: &mut Program) {
-    let mut passman = PassManager::new();
-    passman.register(Pass::Function(Box::new(ConstFold::default())));
-    passman.register(Pass::Function(Box::new(MulDiv::default())));
-    passman.run_passes(program);
+/// Optimization passes, in the order they run by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OptPass {
+    Mem2Reg,
+    TailRec,
+    Inline,
+    Sccp,
+    PureCalls,
+    Gvn,
+    Licm,
+    DeadCodeElim,
+    MulDiv,
+}
+
+const PASSES: [OptPass; 9] = [
+    OptPass::Mem2Reg,
+    OptPass::TailRec,
+    OptPass::Inline,
+    OptPass::Sccp,
+    OptPass::PureCalls,
+    OptPass::Gvn,
+    OptPass::Licm,
+    OptPass::DeadCodeElim,
+    OptPass::MulDiv,
+];
+
+impl OptPass {
+    pub fn name(self) -> &'static str {
+        match self {
+            OptPass::Mem2Reg => "mem2reg",
+            OptPass::TailRec => "tail-rec",
+            OptPass::Inline => "inline",
+            OptPass::Sccp => "sccp",
+            OptPass::PureCalls => "pure-calls",
+            OptPass::Gvn => "gvn",
+            OptPass::Licm => "licm",
+            OptPass::DeadCodeElim => "dce",
+            OptPass::MulDiv => "mul-div",
+        }
+    }
+
+    /// Get a pass from its name, the dashes of which may be left out. `constfold`, the pass
+    /// replaced by `sccp`, is kept as its alias.
+    pub fn from_name(name: &str) -> Option<Self> {
+        if name == "constfold" || name == "const-fold" {
+            return Some(OptPass::Sccp);
+        }
+        PASSES
+            .into_iter()
+            .find(|pass| pass.name() == name || pass.name().replace('-', "") == name)
+    }
+
+    /// Get a pass from its name, or an error listing the names of all passes.
+    pub fn parse(name: &str) -> Result<Self, String> {
+        Self::from_name(name).ok_or_else(|| {
+            format!(
+                "unknown optimization pass: {} (expected one of {})",
+                name,
+                PASSES.map(OptPass::name).join(", ")
+            )
+        })
+    }
+
+    /// Get the lowest optimization level that runs the pass.
+    fn level(self) -> u8 {
+        match self {
+            OptPass::Mem2Reg | OptPass::TailRec | OptPass::Sccp | OptPass::DeadCodeElim => 1,
+            OptPass::Inline
+            | OptPass::PureCalls
+            | OptPass::Gvn
+            | OptPass::Licm
+            | OptPass::MulDiv => 2,
+        }
+    }
+
+    fn to_pass(self, pipeline: &Pipeline) -> Pass {
+        match self {
+            OptPass::Mem2Reg => Pass::Function(Box::new(Mem2Reg)),
+            OptPass::TailRec => Pass::Function(Box::new(TailRec)),
+            OptPass::Inline => Pass::Module(Box::new(Inline::new(pipeline.inline_threshold))),
+            OptPass::Sccp => Pass::Function(Box::new(Sccp)),
+            OptPass::PureCalls => Pass::Module(Box::new(PureCalls)),
+            OptPass::Gvn => Pass::Function(Box::new(Gvn)),
+            OptPass::Licm => Pass::Function(Box::new(Licm)),
+            OptPass::DeadCodeElim => Pass::Function(Box::new(DeadCodeElim)),
+            OptPass::MulDiv => Pass::Module(Box::new(MulDiv)),
+        }
+    }
+}
+
+/// The optimization passes to run, and what to report while running them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pipeline {
+    passes: Vec<OptPass>,
+    /// Passes after which the program is printed to stderr.
+    print_after: HashSet<OptPass>,
+    /// Print the program to stderr after every pass.
+    pub print_after_all: bool,
+    /// Report the time spent in each pass to stderr.
+    pub time_passes: bool,
+    /// Verify the program after every pass, which is the default in debug builds.
+    pub verify: bool,
+    /// Maximum number of instructions of an inlined function.
+    pub inline_threshold: usize,
+}
+
+impl Pipeline {
+    /// Create a pipeline running the given passes in order. A pass may appear several times.
+    pub fn new(passes: Vec<OptPass>) -> Self {
+        Self {
+            passes,
+            print_after: HashSet::new(),
+            print_after_all: false,
+            time_passes: false,
+            verify: cfg!(debug_assertions),
+            inline_threshold: DEFAULT_THRESHOLD,
+        }
+    }
+
+    /// Create the pipeline of an optimization level, from 0 for no optimization to 2 for all
+    /// passes.
+    pub fn for_level(level: u8) -> Self {
+        Self::new(
+            PASSES
+                .into_iter()
+                .filter(|pass| pass.level() <= level)
+                .collect(),
+        )
+    }
+
+    /// Create a pipeline from a comma separated list of passes, as given to `--passes`.
+    pub fn parse(passes: &str) -> Result<Self, String> {
+        let passes = passes
+            .split(',')
+            .filter(|name| !name.is_empty())
+            .map(OptPass::parse)
+            .collect::<Result<_, _>>()?;
+        Ok(Self::new(passes))
+    }
+
+    /// Enable or disable a pass. An enabled pass that is not in the pipeline yet is inserted
+    /// before the first pass that runs after it by default.
+    pub fn set(&mut self, pass: OptPass, enabled: bool) {
+        if !enabled {
+            self.passes.retain(|p| *p != pass);
+        } else if !self.passes.contains(&pass) {
+            let index = self
+                .passes
+                .iter()
+                .position(|p| (*p as u8) > (pass as u8))
+                .unwrap_or(self.passes.len());
+            self.passes.insert(index, pass);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+
+    /// Print the program after every run of a pass, which must be in the pipeline.
+    pub fn add_print_after(&mut self, pass: OptPass) -> Result<(), String> {
+        if !self.passes.contains(&pass) {
+            return Err(format!(
+                "cannot print the IR after {}, which is not in the pipeline",
+                pass.name()
+            ));
+        }
+        self.print_after.insert(pass);
+        Ok(())
+    }
+
+    fn prints_after(&self, pass: OptPass) -> bool {
+        self.print_after_all || self.print_after.contains(&pass)
+    }
+}
+
+/// Run the passes of the pipeline on the program one by one, so that each of them can be timed,
+/// verified and followed by a dump.
+pub fn opt(program: &mut Program, pipeline: &Pipeline) {
+    let mut times: Vec<(OptPass, Duration)> = vec![];
+    for &pass in &pipeline.passes {
+        let mut passman = PassManager::new();
+        passman.register(pass.to_pass(pipeline));
+        let start = Instant::now();
+        passman.run_passes(program);
+        let elapsed = start.elapsed();
+        match times.iter_mut().find(|(p, _)| *p == pass) {
+            Some((_, time)) => *time += elapsed,
+            None => times.push((pass, elapsed)),
+        }
+        if pipeline.prints_after(pass) {
+            eprintln!("*** IR dump after {} ***", pass.name());
+            KoopaGenerator::new(io::stderr())
+                .generate_on(program)
+                .unwrap();
+            eprintln!();
+        }
+        if pipeline.verify {
+            assert_valid(program, &format!("pass `{}`", pass.name()));
+        }
+    }
+    if pipeline.time_passes {
+        report_times(&times);
+    }
+}
+
+/// Print the time spent in each pass, summed over the runs of the pass.
+fn report_times(times: &[(OptPass, Duration)]) {
+    let total: Duration = times.iter().map(|(_, time)| *time).sum();
+    eprintln!("*** Pass execution timing ***");
+    eprintln!("{:>12}  {:>7}  pass", "time (ms)", "%");
+    for (pass, time) in times {
+        let percent = if total.is_zero() {
+            0.0
+        } else {
+            time.as_secs_f64() / total.as_secs_f64() * 100.0
+        };
+        eprintln!(
+            "{:>12.3}  {:>6.1}%  {}",
+            time.as_secs_f64() * 1000.0,
+            percent,
+            pass.name()
+        );
+    }
+    eprintln!(
+        "{:>12.3}  {:>6.1}%  total",
+        total.as_secs_f64() * 1000.0,
+        100.0
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipeline() {
+        // Dashes may be left out, and `constfold` selects the pass that replaced it.
+        let pipeline = Pipeline::parse("mem2reg,constfold,tailrec,dce,gvn,,dce").unwrap();
+        assert_eq!(
+            pipeline.passes,
+            vec![
+                OptPass::Mem2Reg,
+                OptPass::Sccp,
+                OptPass::TailRec,
+                OptPass::DeadCodeElim,
+                OptPass::Gvn,
+                OptPass::DeadCodeElim,
+            ]
+        );
+        assert_eq!(OptPass::parse("const-fold"), Ok(OptPass::Sccp));
+        let error = Pipeline::parse("mem2reg,fold").unwrap_err();
+        assert!(error.starts_with("unknown optimization pass: fold (expected one of mem2reg,"));
+
+        // Switches override the level, and an enabled pass runs where it does by default.
+        let mut pipeline = Pipeline::for_level(1);
+        pipeline.set(OptPass::Gvn, true);
+        pipeline.set(OptPass::TailRec, false);
+        pipeline.set(OptPass::Inline, true);
+        assert_eq!(
+            pipeline.passes,
+            vec![
+                OptPass::Mem2Reg,
+                OptPass::Inline,
+                OptPass::Sccp,
+                OptPass::Gvn,
+                OptPass::DeadCodeElim,
+            ]
+        );
+        let mut pipeline = Pipeline::for_level(0);
+        pipeline.set(OptPass::Licm, true);
+        pipeline.set(OptPass::Mem2Reg, true);
+        pipeline.set(OptPass::Mem2Reg, true);
+        assert_eq!(pipeline.passes, vec![OptPass::Mem2Reg, OptPass::Licm]);
+
+        // The IR can only be printed after passes that run.
+        let mut pipeline = Pipeline::for_level(1);
+        assert!(pipeline.add_print_after(OptPass::Gvn).is_err());
+        assert!(!pipeline.prints_after(OptPass::Gvn));
+        assert_eq!(pipeline.add_print_after(OptPass::Sccp), Ok(()));
+        assert!(pipeline.prints_after(OptPass::Sccp));
+        assert!(!pipeline.prints_after(OptPass::Mem2Reg));
+    }
 }
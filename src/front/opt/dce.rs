@@ -0,0 +1,166 @@
+//! Dead code elimination.
+//!
+//! Branches on constant conditions are turned into jumps, blocks that become unreachable from
+//! the entry block are removed, and then instructions without side effects whose results are
+//! never used are deleted.
+
+use crate::util::cfg::Cfg;
+use koopa::ir::builder::LocalInstBuilder;
+use koopa::ir::{BasicBlock, Function, FunctionData, Value, ValueKind};
+use koopa::opt::FunctionPass;
+
+#[derive(Default)]
+pub(crate) struct DeadCodeElim;
+
+impl FunctionPass for DeadCodeElim {
+    fn run_on(&mut self, _: Function, data: &mut FunctionData) {
+        if data.layout().entry_bb().is_none() {
+            return;
+        }
+        fold_branches(data);
+        remove_unreachable_blocks(data);
+        remove_dead_insts(data);
+    }
+}
+
+/// Replace branches whose target is known with jumps.
+fn fold_branches(data: &mut FunctionData) {
+    let mut folded = vec![];
+    for (_, node) in data.layout().bbs() {
+        let last = match node.insts().back_key() {
+            Some(last) => *last,
+            None => continue,
+        };
+        let branch = match data.dfg().value(last).kind() {
+            ValueKind::Branch(branch) => branch,
+            _ => continue,
+        };
+        let target = match data.dfg().value(branch.cond()).kind() {
+            ValueKind::Integer(cond) if cond.value() != 0 => {
+                Some((branch.true_bb(), branch.true_args()))
+            }
+            ValueKind::Integer(_) => Some((branch.false_bb(), branch.false_args())),
+            _ if branch.true_bb() == branch.false_bb()
+                && branch.true_args() == branch.false_args() =>
+            {
+                Some((branch.true_bb(), branch.true_args()))
+            }
+            _ => None,
+        };
+        if let Some((bb, args)) = target {
+            folded.push((last, bb, args.to_vec()));
+        }
+    }
+    for (inst, bb, args) in folded {
+        data.dfg_mut()
+            .replace_value_with(inst)
+            .jump_with_args(bb, args);
+    }
+}
+
+/// Remove the blocks that are not reachable from the entry block.
+pub(super) fn remove_unreachable_blocks(data: &mut FunctionData) {
+    let cfg = Cfg::new(data);
+    let unreachable: Vec<BasicBlock> = data
+        .layout()
+        .bbs()
+        .keys()
+        .filter(|bb| !cfg.is_reachable(**bb))
+        .copied()
+        .collect();
+    if unreachable.is_empty() {
+        return;
+    }
+
+    let mut insts = vec![];
+    for bb in &unreachable {
+        let (_, node) = data.layout_mut().bbs_mut().remove(bb).unwrap();
+        insts.extend(node.insts().keys().copied());
+    }
+    // Instructions in unreachable blocks are only used by each other, so they can be removed
+    // once their users are gone.
+    while !insts.is_empty() {
+        let len = insts.len();
+        insts.retain(|inst| {
+            if data.dfg().value(*inst).used_by().is_empty() {
+                data.dfg_mut().remove_value(*inst);
+                false
+            } else {
+                true
+            }
+        });
+        assert!(
+            insts.len() < len,
+            "Unreachable values are used by reachable ones."
+        );
+    }
+    for bb in unreachable {
+        data.dfg_mut().remove_bb(bb);
+    }
+}
+
+/// Check if an instruction can be removed when its result is not used.
+fn has_side_effects(data: &FunctionData, inst: Value) -> bool {
+    !matches!(
+        data.dfg().value(inst).kind(),
+        ValueKind::Alloc(_)
+            | ValueKind::Load(_)
+            | ValueKind::GetPtr(_)
+            | ValueKind::GetElemPtr(_)
+            | ValueKind::Binary(_)
+    )
+}
+
+/// Remove the instructions without side effects whose results are not used, and then the
+/// instructions that only they used.
+fn remove_dead_insts(data: &mut FunctionData) {
+    let mut worklist: Vec<Value> = data
+        .layout()
+        .bbs()
+        .nodes()
+        .flat_map(|node| node.insts().keys().copied())
+        .collect();
+    while let Some(inst) = worklist.pop() {
+        if !data.dfg().values().contains_key(&inst) {
+            continue;
+        }
+        let bb = match data.layout().parent_bb(inst) {
+            Some(bb) => bb,
+            None => continue,
+        };
+        if has_side_effects(data, inst) || !data.dfg().value(inst).used_by().is_empty() {
+            continue;
+        }
+        data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+        let removed = data.dfg_mut().remove_value(inst);
+        worklist.extend(
+            removed
+                .kind()
+                .value_uses()
+                .filter(|value| data.dfg().values().contains_key(value)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::front::opt::OptPass;
+    use crate::util::testing::{callees, compile_with, func, inst_kinds, interpret};
+    use koopa::ir::ValueKind;
+
+    #[test]
+    fn test_dce() {
+        let program = compile_with(
+            "int main() { int x = getint(); int y = x * 2; getint();
+            if (0) { putint(y); } return x; }",
+            &[OptPass::Mem2Reg, OptPass::DeadCodeElim],
+        );
+        let main = func(&program, "main");
+        // The calls have side effects even if their results are unused.
+        assert_eq!(callees(&program, main), ["getint", "getint"]);
+        assert!(!inst_kinds(main)
+            .iter()
+            .any(|kind| matches!(kind, ValueKind::Binary(_) | ValueKind::Branch(_))));
+        assert_eq!(interpret(&program, b"3 4").0, Ok(3));
+    }
+}
@@ -0,0 +1,212 @@
+//! Global value numbering.
+//!
+//! Pure computations, i.e. binary operations and pointer arithmetic, are numbered by their
+//! operator and operands while walking the dominator tree. A computation that is already
+//! available in the current block or a dominating one is replaced with the earlier value.
+//!
+//! Loads are numbered by their address within a block only, since a store or a call on any path
+//! from a dominating block may change the loaded value. A store or a call in the block makes all
+//! earlier loads unavailable, as the address it writes to may be any of theirs.
+
+use crate::util::cfg::Cfg;
+use crate::util::ir::replace_all_uses;
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, Value, ValueKind};
+use koopa::opt::FunctionPass;
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub(crate) struct Gvn;
+
+impl FunctionPass for Gvn {
+    fn run_on(&mut self, _: Function, data: &mut FunctionData) {
+        if data.layout().entry_bb().is_none() {
+            return;
+        }
+        let cfg = Cfg::new(data);
+        let children = cfg.dominators().children();
+        let mut table = ValueTable::default();
+
+        // Walk the dominator tree, forgetting the values of a block when leaving it.
+        let mut walk = vec![(cfg.entry.unwrap(), false)];
+        while let Some((bb, leaving)) = walk.pop() {
+            if leaving {
+                table.leave();
+                continue;
+            }
+            table.enter();
+            number_block(data, bb, &mut table);
+            walk.push((bb, true));
+            for child in children[&bb].iter().rev() {
+                walk.push((*child, false));
+            }
+        }
+    }
+}
+
+/// An operand of an expression. Integer constants are compared by their values, since every
+/// use of a constant is a different value.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Operand {
+    Value(Value),
+    Const(i32),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Expr {
+    Binary(BinaryOp, Operand, Operand),
+    GetPtr(Operand, Operand),
+    GetElemPtr(Operand, Operand),
+}
+
+/// Available expressions in scopes of the dominator tree.
+#[derive(Default)]
+struct ValueTable {
+    values: HashMap<Expr, Value>,
+    /// The expressions added in every scope.
+    scopes: Vec<Vec<Expr>>,
+}
+
+impl ValueTable {
+    fn enter(&mut self) {
+        self.scopes.push(vec![]);
+    }
+
+    fn leave(&mut self) {
+        for expr in self.scopes.pop().unwrap() {
+            self.values.remove(&expr);
+        }
+    }
+
+    /// Get the value of an expression, or make `value` its value.
+    fn lookup_or_insert(&mut self, expr: Expr, value: Value) -> Option<Value> {
+        if let Some(value) = self.values.get(&expr) {
+            return Some(*value);
+        }
+        if let Expr::Binary(op, lhs, rhs) = expr {
+            if is_commutative(op) {
+                if let Some(value) = self.values.get(&Expr::Binary(op, rhs, lhs)) {
+                    return Some(*value);
+                }
+            }
+        }
+        self.values.insert(expr, value);
+        self.scopes.last_mut().unwrap().push(expr);
+        None
+    }
+}
+
+fn is_commutative(op: BinaryOp) -> bool {
+    matches!(
+        op,
+        BinaryOp::Add
+            | BinaryOp::Mul
+            | BinaryOp::And
+            | BinaryOp::Or
+            | BinaryOp::Xor
+            | BinaryOp::Eq
+            | BinaryOp::NotEq
+    )
+}
+
+fn operand(data: &FunctionData, value: Value) -> Operand {
+    match data.dfg().values().get(&value).map(|v| v.kind()) {
+        Some(ValueKind::Integer(i)) => Operand::Const(i.value()),
+        _ => Operand::Value(value),
+    }
+}
+
+fn expr(data: &FunctionData, inst: Value) -> Option<Expr> {
+    let expr = match data.dfg().value(inst).kind() {
+        ValueKind::Binary(bin) => {
+            Expr::Binary(bin.op(), operand(data, bin.lhs()), operand(data, bin.rhs()))
+        }
+        ValueKind::GetPtr(ptr) => {
+            Expr::GetPtr(operand(data, ptr.src()), operand(data, ptr.index()))
+        }
+        ValueKind::GetElemPtr(ptr) => {
+            Expr::GetElemPtr(operand(data, ptr.src()), operand(data, ptr.index()))
+        }
+        _ => return None,
+    };
+    Some(expr)
+}
+
+/// Replace the redundant computations and loads in a block with their available values.
+fn number_block(data: &mut FunctionData, bb: BasicBlock, table: &mut ValueTable) {
+    let insts: Vec<Value> = data
+        .layout()
+        .bbs()
+        .node(&bb)
+        .unwrap()
+        .insts()
+        .keys()
+        .copied()
+        .collect();
+    // The loads available in the block, by their addresses.
+    let mut loads: HashMap<Value, Value> = HashMap::new();
+    for inst in insts {
+        let available = match data.dfg().value(inst).kind() {
+            ValueKind::Load(load) => match loads.get(&load.src()) {
+                Some(value) => Some(*value),
+                None => {
+                    loads.insert(load.src(), inst);
+                    None
+                }
+            },
+            ValueKind::Store(_) | ValueKind::Call(_) => {
+                loads.clear();
+                None
+            }
+            _ => match expr(data, inst) {
+                Some(expr) => table.lookup_or_insert(expr, inst),
+                None => continue,
+            },
+        };
+        if let Some(value) = available {
+            replace_all_uses(data, inst, value);
+            data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+            data.dfg_mut().remove_value(inst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::front::opt::OptPass;
+    use crate::util::testing::{compile_with, func, inst_kinds, interpret};
+    use koopa::ir::ValueKind;
+
+    #[test]
+    fn test_gvn_loads() {
+        let program = compile_with(
+            "int a[2] = {1, 2};
+            int main() { int i = getint(); int x = a[i]; int y = a[i]; a[i] = x + 5;
+                return x + y + a[i] + (i + 1) * (i + 1); }",
+            &[OptPass::Mem2Reg, OptPass::Gvn],
+        );
+        let kinds = inst_kinds(func(&program, "main"));
+        let count = |f: fn(&ValueKind) -> bool| kinds.iter().filter(|kind| f(kind)).count();
+        // The address and `i + 1` are computed once. `a[i]` is loaded once before the store,
+        // and loaded again after it, since the store changes the loaded value.
+        assert_eq!(count(|kind| matches!(kind, ValueKind::GetElemPtr(_))), 1);
+        assert_eq!(count(|kind| matches!(kind, ValueKind::Load(_))), 2);
+        assert_eq!(count(|kind| matches!(kind, ValueKind::Binary(_))), 6);
+        assert_eq!(interpret(&program, b"1").0, Ok(15));
+
+        // A call may store to the global array too.
+        let program = compile_with(
+            "int a[2] = {1, 2}; void f() { a[0] = 3; }
+            int main() { int x = a[0]; f(); return x + a[0]; }",
+            &[OptPass::Mem2Reg, OptPass::Gvn],
+        );
+        let kinds = inst_kinds(func(&program, "main"));
+        assert_eq!(
+            kinds
+                .iter()
+                .filter(|kind| matches!(kind, ValueKind::Load(_)))
+                .count(),
+            2
+        );
+        assert_eq!(interpret(&program, b"").0, Ok(4));
+    }
+}
@@ -0,0 +1,367 @@
+//! Function inlining.
+//!
+//! Calls to small functions that are not recursive are replaced with a copy of the callee's
+//! body. The block containing the call is split after it, parameters of the callee are replaced
+//! with the arguments, and returns become jumps to the second half of the block, which receives
+//! the return value as its parameter.
+//!
+//! Functions are visited callees first, so that a function is inlined with the calls in its
+//! body already inlined. The runtime library functions only have declarations and are never
+//! inlined.
+
+use crate::front::ir::builtin::is_builtin;
+use crate::util::cfg::Cfg;
+use crate::util::ir::{replace_all_uses, replace_operand};
+use koopa::ir::builder::{BasicBlockBuilder, LocalInstBuilder, ValueBuilder};
+use koopa::ir::entities::ValueData;
+use koopa::ir::{BasicBlock, Function, FunctionData, Program, Type, Value, ValueKind};
+use koopa::opt::ModulePass;
+use std::collections::{HashMap, HashSet};
+
+/// The default maximum number of instructions of an inlined function.
+pub(super) const DEFAULT_THRESHOLD: usize = 32;
+
+/// Calls are no longer inlined into a function once it has this many instructions.
+const MAX_CALLER_SIZE: usize = 4096;
+
+pub(crate) struct Inline {
+    threshold: usize,
+    /// Number of inlined calls, used to give the copied blocks and values unique names.
+    inlined: usize,
+}
+
+impl Inline {
+    /// Create the pass, inlining functions with at most `threshold` instructions.
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            inlined: 0,
+        }
+    }
+
+    fn is_inlinable(
+        &self,
+        program: &Program,
+        calls: &HashMap<Function, HashSet<Function>>,
+        callee: Function,
+    ) -> bool {
+        let data = program.func(callee);
+        data.layout().entry_bb().is_some()
+            && !is_builtin(data.name())
+            && !is_recursive(calls, callee)
+            && size(data) <= self.threshold
+    }
+}
+
+impl ModulePass for Inline {
+    fn run_on(&mut self, program: &mut Program) {
+        let calls = call_graph(program);
+        for func in bottom_up_order(program, &calls) {
+            let sites: Vec<(Value, Function)> = program
+                .func(func)
+                .layout()
+                .bbs()
+                .nodes()
+                .flat_map(|node| node.insts().keys().copied())
+                .filter_map(|inst| match program.func(func).dfg().value(inst).kind() {
+                    ValueKind::Call(call) => Some((inst, call.callee())),
+                    _ => None,
+                })
+                .collect();
+            for (call, callee) in sites {
+                if size(program.func(func)) >= MAX_CALLER_SIZE
+                    || !self.is_inlinable(program, &calls, callee)
+                {
+                    continue;
+                }
+                let body = Body::new(program.func(callee));
+                let tag = self.inlined;
+                self.inlined += 1;
+                inline_call(program.func_mut(func), call, &body, tag);
+            }
+        }
+    }
+}
+
+/// Get the number of instructions of a function.
+fn size(data: &FunctionData) -> usize {
+    data.layout()
+        .bbs()
+        .nodes()
+        .map(|node| node.insts().len())
+        .sum()
+}
+
+/// Get the functions called by every function with a body.
+fn call_graph(program: &Program) -> HashMap<Function, HashSet<Function>> {
+    let mut calls = HashMap::new();
+    for (func, data) in program.funcs() {
+        let callees: HashSet<Function> = data
+            .layout()
+            .bbs()
+            .nodes()
+            .flat_map(|node| node.insts().keys())
+            .filter_map(|inst| match data.dfg().value(*inst).kind() {
+                ValueKind::Call(call) => Some(call.callee()),
+                _ => None,
+            })
+            .collect();
+        calls.insert(*func, callees);
+    }
+    calls
+}
+
+/// Check if a function may call itself, directly or through other functions.
+fn is_recursive(calls: &HashMap<Function, HashSet<Function>>, func: Function) -> bool {
+    let mut visited = HashSet::new();
+    let mut worklist: Vec<Function> = calls[&func].iter().copied().collect();
+    while let Some(callee) = worklist.pop() {
+        if callee == func {
+            return true;
+        }
+        if visited.insert(callee) {
+            worklist.extend(calls[&callee].iter().copied());
+        }
+    }
+    false
+}
+
+/// Get the functions in post order of the call graph, so that callees come before their callers
+/// unless they are recursive.
+fn bottom_up_order(
+    program: &Program,
+    calls: &HashMap<Function, HashSet<Function>>,
+) -> Vec<Function> {
+    let mut order = vec![];
+    let mut visited = HashSet::new();
+    for func in program.func_layout() {
+        if !visited.insert(*func) {
+            continue;
+        }
+        let mut stack = vec![(*func, calls[func].iter().copied().collect::<Vec<_>>())];
+        while let Some((func, callees)) = stack.last_mut() {
+            match callees.pop() {
+                Some(callee) => {
+                    if visited.insert(callee) {
+                        stack.push((callee, calls[&callee].iter().copied().collect()));
+                    }
+                }
+                None => {
+                    order.push(*func);
+                    stack.pop();
+                }
+            }
+        }
+    }
+    order
+}
+
+/// A copy of the body of a callee, so that it can be read while the caller is modified.
+struct Body {
+    params: Vec<Value>,
+    /// Reachable blocks in reverse post order, so that values are copied before their uses.
+    blocks: Vec<(BasicBlock, Vec<Value>)>,
+    bb_names: HashMap<BasicBlock, Option<String>>,
+    bb_params: HashMap<BasicBlock, Vec<Value>>,
+    values: HashMap<Value, ValueData>,
+}
+
+impl Body {
+    fn new(data: &FunctionData) -> Self {
+        let cfg = Cfg::new(data);
+        let blocks = cfg
+            .rpo()
+            .iter()
+            .map(|bb| {
+                let insts = data.layout().bbs().node(bb).unwrap().insts();
+                (*bb, insts.keys().copied().collect())
+            })
+            .collect();
+        Self {
+            params: data.params().to_vec(),
+            blocks,
+            bb_names: cfg
+                .rpo()
+                .iter()
+                .map(|bb| (*bb, data.dfg().bb(*bb).name().clone()))
+                .collect(),
+            bb_params: cfg
+                .rpo()
+                .iter()
+                .map(|bb| (*bb, data.dfg().bb(*bb).params().to_vec()))
+                .collect(),
+            values: data.dfg().values().clone(),
+        }
+    }
+}
+
+/// Give a copied block or value a name that the front end never generates.
+fn inlined_name(name: &Option<String>, tag: usize) -> Option<String> {
+    name.as_ref()
+        .map(|name| format!("{}inline{}_{}", &name[..1], tag, &name[1..]))
+}
+
+/// Maps the values and blocks of a callee to their copies in the caller.
+struct Copier<'a> {
+    body: &'a Body,
+    tag: usize,
+    values: HashMap<Value, Value>,
+    bbs: HashMap<BasicBlock, BasicBlock>,
+}
+
+impl Copier<'_> {
+    /// Get the copy of a value, copying constants on their first use.
+    fn value(&mut self, data: &mut FunctionData, value: Value) -> Value {
+        if value.is_global() {
+            return value;
+        }
+        if let Some(copy) = self.values.get(&value) {
+            return *copy;
+        }
+        let copy = self.copy(data, value);
+        self.values.insert(value, copy);
+        copy
+    }
+
+    /// Create a copy of a value with its operands and targets replaced with their copies.
+    fn copy(&mut self, data: &mut FunctionData, value: Value) -> Value {
+        let mut value_data = self.body.values[&value].clone();
+        let operands: Vec<Value> = value_data.kind().value_uses().collect();
+        for operand in operands {
+            let copy = self.value(data, operand);
+            replace_operand(value_data.kind_mut(), operand, copy);
+        }
+        match value_data.kind_mut() {
+            ValueKind::Jump(jump) => *jump.target_mut() = self.bbs[&jump.target()],
+            ValueKind::Branch(branch) => {
+                *branch.true_bb_mut() = self.bbs[&branch.true_bb()];
+                *branch.false_bb_mut() = self.bbs[&branch.false_bb()];
+            }
+            _ => {}
+        }
+        let copy = data.dfg_mut().new_value().raw(value_data);
+        let name = inlined_name(self.body.values[&value].name(), self.tag);
+        data.dfg_mut().set_value_name(copy, name);
+        copy
+    }
+}
+
+/// Replace a call with a copy of the body of the callee.
+fn inline_call(data: &mut FunctionData, call: Value, body: &Body, tag: usize) {
+    let bb = data.layout().parent_bb(call).unwrap();
+    let (args, ty) = match data.dfg().value(call).kind() {
+        ValueKind::Call(c) => (c.args().to_vec(), data.dfg().value(call).ty().clone()),
+        _ => unreachable!(),
+    };
+
+    // Split the block after the call. The second half receives the return value.
+    let types: Vec<Type> = if ty.is_unit() { vec![] } else { vec![ty] };
+    let exit_name = format!("%inline{}_exit", tag);
+    let exit = data
+        .dfg_mut()
+        .new_bb()
+        .basic_block_with_params(Some(exit_name), types);
+    data.layout_mut()
+        .bbs_mut()
+        .cursor_mut(bb)
+        .insert_key_after(exit)
+        .unwrap();
+    let rest: Vec<Value> = data
+        .layout()
+        .bbs()
+        .node(&bb)
+        .unwrap()
+        .insts()
+        .keys()
+        .skip_while(|inst| **inst != call)
+        .skip(1)
+        .copied()
+        .collect();
+    for inst in rest {
+        data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+        data.layout_mut()
+            .bb_mut(exit)
+            .insts_mut()
+            .push_key_back(inst)
+            .unwrap();
+    }
+    data.layout_mut().bb_mut(bb).insts_mut().remove(&call);
+    if let Some(ret) = data.dfg().bb(exit).params().first().copied() {
+        replace_all_uses(data, call, ret);
+    }
+    data.dfg_mut().remove_value(call);
+
+    let mut copier = Copier {
+        body,
+        tag,
+        values: body.params.iter().copied().zip(args).collect(),
+        bbs: HashMap::new(),
+    };
+    for (callee_bb, _) in &body.blocks {
+        let types = body.bb_params[callee_bb]
+            .iter()
+            .map(|param| body.values[param].ty().clone())
+            .collect();
+        let name = inlined_name(&body.bb_names[callee_bb], tag);
+        let copy = data.dfg_mut().new_bb().basic_block_with_params(name, types);
+        data.layout_mut()
+            .bbs_mut()
+            .cursor_mut(exit)
+            .insert_key_before(copy)
+            .unwrap();
+        let params = data.dfg().bb(copy).params().to_vec();
+        copier
+            .values
+            .extend(body.bb_params[callee_bb].iter().copied().zip(params));
+        copier.bbs.insert(*callee_bb, copy);
+    }
+
+    for (callee_bb, insts) in &body.blocks {
+        let copy_bb = copier.bbs[callee_bb];
+        for inst in insts {
+            let copy = match body.values[inst].kind() {
+                ValueKind::Return(ret) => {
+                    let args = match ret.value() {
+                        Some(value) => vec![copier.value(data, value)],
+                        None => vec![],
+                    };
+                    data.dfg_mut().new_value().jump_with_args(exit, args)
+                }
+                _ => copier.value(data, *inst),
+            };
+            data.layout_mut()
+                .bb_mut(copy_bb)
+                .insts_mut()
+                .push_key_back(copy)
+                .unwrap();
+        }
+    }
+
+    let entry = copier.bbs[&body.blocks[0].0];
+    let jump = data.dfg_mut().new_value().jump(entry);
+    data.layout_mut()
+        .bb_mut(bb)
+        .insts_mut()
+        .push_key_back(jump)
+        .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::front::opt::{opt, OptPass, Pipeline};
+    use crate::util::testing::{callees, compile, func, interpret};
+
+    #[test]
+    fn test_inline_threshold() {
+        let input = "int poly(int x) { return (x * x + 2) * x - 1; }
+            int main() { return poly(getint()); }";
+        let mut pipeline = Pipeline::new(vec![OptPass::Mem2Reg, OptPass::Inline]);
+        for (threshold, expected) in [(32, vec!["getint"]), (3, vec!["getint", "poly"])] {
+            pipeline.inline_threshold = threshold;
+            let mut program = compile(input);
+            opt(&mut program, &pipeline);
+            assert_eq!(callees(&program, func(&program, "main")), expected);
+            assert_eq!(interpret(&program, b"3").0, Ok(32));
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! Loop invariant code motion.
+//!
+//! Every natural loop gets a preheader, a block that is the only predecessor of the loop header
+//! from outside the loop. Binary operations and address computations whose operands are all
+//! defined outside a loop are then moved to the end of its preheader, inner loops first, so that
+//! invariants of nested loops can move out of several loops.
+//!
+//! Loads are not moved since the memory may change in the loop. Hoisted instructions are
+//! executed even if the loop body is not, which is safe since none of them trap on RISC-V.
+//! Divisions are the exception: dividing by zero is undefined, so they are only moved from the
+//! blocks that dominate every exit of the loop, which run whenever the loop is left. Other passes
+//! may move other instructions under the same condition.
+
+use crate::util::cfg::{Cfg, Dominators, Loop};
+use koopa::ir::builder::{BasicBlockBuilder, LocalInstBuilder, ValueBuilder};
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, Type, Value, ValueKind};
+use koopa::opt::FunctionPass;
+
+#[derive(Default)]
+pub(crate) struct Licm;
+
+impl FunctionPass for Licm {
+    fn run_on(&mut self, _: Function, data: &mut FunctionData) {
+        hoist_loop_invariants(data, is_movable);
+    }
+}
+
+/// Move the loop invariant instructions of a function for which `is_movable` holds out of their
+/// loops, giving the loops preheaders.
+///
+/// The last argument of `is_movable` tells if the block of the instruction dominates every exit
+/// of the loop, so that it is executed whenever the loop is left.
+pub(super) fn hoist_loop_invariants(
+    data: &mut FunctionData,
+    is_movable: impl Fn(&FunctionData, Value, bool) -> bool,
+) {
+    if data.layout().entry_bb().is_none() {
+        return;
+    }
+    let cfg = Cfg::new(data);
+    let loops = cfg.loops(&cfg.dominators());
+    if loops.is_empty() {
+        return;
+    }
+    for l in &loops {
+        insert_preheader(data, &cfg, l);
+    }
+
+    // Preheaders of inner loops are part of the outer loops now.
+    let cfg = Cfg::new(data);
+    let doms = cfg.dominators();
+    for l in cfg.loops(&doms) {
+        if let [preheader] = outside_preds(&cfg, &l)[..] {
+            hoist_invariants(data, &cfg, &doms, &l, preheader, &is_movable);
+        }
+    }
+}
+
+/// Get the predecessors of a loop header that are not in the loop.
+fn outside_preds(cfg: &Cfg, l: &Loop) -> Vec<BasicBlock> {
+    cfg.preds(l.header)
+        .iter()
+        .filter(|pred| cfg.is_reachable(**pred) && !l.body.contains(pred))
+        .copied()
+        .collect()
+}
+
+/// Make sure that the header of a loop has a single predecessor outside the loop, which has no
+/// other successors.
+fn insert_preheader(data: &mut FunctionData, cfg: &Cfg, l: &Loop) {
+    let preds = outside_preds(cfg, l);
+    if let [pred] = preds[..] {
+        if cfg.succs(pred) == [l.header] {
+            return;
+        }
+    }
+
+    let header = l.header;
+    let name = data
+        .dfg()
+        .bb(header)
+        .name()
+        .as_ref()
+        .map(|name| format!("{}_preheader", name));
+    let types: Vec<Type> = data
+        .dfg()
+        .bb(header)
+        .params()
+        .iter()
+        .map(|param| data.dfg().value(*param).ty().clone())
+        .collect();
+    let preheader = data.dfg_mut().new_bb().basic_block_with_params(name, types);
+    data.layout_mut()
+        .bbs_mut()
+        .cursor_mut(header)
+        .insert_key_before(preheader)
+        .unwrap();
+    let args = data.dfg().bb(preheader).params().to_vec();
+    let jump = data.dfg_mut().new_value().jump_with_args(header, args);
+    data.layout_mut()
+        .bb_mut(preheader)
+        .insts_mut()
+        .push_key_back(jump)
+        .unwrap();
+
+    // Redirect the edges entering the loop to the preheader.
+    for pred in preds {
+        let last = *data
+            .layout()
+            .bbs()
+            .node(&pred)
+            .unwrap()
+            .insts()
+            .back_key()
+            .unwrap();
+        let mut last_data = data.dfg().value(last).clone();
+        match last_data.kind_mut() {
+            ValueKind::Jump(jump) => *jump.target_mut() = preheader,
+            ValueKind::Branch(branch) => {
+                if branch.true_bb() == header {
+                    *branch.true_bb_mut() = preheader;
+                }
+                if branch.false_bb() == header {
+                    *branch.false_bb_mut() = preheader;
+                }
+            }
+            _ => unreachable!(),
+        }
+        data.dfg_mut().replace_value_with(last).raw(last_data);
+    }
+}
+
+/// Check if an instruction can be moved out of a loop when its operands are invariant, given if
+/// its block dominates every exit of the loop.
+pub(super) fn is_movable(data: &FunctionData, inst: Value, dominates_exits: bool) -> bool {
+    match data.dfg().value(inst).kind() {
+        ValueKind::Binary(binary) => {
+            dominates_exits || !matches!(binary.op(), BinaryOp::Div | BinaryOp::Mod)
+        }
+        ValueKind::GetPtr(_) | ValueKind::GetElemPtr(_) => true,
+        _ => false,
+    }
+}
+
+/// Move the loop invariant instructions of a loop to its preheader.
+fn hoist_invariants(
+    data: &mut FunctionData,
+    cfg: &Cfg,
+    doms: &Dominators,
+    l: &Loop,
+    preheader: BasicBlock,
+    is_movable: impl Fn(&FunctionData, Value, bool) -> bool,
+) {
+    // Values defined in the preheader, including the hoisted ones, are invariant.
+    let is_invariant = |data: &FunctionData, value: Value| {
+        if !data.dfg().values().contains_key(&value) {
+            return true;
+        }
+        match data.dfg().value(value).kind() {
+            ValueKind::BlockArgRef(_) => !l
+                .body
+                .iter()
+                .any(|bb| data.dfg().bb(*bb).params().contains(&value)),
+            _ => data
+                .layout()
+                .parent_bb(value)
+                .is_none_or(|bb| !l.body.contains(&bb)),
+        }
+    };
+
+    // Definitions come before their uses in reverse post order.
+    let blocks: Vec<BasicBlock> = cfg
+        .rpo()
+        .iter()
+        .filter(|bb| l.body.contains(bb))
+        .copied()
+        .collect();
+    let terminator = *data
+        .layout()
+        .bbs()
+        .node(&preheader)
+        .unwrap()
+        .insts()
+        .back_key()
+        .unwrap();
+    let exits: Vec<BasicBlock> = l
+        .body
+        .iter()
+        .filter(|bb| cfg.succs(**bb).iter().any(|succ| !l.body.contains(succ)))
+        .copied()
+        .collect();
+    for bb in blocks {
+        // A loop without exits is never left.
+        let dominates_exits =
+            !exits.is_empty() && exits.iter().all(|exit| doms.dominates(bb, *exit));
+        let insts: Vec<Value> = data
+            .layout()
+            .bbs()
+            .node(&bb)
+            .unwrap()
+            .insts()
+            .keys()
+            .copied()
+            .collect();
+        for inst in insts {
+            if !is_movable(data, inst, dominates_exits) {
+                continue;
+            }
+            let operands: Vec<Value> = data.dfg().value(inst).kind().value_uses().collect();
+            if !operands.iter().all(|v| is_invariant(data, *v)) {
+                continue;
+            }
+            data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+            data.layout_mut()
+                .bb_mut(preheader)
+                .insts_mut()
+                .cursor_mut(terminator)
+                .insert_key_before(inst)
+                .unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::front::opt::OptPass;
+    use crate::util::testing::{compile_with, func, interpret};
+    use koopa::ir::{BinaryOp, ValueKind};
+
+    #[test]
+    fn test_licm_divisions() {
+        let program = compile_with(
+            "int main() { int x = getint(); int d = getint(); int s = 0; int i = 0;
+            while (i < x / d) { s = s + x % d + (x + d); i = i + 1; } return s; }",
+            &[OptPass::Mem2Reg, OptPass::Licm],
+        );
+        let main = func(&program, "main");
+        let entry = main.layout().entry_bb().unwrap();
+        let block_of = |op: BinaryOp| {
+            let (bb, _) = main
+                .layout()
+                .bbs()
+                .iter()
+                .find(|(_, node)| {
+                    node.insts().keys().any(|inst| {
+                        matches!(main.dfg().value(*inst).kind(), ValueKind::Binary(b) if b.op() == op)
+                    })
+                })
+                .unwrap();
+            *bb
+        };
+        let preheader = block_of(BinaryOp::Add);
+        assert_ne!(preheader, entry);
+        // The condition runs before the loop is left, but the body may never run.
+        assert_eq!(block_of(BinaryOp::Div), preheader);
+        assert_ne!(block_of(BinaryOp::Mod), preheader);
+        assert_eq!(interpret(&program, b"0 1").0, Ok(0));
+        assert_eq!(interpret(&program, b"7 3").0, Ok(22));
+    }
+}
@@ -0,0 +1,376 @@
+//! Promote scalar allocs to SSA values.
+//!
+//! Uses the classic construction from Cytron et al., "Efficiently Computing Static Single
+//! Assignment Form and the Control Dependence Graph". A basic block parameter is placed on the
+//! iterated dominance frontier of the stores to an alloc wherever the alloc is live, then loads
+//! and stores are renamed in a walk over the dominator tree and the branches pass the reaching
+//! values as block arguments.
+
+use crate::util::cfg::Cfg;
+use crate::util::ir::replace_all_uses;
+use crate::util::remove_pointer;
+use koopa::ir::builder::{BasicBlockBuilder, LocalInstBuilder, ValueBuilder};
+use koopa::ir::{BasicBlock, Function, FunctionData, Type, TypeKind, Value, ValueKind};
+use koopa::opt::FunctionPass;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Default)]
+pub(crate) struct Mem2Reg;
+
+impl FunctionPass for Mem2Reg {
+    fn run_on(&mut self, _: Function, data: &mut FunctionData) {
+        if data.layout().entry_bb().is_none() {
+            return;
+        }
+        let allocs = promotable_allocs(data);
+        if allocs.is_empty() {
+            return;
+        }
+        let cfg = Cfg::new(data);
+        let params = place_params(data, &cfg, &allocs);
+        Renamer::new(data, &allocs, params).run(data, &cfg);
+    }
+}
+
+/// Find the allocs that hold a scalar and are only loaded from and stored to.
+fn promotable_allocs(data: &FunctionData) -> Vec<Value> {
+    let mut allocs = vec![];
+    for (_, node) in data.layout().bbs() {
+        for inst in node.insts().keys() {
+            let inst_data = data.dfg().value(*inst);
+            if !matches!(inst_data.kind(), ValueKind::Alloc(_)) {
+                continue;
+            }
+            if let TypeKind::Array(..) = remove_pointer(inst_data.ty().clone()).kind() {
+                continue;
+            }
+            let promotable =
+                inst_data
+                    .used_by()
+                    .iter()
+                    .all(|user| match data.dfg().value(*user).kind() {
+                        ValueKind::Load(_) => true,
+                        ValueKind::Store(store) => store.dest() == *inst && store.value() != *inst,
+                        _ => false,
+                    });
+            if promotable {
+                allocs.push(*inst);
+            }
+        }
+    }
+    allocs
+}
+
+/// Compute the blocks where every alloc is live on entry, i.e. it may be loaded before it is
+/// stored to.
+fn live_in_blocks(
+    data: &FunctionData,
+    cfg: &Cfg,
+    allocs: &[Value],
+) -> HashMap<BasicBlock, HashSet<Value>> {
+    let promoted: HashSet<Value> = allocs.iter().copied().collect();
+    let mut gen_kill = HashMap::new();
+    for (bb, node) in data.layout().bbs() {
+        let mut uses = HashSet::new();
+        let mut defs = HashSet::new();
+        for inst in node.insts().keys() {
+            match data.dfg().value(*inst).kind() {
+                ValueKind::Load(load)
+                    if promoted.contains(&load.src()) && !defs.contains(&load.src()) =>
+                {
+                    uses.insert(load.src());
+                }
+                ValueKind::Store(store) if promoted.contains(&store.dest()) => {
+                    defs.insert(store.dest());
+                }
+                _ => {}
+            }
+        }
+        gen_kill.insert(*bb, (uses, defs));
+    }
+
+    let mut live_in: HashMap<BasicBlock, HashSet<Value>> = gen_kill
+        .iter()
+        .map(|(bb, (uses, _))| (*bb, uses.clone()))
+        .collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for bb in cfg.rpo().iter().rev() {
+            let (_, defs) = &gen_kill[bb];
+            let mut live: HashSet<Value> = HashSet::new();
+            for succ in cfg.succs(*bb) {
+                live.extend(live_in[succ].iter().filter(|v| !defs.contains(v)));
+            }
+            let bb_live_in = live_in.get_mut(bb).unwrap();
+            let len = bb_live_in.len();
+            bb_live_in.extend(live);
+            changed |= bb_live_in.len() != len;
+        }
+    }
+    live_in
+}
+
+/// Place block parameters for the allocs and return the alloc of every new parameter.
+fn place_params(
+    data: &mut FunctionData,
+    cfg: &Cfg,
+    allocs: &[Value],
+) -> HashMap<BasicBlock, Vec<(Value, Value)>> {
+    let doms = cfg.dominators();
+    let frontiers = doms.frontiers(cfg);
+    let live_in = live_in_blocks(data, cfg, allocs);
+
+    let mut placed: HashMap<BasicBlock, Vec<Value>> = HashMap::new();
+    for alloc in allocs {
+        let mut worklist: Vec<BasicBlock> = data
+            .dfg()
+            .value(*alloc)
+            .used_by()
+            .iter()
+            .filter(|user| matches!(data.dfg().value(**user).kind(), ValueKind::Store(_)))
+            .filter_map(|user| data.layout().parent_bb(*user))
+            .filter(|bb| cfg.is_reachable(*bb))
+            .collect();
+        let mut visited: HashSet<BasicBlock> = worklist.iter().copied().collect();
+        while let Some(bb) = worklist.pop() {
+            for frontier in &frontiers[&bb] {
+                let bb_allocs = placed.entry(*frontier).or_default();
+                if bb_allocs.contains(alloc) || !live_in[frontier].contains(alloc) {
+                    continue;
+                }
+                bb_allocs.push(*alloc);
+                if visited.insert(*frontier) {
+                    worklist.push(*frontier);
+                }
+            }
+        }
+    }
+
+    let mut params = HashMap::new();
+    for (bb, bb_allocs) in placed {
+        if bb_allocs.is_empty() {
+            continue;
+        }
+        // Koopa can only create parameters with a new block, so they are moved from a
+        // temporary one.
+        let types: Vec<Type> = bb_allocs
+            .iter()
+            .map(|alloc| remove_pointer(data.dfg().value(*alloc).ty().clone()))
+            .collect();
+        let temp = data.dfg_mut().new_bb().basic_block_with_params(None, types);
+        let bb_params = std::mem::take(data.dfg_mut().bb_mut(temp).params_mut());
+        data.dfg_mut().remove_bb(temp);
+        *data.dfg_mut().bb_mut(bb).params_mut() = bb_params.clone();
+        params.insert(bb, bb_allocs.into_iter().zip(bb_params).collect());
+    }
+    params
+}
+
+struct Renamer {
+    promoted: HashSet<Value>,
+    /// The `(alloc, parameter)` pairs of every block.
+    params: HashMap<BasicBlock, Vec<(Value, Value)>>,
+    /// The values reaching the current point for every alloc.
+    stacks: HashMap<Value, Vec<Value>>,
+    /// Values for allocs that are loaded before any store.
+    initial: HashMap<Value, Value>,
+    /// Removed loads and the values they are replaced with.
+    replaced: HashMap<Value, Value>,
+    removed: Vec<Value>,
+}
+
+impl Renamer {
+    fn new(
+        data: &mut FunctionData,
+        allocs: &[Value],
+        params: HashMap<BasicBlock, Vec<(Value, Value)>>,
+    ) -> Self {
+        let mut initial = HashMap::new();
+        for alloc in allocs {
+            let ty = remove_pointer(data.dfg().value(*alloc).ty().clone());
+            let value = if ty.is_i32() {
+                data.dfg_mut().new_value().integer(0)
+            } else {
+                data.dfg_mut().new_value().undef(ty)
+            };
+            initial.insert(*alloc, value);
+        }
+        Self {
+            promoted: allocs.iter().copied().collect(),
+            params,
+            stacks: allocs.iter().map(|alloc| (*alloc, vec![])).collect(),
+            initial,
+            replaced: HashMap::new(),
+            removed: vec![],
+        }
+    }
+
+    fn current(&self, alloc: Value) -> Value {
+        self.stacks[&alloc]
+            .last()
+            .copied()
+            .unwrap_or(self.initial[&alloc])
+    }
+
+    fn run(mut self, data: &mut FunctionData, cfg: &Cfg) {
+        let doms = cfg.dominators();
+        let children = doms.children();
+
+        // Walk the dominator tree, popping the values pushed in a block when leaving it.
+        let entry = cfg.entry.unwrap();
+        let mut walk = vec![(entry, false)];
+        let mut pushed: Vec<Vec<Value>> = vec![];
+        while let Some((bb, leaving)) = walk.pop() {
+            if leaving {
+                for alloc in pushed.pop().unwrap() {
+                    self.stacks.get_mut(&alloc).unwrap().pop();
+                }
+                continue;
+            }
+            pushed.push(self.rename_block(data, bb));
+            walk.push((bb, true));
+            for child in children[&bb].iter().rev() {
+                walk.push((*child, false));
+            }
+        }
+        // Unreachable blocks only see the initial values.
+        let unreachable: Vec<BasicBlock> = data
+            .layout()
+            .bbs()
+            .keys()
+            .filter(|bb| !cfg.is_reachable(**bb))
+            .copied()
+            .collect();
+        for bb in unreachable {
+            for alloc in self.rename_block(data, bb) {
+                self.stacks.get_mut(&alloc).unwrap().pop();
+            }
+        }
+
+        for (load, value) in &self.replaced {
+            replace_all_uses(data, *load, *value);
+        }
+        for inst in &self.removed {
+            let bb = data.layout().parent_bb(*inst).unwrap();
+            data.layout_mut().bb_mut(bb).insts_mut().remove(inst);
+        }
+        // Stores use the loads and the allocs, and loads use the allocs.
+        let kind_order = |inst: &Value| match data.dfg().value(*inst).kind() {
+            ValueKind::Store(_) => 0,
+            ValueKind::Load(_) => 1,
+            _ => 2,
+        };
+        self.removed.sort_by_key(kind_order);
+        for inst in self.removed {
+            data.dfg_mut().remove_value(inst);
+        }
+        for value in self.initial.into_values() {
+            if data.dfg().value(value).used_by().is_empty() {
+                data.dfg_mut().remove_value(value);
+            }
+        }
+    }
+
+    /// Rename the loads and stores in a block and pass the reaching values to its successors.
+    ///
+    /// Returns the allocs whose stacks are pushed.
+    fn rename_block(&mut self, data: &mut FunctionData, bb: BasicBlock) -> Vec<Value> {
+        let mut pushed = vec![];
+        for (alloc, param) in self.params.get(&bb).into_iter().flatten() {
+            self.stacks.get_mut(alloc).unwrap().push(*param);
+            pushed.push(*alloc);
+        }
+
+        let insts: Vec<Value> = data
+            .layout()
+            .bbs()
+            .node(&bb)
+            .unwrap()
+            .insts()
+            .keys()
+            .copied()
+            .collect();
+        for inst in insts {
+            match data.dfg().value(inst).kind() {
+                ValueKind::Alloc(_) if self.promoted.contains(&inst) => self.removed.push(inst),
+                ValueKind::Load(load) if self.promoted.contains(&load.src()) => {
+                    let value = self.current(load.src());
+                    self.replaced.insert(inst, value);
+                    self.removed.push(inst);
+                }
+                ValueKind::Store(store) if self.promoted.contains(&store.dest()) => {
+                    let value = self.resolve(store.value());
+                    self.stacks.get_mut(&store.dest()).unwrap().push(value);
+                    pushed.push(store.dest());
+                    self.removed.push(inst);
+                }
+                ValueKind::Jump(jump) => {
+                    let target = jump.target();
+                    let args = self.block_args(target);
+                    data.dfg_mut()
+                        .replace_value_with(inst)
+                        .jump_with_args(target, args);
+                }
+                ValueKind::Branch(branch) => {
+                    let (cond, true_bb, false_bb) =
+                        (branch.cond(), branch.true_bb(), branch.false_bb());
+                    let true_args = self.block_args(true_bb);
+                    let false_args = self.block_args(false_bb);
+                    data.dfg_mut()
+                        .replace_value_with(inst)
+                        .branch_with_args(cond, true_bb, false_bb, true_args, false_args);
+                }
+                _ => {}
+            }
+        }
+        pushed
+    }
+
+    /// Get the value of a removed load, or the value itself.
+    fn resolve(&self, value: Value) -> Value {
+        self.replaced.get(&value).copied().unwrap_or(value)
+    }
+
+    fn block_args(&self, bb: BasicBlock) -> Vec<Value> {
+        self.params
+            .get(&bb)
+            .into_iter()
+            .flatten()
+            .map(|(alloc, _)| self.current(*alloc))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::front::opt::OptPass;
+    use crate::util::testing::{compile_with, func, inst_kinds, interpret};
+    use koopa::ir::ValueKind;
+
+    #[test]
+    fn test_mem2reg_loop() {
+        let program = compile_with(
+            "int main() { int s = 0; int i = 0; int a[2];
+            while (i < 10) { s = s + i; i = i + 1; } a[1] = s; return a[1]; }",
+            &[OptPass::Mem2Reg],
+        );
+        let main = func(&program, "main");
+        // Only the array stays in memory.
+        let allocs = inst_kinds(main)
+            .iter()
+            .filter(|kind| matches!(kind, ValueKind::Alloc(_)))
+            .count();
+        assert_eq!(allocs, 1);
+        // The loop header merges `s` and `i` from the entry and from the back edge.
+        let params: Vec<usize> = main
+            .layout()
+            .bbs()
+            .keys()
+            .map(|bb| main.dfg().bb(*bb).params().len())
+            .filter(|n| *n > 0)
+            .collect();
+        assert_eq!(params, [2]);
+        assert_eq!(interpret(&program, b"").0, Ok(45));
+    }
+}
@@ -1,91 +1,289 @@
-//! Optimize multiplication division and modulo
+//! Strength reduction of multiplication, division and modulo by constants.
+//!
+//! Multiplications by a power of two, its negation, or a power of two plus or minus one become
+//! shifts followed by at most one addition or subtraction. Divisions by a power of two add a bias
+//! to negative dividends before shifting, so that the quotient rounds toward zero. Other divisors
+//! use the magic numbers of Granlund and Montgomery: the quotient is the high word of the product
+//! of the dividend and the magic number, corrected and shifted, the high word being computed by
+//! the `mulh` intrinsic. Modulo is the dividend minus the product of the quotient and the divisor.
 
+use crate::front::ir::builtin::Intrinsic;
+use crate::util::ir::replace_all_uses;
 use koopa::ir::builder::{LocalInstBuilder, ValueBuilder};
-use koopa::ir::{BinaryOp, Function, FunctionData, ValueKind};
-use koopa::opt::FunctionPass;
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, Program, Value, ValueKind};
+use koopa::opt::ModulePass;
 
 #[derive(Default)]
 pub(crate) struct MulDiv;
 
-impl FunctionPass for MulDiv {
-    fn run_on(&mut self, _: Function, data: &mut FunctionData) {
-        let mut rhs_replaced = vec![];
-        let mut lhs_replaced = vec![];
-        for (v, value) in data.dfg().values() {
-            match value.kind() {
-                ValueKind::Binary(bin) => {
-                    let lhs = data.dfg().value(bin.lhs()).kind();
-                    let rhs = data.dfg().value(bin.rhs()).kind();
-                    if let ValueKind::Integer(rhs) = rhs {
-                        let ord = if let Some(ord) = power_of_two(rhs.value()) {
-                            ord
-                        } else {
-                            continue;
-                        };
-                        match bin.op() {
-                            BinaryOp::Mul => {
-                                rhs_replaced.push((*v, ord, BinaryOp::Shl, bin.lhs(), bin.rhs()));
-                            }
-                            BinaryOp::Div => {
-                                rhs_replaced.push((*v, ord, BinaryOp::Shr, bin.lhs(), bin.rhs()));
-                            }
-                            BinaryOp::Mod => {
-                                rhs_replaced.push((
-                                    *v,
-                                    rhs.value() - 1,
-                                    BinaryOp::And,
-                                    bin.lhs(),
-                                    bin.rhs(),
-                                ));
-                            }
-                            _ => continue,
-                        }
-                    } else if let ValueKind::Integer(lhs) = lhs {
-                        let ord = if let Some(ord) = power_of_two(lhs.value()) {
-                            ord
-                        } else {
-                            continue;
-                        };
-                        match bin.op() {
-                            BinaryOp::Mul => {
-                                lhs_replaced.push((*v, ord, BinaryOp::Shl, bin.lhs(), bin.rhs()));
-                            }
-                            _ => continue,
-                        }
-                    }
-                }
-                _ => continue,
-            };
+impl ModulePass for MulDiv {
+    fn run_on(&mut self, program: &mut Program) {
+        let mulh_name = format!("@{}", Intrinsic::MulHigh.name());
+        let mut mulh = program
+            .func_layout()
+            .iter()
+            .copied()
+            .find(|func| program.func(*func).name() == mulh_name);
+        for func in program.func_layout().to_vec() {
+            let data = program.func(func);
+            let insts: Vec<(Value, BasicBlock)> = data
+                .layout()
+                .bbs()
+                .iter()
+                .flat_map(|(bb, node)| node.insts().keys().map(|inst| (*inst, *bb)))
+                .filter(|(inst, _)| reduction(data, *inst).is_some())
+                .collect();
+            let needs_mulh = insts.iter().any(|(inst, _)| {
+                matches!(reduction(data, *inst), Some((op, _, c)) if op != BinaryOp::Mul && needs_magic(c))
+            });
+            if needs_mulh && mulh.is_none() {
+                mulh = Some(Intrinsic::MulHigh.declare(program));
+            }
+            let data = program.func_mut(func);
+            // The operands are read again, as they may be results of instructions replaced before,
+            // which may even have become constants.
+            for (inst, bb) in insts {
+                let Some((op, operand, c)) = reduction(data, inst) else {
+                    continue;
+                };
+                let mut builder = Builder {
+                    data,
+                    bb,
+                    inst,
+                    mulh,
+                };
+                let result = match op {
+                    BinaryOp::Mul => builder.mul(operand, c),
+                    BinaryOp::Div => builder.div(operand, c),
+                    _ => builder.rem(operand, c),
+                };
+                replace_all_uses(data, inst, result);
+                data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+                data.dfg_mut().remove_value(inst);
+            }
         }
+    }
+}
 
-        for (v, val, op, lhs, rhs) in &rhs_replaced {
-            let rhs_builder = data.dfg_mut().replace_value_with(*rhs);
-            let rhs = rhs_builder.integer(*val);
-            let builder = data.dfg_mut().replace_value_with(*v);
-            builder.binary(*op, *lhs, rhs);
-        }
+/// Get the operation, the non-constant operand and the constant of a multiplication, division or
+/// modulo by a constant.
+fn reduction(data: &FunctionData, inst: Value) -> Option<(BinaryOp, Value, i32)> {
+    let constant = |value: Value| match data.dfg().value(value).kind() {
+        ValueKind::Integer(int) => Some(int.value()),
+        _ => None,
+    };
+    let ValueKind::Binary(bin) = data.dfg().value(inst).kind() else {
+        return None;
+    };
+    let (lhs, rhs) = (bin.lhs(), bin.rhs());
+    match (bin.op(), constant(lhs), constant(rhs)) {
+        // Constant operations are left to constant folding.
+        (_, Some(_), Some(_)) => None,
+        (BinaryOp::Mul, Some(c), None) => Some((BinaryOp::Mul, rhs, c)),
+        (BinaryOp::Mul, None, Some(c)) => Some((BinaryOp::Mul, lhs, c)),
+        (op @ (BinaryOp::Div | BinaryOp::Mod), None, Some(c)) if c != 0 => Some((op, lhs, c)),
+        _ => None,
+    }
+}
+
+/// Check if dividing by a constant takes a magic number.
+fn needs_magic(divisor: i32) -> bool {
+    divisor != i32::MIN && divisor.unsigned_abs() > 1 && !divisor.unsigned_abs().is_power_of_two()
+}
 
-        for (v, val, op, lhs, rhs) in &lhs_replaced {
-            let lhs_builder = data.dfg_mut().replace_value_with(*lhs);
-            let lhs = lhs_builder.integer(*val);
-            let builder = data.dfg_mut().replace_value_with(*v);
-            builder.binary(*op, *rhs, lhs);
+/// Get the magic number and the shift amount of a divisor, as in Hacker's Delight, figure 10-1.
+///
+/// The divisor must not be 0, 1, -1 or a power of two or its negation.
+fn magic(divisor: i32) -> (i32, i32) {
+    const TWO31: u32 = 1 << 31;
+    let ad = divisor.unsigned_abs();
+    let t = TWO31 + ((divisor as u32) >> 31);
+    // The absolute value of the largest dividend that is one less than a multiple of the divisor.
+    let anc = t - 1 - t % ad;
+    let mut p = 31;
+    let (mut q1, mut r1) = (TWO31 / anc, TWO31 % anc);
+    let (mut q2, mut r2) = (TWO31 / ad, TWO31 % ad);
+    loop {
+        p += 1;
+        q1 = q1.wrapping_mul(2);
+        r1 = r1.wrapping_mul(2);
+        if r1 >= anc {
+            q1 = q1.wrapping_add(1);
+            r1 = r1.wrapping_sub(anc);
+        }
+        q2 = q2.wrapping_mul(2);
+        r2 = r2.wrapping_mul(2);
+        if r2 >= ad {
+            q2 = q2.wrapping_add(1);
+            r2 = r2.wrapping_sub(ad);
+        }
+        let delta = ad - r2;
+        if !(q1 < delta || (q1 == delta && r1 == 0)) {
+            break;
         }
     }
+    let magic = q2.wrapping_add(1) as i32;
+    let magic = if divisor < 0 {
+        magic.wrapping_neg()
+    } else {
+        magic
+    };
+    (magic, p - 32)
 }
 
-fn power_of_two(mut n: i32) -> Option<i32> {
-    if n == 0 {
-        return None;
+/// Inserts the instructions replacing `inst` before it.
+struct Builder<'a> {
+    data: &'a mut FunctionData,
+    bb: BasicBlock,
+    inst: Value,
+    mulh: Option<Function>,
+}
+
+impl Builder<'_> {
+    fn integer(&mut self, value: i32) -> Value {
+        self.data.dfg_mut().new_value().integer(value)
     }
-    let mut ord = 0;
-    while n % 2 == 0 {
-        n /= 2;
-        ord += 1;
+
+    fn insert(&mut self, value: Value) -> Value {
+        self.data
+            .layout_mut()
+            .bb_mut(self.bb)
+            .insts_mut()
+            .cursor_mut(self.inst)
+            .insert_key_before(value)
+            .unwrap();
+        value
     }
-    if n == 1 {
-        Some(ord)
-    } else {
-        None
+
+    fn binary(&mut self, op: BinaryOp, lhs: Value, rhs: Value) -> Value {
+        let value = self.data.dfg_mut().new_value().binary(op, lhs, rhs);
+        self.insert(value)
+    }
+
+    fn binary_imm(&mut self, op: BinaryOp, lhs: Value, rhs: i32) -> Value {
+        let rhs = self.integer(rhs);
+        self.binary(op, lhs, rhs)
+    }
+
+    fn neg(&mut self, value: Value) -> Value {
+        let zero = self.integer(0);
+        self.binary(BinaryOp::Sub, zero, value)
+    }
+
+    /// Multiply a value by a constant.
+    fn mul(&mut self, lhs: Value, rhs: i32) -> Value {
+        let abs = rhs.unsigned_abs();
+        if rhs == 0 {
+            self.integer(0)
+        } else if rhs == 1 {
+            lhs
+        } else if rhs == -1 {
+            self.neg(lhs)
+        } else if abs.is_power_of_two() {
+            let shifted = self.binary_imm(BinaryOp::Shl, lhs, abs.trailing_zeros() as i32);
+            if rhs < 0 && rhs != i32::MIN {
+                self.neg(shifted)
+            } else {
+                shifted
+            }
+        } else if rhs > 0 && (rhs as u32 - 1).is_power_of_two() {
+            let shifted = self.binary_imm(BinaryOp::Shl, lhs, (rhs - 1).trailing_zeros() as i32);
+            self.binary(BinaryOp::Add, shifted, lhs)
+        } else if rhs > 0 && (rhs as u32 + 1).is_power_of_two() {
+            let shifted =
+                self.binary_imm(BinaryOp::Shl, lhs, (rhs as u32 + 1).trailing_zeros() as i32);
+            self.binary(BinaryOp::Sub, shifted, lhs)
+        } else {
+            self.binary_imm(BinaryOp::Mul, lhs, rhs)
+        }
+    }
+
+    /// Divide a value by a nonzero constant, rounding toward zero.
+    fn div(&mut self, lhs: Value, rhs: i32) -> Value {
+        let abs = rhs.unsigned_abs();
+        if rhs == 1 {
+            lhs
+        } else if rhs == -1 {
+            self.neg(lhs)
+        } else if rhs == i32::MIN {
+            self.binary_imm(BinaryOp::Eq, lhs, i32::MIN)
+        } else if abs.is_power_of_two() {
+            let shift = abs.trailing_zeros() as i32;
+            let biased = self.bias(lhs, shift);
+            let quotient = self.binary_imm(BinaryOp::Sar, biased, shift);
+            if rhs < 0 {
+                self.neg(quotient)
+            } else {
+                quotient
+            }
+        } else {
+            let (magic, shift) = magic(rhs);
+            let magic_value = self.integer(magic);
+            let call = self
+                .data
+                .dfg_mut()
+                .new_value()
+                .call(self.mulh.unwrap(), vec![magic_value, lhs]);
+            let mut quotient = self.insert(call);
+            if rhs > 0 && magic < 0 {
+                quotient = self.binary(BinaryOp::Add, quotient, lhs);
+            } else if rhs < 0 && magic > 0 {
+                quotient = self.binary(BinaryOp::Sub, quotient, lhs);
+            }
+            if shift > 0 {
+                quotient = self.binary_imm(BinaryOp::Sar, quotient, shift);
+            }
+            // Add one to negative quotients, which were rounded down.
+            let sign = self.binary_imm(BinaryOp::Shr, quotient, 31);
+            self.binary(BinaryOp::Add, quotient, sign)
+        }
+    }
+
+    /// Get the remainder of a value divided by a nonzero constant, with the sign of the value.
+    fn rem(&mut self, lhs: Value, rhs: i32) -> Value {
+        let abs = rhs.unsigned_abs();
+        if abs == 1 {
+            self.integer(0)
+        } else if abs.is_power_of_two() && rhs != i32::MIN {
+            let shift = abs.trailing_zeros() as i32;
+            let biased = self.bias(lhs, shift);
+            let multiple = self.binary_imm(BinaryOp::And, biased, -(abs as i32));
+            self.binary(BinaryOp::Sub, lhs, multiple)
+        } else {
+            let quotient = self.div(lhs, rhs);
+            let multiple = self.mul(quotient, rhs);
+            self.binary(BinaryOp::Sub, lhs, multiple)
+        }
+    }
+
+    /// Add `2^shift - 1` to a value if it is negative, so that shifting it right by `shift`
+    /// rounds toward zero.
+    fn bias(&mut self, value: Value, shift: i32) -> Value {
+        let bias = if shift == 1 {
+            self.binary_imm(BinaryOp::Shr, value, 31)
+        } else {
+            let sign = self.binary_imm(BinaryOp::Sar, value, 31);
+            self.binary_imm(BinaryOp::Shr, sign, 32 - shift)
+        };
+        self.binary(BinaryOp::Add, value, bias)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::testing::{compile_at, interpret};
+
+    #[test]
+    fn test_mul_div() {
+        let program = compile_at(
+            "int main() { int x = getint();
+            putint(x / 4); putch(32); putint(x % 4); putch(32); putint(x / -7); putch(32);
+            putint(x % 7); putch(32); putint(x * 9); putch(32); putint(x * -8); return 0; }",
+            2,
+        );
+        assert_eq!(
+            interpret(&program, b"-23"),
+            (Ok(0), "-5 -3 3 -2 -207 184".to_string())
+        );
     }
 }
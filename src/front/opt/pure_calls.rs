@@ -0,0 +1,345 @@
+//! Interprocedural purity analysis and the optimization of calls to pure functions.
+//!
+//! Every function is classified from the loads, stores and calls of its body. A load or store is
+//! local when its address is computed from an `alloc` of the function, whose memory cannot be
+//! seen by the callers. Functions that only access local memory and only call pure functions are
+//! pure, functions that also load from other memory are read-only, and the others, including all
+//! those calling the runtime library, have side effects. Recursive functions are classified by
+//! iterating from the optimistic assumption that every function is pure. The intrinsics are pure.
+//!
+//! With the classification:
+//! - calls to pure functions whose arguments are all constants are evaluated by the interpreter;
+//! - calls to pure and read-only functions that terminate and whose result is unused are removed;
+//! - calls to pure functions that terminate and whose arguments are loop invariant are moved out
+//!   of loops, along with the other invariants of the loop, if their blocks dominate every exit of
+//!   the loop.
+//!
+//! A function terminates if its body has no cycles and it only calls intrinsics and functions
+//! that terminate, so recursive functions never do.
+
+use crate::front::ir::builtin::{is_builtin, Intrinsic};
+use crate::front::opt::licm::{hoist_loop_invariants, is_movable};
+use crate::interp::evaluate;
+use crate::util::cfg::Cfg;
+use koopa::ir::builder::ValueBuilder;
+use koopa::ir::{BasicBlock, Function, FunctionData, Program, Value, ValueKind};
+use koopa::opt::ModulePass;
+use std::collections::{HashMap, HashSet};
+
+/// Maximum number of instructions executed to evaluate a call at compile time.
+const MAX_EVAL_STEPS: u64 = 1 << 20;
+
+#[derive(Default)]
+pub(crate) struct PureCalls;
+
+/// The effects a function may have, from the weakest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Effects {
+    Pure,
+    ReadOnly,
+    /// Writes to non-local memory or does I/O.
+    Impure,
+}
+
+impl ModulePass for PureCalls {
+    fn run_on(&mut self, program: &mut Program) {
+        let effects = classify(program);
+        let is_pure = |func: Function| effects.get(&func) == Some(&Effects::Pure);
+        let terminating = terminating_functions(program);
+
+        let folded = fold_calls(program, &is_pure);
+        for (func, calls) in folded {
+            let data = program.func_mut(func);
+            for (call, value) in calls {
+                replace_with_integer(data, call, value);
+            }
+        }
+
+        for func in program.func_layout().to_vec() {
+            let data = program.func_mut(func);
+            remove_unused_calls(data, &effects, &terminating);
+            hoist_loop_invariants(data, |data, inst, dominates_exits| {
+                let is_hoistable = |func| is_pure(func) && terminating.contains(&func);
+                is_movable(data, inst, dominates_exits)
+                    || (dominates_exits && callee(data, inst).is_some_and(is_hoistable))
+            });
+        }
+    }
+}
+
+/// Classify every function of a program.
+fn classify(program: &Program) -> HashMap<Function, Effects> {
+    let mut effects: HashMap<Function, Effects> = program
+        .func_layout()
+        .iter()
+        .map(|func| {
+            let data = program.func(*func);
+            let effects = if is_builtin(data.name()) {
+                // The runtime library does I/O.
+                Effects::Impure
+            } else if data.layout().entry_bb().is_some()
+                || Intrinsic::from_name(data.name()).is_some()
+            {
+                Effects::Pure
+            } else {
+                // Nothing is known of other declared functions.
+                Effects::Impure
+            };
+            (*func, effects)
+        })
+        .collect();
+
+    // The effects only grow, so this terminates.
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for func in program.func_layout() {
+            let data = program.func(*func);
+            if data.layout().entry_bb().is_none() || effects[func] == Effects::Impure {
+                continue;
+            }
+            let mut func_effects = Effects::Pure;
+            for (_, node) in data.layout().bbs() {
+                for inst in node.insts().keys() {
+                    let inst_effects = match data.dfg().value(*inst).kind() {
+                        ValueKind::Load(load) if !is_local(data, load.src()) => Effects::ReadOnly,
+                        ValueKind::Store(store) if !is_local(data, store.dest()) => Effects::Impure,
+                        ValueKind::Call(call) => effects[&call.callee()],
+                        _ => Effects::Pure,
+                    };
+                    func_effects = func_effects.max(inst_effects);
+                }
+            }
+            if func_effects != effects[func] {
+                effects.insert(*func, func_effects);
+                changed = true;
+            }
+        }
+    }
+    effects
+}
+
+/// Get the functions of a program that always terminate.
+fn terminating_functions(program: &Program) -> HashSet<Function> {
+    let mut terminating: HashSet<Function> = program
+        .func_layout()
+        .iter()
+        .filter(|func| Intrinsic::from_name(program.func(**func).name()).is_some())
+        .copied()
+        .collect();
+    let candidates: Vec<Function> = program
+        .func_layout()
+        .iter()
+        .filter(|func| {
+            program.func(**func).layout().entry_bb().is_some() && is_acyclic(program.func(**func))
+        })
+        .copied()
+        .collect();
+
+    // Only functions whose callees are known to terminate are added, so recursive ones never are.
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for func in &candidates {
+            if terminating.contains(func) {
+                continue;
+            }
+            let data = program.func(*func);
+            let calls_terminating = data.layout().bbs().nodes().all(|node| {
+                node.insts().keys().all(|inst| {
+                    callee(data, *inst).is_none_or(|callee| terminating.contains(&callee))
+                })
+            });
+            if calls_terminating {
+                terminating.insert(*func);
+                changed = true;
+            }
+        }
+    }
+    terminating
+}
+
+/// Check if the control flow graph of a function has no cycles, i.e. no edge goes back in
+/// reverse post order.
+fn is_acyclic(data: &FunctionData) -> bool {
+    let cfg = Cfg::new(data);
+    let order: HashMap<BasicBlock, usize> = cfg
+        .rpo()
+        .iter()
+        .enumerate()
+        .map(|(i, bb)| (*bb, i))
+        .collect();
+    cfg.rpo()
+        .iter()
+        .all(|bb| cfg.succs(*bb).iter().all(|succ| order[succ] > order[bb]))
+}
+
+/// Check if an address points into the memory allocated by the function itself.
+fn is_local(data: &FunctionData, mut address: Value) -> bool {
+    loop {
+        if address.is_global() {
+            return false;
+        }
+        match data.dfg().value(address).kind() {
+            ValueKind::Alloc(_) => return true,
+            ValueKind::GetPtr(get_ptr) => address = get_ptr.src(),
+            ValueKind::GetElemPtr(get_elem_ptr) => address = get_elem_ptr.src(),
+            // Parameters, loaded pointers and block parameters may point anywhere.
+            _ => return false,
+        }
+    }
+}
+
+/// Get the function called by an instruction, if it is a call.
+fn callee(data: &FunctionData, inst: Value) -> Option<Function> {
+    match data.dfg().value(inst).kind() {
+        ValueKind::Call(call) => Some(call.callee()),
+        _ => None,
+    }
+}
+
+/// Evaluate the calls to pure functions with constant arguments, giving their values by function.
+fn fold_calls(
+    program: &Program,
+    is_pure: &impl Fn(Function) -> bool,
+) -> Vec<(Function, Vec<(Value, i32)>)> {
+    let mut folded = vec![];
+    for func in program.func_layout() {
+        let data = program.func(*func);
+        let mut calls = vec![];
+        for (_, node) in data.layout().bbs() {
+            for inst in node.insts().keys() {
+                let ValueKind::Call(call) = data.dfg().value(*inst).kind() else {
+                    continue;
+                };
+                if !is_pure(call.callee()) || data.dfg().value(*inst).ty().is_unit() {
+                    continue;
+                }
+                let args: Option<Vec<i32>> = call
+                    .args()
+                    .iter()
+                    .map(|arg| match data.dfg().value(*arg).kind() {
+                        ValueKind::Integer(int) => Some(int.value()),
+                        _ => None,
+                    })
+                    .collect();
+                let Some(args) = args else {
+                    continue;
+                };
+                let callee_data = program.func(call.callee());
+                let value = match Intrinsic::from_name(callee_data.name()) {
+                    Some(intrinsic) => Some(intrinsic.eval(&args)),
+                    None => evaluate(program, call.callee(), args, MAX_EVAL_STEPS),
+                };
+                if let Some(value) = value {
+                    calls.push((*inst, value));
+                }
+            }
+        }
+        if !calls.is_empty() {
+            folded.push((*func, calls));
+        }
+    }
+    folded
+}
+
+/// Replace an instruction with a constant.
+fn replace_with_integer(data: &mut FunctionData, inst: Value, value: i32) {
+    let bb = data.layout().parent_bb(inst).unwrap();
+    data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+    data.dfg_mut().replace_value_with(inst).integer(value);
+}
+
+/// Remove the calls to terminating functions without side effects whose results are not used, and
+/// then the calls whose results only they used.
+fn remove_unused_calls(
+    data: &mut FunctionData,
+    effects: &HashMap<Function, Effects>,
+    terminating: &HashSet<Function>,
+) {
+    let mut changed = true;
+    while changed {
+        changed = false;
+        let unused: Vec<Value> = data
+            .layout()
+            .bbs()
+            .nodes()
+            .flat_map(|node| node.insts().keys().copied())
+            .filter(|inst| {
+                callee(data, *inst).is_some_and(|func| {
+                    effects[&func] < Effects::Impure && terminating.contains(&func)
+                }) && data.dfg().value(*inst).used_by().is_empty()
+            })
+            .collect();
+        for inst in unused {
+            let bb = data.layout().parent_bb(inst).unwrap();
+            data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+            data.dfg_mut().remove_value(inst);
+            changed = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::front::opt::OptPass;
+    use crate::util::testing::{
+        callees, compile_at, compile_with, func, interpret, returns_integer,
+    };
+    use koopa::ir::{BasicBlock, ValueKind};
+
+    #[test]
+    fn test_pure_calls() {
+        let program = compile_with(
+            "int fib(int n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+            int sq(int x) { return x * x; }
+            int main() { int n = getint(); sq(n); fib(n); putint(n); return fib(10); }",
+            &[OptPass::Mem2Reg, OptPass::PureCalls],
+        );
+        let main = func(&program, "main");
+        // `fib` may not terminate, so its unused call is kept.
+        assert_eq!(callees(&program, main), ["getint", "fib", "putint"]);
+        assert!(returns_integer(main, 55));
+    }
+
+    #[test]
+    fn test_hoist_pure_calls() {
+        let input = "int depth(int n) { if (n == 0) return 0; return depth(n - 1) + 1; }
+            int sq(int x) { return x * x; }
+            int main() { int n = getint(); int s = 0; int i = 0;
+                while (i < sq(n)) { if (n >= 0) s = s + depth(n) + sq(n + 1); i = i + 1; }
+                return s; }";
+        let program = compile_with(input, &[OptPass::Mem2Reg, OptPass::PureCalls]);
+        let main = func(&program, "main");
+        let calls: Vec<(&str, BasicBlock)> = main
+            .layout()
+            .bbs()
+            .iter()
+            .flat_map(|(bb, node)| node.insts().keys().map(move |inst| (*bb, *inst)))
+            .filter_map(|(bb, inst)| match main.dfg().value(inst).kind() {
+                ValueKind::Call(call) => Some((program.func(call.callee()).name(), bb)),
+                _ => None,
+            })
+            .collect();
+        let entry = calls[0].1;
+        let hoisted: Vec<(&str, bool)> = calls
+            .iter()
+            .map(|(name, bb)| (*name, *bb == entry))
+            .collect();
+        // Only the call in the condition runs whenever the loop is left.
+        assert_eq!(
+            hoisted,
+            [
+                ("@getint", true),
+                ("@sq", true),
+                ("@depth", false),
+                ("@sq", false)
+            ]
+        );
+
+        // The guarded call to `depth` would not terminate.
+        let program = compile_at(input, 2);
+        assert_eq!(interpret(&program, b"-1").0, Ok(0));
+        assert_eq!(interpret(&program, b"2").0, Ok(44));
+    }
+}
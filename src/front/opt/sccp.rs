@@ -0,0 +1,323 @@
+//! Sparse conditional constant propagation.
+//!
+//! Implements the algorithm of Wegman and Zadeck, "Constant Propagation with Conditional
+//! Branches", on the SSA form built by `mem2reg`. Every value starts out unknown and is lowered to
+//! a constant or to overdefined as the blocks it depends on become executable. Only the edges
+//! taken by branches whose condition is not a known constant are followed, so block parameters
+//! merge the arguments of executable edges only, and a value is constant when it is on all paths
+//! that can actually run.
+//!
+//! The analysis is a single worklist run. Afterwards constant values replace their uses, constant
+//! block parameters are removed, branches on known conditions become jumps, and the blocks that
+//! never became executable are deleted.
+
+use crate::front::opt::const_fold::fold;
+use crate::front::opt::dce::remove_unreachable_blocks;
+use crate::util::cfg::Cfg;
+use crate::util::ir::{remove_params, replace_all_uses};
+use koopa::ir::builder::{LocalInstBuilder, ValueBuilder};
+use koopa::ir::{BasicBlock, Function, FunctionData, Value, ValueKind};
+use koopa::opt::FunctionPass;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Default)]
+pub(crate) struct Sccp;
+
+impl FunctionPass for Sccp {
+    fn run_on(&mut self, _: Function, data: &mut FunctionData) {
+        if data.layout().entry_bb().is_none() {
+            return;
+        }
+        let mut solver = Solver::new(data);
+        solver.run(data);
+        solver.rewrite(data);
+    }
+}
+
+/// What is known about a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lattice {
+    /// No executable definition of the value has been seen yet.
+    Unknown,
+    Constant(i32),
+    /// The value may differ between executions.
+    Overdefined,
+}
+
+impl Lattice {
+    fn meet(self, other: Lattice) -> Lattice {
+        match (self, other) {
+            (Lattice::Unknown, other) | (other, Lattice::Unknown) => other,
+            (Lattice::Constant(a), Lattice::Constant(b)) if a == b => self,
+            _ => Lattice::Overdefined,
+        }
+    }
+}
+
+/// An edge of the control flow graph, as the block it leaves and the index of the target in the
+/// terminator of the block, so that both targets of a branch to a single block are told apart.
+type Edge = (BasicBlock, usize);
+
+struct Solver {
+    cfg: Cfg,
+    values: HashMap<Value, Lattice>,
+    executable_blocks: HashSet<BasicBlock>,
+    executable_edges: HashSet<Edge>,
+    /// Blocks that just became executable, all instructions of which are to be visited.
+    block_worklist: Vec<BasicBlock>,
+    /// Values whose lattice value was lowered, the users of which are to be visited.
+    value_worklist: Vec<Value>,
+}
+
+impl Solver {
+    fn new(data: &FunctionData) -> Self {
+        Self {
+            cfg: Cfg::new(data),
+            values: HashMap::new(),
+            executable_blocks: HashSet::new(),
+            executable_edges: HashSet::new(),
+            block_worklist: vec![data.layout().entry_bb().unwrap()],
+            value_worklist: vec![],
+        }
+    }
+
+    fn run(&mut self, data: &FunctionData) {
+        self.executable_blocks
+            .insert(data.layout().entry_bb().unwrap());
+        loop {
+            if let Some(bb) = self.block_worklist.pop() {
+                for param in data.dfg().bb(bb).params() {
+                    self.visit_param(data, bb, *param);
+                }
+                for inst in data.layout().bbs().node(&bb).unwrap().insts().keys() {
+                    self.visit_inst(data, *inst);
+                }
+            } else if let Some(value) = self.value_worklist.pop() {
+                for user in data.dfg().value(value).used_by() {
+                    let in_executable_block = data
+                        .layout()
+                        .parent_bb(*user)
+                        .is_some_and(|bb| self.executable_blocks.contains(&bb));
+                    if in_executable_block {
+                        self.visit_inst(data, *user);
+                    }
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Get the lattice value of an operand.
+    fn get(&self, data: &FunctionData, value: Value) -> Lattice {
+        if let Some(lattice) = self.values.get(&value) {
+            return *lattice;
+        }
+        if !data.dfg().values().contains_key(&value) {
+            // Global values are not constant.
+            return Lattice::Overdefined;
+        }
+        match data.dfg().value(value).kind() {
+            ValueKind::Integer(int) => Lattice::Constant(int.value()),
+            ValueKind::BlockArgRef(_) => Lattice::Unknown,
+            _ if data.layout().parent_bb(value).is_some() => Lattice::Unknown,
+            _ => Lattice::Overdefined,
+        }
+    }
+
+    /// Lower the lattice value of a value, scheduling its users if it changed.
+    fn set(&mut self, value: Value, lattice: Lattice) {
+        let old = self.values.get(&value).copied().unwrap_or(Lattice::Unknown);
+        let new = old.meet(lattice);
+        if new != old {
+            self.values.insert(value, new);
+            self.value_worklist.push(value);
+        }
+    }
+
+    fn visit_inst(&mut self, data: &FunctionData, inst: Value) {
+        match data.dfg().value(inst).kind() {
+            ValueKind::Binary(bin) => {
+                let lattice = match (self.get(data, bin.lhs()), self.get(data, bin.rhs())) {
+                    (Lattice::Unknown, _) | (_, Lattice::Unknown) => return,
+                    (Lattice::Constant(lhs), Lattice::Constant(rhs)) => {
+                        fold(bin.op(), lhs, rhs).map_or(Lattice::Overdefined, Lattice::Constant)
+                    }
+                    _ => Lattice::Overdefined,
+                };
+                self.set(inst, lattice);
+            }
+            ValueKind::Jump(_) => self.mark_edge(data, (self.parent(data, inst), 0)),
+            ValueKind::Branch(branch) => {
+                let bb = self.parent(data, inst);
+                match self.get(data, branch.cond()) {
+                    Lattice::Unknown => {}
+                    Lattice::Constant(cond) => self.mark_edge(data, (bb, (cond == 0) as usize)),
+                    Lattice::Overdefined => {
+                        self.mark_edge(data, (bb, 0));
+                        self.mark_edge(data, (bb, 1));
+                    }
+                }
+            }
+            _ if !data.dfg().value(inst).ty().is_unit() => self.set(inst, Lattice::Overdefined),
+            _ => {}
+        }
+    }
+
+    fn parent(&self, data: &FunctionData, inst: Value) -> BasicBlock {
+        data.layout().parent_bb(inst).unwrap()
+    }
+
+    /// Mark an edge as executable. The parameters of its target are visited again, as the
+    /// arguments passed by the edge may have changed since it was last visited.
+    fn mark_edge(&mut self, data: &FunctionData, edge: Edge) {
+        let target = edge_target(data, edge);
+        self.executable_edges.insert(edge);
+        if self.executable_blocks.insert(target) {
+            self.block_worklist.push(target);
+        } else {
+            for param in data.dfg().bb(target).params() {
+                self.visit_param(data, target, *param);
+            }
+        }
+    }
+
+    /// Merge the arguments passed to a block parameter by the executable edges.
+    fn visit_param(&mut self, data: &FunctionData, bb: BasicBlock, param: Value) {
+        let ValueKind::BlockArgRef(arg_ref) = data.dfg().value(param).kind() else {
+            unreachable!()
+        };
+        let mut lattice = Lattice::Unknown;
+        for pred in self.cfg.preds(bb) {
+            for index in 0..2 {
+                let edge = (*pred, index);
+                if self.executable_edges.contains(&edge) && edge_target(data, edge) == bb {
+                    let arg = edge_args(data, edge)[arg_ref.index()];
+                    lattice = lattice.meet(self.get(data, arg));
+                }
+            }
+        }
+        if lattice != Lattice::Unknown {
+            self.set(param, lattice);
+        }
+    }
+
+    fn rewrite(&self, data: &mut FunctionData) {
+        // Replace constant values with integers.
+        let mut constant_params: HashMap<BasicBlock, Vec<bool>> = HashMap::new();
+        for bb in &self.executable_blocks {
+            let params = data.dfg().bb(*bb).params().to_vec();
+            let keep: Vec<bool> = params
+                .iter()
+                .map(|param| !matches!(self.values.get(param), Some(Lattice::Constant(_))))
+                .collect();
+            if keep.contains(&false) {
+                constant_params.insert(*bb, keep);
+            }
+        }
+        let mut constant_insts = vec![];
+        for (value, lattice) in &self.values {
+            let Lattice::Constant(c) = lattice else {
+                continue;
+            };
+            let constant = data.dfg_mut().new_value().integer(*c);
+            replace_all_uses(data, *value, constant);
+            if let Some(bb) = data.layout().parent_bb(*value) {
+                constant_insts.push((*value, bb));
+            }
+        }
+        for (inst, bb) in constant_insts {
+            data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+            data.dfg_mut().remove_value(inst);
+        }
+        for (bb, keep) in constant_params {
+            remove_params(data, bb, &keep);
+        }
+
+        // Replace branches with a single executable edge by jumps.
+        for bb in &self.executable_blocks {
+            let last = *data
+                .layout()
+                .bbs()
+                .node(bb)
+                .unwrap()
+                .insts()
+                .back_key()
+                .unwrap();
+            let ValueKind::Branch(branch) = data.dfg().value(last).kind() else {
+                continue;
+            };
+            let taken = match (
+                self.executable_edges.contains(&(*bb, 0)),
+                self.executable_edges.contains(&(*bb, 1)),
+            ) {
+                (true, false) => (branch.true_bb(), branch.true_args().to_vec()),
+                (false, true) => (branch.false_bb(), branch.false_args().to_vec()),
+                _ => continue,
+            };
+            data.dfg_mut()
+                .replace_value_with(last)
+                .jump_with_args(taken.0, taken.1);
+        }
+        remove_unreachable_blocks(data);
+    }
+}
+
+/// Get the target of an edge.
+fn edge_target(data: &FunctionData, (bb, index): Edge) -> BasicBlock {
+    let last = *data
+        .layout()
+        .bbs()
+        .node(&bb)
+        .unwrap()
+        .insts()
+        .back_key()
+        .unwrap();
+    match data.dfg().value(last).kind() {
+        ValueKind::Jump(jump) => jump.target(),
+        ValueKind::Branch(branch) if index == 0 => branch.true_bb(),
+        ValueKind::Branch(branch) => branch.false_bb(),
+        _ => unreachable!(),
+    }
+}
+
+/// Get the arguments passed by an edge.
+fn edge_args(data: &FunctionData, (bb, index): Edge) -> &[Value] {
+    let last = *data
+        .layout()
+        .bbs()
+        .node(&bb)
+        .unwrap()
+        .insts()
+        .back_key()
+        .unwrap();
+    match data.dfg().value(last).kind() {
+        ValueKind::Jump(jump) => jump.args(),
+        ValueKind::Branch(branch) if index == 0 => branch.true_args(),
+        ValueKind::Branch(branch) => branch.false_args(),
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::front::opt::OptPass;
+    use crate::util::testing::{compile_with, func, inst_kinds, returns_integer};
+    use koopa::ir::ValueKind;
+
+    #[test]
+    fn test_sccp() {
+        let program = compile_with(
+            "int main() { int a = 3; int b; int i = 0;
+            if (a > 2) b = 4; else b = getint();
+            while (i < 10) { if (b != 4) a = a + 1; i = i + 1; }
+            return a * b; }",
+            &[OptPass::Mem2Reg, OptPass::Sccp],
+        );
+        let main = func(&program, "main");
+        assert!(!inst_kinds(main)
+            .iter()
+            .any(|kind| matches!(kind, ValueKind::Call(_))));
+        assert!(returns_integer(main, 12));
+    }
+}
@@ -0,0 +1,135 @@
+//! Tail recursion elimination.
+//!
+//! A function that returns the result of calling itself jumps back to its start instead. The
+//! instructions of the entry block other than `alloc`s move to a new loop header, whose parameters
+//! replace those of the function, and every recursive call in tail position becomes a jump passing
+//! its arguments to the header. Calls passing pointers into the stack frame of the function are
+//! left alone, as the frame they point to is reused by the next iteration.
+
+use crate::util::ir::{is_tail_call, may_point_to_frame, replace_all_uses};
+use koopa::ir::builder::{BasicBlockBuilder, LocalInstBuilder};
+use koopa::ir::{Function, FunctionData, Type, Value, ValueKind};
+use koopa::opt::FunctionPass;
+
+#[derive(Default)]
+pub(crate) struct TailRec;
+
+impl FunctionPass for TailRec {
+    fn run_on(&mut self, func: Function, data: &mut FunctionData) {
+        let Some(entry) = data.layout().entry_bb() else {
+            return;
+        };
+        let calls: Vec<Value> = data
+            .layout()
+            .bbs()
+            .nodes()
+            .flat_map(|node| node.insts().keys().copied())
+            .filter(|inst| is_recursive_tail_call(func, data, *inst))
+            .collect();
+        if calls.is_empty() {
+            return;
+        }
+
+        let name = data
+            .dfg()
+            .bb(entry)
+            .name()
+            .as_ref()
+            .map(|name| format!("{}_loop", name));
+        let types: Vec<Type> = data
+            .params()
+            .iter()
+            .map(|param| data.dfg().value(*param).ty().clone())
+            .collect();
+        let header = data.dfg_mut().new_bb().basic_block_with_params(name, types);
+        data.layout_mut()
+            .bbs_mut()
+            .cursor_mut(entry)
+            .insert_key_after(header)
+            .unwrap();
+        let moved: Vec<Value> = data
+            .layout()
+            .bbs()
+            .node(&entry)
+            .unwrap()
+            .insts()
+            .keys()
+            .filter(|inst| !matches!(data.dfg().value(**inst).kind(), ValueKind::Alloc(_)))
+            .copied()
+            .collect();
+        for inst in moved {
+            data.layout_mut().bb_mut(entry).insts_mut().remove(&inst);
+            data.layout_mut()
+                .bb_mut(header)
+                .insts_mut()
+                .push_key_back(inst)
+                .unwrap();
+        }
+        let params = data.params().to_vec();
+        let header_params = data.dfg().bb(header).params().to_vec();
+        for (param, header_param) in params.iter().zip(header_params) {
+            replace_all_uses(data, *param, header_param);
+        }
+        let jump = data.dfg_mut().new_value().jump_with_args(header, params);
+        data.layout_mut()
+            .bb_mut(entry)
+            .insts_mut()
+            .push_key_back(jump)
+            .unwrap();
+
+        for call in calls {
+            let ValueKind::Call(call_data) = data.dfg().value(call).kind() else {
+                unreachable!()
+            };
+            let args = call_data.args().to_vec();
+            let bb = data.layout().parent_bb(call).unwrap();
+            let ret = *data
+                .layout()
+                .bbs()
+                .node(&bb)
+                .unwrap()
+                .insts()
+                .back_key()
+                .unwrap();
+            data.layout_mut().bb_mut(bb).insts_mut().remove(&ret);
+            data.dfg_mut().remove_value(ret);
+            data.dfg_mut()
+                .replace_value_with(call)
+                .jump_with_args(header, args);
+        }
+    }
+}
+
+/// Check if an instruction is a call of the function itself in tail position, none of the
+/// arguments of which may point into the stack frame.
+fn is_recursive_tail_call(func: Function, data: &FunctionData, inst: Value) -> bool {
+    let ValueKind::Call(call) = data.dfg().value(inst).kind() else {
+        return false;
+    };
+    call.callee() == func
+        && is_tail_call(data, inst)
+        && !call.args().iter().any(|arg| may_point_to_frame(data, *arg))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::front::opt::OptPass;
+    use crate::util::testing::{compile_with, func, inst_kinds, interpret};
+    use koopa::ir::ValueKind;
+
+    #[test]
+    fn test_tail_rec() {
+        let program = compile_with(
+            "int gcd(int a, int b) { if (b == 0) return a; return gcd(b, a % b); }
+            int main() { return gcd(12, 18); }",
+            &[OptPass::Mem2Reg, OptPass::TailRec],
+        );
+        let kinds = inst_kinds(func(&program, "gcd"));
+        assert!(!kinds.iter().any(|kind| matches!(kind, ValueKind::Call(_))));
+        assert!(kinds.iter().any(|kind| match kind {
+            ValueKind::Jump(jump) => jump.args().len() == 2,
+            _ => false,
+        }));
+        assert_eq!(interpret(&program, b"").0, Ok(6));
+    }
+}
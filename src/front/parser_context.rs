@@ -1,3 +1,5 @@
+use crate::front::diagnostic::Diagnostics;
+
 #[derive(Clone)]
 pub struct BlockIdGenerator {
     max_id: i32,
@@ -28,18 +30,23 @@ impl BlockIdGenerator {
 }
 
 #[derive(Clone)]
-pub struct ParserContext<'a> {
+pub struct ParserContext {
     pub generator: BlockIdGenerator,
-    pub file_path: &'a str,
-    pub input: &'a str,
+    /// Errors found while parsing that do not stop it.
+    pub diagnostics: Diagnostics,
 }
 
-impl<'a> ParserContext<'a> {
-    pub fn new(file_path: &'a str, input: &'a str) -> Self {
+impl ParserContext {
+    pub fn new() -> Self {
         ParserContext {
             generator: BlockIdGenerator::new(),
-            file_path,
-            input,
+            diagnostics: Diagnostics::new(),
         }
     }
 }
+
+impl Default for ParserContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,515 @@
+//! Interpreter of Koopa IR programs.
+//!
+//! Runs a program without RISC-V tooling, with the functions of the runtime library reading the
+//! given input and writing the given output. Memory is an array of words and pointers are indices
+//! into it, so that `i32` and pointers both take one word. Index 0 is never allocated and acts as
+//! the null pointer.
+//!
+//! Arithmetic follows RISC-V rather than C, so that a program gives the same results as its
+//! assembly: dividing by zero gives -1 and leaves the remainder unchanged, and shifts only use the
+//! low 5 bits of their amount. The intrinsics implementing `float` operations are evaluated as
+//! the RISC-V F extension would.
+//!
+//! Calls do not recurse in the interpreter, frames are kept in a vector instead, so deep
+//! recursion of the program only consumes its memory.
+
+use crate::front::ir::builtin::Intrinsic;
+use crate::util::runtime::Runtime;
+use koopa::ir::{BasicBlock, BinaryOp, Function, Program, Type, TypeKind, Value, ValueKind};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+/// Maximum number of words of memory, shared by global variables and the stack.
+const MEMORY_WORDS: usize = 1 << 26;
+/// Maximum number of words of memory of calls evaluated at compile time.
+const EVAL_MEMORY_WORDS: usize = 1 << 20;
+/// Maximum depth of calls.
+const MAX_FRAMES: usize = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeError {
+    NoMain,
+    /// A load or store at an address that is not allocated, in the given function.
+    OutOfBounds(String, i32),
+    /// The memory is exhausted, usually by a deep recursion, in the given function.
+    OutOfMemory(String),
+    /// Calls are nested too deeply when calling the given function.
+    StackOverflow(String),
+    UnknownBuiltin(String),
+    Output(String),
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::NoMain => write!(f, "the program has no `main` function"),
+            RuntimeError::OutOfBounds(func, address) => {
+                write!(f, "memory access out of bounds at {} in {}", address, func)
+            }
+            RuntimeError::OutOfMemory(func) => write!(f, "out of memory in {}", func),
+            RuntimeError::StackOverflow(func) => write!(f, "stack overflow calling {}", func),
+            RuntimeError::UnknownBuiltin(name) => {
+                write!(
+                    f,
+                    "{} is declared but not part of the runtime library",
+                    name
+                )
+            }
+            RuntimeError::Output(e) => write!(f, "failed to write the output: {}", e),
+        }
+    }
+}
+
+/// Run the `main` function of a program, returning the value it returns.
+pub fn run(program: &Program, input: &[u8], output: &mut impl Write) -> Result<i32, RuntimeError> {
+    let mut interpreter = Interpreter::new(program, input, output);
+    interpreter.init_globals();
+    let result = interpreter.run();
+    interpreter.runtime.report_total_time();
+    result
+}
+
+/// Call a function with a body at compile time, without input, giving the value it returns.
+///
+/// Gives `None` if the call fails, writes any output, or does not return within `max_steps`
+/// instructions. The memory is limited to [`EVAL_MEMORY_WORDS`].
+pub fn evaluate(program: &Program, func: Function, args: Vec<i32>, max_steps: u64) -> Option<i32> {
+    let mut output = vec![];
+    let result = {
+        let mut interpreter = Interpreter::new(program, &[], &mut output);
+        interpreter.memory_words = EVAL_MEMORY_WORDS;
+        interpreter.init_globals();
+        interpreter.enter(func, args, None).ok()?;
+        (0..max_steps).find_map(|_| interpreter.next().transpose())
+    };
+    match result {
+        Some(Ok(value)) if output.is_empty() => Some(value),
+        _ => None,
+    }
+}
+
+struct Frame {
+    func: Function,
+    bb: BasicBlock,
+    /// Index of the next instruction in the block.
+    index: usize,
+    values: HashMap<Value, i32>,
+    /// Size of the memory when the function was called, which is restored when it returns.
+    stack_base: usize,
+    /// The call in the caller waiting for the returned value.
+    call: Option<Value>,
+}
+
+struct Interpreter<'a, W: Write> {
+    program: &'a Program,
+    /// Instructions of every block of every function.
+    insts: HashMap<BasicBlock, Vec<Value>>,
+    memory: Vec<i32>,
+    globals: HashMap<Value, i32>,
+    frames: Vec<Frame>,
+    /// Maximum number of words of memory.
+    memory_words: usize,
+    runtime: Runtime<'a, W>,
+}
+
+impl<'a, W: Write> Interpreter<'a, W> {
+    fn new(program: &'a Program, input: &'a [u8], output: &'a mut W) -> Self {
+        let mut insts = HashMap::new();
+        for func in program.func_layout() {
+            for (bb, node) in program.func(*func).layout().bbs() {
+                insts.insert(*bb, node.insts().keys().copied().collect());
+            }
+        }
+        Self {
+            program,
+            insts,
+            memory: vec![0],
+            globals: HashMap::new(),
+            frames: vec![],
+            memory_words: MEMORY_WORDS,
+            runtime: Runtime::new(input, output),
+        }
+    }
+
+    fn init_globals(&mut self) {
+        for global in self.program.inst_layout() {
+            let (ty, init) = {
+                let data = self.program.borrow_value(*global);
+                match data.kind() {
+                    ValueKind::GlobalAlloc(alloc) => (pointee(data.ty()), alloc.init()),
+                    _ => continue,
+                }
+            };
+            let address = self.memory.len();
+            self.memory.resize(address + words(&ty), 0);
+            self.init_global(address, init);
+            self.globals.insert(*global, address as i32);
+        }
+    }
+
+    fn init_global(&mut self, address: usize, init: Value) {
+        let data = self.program.borrow_value(init);
+        match data.kind() {
+            ValueKind::Integer(int) => self.memory[address] = int.value(),
+            ValueKind::Aggregate(agg) => {
+                let elem_words = match data.ty().kind() {
+                    TypeKind::Array(base, _) => words(base),
+                    _ => 1,
+                };
+                for (i, elem) in agg.elems().iter().enumerate() {
+                    self.init_global(address + i * elem_words, *elem);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn run(&mut self) -> Result<i32, RuntimeError> {
+        let main = self
+            .program
+            .func_layout()
+            .iter()
+            .copied()
+            .find(|func| self.program.func(*func).name() == "@main")
+            .ok_or(RuntimeError::NoMain)?;
+        self.enter(main, vec![], None)?;
+        loop {
+            if let Some(code) = self.next()? {
+                return Ok(code);
+            }
+        }
+    }
+
+    /// Execute the next instruction, returning the value returned by the outermost function if
+    /// it returns.
+    fn next(&mut self) -> Result<Option<i32>, RuntimeError> {
+        let frame = self.frames.last_mut().unwrap();
+        let inst = self.insts[&frame.bb][frame.index];
+        frame.index += 1;
+        self.step(inst)
+    }
+
+    /// Execute an instruction of the current function, returning the returned value if it returns
+    /// from the outermost function.
+    fn step(&mut self, inst: Value) -> Result<Option<i32>, RuntimeError> {
+        let data = self.program.func(self.frame().func);
+        let inst_data = data.dfg().value(inst);
+        match inst_data.kind() {
+            ValueKind::Alloc(_) => {
+                // Allocations in loops reuse the memory of their first execution, as their stack
+                // slot would.
+                if !self.frame().values.contains_key(&inst) {
+                    let address = self.allocate(words(&pointee(inst_data.ty())))?;
+                    self.set(inst, address);
+                }
+            }
+            ValueKind::Load(load) => {
+                let value = self.load(self.value(load.src()))?;
+                self.set(inst, value);
+            }
+            ValueKind::Store(store) => self.store_value(self.value(store.dest()), store.value())?,
+            ValueKind::GetPtr(get_ptr) => {
+                let stride = words(&pointee(&self.ty(get_ptr.src())));
+                let offset = self.value(get_ptr.index()).wrapping_mul(stride as i32);
+                self.set(inst, self.value(get_ptr.src()).wrapping_add(offset));
+            }
+            ValueKind::GetElemPtr(get_elem_ptr) => {
+                let stride = match pointee(&self.ty(get_elem_ptr.src())).kind() {
+                    TypeKind::Array(base, _) => words(base),
+                    _ => 1,
+                };
+                let offset = self.value(get_elem_ptr.index()).wrapping_mul(stride as i32);
+                self.set(inst, self.value(get_elem_ptr.src()).wrapping_add(offset));
+            }
+            ValueKind::Binary(binary) => {
+                let (lhs, rhs) = (self.value(binary.lhs()), self.value(binary.rhs()));
+                self.set(inst, eval_binary(binary.op(), lhs, rhs));
+            }
+            ValueKind::Branch(branch) => {
+                if self.value(branch.cond()) != 0 {
+                    self.jump(branch.true_bb(), branch.true_args());
+                } else {
+                    self.jump(branch.false_bb(), branch.false_args());
+                }
+            }
+            ValueKind::Jump(jump) => self.jump(jump.target(), jump.args()),
+            ValueKind::Call(call) => {
+                let args: Vec<i32> = call.args().iter().map(|arg| self.value(*arg)).collect();
+                let callee = self.program.func(call.callee());
+                if callee.layout().entry_bb().is_some() {
+                    self.enter(call.callee(), args, Some(inst))?;
+                } else if let Some(intrinsic) = Intrinsic::from_name(callee.name()) {
+                    self.set(inst, intrinsic.eval(&args));
+                } else if let Some(value) = self.call_builtin(callee.name(), &args)? {
+                    self.set(inst, value);
+                }
+            }
+            ValueKind::Return(ret) => {
+                let value = ret.value().map_or(0, |value| self.value(value));
+                let frame = self.frames.pop().unwrap();
+                self.memory.truncate(frame.stack_base);
+                if self.frames.is_empty() {
+                    return Ok(Some(value));
+                }
+                if let Some(call) = frame.call {
+                    self.set(call, value);
+                }
+            }
+            _ => unreachable!("not an instruction"),
+        }
+        Ok(None)
+    }
+
+    fn frame(&self) -> &Frame {
+        self.frames.last().unwrap()
+    }
+
+    fn func_name(&self) -> String {
+        self.program.func(self.frame().func).name().to_string()
+    }
+
+    /// Push the frame of a function with a body.
+    fn enter(
+        &mut self,
+        func: Function,
+        args: Vec<i32>,
+        call: Option<Value>,
+    ) -> Result<(), RuntimeError> {
+        let data = self.program.func(func);
+        if self.frames.len() >= MAX_FRAMES {
+            return Err(RuntimeError::StackOverflow(data.name().to_string()));
+        }
+        let frame = Frame {
+            func,
+            bb: data.layout().entry_bb().unwrap(),
+            index: 0,
+            values: data.params().iter().copied().zip(args).collect(),
+            stack_base: self.memory.len(),
+            call,
+        };
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    fn jump(&mut self, target: BasicBlock, args: &[Value]) {
+        let args: Vec<i32> = args.iter().map(|arg| self.value(*arg)).collect();
+        let data = self.program.func(self.frame().func);
+        let frame = self.frames.last_mut().unwrap();
+        for (param, arg) in data.dfg().bb(target).params().iter().zip(args) {
+            frame.values.insert(*param, arg);
+        }
+        frame.bb = target;
+        frame.index = 0;
+    }
+
+    fn value(&self, value: Value) -> i32 {
+        if value.is_global() {
+            return self.globals[&value];
+        }
+        let frame = self.frame();
+        match self.program.func(frame.func).dfg().value(value).kind() {
+            ValueKind::Integer(int) => int.value(),
+            ValueKind::ZeroInit(_) | ValueKind::Undef(_) => 0,
+            _ => frame.values[&value],
+        }
+    }
+
+    fn set(&mut self, value: Value, result: i32) {
+        self.frames.last_mut().unwrap().values.insert(value, result);
+    }
+
+    fn ty(&self, value: Value) -> Type {
+        if value.is_global() {
+            self.program.borrow_value(value).ty().clone()
+        } else {
+            let data = self.program.func(self.frame().func);
+            data.dfg().value(value).ty().clone()
+        }
+    }
+
+    fn allocate(&mut self, words: usize) -> Result<i32, RuntimeError> {
+        let address = self.memory.len();
+        if address + words > self.memory_words {
+            return Err(RuntimeError::OutOfMemory(self.func_name()));
+        }
+        self.memory.resize(address + words, 0);
+        Ok(address as i32)
+    }
+
+    fn check_address(&self, address: i32) -> Result<usize, RuntimeError> {
+        if address <= 0 || address as usize >= self.memory.len() {
+            return Err(RuntimeError::OutOfBounds(self.func_name(), address));
+        }
+        Ok(address as usize)
+    }
+
+    fn load(&self, address: i32) -> Result<i32, RuntimeError> {
+        Ok(self.memory[self.check_address(address)?])
+    }
+
+    fn store(&mut self, address: i32, value: i32) -> Result<(), RuntimeError> {
+        let address = self.check_address(address)?;
+        self.memory[address] = value;
+        Ok(())
+    }
+
+    /// Store a value, which may be an aggregate or a zero initializer of a local array.
+    fn store_value(&mut self, address: i32, value: Value) -> Result<(), RuntimeError> {
+        if value.is_global() {
+            return self.store(address, self.value(value));
+        }
+        let data = self.program.func(self.frame().func).dfg().value(value);
+        match data.kind() {
+            ValueKind::Aggregate(agg) => {
+                let elem_words = match data.ty().kind() {
+                    TypeKind::Array(base, _) => words(base),
+                    _ => 1,
+                };
+                for (i, elem) in agg.elems().iter().enumerate() {
+                    let offset = (i * elem_words) as i32;
+                    self.store_value(address.wrapping_add(offset), *elem)?;
+                }
+                Ok(())
+            }
+            ValueKind::ZeroInit(_) => {
+                for i in 0..words(data.ty()) {
+                    self.store(address.wrapping_add(i as i32), 0)?;
+                }
+                Ok(())
+            }
+            _ => self.store(address, self.value(value)),
+        }
+    }
+
+    /// Call a function of the runtime library, returning its result if it has one.
+    fn call_builtin(&mut self, name: &str, args: &[i32]) -> Result<Option<i32>, RuntimeError> {
+        let output_error = |e: io::Error| RuntimeError::Output(e.to_string());
+        let result = match name {
+            "@getint" => Some(self.runtime.read_int()),
+            "@getch" => Some(self.runtime.read_char()),
+            "@getfloat" => Some(self.runtime.read_float().to_bits() as i32),
+            "@getarray" => {
+                let values = self.runtime.read_array();
+                for (i, value) in values.iter().enumerate() {
+                    self.store(args[0].wrapping_add(i as i32), *value)?;
+                }
+                Some(values.len() as i32)
+            }
+            "@getfarray" => {
+                let values = self.runtime.read_float_array();
+                for (i, value) in values.iter().enumerate() {
+                    self.store(args[0].wrapping_add(i as i32), value.to_bits() as i32)?;
+                }
+                Some(values.len() as i32)
+            }
+            "@putint" => {
+                self.runtime.put_int(args[0]).map_err(output_error)?;
+                None
+            }
+            "@putch" => {
+                self.runtime.put_char(args[0]).map_err(output_error)?;
+                None
+            }
+            "@putfloat" => {
+                let value = f32::from_bits(args[0] as u32);
+                self.runtime.put_float(value).map_err(output_error)?;
+                None
+            }
+            "@putarray" => {
+                let values = (0..args[0].max(0))
+                    .map(|i| self.load(args[1].wrapping_add(i)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.runtime.put_array(&values).map_err(output_error)?;
+                None
+            }
+            "@putfarray" => {
+                let values = (0..args[0].max(0))
+                    .map(|i| Ok(f32::from_bits(self.load(args[1].wrapping_add(i))? as u32)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.runtime
+                    .put_float_array(&values)
+                    .map_err(output_error)?;
+                None
+            }
+            "@starttime" => {
+                self.runtime.start_timer();
+                None
+            }
+            "@stoptime" => {
+                self.runtime.stop_timer();
+                None
+            }
+            _ => return Err(RuntimeError::UnknownBuiltin(name.to_string())),
+        };
+        Ok(result)
+    }
+}
+
+/// Get the number of words taken by a value of a type.
+fn words(ty: &Type) -> usize {
+    match ty.kind() {
+        TypeKind::Array(base, len) => words(base) * len,
+        TypeKind::Unit => 0,
+        _ => 1,
+    }
+}
+
+fn pointee(ty: &Type) -> Type {
+    match ty.kind() {
+        TypeKind::Pointer(base) => base.clone(),
+        _ => ty.clone(),
+    }
+}
+
+fn eval_binary(op: BinaryOp, lhs: i32, rhs: i32) -> i32 {
+    match op {
+        BinaryOp::NotEq => (lhs != rhs) as i32,
+        BinaryOp::Eq => (lhs == rhs) as i32,
+        BinaryOp::Gt => (lhs > rhs) as i32,
+        BinaryOp::Lt => (lhs < rhs) as i32,
+        BinaryOp::Ge => (lhs >= rhs) as i32,
+        BinaryOp::Le => (lhs <= rhs) as i32,
+        BinaryOp::Add => lhs.wrapping_add(rhs),
+        BinaryOp::Sub => lhs.wrapping_sub(rhs),
+        BinaryOp::Mul => lhs.wrapping_mul(rhs),
+        BinaryOp::Div if rhs == 0 => -1,
+        BinaryOp::Div => lhs.wrapping_div(rhs),
+        BinaryOp::Mod if rhs == 0 => lhs,
+        BinaryOp::Mod => lhs.wrapping_rem(rhs),
+        BinaryOp::And => lhs & rhs,
+        BinaryOp::Or => lhs | rhs,
+        BinaryOp::Xor => lhs ^ rhs,
+        BinaryOp::Shl => lhs.wrapping_shl(rhs as u32),
+        BinaryOp::Shr => (lhs as u32).wrapping_shr(rhs as u32) as i32,
+        BinaryOp::Sar => lhs.wrapping_shr(rhs as u32),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::testing::{compile, interpret};
+
+    #[test]
+    fn test_interp() {
+        let program = compile(
+            "int a[3] = {1, 2};
+            int fib(int n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+            int main() { int n = getint(); a[2] = fib(n); putarray(3, a); putch(10);
+                putint(7 / 0); return a[2] % 256; }",
+        );
+        assert_eq!(
+            interpret(&program, b" 10\n"),
+            (Ok(55), "3: 1 2 55\n\n-1".to_string())
+        );
+        let fib = *program
+            .funcs()
+            .iter()
+            .find(|(_, data)| data.name() == "@fib")
+            .unwrap()
+            .0;
+        assert_eq!(evaluate(&program, fib, vec![20], 1 << 20), Some(6765));
+        assert_eq!(evaluate(&program, fib, vec![20], 1000), None);
+    }
+}
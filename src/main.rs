@@ -1,39 +1,145 @@
+use crate::front::diagnostic::Diagnostic;
 use crate::front::generate_ir;
 use crate::front::opt::opt;
+use back::{AsmProgram, Assembly, SimResult};
 use front::parser;
 use front::parser_context::ParserContext;
 use koopa::back::KoopaGenerator;
+use koopa::ir::Program;
 use std::fs;
-use util::args::Params;
-use util::logger::{show_error, show_parse_error};
+use std::io::{self, BufWriter, Read, Write};
+use std::process::exit;
+use util::args::{Emit, Mode, Params, STDIO};
+use util::logger::{show_diagnostics, show_error};
+use util::verify::assert_valid;
 
 mod back;
 mod front;
+mod interp;
 pub mod macros;
 mod util;
 
 fn main() {
     let params = Params::parse();
-    let input = fs::read_to_string(&params.input).unwrap_or_else(|e| {
-        show_error(&format!("Failed to read input file: {}", e), 1);
-    });
+    let (input, file_path) = if params.input == STDIO {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input).unwrap_or_else(|e| {
+            show_error(&format!("Failed to read stdin: {}", e), 1);
+        });
+        (input, "<stdin>")
+    } else {
+        let input = fs::read_to_string(&params.input).unwrap_or_else(|e| {
+            show_error(&format!("Failed to read input file: {}", e), 1);
+        });
+        (input, params.input.as_str())
+    };
     let parser = parser::CompUnitParser::new();
-    let mut context = ParserContext::new(&params.input, &input);
-    let comp_unit = parser
-        .parse(&mut context, &input)
-        .unwrap_or_else(|e| show_parse_error(e, &input, &params.input));
-    let mut program = generate_ir(comp_unit);
-    if params.koopa {
-        KoopaGenerator::from_path(&params.output)
-            .unwrap()
-            .generate_on(&program)
-            .unwrap();
-    }
-    if params.perf {
-        opt(&mut program);
-    }
-    if params.riscv || params.perf {
-        let asm = back::generate_asm(program);
-        fs::write(&params.output, asm).unwrap();
+    let mut context = ParserContext::new();
+    let comp_unit = match parser.parse(&mut context, &input) {
+        Ok(comp_unit) if !context.diagnostics.has_errors() => comp_unit,
+        result => {
+            if let Err(e) = result {
+                context.diagnostics.push(Diagnostic::syntax_error(e));
+            }
+            show_diagnostics(&context.diagnostics, &input, file_path);
+            exit(1)
+        }
+    };
+    if params.emit.contains(&Emit::Ast) {
+        write_output(&params, Emit::Ast, &format!("{:#?}\n", comp_unit));
+    }
+    let (mut program, diagnostics) = generate_ir(comp_unit, &params.lints);
+    if !diagnostics.is_empty() {
+        show_diagnostics(&diagnostics, &input, file_path);
+    }
+    if diagnostics.has_errors() {
+        exit(2)
+    }
+    if params.pipeline.verify {
+        assert_valid(&program, "IR generation");
+    }
+    if params.emit.contains(&Emit::Koopa) {
+        write_output(&params, Emit::Koopa, &koopa_text(&program));
+    }
+    if params.mode != Mode::Compile && !params.pipeline.is_empty() {
+        opt(&mut program, &params.pipeline);
+    }
+    match params.mode {
+        Mode::Compile => {}
+        Mode::Run => run_program(&program),
+        Mode::Simulate => {
+            let asm = back::generate_asm(program, params.reg_alloc, params.dump_liveness);
+            simulate_program(&asm);
+        }
+    }
+    if !params.emit.contains(&Emit::KoopaOpt) && !params.emit.contains(&Emit::Asm) {
+        return;
+    }
+    if !params.pipeline.is_empty() {
+        opt(&mut program, &params.pipeline);
+    }
+    if params.emit.contains(&Emit::KoopaOpt) {
+        write_output(&params, Emit::KoopaOpt, &koopa_text(&program));
+    }
+    if params.emit.contains(&Emit::Asm) {
+        let asm = back::generate_asm(program, params.reg_alloc, params.dump_liveness);
+        write_output(&params, Emit::Asm, &asm.dump());
+    }
+}
+
+/// Interpret the program with stdin and stdout, and exit with the value returned by `main`.
+fn run_program(program: &Program) -> ! {
+    let input = read_stdin();
+    let mut output = BufWriter::new(io::stdout().lock());
+    let result = interp::run(program, &input, &mut output);
+    flush(output);
+    match result {
+        Ok(code) => exit(code),
+        Err(e) => show_error(&format!("Runtime error: {}", e), 5),
+    }
+}
+
+/// Simulate the assembly with stdin and stdout, print the number of executed instructions to
+/// stderr, and exit with the value returned by `main`.
+fn simulate_program(asm: &AsmProgram) -> ! {
+    let input = read_stdin();
+    let mut output = BufWriter::new(io::stdout().lock());
+    let result = back::simulate(asm, &input, &mut output);
+    flush(output);
+    match result {
+        Ok(SimResult { exit_code, insts }) => {
+            eprintln!("{} instructions executed", insts);
+            exit(exit_code)
+        }
+        Err(e) => show_error(&format!("Runtime error: {}", e), 5),
+    }
+}
+
+fn read_stdin() -> Vec<u8> {
+    let mut input = vec![];
+    io::stdin().read_to_end(&mut input).unwrap_or_else(|e| {
+        show_error(&format!("Failed to read stdin: {}", e), 1);
+    });
+    input
+}
+
+fn flush(mut output: impl Write) {
+    output.flush().unwrap_or_else(|e| {
+        show_error(&format!("Failed to write stdout: {}", e), 1);
+    });
+}
+
+fn koopa_text(program: &Program) -> String {
+    let mut generator = KoopaGenerator::new(vec![]);
+    generator.generate_on(program).unwrap();
+    String::from_utf8(generator.writer()).unwrap()
+}
+
+fn write_output(params: &Params, emit: Emit, content: &str) {
+    match params.output_path(emit) {
+        Some(path) => fs::write(&path, content).unwrap_or_else(|e| {
+            show_error(&format!("Failed to write {}: {}", path, e), 1);
+        }),
+        None => print!("{}", content),
     }
 }
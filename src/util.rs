@@ -1,7 +1,13 @@
 use koopa::ir::{Type, TypeKind};
 
 pub mod args;
+pub mod cfg;
+pub mod ir;
 pub mod logger;
+pub mod runtime;
+#[cfg(test)]
+pub mod testing;
+pub mod verify;
 
 pub fn remove_pointer(ty: Type) -> Type {
     match ty.kind() {
@@ -9,3 +15,21 @@ pub fn remove_pointer(ty: Type) -> Type {
         _ => ty,
     }
 }
+
+/// Parse an unsigned float literal of C, either decimal or hexadecimal like `0x1.8p3`.
+pub fn parse_float(s: &str) -> Option<f32> {
+    let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) else {
+        return s.parse().ok();
+    };
+    let (mantissa, exponent) = hex.split_once(['p', 'P'])?;
+    let exponent: i32 = exponent.parse().ok()?;
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if int.is_empty() && frac.is_empty() {
+        return None;
+    }
+    let mut value = 0f64;
+    for digit in int.chars().chain(frac.chars()) {
+        value = value * 16.0 + digit.to_digit(16)? as f64;
+    }
+    Some((value * 2f64.powi(exponent - 4 * frac.len() as i32)) as f32)
+}
@@ -1,61 +1,283 @@
+use crate::back::RegAllocStrategy;
+use crate::front::lint::Lints;
+use crate::front::opt::{OptPass, Pipeline};
 use crate::util::logger::show_error;
 use std::env::args;
+use std::process::exit;
+
+/// Path of the input or output meaning stdin or stdout.
+pub const STDIO: &str = "-";
+
+const HELP: &str = "\
+Usage: compiler [options] [input]
+       compiler run [options] <input>
+       compiler sim [options] <input>
+
+Compile a SysY source file to Koopa IR or RISC-V assembly. The input is read from stdin if it is
+missing or `-`.
+
+With `run`, the program is compiled to Koopa IR, optimized according to the options, and then
+interpreted with the runtime library reading stdin and writing stdout. The exit code is the value
+returned by `main`. With `sim`, the program is compiled to RISC-V assembly and simulated in the
+same way, and the number of executed instructions is printed to stderr.
+
+Options:
+  -o <file>              Write the output to <file>, or to stdout if it is `-` (default)
+  --emit=<kinds>         Comma separated list of outputs to emit, among:
+                           ast        the parsed syntax tree
+                           koopa      Koopa IR before optimization
+                           koopa-opt  Koopa IR after optimization
+                           asm        RISC-V assembly (default)
+                         With several outputs, <file> is used as a prefix of their paths
+  -O0, -O1, -O2          Optimization level (default: -O0)
+  -f<pass>, -fno-<pass>  Run or skip an optimization pass regardless of the level
+  -finline-threshold=<n>
+                         Inline functions of at most <n> instructions (default: 32)
+  --passes=<passes>      Comma separated list of passes to run in order, instead of those of
+                         the level. Passes may be repeated
+  --print-after=<pass>   Print the Koopa IR to stderr after each run of <pass>, which must be
+                         in the pipeline
+  --print-after-all      Print the Koopa IR to stderr after every pass
+  --time-passes          Print the time spent in each pass to stderr
+  --verify-ir            Verify the Koopa IR after generation and after every pass, which is
+                         always done by debug builds
+  -regalloc <name>       Register allocator, `greedy`, `linear` or `coloring`
+                         (default: `coloring` with -O2, `linear` otherwise)
+  -W<lint>, -Wno-<lint>  Enable or disable a warning
+  -Wall                  Enable all warnings
+  -Werror                Report warnings as errors
+  -w                     Disable all warnings
+  -dump-liveness         Print the live registers of the generated assembly to stderr
+  -koopa                 Same as --emit=koopa
+  -riscv                 Same as --emit=asm
+  -perf                  Same as --emit=asm -O2
+  -h, --help             Print this help
+  -V, --version          Print the version
+";
+
+/// Kinds of output, in the order they are produced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    Ast,
+    Koopa,
+    KoopaOpt,
+    Asm,
+}
+
+impl Emit {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ast" => Some(Emit::Ast),
+            "koopa" => Some(Emit::Koopa),
+            "koopa-opt" => Some(Emit::KoopaOpt),
+            "asm" => Some(Emit::Asm),
+            _ => None,
+        }
+    }
+
+    /// Get the extension added to the output path when there are several outputs.
+    fn extension(self) -> &'static str {
+        match self {
+            Emit::Ast => "ast",
+            Emit::Koopa => "koopa",
+            Emit::KoopaOpt => "opt.koopa",
+            Emit::Asm => "s",
+        }
+    }
+}
+
+/// What to do with the compiled program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Write the requested outputs.
+    Compile,
+    /// Interpret the Koopa IR.
+    Run,
+    /// Simulate the RISC-V assembly.
+    Simulate,
+}
 
 #[derive(Debug, Clone)]
 pub struct Params {
     pub output: String,
     pub input: String,
+    pub mode: Mode,
+
+    /// Outputs to produce, without duplicates.
+    pub emit: Vec<Emit>,
+    pub pipeline: Pipeline,
 
-    pub koopa: bool,
-    pub riscv: bool,
-    pub perf: bool,
+    pub reg_alloc: RegAllocStrategy,
+    /// Print the live registers of the generated assembly to stderr.
+    pub dump_liveness: bool,
+    pub lints: Lints,
 }
 
 impl Params {
     pub fn parse() -> Self {
-        let mut args = args();
-        let (mut input, mut output, mut koopa, mut riscv, mut perf) =
-            (String::new(), String::new(), false, false, false);
-        args.next();
+        let mut args = args().skip(1).peekable();
+        let mode = match args.peek().map(String::as_str) {
+            Some("run") => Mode::Run,
+            Some("sim") => Mode::Simulate,
+            _ => Mode::Compile,
+        };
+        if mode != Mode::Compile {
+            args.next();
+        }
+        let (mut input, mut output) = (None, None);
+        let mut emit = vec![];
+        let mut opt_level = 0;
+        let mut passes = None;
+        let mut pass_switches = vec![];
+        let mut inline_threshold = None;
+        let mut print_after = vec![];
+        let (mut print_after_all, mut time_passes, mut verify_ir) = (false, false, false);
+        let mut reg_alloc = None;
+        let mut dump_liveness = false;
+        let mut lints = Lints::default();
         while let Some(arg) = args.next() {
             match arg.as_str() {
+                "-h" | "--help" => {
+                    print!("{}", HELP);
+                    exit(0);
+                }
+                "-V" | "--version" => {
+                    println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+                    exit(0);
+                }
                 "-o" => {
                     if let Some(output_path) = args.next() {
-                        output = output_path;
+                        output = Some(output_path);
                     } else {
                         show_error("missing output file", 1);
                     }
                 }
-                "-koopa" => koopa = true,
-                "-riscv" => riscv = true,
-                "-perf" => perf = true,
+                "-koopa" => emit.push(Emit::Koopa),
+                "-riscv" => emit.push(Emit::Asm),
+                "-perf" => {
+                    emit.push(Emit::Asm);
+                    opt_level = 2;
+                }
+                "-O0" => opt_level = 0,
+                "-O1" => opt_level = 1,
+                "-O2" => opt_level = 2,
+                "-dump-liveness" => dump_liveness = true,
+                "--print-after-all" => print_after_all = true,
+                "--time-passes" => time_passes = true,
+                "--verify-ir" => verify_ir = true,
+                "-regalloc" => {
+                    let name = args.next().unwrap_or_else(|| {
+                        show_error("missing register allocator", 1);
+                    });
+                    reg_alloc = Some(RegAllocStrategy::from_name(&name).unwrap_or_else(|| {
+                        show_error(&format!("unknown register allocator: {}", name), 1);
+                    }));
+                }
+                "-w" => lints.disable_all(),
+                _ if arg.starts_with("--emit=") => {
+                    for name in arg["--emit=".len()..].split(',') {
+                        emit.push(Emit::from_name(name).unwrap_or_else(|| {
+                            show_error(&format!("unknown output kind: {}", name), 1);
+                        }));
+                    }
+                }
+                _ if arg.starts_with("-O") => {
+                    show_error(&format!("unknown optimization level: {}", arg), 1);
+                }
+                _ if arg.starts_with("--passes=") => {
+                    passes = Some(or_exit(Pipeline::parse(&arg["--passes=".len()..])));
+                }
+                _ if arg.starts_with("--print-after=") => {
+                    print_after.push(or_exit(OptPass::parse(&arg["--print-after=".len()..])));
+                }
+                _ if arg.starts_with("-finline-threshold=") => {
+                    let n = &arg["-finline-threshold=".len()..];
+                    inline_threshold = Some(n.parse().unwrap_or_else(|_| {
+                        show_error(&format!("invalid inline threshold: {}", n), 1);
+                    }));
+                }
+                _ if arg.starts_with("-f") => match arg[2..].strip_prefix("no-") {
+                    Some(name) => pass_switches.push((or_exit(OptPass::parse(name)), false)),
+                    None => pass_switches.push((or_exit(OptPass::parse(&arg[2..])), true)),
+                },
+                _ if arg.starts_with("-W") => {
+                    if !lints.apply_flag(&arg[2..]) {
+                        show_error(&format!("unknown warning option: {}", arg), 1);
+                    }
+                }
+                _ if arg.starts_with('-') && arg != STDIO => {
+                    show_error(&format!("unknown option: {}", arg), 1);
+                }
                 _ => {
-                    if input.is_empty() {
-                        input = arg;
+                    if input.is_none() {
+                        input = Some(arg);
                     } else {
                         show_error("multiple input files", 1);
                     }
                 }
             }
         }
-        if input.is_empty() {
-            show_error("missing input file", 1);
+
+        let run = mode != Mode::Compile;
+        if run && input.as_deref().unwrap_or(STDIO) == STDIO {
+            show_error(
+                "the program to run must be given as a file, stdin is its input",
+                1,
+            );
+        }
+        if run && !emit.is_empty() {
+            show_error("outputs cannot be emitted when running the program", 1);
         }
-        if output.is_empty() {
-            show_error("missing output file", 1);
+        if emit.is_empty() && !run {
+            emit.push(Emit::Asm);
         }
-        if !koopa && !riscv && !perf {
-            show_error("no output format specified", 1);
+        emit.sort_by_key(|emit| *emit as u8);
+        emit.dedup();
+        // Switches of passes apply on top of the level or of the given passes, wherever they
+        // are given.
+        let mut pipeline = passes.unwrap_or_else(|| Pipeline::for_level(opt_level));
+        for (pass, enabled) in pass_switches {
+            pipeline.set(pass, enabled);
         }
-        if (koopa && riscv) || (koopa && perf) || (riscv && perf) {
-            show_error("multiple output formats specified", 1);
+        for pass in print_after {
+            or_exit(pipeline.add_print_after(pass));
         }
+        pipeline.print_after_all = print_after_all;
+        pipeline.time_passes = time_passes;
+        pipeline.verify |= verify_ir;
+        if let Some(inline_threshold) = inline_threshold {
+            pipeline.inline_threshold = inline_threshold;
+        }
+        let reg_alloc = reg_alloc.unwrap_or(if opt_level >= 2 {
+            RegAllocStrategy::GraphColoring
+        } else {
+            RegAllocStrategy::LinearScan
+        });
         Params {
-            output,
-            input,
-            koopa,
-            riscv,
-            perf,
+            output: output.unwrap_or_else(|| STDIO.to_string()),
+            input: input.unwrap_or_else(|| STDIO.to_string()),
+            mode,
+            emit,
+            pipeline,
+            reg_alloc,
+            dump_liveness,
+            lints,
         }
     }
+
+    /// Get the path to write an output to, `None` for stdout.
+    pub fn output_path(&self, emit: Emit) -> Option<String> {
+        if self.output == STDIO {
+            None
+        } else if self.emit.len() == 1 {
+            Some(self.output.clone())
+        } else {
+            Some(format!("{}.{}", self.output, emit.extension()))
+        }
+    }
+}
+
+/// Get the value of a result, or report its error and exit.
+fn or_exit<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|error| show_error(&error, 1))
 }
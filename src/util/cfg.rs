@@ -0,0 +1,274 @@
+//! Control flow graph of a Koopa IR function.
+
+use koopa::ir::{BasicBlock, FunctionData, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+/// Get the successors of a basic block, in the order they appear in its terminator.
+pub fn successors(func_data: &FunctionData, bb: BasicBlock) -> Vec<BasicBlock> {
+    let node = match func_data.layout().bbs().node(&bb) {
+        Some(node) => node,
+        None => return vec![],
+    };
+    let last = match node.insts().back_key() {
+        Some(last) => *last,
+        None => return vec![],
+    };
+    match func_data.dfg().value(last).kind() {
+        ValueKind::Branch(branch) => vec![branch.true_bb(), branch.false_bb()],
+        ValueKind::Jump(jump) => vec![jump.target()],
+        _ => vec![],
+    }
+}
+
+pub struct Cfg {
+    pub entry: Option<BasicBlock>,
+    succs: HashMap<BasicBlock, Vec<BasicBlock>>,
+    preds: HashMap<BasicBlock, Vec<BasicBlock>>,
+    /// Reachable basic blocks in reverse post order.
+    rpo: Vec<BasicBlock>,
+}
+
+impl Cfg {
+    pub fn new(func_data: &FunctionData) -> Self {
+        let mut succs = HashMap::new();
+        let mut preds: HashMap<BasicBlock, Vec<BasicBlock>> = HashMap::new();
+        for (bb, _) in func_data.layout().bbs() {
+            preds.entry(*bb).or_default();
+        }
+        for (bb, _) in func_data.layout().bbs() {
+            let bb_succs = successors(func_data, *bb);
+            for succ in &bb_succs {
+                let succ_preds = preds.entry(*succ).or_default();
+                if !succ_preds.contains(bb) {
+                    succ_preds.push(*bb);
+                }
+            }
+            succs.insert(*bb, bb_succs);
+        }
+        let entry = func_data.layout().entry_bb();
+
+        // Iterative DFS for post order.
+        let mut post_order = vec![];
+        if let Some(entry) = entry {
+            let mut visited = HashSet::new();
+            let mut stack = vec![(entry, 0usize)];
+            visited.insert(entry);
+            while let Some((bb, i)) = stack.pop() {
+                let bb_succs: &Vec<BasicBlock> = &succs[&bb];
+                if i < bb_succs.len() {
+                    stack.push((bb, i + 1));
+                    let succ = bb_succs[i];
+                    if visited.insert(succ) {
+                        stack.push((succ, 0));
+                    }
+                } else {
+                    post_order.push(bb);
+                }
+            }
+        }
+        post_order.reverse();
+
+        Self {
+            entry,
+            succs,
+            preds,
+            rpo: post_order,
+        }
+    }
+
+    pub fn succs(&self, bb: BasicBlock) -> &[BasicBlock] {
+        self.succs.get(&bb).map_or(&[], |s| s.as_slice())
+    }
+
+    pub fn preds(&self, bb: BasicBlock) -> &[BasicBlock] {
+        self.preds.get(&bb).map_or(&[], |p| p.as_slice())
+    }
+
+    /// Reachable basic blocks in reverse post order.
+    pub fn rpo(&self) -> &[BasicBlock] {
+        &self.rpo
+    }
+
+    pub fn is_reachable(&self, bb: BasicBlock) -> bool {
+        self.rpo.contains(&bb)
+    }
+
+    /// Compute the dominator tree.
+    ///
+    /// Uses the iterative algorithm from Cooper, Harvey and Kennedy,
+    /// "A Simple, Fast Dominance Algorithm".
+    pub fn dominators(&self) -> Dominators {
+        let order: HashMap<BasicBlock, usize> = self
+            .rpo
+            .iter()
+            .enumerate()
+            .map(|(i, bb)| (*bb, i))
+            .collect();
+        let mut idom: HashMap<BasicBlock, BasicBlock> = HashMap::new();
+        let entry = match self.entry {
+            Some(entry) => entry,
+            None => return Dominators { idom, order },
+        };
+        idom.insert(entry, entry);
+
+        let intersect = |idom: &HashMap<BasicBlock, BasicBlock>, mut a, mut b| {
+            while a != b {
+                while order[&a] > order[&b] {
+                    a = idom[&a];
+                }
+                while order[&b] > order[&a] {
+                    b = idom[&b];
+                }
+            }
+            a
+        };
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for bb in self.rpo.iter().skip(1) {
+                let mut new_idom = None;
+                for pred in self.preds(*bb) {
+                    if !idom.contains_key(pred) {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => *pred,
+                        Some(cur) => intersect(&idom, *pred, cur),
+                    });
+                }
+                let new_idom = new_idom.unwrap();
+                if idom.get(bb) != Some(&new_idom) {
+                    idom.insert(*bb, new_idom);
+                    changed = true;
+                }
+            }
+        }
+        Dominators { idom, order }
+    }
+
+    /// Find the natural loops, inner loops first.
+    ///
+    /// The natural loop of a back edge, i.e. an edge whose target dominates its source, consists
+    /// of the target and the blocks that reach the source without passing the target. Loops of
+    /// back edges sharing a header are merged.
+    pub fn loops(&self, doms: &Dominators) -> Vec<Loop> {
+        let mut loops: Vec<Loop> = vec![];
+        for bb in &self.rpo {
+            for succ in self.succs(*bb) {
+                if !doms.dominates(*succ, *bb) {
+                    continue;
+                }
+                let index = match loops.iter().position(|l| l.header == *succ) {
+                    Some(index) => index,
+                    None => {
+                        loops.push(Loop {
+                            header: *succ,
+                            body: HashSet::from([*succ]),
+                        });
+                        loops.len() - 1
+                    }
+                };
+                let body = &mut loops[index].body;
+                let mut worklist = vec![*bb];
+                while let Some(node) = worklist.pop() {
+                    if body.insert(node) {
+                        worklist.extend(self.preds(node).iter().filter(|p| self.is_reachable(**p)));
+                    }
+                }
+            }
+        }
+        // A loop nested in another one has fewer blocks.
+        loops.sort_by_key(|l| l.body.len());
+        loops
+    }
+
+    /// Compute the loop nesting depth of every reachable basic block.
+    pub fn loop_depths(&self, doms: &Dominators) -> HashMap<BasicBlock, u32> {
+        let mut depths: HashMap<BasicBlock, u32> = self.rpo.iter().map(|bb| (*bb, 0)).collect();
+        for l in self.loops(doms) {
+            for bb in l.body {
+                *depths.get_mut(&bb).unwrap() += 1;
+            }
+        }
+        depths
+    }
+}
+
+/// A natural loop.
+pub struct Loop {
+    pub header: BasicBlock,
+    /// The blocks of the loop, including the header.
+    pub body: HashSet<BasicBlock>,
+}
+
+pub struct Dominators {
+    idom: HashMap<BasicBlock, BasicBlock>,
+    order: HashMap<BasicBlock, usize>,
+}
+
+impl Dominators {
+    /// Get the immediate dominator of a basic block, `None` for the entry and unreachable blocks.
+    pub fn idom(&self, bb: BasicBlock) -> Option<BasicBlock> {
+        self.idom.get(&bb).copied().filter(|idom| *idom != bb)
+    }
+
+    /// Get the children of every block in the dominator tree, in reverse post order.
+    pub fn children(&self) -> HashMap<BasicBlock, Vec<BasicBlock>> {
+        let mut blocks: Vec<BasicBlock> = self.order.keys().copied().collect();
+        blocks.sort_by_key(|bb| self.order[bb]);
+        let mut children: HashMap<BasicBlock, Vec<BasicBlock>> =
+            blocks.iter().map(|bb| (*bb, vec![])).collect();
+        for bb in blocks {
+            if let Some(idom) = self.idom(bb) {
+                children.get_mut(&idom).unwrap().push(bb);
+            }
+        }
+        children
+    }
+
+    /// Compute the dominance frontier of every reachable block.
+    ///
+    /// Uses the algorithm from Cooper, Harvey and Kennedy, walking up the dominator tree from
+    /// the predecessors of every join point.
+    pub fn frontiers(&self, cfg: &Cfg) -> HashMap<BasicBlock, HashSet<BasicBlock>> {
+        let mut frontiers: HashMap<BasicBlock, HashSet<BasicBlock>> =
+            self.order.keys().map(|bb| (*bb, HashSet::new())).collect();
+        for bb in self.order.keys() {
+            let preds: Vec<BasicBlock> = cfg
+                .preds(*bb)
+                .iter()
+                .filter(|p| self.order.contains_key(p))
+                .copied()
+                .collect();
+            if preds.len() < 2 {
+                continue;
+            }
+            let idom = self.idom(*bb);
+            for pred in preds {
+                let mut runner = Some(pred);
+                while let Some(node) = runner.filter(|node| Some(*node) != idom) {
+                    frontiers.get_mut(&node).unwrap().insert(*bb);
+                    runner = self.idom(node);
+                }
+            }
+        }
+        frontiers
+    }
+
+    /// Check if `a` dominates `b`. Every block dominates itself.
+    pub fn dominates(&self, a: BasicBlock, mut b: BasicBlock) -> bool {
+        if !self.order.contains_key(&a) || !self.order.contains_key(&b) {
+            return false;
+        }
+        loop {
+            if a == b {
+                return true;
+            }
+            match self.idom(b) {
+                Some(idom) if self.order[&idom] >= self.order[&a] => b = idom,
+                _ => return false,
+            }
+        }
+    }
+}
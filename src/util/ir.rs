@@ -0,0 +1,180 @@
+//! Helpers for rewriting Koopa IR functions.
+
+use crate::util::cfg::Cfg;
+use koopa::ir::builder::{BasicBlockBuilder, LocalInstBuilder, ValueBuilder};
+use koopa::ir::{BasicBlock, FunctionData, Type, TypeKind, Value, ValueKind};
+
+/// Replace every operand `old` of a value with `new`.
+pub fn replace_operand(kind: &mut ValueKind, old: Value, new: Value) {
+    let replace = |value: &mut Value| {
+        if *value == old {
+            *value = new;
+        }
+    };
+    match kind {
+        ValueKind::Aggregate(agg) => agg.elems_mut().iter_mut().for_each(replace),
+        ValueKind::GlobalAlloc(alloc) => replace(alloc.init_mut()),
+        ValueKind::Load(load) => replace(load.src_mut()),
+        ValueKind::Store(store) => {
+            replace(store.value_mut());
+            replace(store.dest_mut());
+        }
+        ValueKind::GetPtr(get_ptr) => {
+            replace(get_ptr.src_mut());
+            replace(get_ptr.index_mut());
+        }
+        ValueKind::GetElemPtr(get_elem_ptr) => {
+            replace(get_elem_ptr.src_mut());
+            replace(get_elem_ptr.index_mut());
+        }
+        ValueKind::Binary(binary) => {
+            replace(binary.lhs_mut());
+            replace(binary.rhs_mut());
+        }
+        ValueKind::Branch(branch) => {
+            replace(branch.cond_mut());
+            branch.true_args_mut().iter_mut().for_each(replace);
+            branch.false_args_mut().iter_mut().for_each(replace);
+        }
+        ValueKind::Jump(jump) => jump.args_mut().iter_mut().for_each(replace),
+        ValueKind::Call(call) => call.args_mut().iter_mut().for_each(replace),
+        ValueKind::Return(ret) => {
+            if let Some(value) = ret.value_mut() {
+                replace(value);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Replace all uses of a local value with another value.
+pub fn replace_all_uses(data: &mut FunctionData, old: Value, new: Value) {
+    let users: Vec<Value> = data.dfg().value(old).used_by().iter().copied().collect();
+    for user in users {
+        let mut user_data = data.dfg().value(user).clone();
+        replace_operand(user_data.kind_mut(), old, new);
+        data.dfg_mut().replace_value_with(user).raw(user_data);
+    }
+}
+
+/// Remove the parameters of a block for which `keep` is false, along with the arguments passed
+/// to them. The removed parameters must no longer be used.
+pub fn remove_params(data: &mut FunctionData, bb: BasicBlock, keep: &[bool]) {
+    let params = data.dfg().bb(bb).params().to_vec();
+    let kept: Vec<Value> = params
+        .iter()
+        .zip(keep)
+        .filter(|(_, keep)| **keep)
+        .map(|(param, _)| *param)
+        .collect();
+    // Parameters refer to their index, so the kept ones are replaced with those of a temporary
+    // block, as Koopa can only create parameters with a new block.
+    let types: Vec<Type> = kept
+        .iter()
+        .map(|param| data.dfg().value(*param).ty().clone())
+        .collect();
+    let temp = data.dfg_mut().new_bb().basic_block_with_params(None, types);
+    let new_params = std::mem::take(data.dfg_mut().bb_mut(temp).params_mut());
+    data.dfg_mut().remove_bb(temp);
+    for (old, new) in kept.iter().zip(&new_params) {
+        replace_all_uses(data, *old, *new);
+    }
+    *data.dfg_mut().bb_mut(bb).params_mut() = new_params;
+    for param in params {
+        data.dfg_mut().remove_value(param);
+    }
+
+    let filter = |args: &[Value]| -> Vec<Value> {
+        args.iter()
+            .zip(keep)
+            .filter(|(_, keep)| **keep)
+            .map(|(arg, _)| *arg)
+            .collect()
+    };
+    let cfg = Cfg::new(data);
+    for pred in cfg.preds(bb) {
+        let last = *data
+            .layout()
+            .bbs()
+            .node(pred)
+            .unwrap()
+            .insts()
+            .back_key()
+            .unwrap();
+        let terminator = data.dfg().value(last).kind().clone();
+        let builder = data.dfg_mut().replace_value_with(last);
+        match terminator {
+            ValueKind::Jump(jump) => {
+                builder.jump_with_args(jump.target(), filter(jump.args()));
+            }
+            ValueKind::Branch(branch) => {
+                let true_args = if branch.true_bb() == bb {
+                    filter(branch.true_args())
+                } else {
+                    branch.true_args().to_vec()
+                };
+                let false_args = if branch.false_bb() == bb {
+                    filter(branch.false_args())
+                } else {
+                    branch.false_args().to_vec()
+                };
+                builder.branch_with_args(
+                    branch.cond(),
+                    branch.true_bb(),
+                    branch.false_bb(),
+                    true_args,
+                    false_args,
+                );
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Check if an instruction is a call in tail position, followed by a return of its result or of
+/// nothing, so that the function has nothing left to do once the callee returns.
+pub fn is_tail_call(data: &FunctionData, inst: Value) -> bool {
+    if !matches!(data.dfg().value(inst).kind(), ValueKind::Call(_)) {
+        return false;
+    }
+    let Some(bb) = data.layout().parent_bb(inst) else {
+        return false;
+    };
+    let insts = data.layout().bbs().node(&bb).unwrap().insts();
+    let cursor = insts.cursor(inst);
+    let Some(next) = cursor.next_key() else {
+        return false;
+    };
+    match data.dfg().value(*next).kind() {
+        ValueKind::Return(ret) => ret.value().is_none_or(|value| value == inst),
+        _ => false,
+    }
+}
+
+/// Check if a value is a pointer that may point into the stack frame of the function, to the
+/// memory of one of its `alloc`s.
+pub fn may_point_to_frame(data: &FunctionData, value: Value) -> bool {
+    if value.is_global() || !matches!(data.dfg().value(value).ty().kind(), TypeKind::Pointer(_)) {
+        return false;
+    }
+    match data.dfg().value(value).kind() {
+        ValueKind::Alloc(_) => true,
+        ValueKind::GetPtr(get_ptr) => may_point_to_frame(data, get_ptr.src()),
+        ValueKind::GetElemPtr(get_elem_ptr) => may_point_to_frame(data, get_elem_ptr.src()),
+        ValueKind::FuncArgRef(_) => false,
+        ValueKind::Load(load) if load.src().is_global() => false,
+        // Pointer variables hold array parameters, or the arrays passed to inlined functions.
+        ValueKind::Load(load) => {
+            data.dfg().value(load.src()).used_by().iter().any(|user| {
+                match data.dfg().value(*user).kind() {
+                    ValueKind::Store(store) if store.dest() == load.src() => {
+                        may_point_to_frame(data, store.value())
+                    }
+                    _ => false,
+                }
+            })
+        }
+        // Block parameters may be any of the pointers passed to them.
+        _ => true,
+    }
+}
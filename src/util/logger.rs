@@ -1,6 +1,6 @@
-use colored::Colorize;
-use lalrpop_util::lexer::Token;
-use lalrpop_util::ParseError;
+use crate::front::diagnostic::{Diagnostic, Diagnostics, Level};
+use colored::{ColoredString, Colorize};
+use std::fmt::Write;
 use std::process::exit;
 
 pub fn show_error(error: &str, exit_code: i32) -> ! {
@@ -12,33 +12,6 @@ pub fn show_error_no_exit(error: &str) {
     eprintln!("{} {}", "Error:".red().bold(), error.bold());
 }
 
-pub fn show_parse_error(error: ParseError<usize, Token, &str>, input: &str, file_path: &str) -> ! {
-    match error {
-        ParseError::InvalidToken { location } => {
-            show_error_position(input, location, "Invalid token", file_path)
-        }
-        ParseError::UnrecognizedEof {
-            location,
-            expected: _,
-        } => {
-            show_error_position(input, location, "Unexpected end of file", file_path);
-        }
-        ParseError::UnrecognizedToken {
-            token: (start, _, end),
-            expected: _,
-        } => {
-            show_error_range(input, start, end, "Unrecognized token", file_path);
-        }
-        ParseError::ExtraToken {
-            token: (start, _, end),
-        } => show_error_range(input, start, end, "Extra token", file_path),
-        ParseError::User { error: message } => {
-            show_error(&format!("Error: {}", message), 1);
-        }
-    }
-    exit(1)
-}
-
 fn get_position(input: &str, location: usize) -> (usize, usize) {
     let mut line = 1;
     let mut column = 1;
@@ -56,12 +29,32 @@ fn get_position(input: &str, location: usize) -> (usize, usize) {
     (line, column)
 }
 
+/// How the range of a snippet is underlined.
+#[derive(Clone, Copy)]
+enum Marker {
+    /// The cause of an error.
+    Primary,
+    /// A place related to an error.
+    Secondary,
+}
+
+impl Marker {
+    fn underline(self, len: usize) -> ColoredString {
+        match self {
+            Marker::Primary => "^".repeat(len).red().bold(),
+            Marker::Secondary => "-".repeat(len).bright_cyan().bold(),
+        }
+    }
+}
+
 fn show_error_line(
+    out: &mut String,
     error_line: &str,
     line_no: usize,
     start_col: usize,
     end_col: usize,
     header_space: usize,
+    marker: Marker,
 ) {
     let line_no = line_no.to_string();
     let space_no = if header_space > line_no.len() {
@@ -69,66 +62,86 @@ fn show_error_line(
     } else {
         0
     };
-    eprintln!(
+    writeln!(
+        out,
         "{}{} {} {}",
         line_no.bright_cyan().bold(),
         " ".repeat(space_no),
         "|".bright_cyan().bold(),
         error_line
-    );
-    eprintln!(
+    )
+    .unwrap();
+    writeln!(
+        out,
         "{} {} {}{}",
         " ".repeat(header_space),
         "|".bright_cyan().bold(),
         " ".repeat(start_col - 1),
-        "^".repeat(end_col - start_col).red().bold()
-    );
+        marker.underline(end_col.saturating_sub(start_col).max(1))
+    )
+    .unwrap();
 }
 
-fn show_file_info(file_path: &str, line_no: usize, column: usize) {
-    eprintln!(
+fn show_file_info(out: &mut String, file_path: &str, line_no: usize, column: usize) {
+    writeln!(
+        out,
         " {} {}:{}:{}",
         "-->".bright_cyan().bold(),
         file_path,
         line_no,
         column
-    );
+    )
+    .unwrap();
 }
 
-pub fn show_error_position(input: &str, location: usize, message: &str, file_path: &str) {
-    let (line_no, column) = get_position(input, location);
-    let lines: Vec<&str> = input.split('\n').collect();
-    let line = lines[line_no - 1];
-    show_error_no_exit(message);
-    let line_no_width = line_no.to_string().len();
-    show_file_info(file_path, line_no, column);
-    eprintln!("{} {}", " ".repeat(line_no_width), "|".bright_cyan().bold());
-    show_error_line(line, line_no, column, column + 1, line_no_width);
-}
-
-pub fn show_error_range(input: &str, start: usize, end: usize, message: &str, file_path: &str) {
+/// Show the lines of a range of the input with the range underlined, returning the width of the
+/// line numbers.
+fn show_snippet(
+    out: &mut String,
+    input: &str,
+    start: usize,
+    end: usize,
+    file_path: &str,
+    marker: Marker,
+) -> usize {
     let (start_line, start_column) = get_position(input, start);
     let (end_line, end_column) = get_position(input, end);
     let lines: Vec<&str> = input.split('\n').collect();
-    show_error_no_exit(message);
-    show_file_info(file_path, start_line, start_column);
+    show_file_info(out, file_path, start_line, start_column);
     if start_line == end_line {
         let line = lines[start_line - 1];
         let line_no = start_line.to_string();
         let line_no_width = line_no.len();
-        eprintln!("{} {}", " ".repeat(line_no_width), "|".bright_cyan().bold());
-        show_error_line(line, start_line, start_column, end_column, line_no_width);
+        writeln!(
+            out,
+            "{} {}",
+            " ".repeat(line_no_width),
+            "|".bright_cyan().bold()
+        )
+        .unwrap();
+        show_error_line(
+            out,
+            line,
+            start_line,
+            start_column,
+            end_column,
+            line_no_width,
+            marker,
+        );
+        line_no_width
     } else {
         let error_lines = &lines[start_line - 1..end_line];
         let max_line_no_width = (start_line..=end_line)
             .map(|n| n.to_string().len())
             .max()
             .unwrap();
-        eprintln!(
+        writeln!(
+            out,
             "{} {}",
             " ".repeat(max_line_no_width),
             "|".bright_cyan().bold(),
-        );
+        )
+        .unwrap();
         for (i, line) in error_lines.iter().enumerate() {
             let start_column = if i == 0 { start_column } else { 1 };
             let end_column = if i == error_lines.len() - 1 {
@@ -137,12 +150,91 @@ pub fn show_error_range(input: &str, start: usize, end: usize, message: &str, fi
                 line.len()
             };
             show_error_line(
+                out,
                 line,
                 start_line + i,
                 start_column,
                 end_column,
                 max_line_no_width,
+                marker,
             );
         }
+        max_line_no_width
+    }
+}
+
+/// Render a diagnostic with the source lines it points at.
+pub fn render_diagnostic(diagnostic: &Diagnostic, input: &str, file_path: &str) -> String {
+    let mut out = String::new();
+    let header = match diagnostic.level {
+        Level::Error => format!("Error[{}]:", diagnostic.code).red().bold(),
+        Level::Warning => format!("Warning[{}]:", diagnostic.code).yellow().bold(),
+    };
+    writeln!(out, "{} {}", header, diagnostic.message.bold()).unwrap();
+    let mut width = 1;
+    if let Some(span) = diagnostic.span {
+        width = show_snippet(
+            &mut out,
+            input,
+            span.start,
+            span.end,
+            file_path,
+            Marker::Primary,
+        );
+    }
+    for note in &diagnostic.notes {
+        match note.span {
+            Some(span) => {
+                let note_header = "Note:".bright_green().bold();
+                writeln!(out, "{} {}", note_header, note.message.bold()).unwrap();
+                width = show_snippet(
+                    &mut out,
+                    input,
+                    span.start,
+                    span.end,
+                    file_path,
+                    Marker::Secondary,
+                );
+            }
+            None => writeln!(
+                out,
+                "{} {} {} {}",
+                " ".repeat(width),
+                "=".bright_cyan().bold(),
+                "note:".bold(),
+                note.message
+            )
+            .unwrap(),
+        }
+    }
+    if let Some(help) = &diagnostic.help {
+        writeln!(
+            out,
+            "{} {} {} {}",
+            " ".repeat(width),
+            "=".bright_cyan().bold(),
+            "help:".bold(),
+            help
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Show all the collected diagnostics followed by the number of errors and warnings.
+pub fn show_diagnostics(diagnostics: &Diagnostics, input: &str, file_path: &str) {
+    for diagnostic in diagnostics.iter() {
+        eprintln!("{}", render_diagnostic(diagnostic, input, file_path));
+    }
+    let plural = |n: usize, word: &str| format!("{} {}{}", n, word, if n == 1 { "" } else { "s" });
+    let (errors, warnings) = (diagnostics.error_count(), diagnostics.warning_count());
+    if errors > 0 {
+        show_error_no_exit(&format!("aborting due to {}", plural(errors, "error")));
+    } else if warnings > 0 {
+        eprintln!(
+            "{} {}",
+            "Warning:".yellow().bold(),
+            format!("{} emitted", plural(warnings, "warning")).bold()
+        );
     }
 }
@@ -0,0 +1,212 @@
+//! The runtime library of SysY, for the interpreter and the simulator.
+//!
+//! Input is read like `scanf` and output written like `printf` would in `libsysy`, `float`s being
+//! written in hexadecimal like `%a` does. Timers print to stderr in the same format.
+
+use crate::util::parse_float;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+pub struct Runtime<'a, W: Write> {
+    input: &'a [u8],
+    output: &'a mut W,
+    timer: Option<Instant>,
+    total_time: Option<Duration>,
+}
+
+impl<'a, W: Write> Runtime<'a, W> {
+    pub fn new(input: &'a [u8], output: &'a mut W) -> Self {
+        Self {
+            input,
+            output,
+            timer: None,
+            total_time: None,
+        }
+    }
+
+    /// Read an integer like `scanf("%d")`, giving 0 if there is none.
+    pub fn read_int(&mut self) -> i32 {
+        let input = self.input.trim_ascii_start();
+        let (negative, digits) = match input.first() {
+            Some(b'-') => (true, &input[1..]),
+            Some(b'+') => (false, &input[1..]),
+            _ => (false, input),
+        };
+        let len = digits.iter().take_while(|c| c.is_ascii_digit()).count();
+        if len == 0 {
+            self.input = input;
+            return 0;
+        }
+        let value = digits[..len].iter().fold(0i32, |value, digit| {
+            value.wrapping_mul(10).wrapping_add((digit - b'0') as i32)
+        });
+        self.input = &digits[len..];
+        if negative {
+            value.wrapping_neg()
+        } else {
+            value
+        }
+    }
+
+    /// Read a byte, giving -1 at the end of the input.
+    pub fn read_char(&mut self) -> i32 {
+        match self.input.split_first() {
+            Some((c, rest)) => {
+                self.input = rest;
+                *c as i32
+            }
+            None => -1,
+        }
+    }
+
+    /// Read a length followed by as many integers, like `getarray`.
+    pub fn read_array(&mut self) -> Vec<i32> {
+        let len = self.read_int();
+        (0..len).map(|_| self.read_int()).collect()
+    }
+
+    /// Read a decimal or hexadecimal float like `scanf("%a")`, giving 0 if there is none.
+    pub fn read_float(&mut self) -> f32 {
+        let input = self.input.trim_ascii_start();
+        let (negative, rest) = match input.first() {
+            Some(b'-') => (true, &input[1..]),
+            Some(b'+') => (false, &input[1..]),
+            _ => (false, input),
+        };
+        let hex = rest.len() > 1 && rest[0] == b'0' && matches!(rest[1], b'x' | b'X');
+        let (digits, exponent) = if hex { (2, b"pP") } else { (0, b"eE") };
+        let is_digit = |c: &u8| {
+            if hex {
+                c.is_ascii_hexdigit()
+            } else {
+                c.is_ascii_digit()
+            }
+        };
+        // The digits, the point and the digits after it.
+        let mut len = digits + rest[digits..].iter().take_while(|c| is_digit(c)).count();
+        if rest.get(len) == Some(&b'.') {
+            len += 1 + rest[len + 1..].iter().take_while(|c| is_digit(c)).count();
+        }
+        // The exponent, if it has digits.
+        if rest.get(len).is_some_and(|c| exponent.contains(c)) {
+            let sign = matches!(rest.get(len + 1), Some(b'+' | b'-')) as usize;
+            let exp_digits = rest[len + 1 + sign..]
+                .iter()
+                .take_while(|c| c.is_ascii_digit())
+                .count();
+            if exp_digits > 0 {
+                len += 1 + sign + exp_digits;
+            }
+        }
+        let text = std::str::from_utf8(&rest[..len]).unwrap_or("");
+        // Hexadecimal floats are parsed with an exponent, which may be omitted here.
+        let value = if hex && !text.contains(['p', 'P']) {
+            parse_float(&format!("{}p0", text))
+        } else {
+            parse_float(text)
+        };
+        match value {
+            Some(value) => {
+                self.input = &rest[len..];
+                if negative {
+                    -value
+                } else {
+                    value
+                }
+            }
+            None => {
+                self.input = input;
+                0.0
+            }
+        }
+    }
+
+    /// Read a length followed by as many floats, like `getfarray`.
+    pub fn read_float_array(&mut self) -> Vec<f32> {
+        let len = self.read_int();
+        (0..len).map(|_| self.read_float()).collect()
+    }
+
+    pub fn put_int(&mut self, value: i32) -> io::Result<()> {
+        write!(self.output, "{}", value)
+    }
+
+    pub fn put_char(&mut self, value: i32) -> io::Result<()> {
+        self.output.write_all(&[value as u8])
+    }
+
+    /// Write the length and the values of an array on a line, like `putarray`.
+    pub fn put_array(&mut self, values: &[i32]) -> io::Result<()> {
+        write!(self.output, "{}:", values.len())?;
+        for value in values {
+            write!(self.output, " {}", value)?;
+        }
+        writeln!(self.output)
+    }
+
+    pub fn put_float(&mut self, value: f32) -> io::Result<()> {
+        write!(self.output, "{}", format_hex_float(value))
+    }
+
+    /// Write the length and the values of an array of floats on a line, like `putfarray`.
+    pub fn put_float_array(&mut self, values: &[f32]) -> io::Result<()> {
+        write!(self.output, "{}:", values.len())?;
+        for value in values {
+            write!(self.output, " {}", format_hex_float(*value))?;
+        }
+        writeln!(self.output)
+    }
+
+    pub fn start_timer(&mut self) {
+        self.timer = Some(Instant::now());
+    }
+
+    pub fn stop_timer(&mut self) {
+        if let Some(start) = self.timer.take() {
+            let elapsed = start.elapsed();
+            eprintln!("Timer: {}", format_duration(elapsed));
+            *self.total_time.get_or_insert(Duration::ZERO) += elapsed;
+        }
+    }
+
+    /// Print the total time of the timers, if any was stopped, as `libsysy` does at exit.
+    pub fn report_total_time(&self) {
+        if let Some(total) = self.total_time {
+            eprintln!("TOTAL: {}", format_duration(total));
+        }
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let micros = duration.as_micros();
+    format!(
+        "{}H-{}M-{}S-{}us",
+        micros / 3_600_000_000,
+        micros / 60_000_000 % 60,
+        micros / 1_000_000 % 60,
+        micros % 1_000_000
+    )
+}
+
+/// Format a float like `printf("%a")` does, after converting it to a `double`.
+fn format_hex_float(value: f32) -> String {
+    let sign = if value.is_sign_negative() { "-" } else { "" };
+    if value.is_nan() {
+        return format!("{}nan", sign);
+    }
+    if value.is_infinite() {
+        return format!("{}inf", sign);
+    }
+    if value == 0.0 {
+        return format!("{}0x0p+0", sign);
+    }
+    let bits = (value as f64).to_bits();
+    let exponent = ((bits >> 52) & 0x7ff) as i32 - 1023;
+    let mantissa = format!("{:013x}", bits & ((1 << 52) - 1));
+    let mantissa = mantissa.trim_end_matches('0');
+    if mantissa.is_empty() {
+        format!("{}0x1p{:+}", sign, exponent)
+    } else {
+        format!("{}0x1.{}p{:+}", sign, mantissa, exponent)
+    }
+}
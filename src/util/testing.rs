@@ -0,0 +1,106 @@
+//! Helpers shared by the tests of the compiler, from source code to the results of running it.
+
+use crate::back::{generate_asm, simulate, AsmProgram, RegAllocStrategy, SimResult};
+use crate::front::ast::CompUnit;
+use crate::front::generate_ir;
+use crate::front::lint::Lints;
+use crate::front::opt::{opt, OptPass, Pipeline};
+use crate::front::parser_context::ParserContext;
+use crate::interp::{run, RuntimeError};
+use crate::parser;
+use koopa::ir::{FunctionData, Program, ValueKind};
+
+/// Parse a program, which must have no syntax errors.
+pub fn parse(input: &str) -> CompUnit {
+    let mut context = ParserContext::new();
+    let comp_unit = parser::CompUnitParser::new()
+        .parse(&mut context, input)
+        .unwrap();
+    assert!(!context.diagnostics.has_errors());
+    comp_unit
+}
+
+/// Parse a program and generate its Koopa IR, both of which must succeed.
+pub fn compile(input: &str) -> Program {
+    let (program, diagnostics) = generate_ir(parse(input), &Lints::default());
+    assert!(!diagnostics.has_errors());
+    program
+}
+
+/// Compile a program and run the given passes on it.
+pub fn compile_with(input: &str, passes: &[OptPass]) -> Program {
+    let mut program = compile(input);
+    opt(&mut program, &Pipeline::new(passes.to_vec()));
+    program
+}
+
+/// Compile a program and optimize it at a level.
+pub fn compile_at(input: &str, level: u8) -> Program {
+    let mut program = compile(input);
+    opt(&mut program, &Pipeline::for_level(level));
+    program
+}
+
+/// Interpret a program, giving its result and what it writes.
+pub fn interpret(program: &Program, input: &[u8]) -> (Result<i32, RuntimeError>, String) {
+    let mut output = vec![];
+    let result = run(program, input, &mut output);
+    (result, String::from_utf8(output).unwrap())
+}
+
+/// Get a function of a program by its name, without the `@`.
+pub fn func<'a>(program: &'a Program, name: &str) -> &'a FunctionData {
+    program
+        .funcs()
+        .values()
+        .find(|data| data.name()[1..] == *name)
+        .unwrap()
+}
+
+/// Get the kinds of the instructions of a function in layout order.
+pub fn inst_kinds(data: &FunctionData) -> Vec<&ValueKind> {
+    data.layout()
+        .bbs()
+        .nodes()
+        .flat_map(|node| node.insts().keys())
+        .map(|inst| data.dfg().value(*inst).kind())
+        .collect()
+}
+
+/// Get the names of the functions called by a function without the `@`, in layout order.
+pub fn callees<'a>(program: &'a Program, data: &FunctionData) -> Vec<&'a str> {
+    inst_kinds(data)
+        .into_iter()
+        .filter_map(|kind| match kind {
+            ValueKind::Call(call) => Some(&program.func(call.callee()).name()[1..]),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Check if a function returns an integer constant.
+pub fn returns_integer(data: &FunctionData, value: i32) -> bool {
+    inst_kinds(data).into_iter().any(|kind| match kind {
+        ValueKind::Return(ret) => ret.value().is_some_and(
+            |v| matches!(data.dfg().value(v).kind(), ValueKind::Integer(i) if i.value() == value),
+        ),
+        _ => false,
+    })
+}
+
+/// Generate the assembly of a program and simulate it, giving its result and what it writes.
+pub fn simulate_asm(
+    program: Program,
+    strategy: RegAllocStrategy,
+    input: &[u8],
+) -> (SimResult, String) {
+    let asm = generate_asm(program, strategy, false);
+    let mut output = vec![];
+    let result = simulate(&asm, input, &mut output).unwrap();
+    (result, String::from_utf8(output).unwrap())
+}
+
+/// Generate the assembly of a program.
+pub fn assemble(program: Program, strategy: RegAllocStrategy) -> AsmProgram {
+    generate_asm(program, strategy, false)
+}
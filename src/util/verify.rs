@@ -0,0 +1,543 @@
+//! Verifier of Koopa IR programs.
+//!
+//! Catches malformed IR produced by IR generation or an optimization pass before it reaches the
+//! backend, where it would only show up as an obscure error or a panic. For every function with a
+//! body, it checks that:
+//!
+//! - Every block ends with exactly one terminator.
+//! - The operands of binary operations, loads, stores, pointer calculations, branches, jumps,
+//!   calls and returns have the expected types.
+//! - Every operand is defined by an instruction that dominates its use, or is an argument of the
+//!   function or of a block dominating the use.
+//! - The use lists of values and blocks agree with their operands.
+
+use crate::util::cfg::{Cfg, Dominators};
+use crate::util::logger::show_error_no_exit;
+use koopa::ir::{BasicBlock, FunctionData, Program, Type, TypeKind, Value, ValueKind};
+use std::collections::HashMap;
+use std::fmt;
+use std::process::exit;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    pub func: String,
+    /// The block the error is in, if it is about a block or an instruction.
+    pub bb: Option<String>,
+    /// The value the error is about.
+    pub value: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "in function {}", self.func)?;
+        if let Some(bb) = &self.bb {
+            write!(f, ", block {}", bb)?;
+        }
+        if let Some(value) = &self.value {
+            write!(f, ", {}", value)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Verify all functions of a program.
+pub fn verify(program: &Program) -> Vec<VerifyError> {
+    let mut errors = vec![];
+    for func in program.func_layout() {
+        let data = program.func(*func);
+        if data.layout().entry_bb().is_none() {
+            continue;
+        }
+        Verifier::new(program, data, &mut errors).run();
+    }
+    errors
+}
+
+/// Verify a program and exit if it is malformed, `stage` telling what produced it.
+pub fn assert_valid(program: &Program, stage: &str) {
+    let errors = verify(program);
+    if errors.is_empty() {
+        return;
+    }
+    show_error_no_exit(&format!("invalid Koopa IR after {}", stage));
+    for error in &errors {
+        show_error_no_exit(&error.to_string());
+    }
+    exit(4);
+}
+
+struct Verifier<'a> {
+    program: &'a Program,
+    data: &'a FunctionData,
+    errors: &'a mut Vec<VerifyError>,
+    cfg: Cfg,
+    doms: Dominators,
+    /// Block and index in the block of every instruction in the layout.
+    positions: HashMap<Value, (BasicBlock, usize)>,
+    /// Block of every block argument.
+    params: HashMap<Value, BasicBlock>,
+}
+
+impl<'a> Verifier<'a> {
+    fn new(program: &'a Program, data: &'a FunctionData, errors: &'a mut Vec<VerifyError>) -> Self {
+        let cfg = Cfg::new(data);
+        let doms = cfg.dominators();
+        let mut positions = HashMap::new();
+        for (bb, node) in data.layout().bbs() {
+            for (i, inst) in node.insts().keys().enumerate() {
+                positions.insert(*inst, (*bb, i));
+            }
+        }
+        let params = data
+            .dfg()
+            .bbs()
+            .iter()
+            .flat_map(|(bb, bb_data)| bb_data.params().iter().map(|param| (*param, *bb)))
+            .collect();
+        Self {
+            program,
+            data,
+            errors,
+            cfg,
+            doms,
+            positions,
+            params,
+        }
+    }
+
+    fn run(mut self) {
+        for (bb, node) in self.data.layout().bbs() {
+            let insts: Vec<Value> = node.insts().keys().copied().collect();
+            if insts.is_empty() {
+                self.error(Some(*bb), None, "block is empty");
+            }
+            for (i, inst) in insts.iter().enumerate() {
+                let is_last = i + 1 == insts.len();
+                let kind = self.data.dfg().value(*inst).kind();
+                if !kind.is_local_inst() {
+                    self.error(
+                        Some(*bb),
+                        Some(*inst),
+                        "value in the layout is not an instruction",
+                    );
+                    continue;
+                }
+                match (is_terminator(kind), is_last) {
+                    (true, false) => self.error(
+                        Some(*bb),
+                        Some(*inst),
+                        "terminator before the end of the block",
+                    ),
+                    (false, true) => self.error(
+                        Some(*bb),
+                        Some(*inst),
+                        "block does not end with a terminator",
+                    ),
+                    _ => {}
+                }
+                self.check_operands(*bb, *inst);
+                self.check_types(*bb, *inst);
+                self.check_targets(*bb, *inst);
+            }
+        }
+        self.check_use_lists();
+    }
+
+    /// Check that the operands of an instruction exist, are available where it is, and know that
+    /// they are used by it.
+    fn check_operands(&mut self, bb: BasicBlock, inst: Value) {
+        let (_, index) = self.positions[&inst];
+        for operand in self.data.dfg().value(inst).kind().value_uses() {
+            if operand.is_global() {
+                let message = match self.program.borrow_values().get(&operand) {
+                    Some(global) if global.used_by().contains(&inst) => None,
+                    Some(_) => Some("use list of a global operand misses it"),
+                    None => Some("operand is an unknown global value"),
+                };
+                if let Some(message) = message {
+                    self.error(Some(bb), Some(inst), message);
+                }
+                continue;
+            }
+            let operand_data = match self.data.dfg().values().get(&operand) {
+                Some(operand_data) => operand_data,
+                None => {
+                    self.error(Some(bb), Some(inst), "operand does not exist");
+                    continue;
+                }
+            };
+            if !operand_data.used_by().contains(&inst) {
+                let message = format!("use list of operand {} misses it", self.describe(operand));
+                self.error(Some(bb), Some(inst), &message);
+            }
+            // Dominance is meaningless in unreachable blocks.
+            if !self.cfg.is_reachable(bb) {
+                continue;
+            }
+            let available = match operand_data.kind() {
+                ValueKind::FuncArgRef(_) => self.data.params().contains(&operand),
+                ValueKind::BlockArgRef(_) => self
+                    .params
+                    .get(&operand)
+                    .is_some_and(|def_bb| self.doms.dominates(*def_bb, bb)),
+                kind if kind.is_local_inst() => match self.positions.get(&operand) {
+                    Some((def_bb, def_index)) if *def_bb == bb => *def_index < index,
+                    Some((def_bb, _)) => self.doms.dominates(*def_bb, bb),
+                    None => false,
+                },
+                _ => true,
+            };
+            if !available {
+                let message = format!(
+                    "operand {} is not defined before this instruction",
+                    self.describe(operand)
+                );
+                self.error(Some(bb), Some(inst), &message);
+            } else if operand_data.ty().is_unit() {
+                let message = format!("operand {} has no value", self.describe(operand));
+                self.error(Some(bb), Some(inst), &message);
+            }
+        }
+    }
+
+    fn check_types(&mut self, bb: BasicBlock, inst: Value) {
+        let inst_data = self.data.dfg().value(inst);
+        let ty = inst_data.ty().clone();
+        let i32 = Type::get_i32();
+        let message = match inst_data.kind() {
+            ValueKind::Alloc(_) => match ty.kind() {
+                TypeKind::Pointer(_) => None,
+                _ => Some(format!("allocation has non-pointer type {}", ty)),
+            },
+            ValueKind::Load(load) => match self.ty(load.src()) {
+                Some(src) if src == Type::get_pointer(ty.clone()) => None,
+                Some(src) => Some(format!("load of type {} from {}", ty, src)),
+                None => None,
+            },
+            ValueKind::Store(store) => match (self.ty(store.value()), self.ty(store.dest())) {
+                (Some(value), Some(dest)) if dest != Type::get_pointer(value.clone()) => {
+                    Some(format!("store of {} to {}", value, dest))
+                }
+                _ => None,
+            },
+            ValueKind::GetPtr(get_ptr) => match self.ty(get_ptr.src()) {
+                Some(src) if src != ty || !matches!(src.kind(), TypeKind::Pointer(_)) => {
+                    Some(format!("pointer calculation of type {} on {}", ty, src))
+                }
+                _ => self.expect_i32(get_ptr.index(), "index"),
+            },
+            ValueKind::GetElemPtr(get_elem_ptr) => match self.ty(get_elem_ptr.src()) {
+                Some(src) => match src.kind() {
+                    TypeKind::Pointer(base) => match base.kind() {
+                        TypeKind::Array(elem, _) if ty == Type::get_pointer(elem.clone()) => {
+                            self.expect_i32(get_elem_ptr.index(), "index")
+                        }
+                        _ => Some(format!("element pointer of type {} on {}", ty, src)),
+                    },
+                    _ => Some(format!("element pointer on non-pointer {}", src)),
+                },
+                None => None,
+            },
+            ValueKind::Binary(binary) => {
+                if ty != i32 {
+                    Some(format!("binary operation has type {}", ty))
+                } else {
+                    self.expect_i32(binary.lhs(), "left operand")
+                        .or_else(|| self.expect_i32(binary.rhs(), "right operand"))
+                }
+            }
+            ValueKind::Branch(branch) => self.expect_i32(branch.cond(), "condition"),
+            ValueKind::Call(call) => {
+                let callee = self.program.func(call.callee());
+                match callee.ty().kind() {
+                    TypeKind::Function(params, ret) => {
+                        let args: Vec<Option<Type>> =
+                            call.args().iter().map(|arg| self.ty(*arg)).collect();
+                        if args.len() != params.len() {
+                            Some(format!(
+                                "call to {} with {} arguments instead of {}",
+                                callee.name(),
+                                args.len(),
+                                params.len()
+                            ))
+                        } else if let Some((i, (arg, param))) = args
+                            .iter()
+                            .zip(params)
+                            .enumerate()
+                            .find(|(_, (arg, param))| arg.as_ref().is_some_and(|arg| arg != *param))
+                        {
+                            Some(format!(
+                                "argument {} of call to {} has type {} instead of {}",
+                                i + 1,
+                                callee.name(),
+                                arg.as_ref().unwrap(),
+                                param
+                            ))
+                        } else if ty != *ret {
+                            Some(format!(
+                                "call to {} has type {} instead of {}",
+                                callee.name(),
+                                ty,
+                                ret
+                            ))
+                        } else {
+                            None
+                        }
+                    }
+                    _ => Some(format!("callee {} is not a function", callee.name())),
+                }
+            }
+            ValueKind::Return(ret) => {
+                let expected = match self.data.ty().kind() {
+                    TypeKind::Function(_, ret) => ret.clone(),
+                    _ => Type::get_unit(),
+                };
+                let actual = match ret.value() {
+                    Some(value) => self.ty(value),
+                    None => Some(Type::get_unit()),
+                };
+                match actual {
+                    Some(actual) if actual != expected => Some(format!(
+                        "return of {} from a function returning {}",
+                        actual, expected
+                    )),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        if let Some(message) = message {
+            self.error(Some(bb), Some(inst), &message);
+        }
+    }
+
+    /// Check the targets of a branch or a jump, and the arguments passed to them.
+    fn check_targets(&mut self, bb: BasicBlock, inst: Value) {
+        let targets: Vec<(BasicBlock, &[Value])> = match self.data.dfg().value(inst).kind() {
+            ValueKind::Branch(branch) => vec![
+                (branch.true_bb(), branch.true_args()),
+                (branch.false_bb(), branch.false_args()),
+            ],
+            ValueKind::Jump(jump) => vec![(jump.target(), jump.args())],
+            _ => return,
+        };
+        for (target, args) in targets {
+            let target_data = match self.data.dfg().bbs().get(&target) {
+                Some(target_data) if self.data.layout().bbs().node(&target).is_some() => {
+                    target_data
+                }
+                _ => {
+                    self.error(Some(bb), Some(inst), "target block is not in the function");
+                    continue;
+                }
+            };
+            let name = self.bb_name(target);
+            if !target_data.used_by().contains(&inst) {
+                let message = format!("use list of target {} misses it", name);
+                self.error(Some(bb), Some(inst), &message);
+            }
+            let params = target_data.params();
+            let message = if args.len() != params.len() {
+                Some(format!(
+                    "{} arguments passed to {} instead of {}",
+                    args.len(),
+                    name,
+                    params.len()
+                ))
+            } else {
+                args.iter()
+                    .zip(params)
+                    .enumerate()
+                    .find_map(|(i, (arg, param))| {
+                        let (arg, param) = (self.ty(*arg)?, self.ty(*param)?);
+                        (arg != param).then(|| {
+                            format!(
+                                "argument {} passed to {} has type {} instead of {}",
+                                i + 1,
+                                name,
+                                arg,
+                                param
+                            )
+                        })
+                    })
+            };
+            if let Some(message) = message {
+                self.error(Some(bb), Some(inst), &message);
+            }
+        }
+    }
+
+    /// Check that every user of a local value or a block actually uses it.
+    fn check_use_lists(&mut self) {
+        let dfg = self.data.dfg();
+        for (value, value_data) in dfg.values() {
+            for user in value_data.used_by() {
+                let uses = dfg
+                    .values()
+                    .get(user)
+                    .is_some_and(|user_data| user_data.kind().value_uses().any(|v| v == *value));
+                if !uses {
+                    let message = format!(
+                        "use list contains {}, which does not use it",
+                        self.describe(*user)
+                    );
+                    self.error(
+                        self.positions.get(value).map(|p| p.0),
+                        Some(*value),
+                        &message,
+                    );
+                }
+            }
+        }
+        for (bb, bb_data) in dfg.bbs() {
+            for user in bb_data.used_by() {
+                let uses = dfg
+                    .values()
+                    .get(user)
+                    .is_some_and(|user_data| user_data.kind().bb_uses().any(|b| b == *bb));
+                if !uses {
+                    let message = format!(
+                        "use list contains {}, which does not jump to the block",
+                        self.describe(*user)
+                    );
+                    self.error(Some(*bb), None, &message);
+                }
+            }
+        }
+    }
+
+    /// Get the type of a value, `None` if it does not exist, which is reported elsewhere.
+    fn ty(&self, value: Value) -> Option<Type> {
+        if value.is_global() {
+            let values = self.program.borrow_values();
+            values.get(&value).map(|data| data.ty().clone())
+        } else {
+            let data = self.data.dfg().values().get(&value)?;
+            Some(data.ty().clone())
+        }
+    }
+
+    fn expect_i32(&self, value: Value, what: &str) -> Option<String> {
+        let ty = self.ty(value)?;
+        (!ty.is_i32()).then(|| format!("{} has type {} instead of i32", what, ty))
+    }
+
+    fn bb_name(&self, bb: BasicBlock) -> String {
+        match self
+            .data
+            .dfg()
+            .bbs()
+            .get(&bb)
+            .and_then(|data| data.name().clone())
+        {
+            Some(name) => name,
+            None => match self.data.layout().bbs().keys().position(|b| *b == bb) {
+                Some(i) => format!("#{}", i),
+                None => "<removed>".to_string(),
+            },
+        }
+    }
+
+    /// Describe a value by its name, or by its position if it has none.
+    fn describe(&self, value: Value) -> String {
+        if value.is_global() {
+            let values = self.program.borrow_values();
+            return match values.get(&value).and_then(|data| data.name().clone()) {
+                Some(name) => name,
+                None => "unnamed global value".to_string(),
+            };
+        }
+        let data = match self.data.dfg().values().get(&value) {
+            Some(data) => data,
+            None => return "<removed value>".to_string(),
+        };
+        if let Some(name) = data.name() {
+            return format!("`{}`", name);
+        }
+        let kind = kind_name(data.kind());
+        match self.positions.get(&value) {
+            Some((bb, i)) => format!("{} #{} of {}", kind, i, self.bb_name(*bb)),
+            None if data.kind().is_local_inst() => format!("{} not in any block", kind),
+            None => kind.to_string(),
+        }
+    }
+
+    fn error(&mut self, bb: Option<BasicBlock>, value: Option<Value>, message: &str) {
+        let error = VerifyError {
+            func: self.data.name().to_string(),
+            bb: bb.map(|bb| self.bb_name(bb)),
+            value: value.map(|value| self.describe(value)),
+            message: message.to_string(),
+        };
+        self.errors.push(error);
+    }
+}
+
+fn is_terminator(kind: &ValueKind) -> bool {
+    matches!(
+        kind,
+        ValueKind::Branch(_) | ValueKind::Jump(_) | ValueKind::Return(_)
+    )
+}
+
+fn kind_name(kind: &ValueKind) -> &'static str {
+    match kind {
+        ValueKind::Integer(_) => "integer",
+        ValueKind::ZeroInit(_) => "zeroinit",
+        ValueKind::Undef(_) => "undef",
+        ValueKind::Aggregate(_) => "aggregate",
+        ValueKind::FuncArgRef(_) => "function argument",
+        ValueKind::BlockArgRef(_) => "block argument",
+        ValueKind::Alloc(_) => "alloc",
+        ValueKind::GlobalAlloc(_) => "global alloc",
+        ValueKind::Load(_) => "load",
+        ValueKind::Store(_) => "store",
+        ValueKind::GetPtr(_) => "getptr",
+        ValueKind::GetElemPtr(_) => "getelemptr",
+        ValueKind::Binary(_) => "binary",
+        ValueKind::Branch(_) => "br",
+        ValueKind::Jump(_) => "jump",
+        ValueKind::Call(_) => "call",
+        ValueKind::Return(_) => "ret",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::front::opt::{opt, Pipeline};
+    use crate::util::testing::compile;
+
+    #[test]
+    fn test_verify() {
+        let mut program = compile(
+            "int a[4]; int main() { int s = 0; int i = 0;
+            while (i < 4) { s = s + a[i] * 2; i = i + 1; } return s; }",
+        );
+        assert_eq!(verify(&program), vec![]);
+        let mut pipeline = Pipeline::for_level(2);
+        pipeline.verify = false;
+        opt(&mut program, &pipeline);
+        assert_eq!(verify(&program), vec![]);
+
+        let main = *program
+            .func_layout()
+            .iter()
+            .find(|func| program.func(**func).name() == "@main")
+            .unwrap();
+        let data = program.func_mut(main);
+        let bb = *data
+            .layout()
+            .bbs()
+            .iter()
+            .find(|(_, node)| node.insts().len() > 1)
+            .unwrap()
+            .0;
+        data.layout_mut().bb_mut(bb).insts_mut().pop_back();
+        let errors = verify(&program);
+        assert!(errors
+            .iter()
+            .any(|e| e.func == "@main" && e.message == "block does not end with a terminator"));
+    }
+}